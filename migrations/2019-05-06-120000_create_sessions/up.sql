@@ -0,0 +1,12 @@
+create table sessions (
+  id uuid primary key,
+  token_hash text not null unique,
+  identity text not null,
+  authorities text[] not null,
+  user_agent text,
+  created_at timestamp with time zone not null default now(),
+  last_accessed_at timestamp with time zone not null default now(),
+  expires_at timestamp with time zone not null
+);
+
+create index sessions_identity_idx on sessions (identity);
@@ -0,0 +1,2 @@
+alter table sessions drop column membership_epoch;
+alter table users drop column membership_epoch;
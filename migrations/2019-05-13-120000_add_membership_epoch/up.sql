@@ -0,0 +1,4 @@
+-- Bumped whenever the direct or nested group membership of a user changes,
+-- credentials issued under an older epoch get their authorities re-resolved.
+alter table users add column membership_epoch bigint not null default 0;
+alter table sessions add column membership_epoch bigint not null default 0;
@@ -0,0 +1,9 @@
+create table login_failures (
+  id uuid primary key,
+  kind text not null,
+  subject text not null,
+  failures integer not null,
+  last_failed_at timestamp with time zone not null,
+  locked_until timestamp with time zone,
+  unique (kind, subject)
+);
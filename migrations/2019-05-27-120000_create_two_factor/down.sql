@@ -0,0 +1,3 @@
+alter table groups drop column require_two_factor;
+drop table recovery_codes;
+drop table two_factor;
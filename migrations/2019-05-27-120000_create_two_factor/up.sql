@@ -0,0 +1,19 @@
+create table two_factor (
+  user_id uuid primary key,
+  secret bytea not null,
+  confirmed_at timestamp with time zone,
+  last_used_step bigint not null default 0,
+  created_at timestamp with time zone not null default now()
+);
+
+create table recovery_codes (
+  id uuid primary key,
+  user_id uuid not null,
+  code_hash text not null,
+  used_at timestamp with time zone
+);
+
+create index recovery_codes_user_id_idx on recovery_codes (user_id);
+
+alter table groups add column require_two_factor boolean not null default false;
+update groups set require_two_factor = true where display_name = 'admin';
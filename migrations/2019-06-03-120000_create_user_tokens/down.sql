@@ -0,0 +1,2 @@
+drop table user_tokens;
+alter table users drop column email;
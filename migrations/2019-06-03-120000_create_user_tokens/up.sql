@@ -0,0 +1,13 @@
+alter table users add column email text unique;
+
+create table user_tokens (
+  id uuid primary key,
+  token_hash text not null unique,
+  user_id uuid not null,
+  purpose text not null,
+  created_at timestamp with time zone not null default now(),
+  expires_at timestamp with time zone not null,
+  used_at timestamp with time zone
+);
+
+create index user_tokens_user_id_idx on user_tokens (user_id);
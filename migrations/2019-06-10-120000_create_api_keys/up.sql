@@ -0,0 +1,13 @@
+create table api_keys (
+  id uuid primary key,
+  user_id uuid not null,
+  name text not null,
+  prefix text not null unique,
+  secret_hash text not null,
+  authorities text[],
+  expires_at timestamp with time zone,
+  last_used_at timestamp with time zone,
+  created_at timestamp with time zone not null default now()
+);
+
+create index api_keys_user_id_idx on api_keys (user_id);
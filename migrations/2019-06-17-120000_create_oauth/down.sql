@@ -0,0 +1,3 @@
+drop table oauth_access_tokens;
+drop table oauth_codes;
+drop table oauth_clients;
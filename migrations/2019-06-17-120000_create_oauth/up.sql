@@ -0,0 +1,33 @@
+create table oauth_clients (
+  id uuid primary key,
+  name text not null,
+  secret_hash text,
+  redirect_uris text[] not null,
+  created_at timestamp with time zone not null default now()
+);
+
+create table oauth_codes (
+  id uuid primary key,
+  code_hash text not null unique,
+  client_id uuid not null,
+  user_id uuid not null,
+  redirect_uri text not null,
+  scope text not null,
+  nonce text,
+  code_challenge text not null,
+  created_at timestamp with time zone not null default now(),
+  expires_at timestamp with time zone not null,
+  used_at timestamp with time zone
+);
+
+create table oauth_access_tokens (
+  id uuid primary key,
+  token_hash text not null unique,
+  client_id uuid not null,
+  user_id uuid not null,
+  scope text not null,
+  created_at timestamp with time zone not null default now(),
+  expires_at timestamp with time zone not null
+);
+
+create index oauth_access_tokens_user_id_idx on oauth_access_tokens (user_id);
@@ -0,0 +1,2 @@
+drop table external_logins;
+drop table external_identities;
@@ -0,0 +1,21 @@
+create table external_identities (
+  id uuid primary key,
+  issuer text not null,
+  subject text not null,
+  user_id uuid not null,
+  synced_group_ids uuid[] not null default '{}',
+  created_at timestamp with time zone not null default now(),
+  last_login_at timestamp with time zone,
+  unique (issuer, subject)
+);
+
+create index external_identities_user_id_idx on external_identities (user_id);
+
+create table external_logins (
+  state_hash text primary key,
+  binding_hash text not null,
+  nonce text not null,
+  code_verifier text not null,
+  created_at timestamp with time zone not null default now(),
+  expires_at timestamp with time zone not null
+);
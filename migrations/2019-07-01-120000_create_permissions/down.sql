@@ -0,0 +1,2 @@
+drop table group_permissions;
+drop table permissions;
@@ -0,0 +1,34 @@
+create table permissions (
+  id uuid primary key,
+  name text not null unique,
+  description text,
+  created_at timestamp with time zone not null default now()
+);
+
+create table group_permissions (
+  group_id uuid not null,
+  permission_id uuid not null,
+  added timestamp with time zone not null default now(),
+  primary key (group_id, permission_id)
+);
+
+create index group_permissions_permission_id_idx on group_permissions (permission_id);
+
+-- Groups used to double as permissions, keep their members authorized by
+-- turning each permission group into a permission granted to the group.
+insert into permissions (id, name, description)
+select md5(random()::text || clock_timestamp()::text)::uuid, display_name, description
+from groups
+where display_name like '%.%';
+
+insert into permissions (id, name, description)
+select md5(random()::text || clock_timestamp()::text)::uuid, '*', 'Every permission'
+where exists (select 1 from groups where display_name = 'admin');
+
+insert into group_permissions (group_id, permission_id)
+select g.id, p.id
+from groups g
+join permissions p on p.name = g.display_name or (g.display_name = 'admin' and p.name = '*');
+
+-- Credentials carry permission names from now on.
+update users set membership_epoch = membership_epoch + 1;
@@ -0,0 +1 @@
+drop table acl_entries;
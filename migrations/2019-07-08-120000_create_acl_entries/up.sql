@@ -0,0 +1,13 @@
+create table acl_entries (
+  id uuid primary key,
+  resource_type text not null,
+  resource_id uuid not null,
+  principal_id uuid not null,
+  principal_type text not null,
+  action text not null,
+  created_at timestamp with time zone not null default now(),
+  constraint acl_entries_action_key
+    unique (resource_type, resource_id, principal_id, action)
+);
+
+create index acl_entries_principal_id_idx on acl_entries (principal_id);
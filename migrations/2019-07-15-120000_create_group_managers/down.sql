@@ -0,0 +1 @@
+drop table group_managers;
@@ -0,0 +1,9 @@
+-- Users allowed to manage the members and description of a group.
+create table group_managers (
+  group_id uuid not null,
+  user_id uuid not null,
+  added timestamp with time zone not null default now(),
+  primary key (group_id, user_id)
+);
+
+create index group_managers_user_id_idx on group_managers (user_id);
@@ -0,0 +1,2 @@
+drop table audit_entries;
+alter table sessions drop column impersonator;
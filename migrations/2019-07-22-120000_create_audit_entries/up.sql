@@ -0,0 +1,15 @@
+-- Identity of the administrator a session impersonates its user for.
+alter table sessions add column impersonator text;
+
+-- Requests made while impersonating another user.
+create table audit_entries (
+  id uuid primary key,
+  actor text not null,
+  identity text not null,
+  method text not null,
+  path text not null,
+  status integer not null,
+  created_at timestamp with time zone not null default now()
+);
+
+create index audit_entries_created_at_idx on audit_entries (created_at);
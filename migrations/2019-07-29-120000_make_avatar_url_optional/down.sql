@@ -0,0 +1,2 @@
+update users set avatar_url = '' where avatar_url is null;
+alter table users alter column avatar_url set not null;
@@ -0,0 +1 @@
+alter table users alter column avatar_url drop not null;
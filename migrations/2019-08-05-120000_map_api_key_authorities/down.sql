@@ -0,0 +1 @@
+-- The group names the authorities were mapped from are not kept.
@@ -0,0 +1,22 @@
+-- API keys used to be limited to groups. Groups that did not turn into
+-- permissions are replaced by the permissions granted to them.
+update api_keys k
+set authorities = array(
+  select distinct name
+  from (
+    select a.name
+    from unnest(k.authorities) as a(name)
+    where not exists (
+      select 1 from groups g
+      where g.display_name = a.name and g.display_name not like '%.%'
+    )
+    union
+    select p.name
+    from unnest(k.authorities) as a(name)
+    join groups g on g.display_name = a.name and g.display_name not like '%.%'
+    join group_permissions gp on gp.group_id = g.id
+    join permissions p on p.id = gp.permission_id
+  ) names
+  order by name
+)
+where k.authorities is not null;
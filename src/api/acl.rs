@@ -0,0 +1,148 @@
+use actix_web::{web, HttpResponse, Scope};
+use futures::Future;
+use uuid::Uuid;
+
+use crate::auth::acl::{self, Authorizer, GROUPS, MANAGE_ACL};
+use crate::auth::policy::{self, Policy};
+use crate::auth::Authentication;
+use crate::db::{
+    acl::{self as entries, NewAclEntry},
+    groups::{self, GroupMembershipType},
+    users, Conn, Database,
+};
+use crate::error::{Error, ErrorKind, Result};
+
+pub fn service(path: &str) -> Scope {
+    web::scope(path)
+        .service(
+            web::resource("/{resource_type}/{resource_id}")
+                .route(web::get().to_async(get_entries))
+                .route(web::post().to_async(add_entry)),
+        )
+        .service(
+            web::resource("/{resource_type}/{resource_id}/{entry_id}")
+                .route(web::delete().to_async(del_entry)),
+        )
+}
+
+fn get_entries(
+    a: Authentication,
+    db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    path: web::Path<(String, Uuid)>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        let (resource_type, resource_id) = path.into_inner();
+        let conn = db.conn()?;
+        check_resource_exists(&conn, &resource_type, &resource_id)?;
+        let allowed = Authorizer::new(&conn, &a).is_allowed(
+            &resource_type,
+            &resource_id,
+            MANAGE_ACL,
+        )?;
+        policy.check(&input.resource("allows_acl", allowed.to_string()))?;
+        entries::find_by_resource(&conn, &resource_type, &resource_id)
+    })
+    .from_err()
+    .map(|res| HttpResponse::Ok().json(res))
+}
+
+fn add_entry(
+    a: Authentication,
+    db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    path: web::Path<(String, Uuid)>,
+    new: web::Json<NewAclEntry>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let new = new.into_inner();
+    web::block(move || -> Result<_> {
+        let (resource_type, resource_id) = path.into_inner();
+        acl::validate_action(&new.action)?;
+        db.transaction(|conn| {
+            check_resource_exists(conn, &resource_type, &resource_id)?;
+            let allowed = Authorizer::new(conn, &a).is_allowed(
+                &resource_type,
+                &resource_id,
+                MANAGE_ACL,
+            )?;
+            policy.check(&input.resource("allows_acl", allowed.to_string()))?;
+            check_principal_exists(conn, &new)?;
+            entries::create(conn, &resource_type, &resource_id, new)
+        })
+    })
+    .from_err()
+    .map(|res| HttpResponse::Created().json(res))
+}
+
+fn del_entry(
+    a: Authentication,
+    db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    path: web::Path<(String, Uuid, Uuid)>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        let (resource_type, resource_id, entry_id) = path.into_inner();
+        let conn = db.conn()?;
+        check_resource_exists(&conn, &resource_type, &resource_id)?;
+        let allowed = Authorizer::new(&conn, &a).is_allowed(
+            &resource_type,
+            &resource_id,
+            MANAGE_ACL,
+        )?;
+        policy.check(&input.resource("allows_acl", allowed.to_string()))?;
+        match entries::del_by_id(
+            &conn,
+            &resource_type,
+            &resource_id,
+            &entry_id,
+        )? {
+            0 => Err(ErrorKind::NotFound)?,
+            _ => Ok(()),
+        }
+    })
+    .from_err()
+    .map(|_| HttpResponse::NoContent().finish())
+}
+
+fn check_resource_exists(
+    conn: &Conn,
+    resource_type: &str,
+    resource_id: &Uuid,
+) -> Result<()> {
+    let exists = match resource_type {
+        GROUPS => groups::find_by_id(conn, resource_id)?.is_some(),
+        _ => false,
+    };
+
+    if exists {
+        Ok(())
+    } else {
+        Err(ErrorKind::NotFound)?
+    }
+}
+
+fn check_principal_exists(conn: &Conn, new: &NewAclEntry) -> Result<()> {
+    let exists = match new.principal_type {
+        GroupMembershipType::User => {
+            users::find_by_id(conn, &new.principal_id)?.is_some()
+        }
+        GroupMembershipType::Group => {
+            groups::find_by_id(conn, &new.principal_id)?.is_some()
+        }
+    };
+
+    if exists {
+        Ok(())
+    } else {
+        Err(Error::validation(
+            "principal_id",
+            format!(
+                "{:?} {} does not exist",
+                new.principal_type, new.principal_id
+            ),
+        ))
+    }
+}
@@ -0,0 +1,81 @@
+use actix_web::{web, HttpResponse, Scope};
+use futures::Future;
+
+use super::page::PageQuery;
+use crate::db::{audit, Database};
+use crate::error::{Error, Result};
+
+pub fn service(path: &str) -> Scope {
+    web::scope(path)
+        .service(web::resource("").route(web::get().to_async(get_entries)))
+}
+
+/// Lists what administrators did while impersonating users.
+fn get_entries(
+    db: web::Data<Database>,
+    query: PageQuery,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let request = query.request().clone();
+    web::block(move || -> Result<_> {
+        let conn = db.conn()?;
+        audit::find_page(&conn, &request)
+    })
+    .from_err()
+    .map(move |res| query.respond(res))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{self, TestRequest};
+    use serde_json::{json, Value};
+
+    use crate::auth::Authentication;
+    use crate::db::audit::{self, NewAuditEntry};
+    use crate::test_helpers::*;
+
+    #[test]
+    fn test_get_entries() {
+        let db = database();
+        {
+            let conn = db.conn().unwrap();
+            audit::create(
+                &conn,
+                NewAuditEntry {
+                    actor: "admin".to_string(),
+                    identity: "bob".to_string(),
+                    method: "GET".to_string(),
+                    path: "/api/users".to_string(),
+                    status: 200,
+                },
+            )
+            .unwrap();
+        }
+        let mut app = app(&db);
+        let get = |cookie| {
+            let req = TestRequest::get().uri("/api/audit-entries");
+            match cookie {
+                Some(cookie) => req.cookie(cookie).to_request(),
+                None => req.to_request(),
+            }
+        };
+
+        let resp = test::call_service(&mut app, get(None));
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+        let user = login(&mut app, &Authentication::new("bob", Vec::new()));
+        let resp = test::call_service(&mut app, get(Some(user)));
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+
+        let auditor = login(
+            &mut app,
+            &Authentication::new("auditor", vec!["audit.get".to_string()]),
+        );
+        let resp = test::call_service(&mut app, get(Some(auditor)));
+        assert_eq!(StatusCode::OK, resp.status());
+        let body: Value =
+            serde_json::from_slice(&test::read_body(resp)).unwrap();
+        assert_eq!(json!(1), body["total"]);
+        assert_eq!(json!("admin"), body["items"][0]["actor"]);
+        assert_eq!(json!("/api/users"), body["items"][0]["path"]);
+    }
+}
@@ -1,24 +1,100 @@
-use actix_web::{web, Error, HttpResponse, Scope};
+use std::sync::Arc;
+
+use actix_web::{web, HttpRequest, HttpResponse, Scope};
 use futures::Future;
+use uuid::Uuid;
 
-use crate::auth::{Authentication, AuthenticationManager};
-use crate::db::{groups, users, Database};
-use crate::error::{ErrorKind, Result};
+use crate::auth::policy::{self, Policy};
+use crate::auth::two_factor::{self, SecondFactor};
+use crate::auth::{
+    membership, Authentication, AuthenticationManager, LockoutPolicy,
+    PasswordPolicy, SessionManager, TokenSigner, TrustedProxies, UserTokens,
+};
+use crate::db::{
+    user_tokens::TokenPurpose,
+    users::{self, UpdateUser, User},
+    Conn, Database,
+};
+use crate::error::{Error, ErrorKind, Result, ResultExt};
+use crate::mail::{self, Mailer};
 use crate::utils;
 
 #[derive(Debug, Deserialize)]
 struct AuthData {
     username: String,
     password: String,
+    /// Second factor, only read by the token endpoint.
+    #[serde(default)]
+    code: Option<String>,
+}
+
+/// A login waiting for its second factor, completed through the two-factor
+/// endpoints with the challenge.
+#[derive(Debug, Serialize)]
+pub(super) struct Challenge {
+    challenge: String,
+    /// The user has to enroll before the login completes.
+    enroll: bool,
+}
+
+pub(super) enum Login {
+    Complete(Authentication),
+    Challenge(Challenge),
+}
+
+#[derive(Debug, Deserialize)]
+struct ResetRequest {
+    /// Username or email address.
+    login: String,
+}
+
+/// Password chosen with a reset or invitation token.
+#[derive(Debug, Deserialize)]
+struct RedeemToken {
+    token: String,
+    password: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChangePassword {
+    old_password: String,
+    new_password: String,
 }
 
 pub fn service(path: &str) -> Scope {
-    web::scope(path).service(
-        web::resource("")
-            .route(web::get().to(userinfo))
-            .route(web::post().to_async(login))
-            .route(web::delete().to(logout)),
-    )
+    web::scope(path)
+        .service(
+            web::resource("")
+                .route(web::get().to(userinfo))
+                .route(web::post().to_async(login))
+                .route(web::delete().to(logout)),
+        )
+        .service(web::resource("/token").route(web::post().to_async(token)))
+        .service(super::two_factor::service("/two-factor"))
+        .service(super::oidc_login::service("/oidc"))
+        .service(super::impersonation::service("/impersonation"))
+        .service(
+            web::resource("/password")
+                .route(web::put().to_async(change_password)),
+        )
+        .service(
+            web::resource("/password-reset")
+                .route(web::post().to_async(request_reset))
+                .route(web::put().to_async(reset_password)),
+        )
+        .service(
+            web::resource("/invitation")
+                .route(web::put().to_async(accept_invitation)),
+        )
+        .service(
+            web::resource("/sessions")
+                .route(web::get().to_async(get_sessions))
+                .route(web::delete().to_async(del_sessions)),
+        )
+        .service(
+            web::resource("/sessions/{session_id}")
+                .route(web::delete().to_async(del_session)),
+        )
 }
 
 fn userinfo(a: Authentication) -> HttpResponse {
@@ -27,36 +103,159 @@ fn userinfo(a: Authentication) -> HttpResponse {
 }
 
 fn login(
+    req: HttpRequest,
     auth_data: web::Json<AuthData>,
     db: web::Data<Database>,
+    lockout: web::Data<LockoutPolicy>,
+    policy: web::Data<PasswordPolicy>,
+    signer: web::Data<TokenSigner>,
     am: AuthenticationManager,
 ) -> impl Future<Item = HttpResponse, Error = Error> {
     let auth_data = auth_data.into_inner();
+    let ip = client_ip(&req);
 
-    web::block(move || -> Result<Authentication> {
+    web::block(move || -> Result<_> {
         let conn = db.conn()?;
-        if let Some(user) = users::find_by_username(&conn, &auth_data.username)?
-        {
-            let verified_password =
-                utils::verify_password(&auth_data.password, &user.password)?;
-
-            if verified_password {
-                let groups = groups::find_by_member_id(&conn, &user.id)?;
-                let authorities = groups.into_iter().map(|g| g.display_name);
-                let identity = user.id.simple().to_string();
-                let authentication = Authentication::new(identity, authorities);
-
-                return Ok(authentication);
+        let ip = ip.as_ref().map(String::as_str);
+        let user = check_password(&conn, &lockout, &policy, &auth_data, ip)?;
+
+        let login = start_login(&conn, &signer, &user)?;
+        if let Login::Complete(_) = login {
+            lockout.record_success(&conn, &user.username)?;
+        }
+        Ok(login)
+    })
+    .from_err()
+    .map(move |login| respond(am, login))
+}
+
+/// Logs in a user whose first factor was verified, unless a second factor
+/// is due, which is then challenged for.
+pub(super) fn start_login(
+    conn: &Conn,
+    signer: &TokenSigner,
+    user: &User,
+) -> Result<Login> {
+    let enroll = match two_factor::second_factor(conn, user)? {
+        SecondFactor::None => {
+            return Ok(Login::Complete(membership::authenticate(conn, user)?));
+        }
+        SecondFactor::Code => false,
+        SecondFactor::Enrollment => true,
+    };
+    let identity = user.id.simple().to_string();
+
+    Ok(Login::Challenge(Challenge {
+        challenge: signer.issue_challenge(&identity)?,
+        enroll,
+    }))
+}
+
+pub(super) fn respond(am: AuthenticationManager, login: Login) -> HttpResponse {
+    match login {
+        Login::Complete(a) => {
+            am.remember(a);
+            HttpResponse::Ok().finish()
+        }
+        Login::Challenge(challenge) => HttpResponse::Accepted().json(challenge),
+    }
+}
+
+/// Issues a bearer token for clients that can not keep cookies.
+///
+/// Users with two-factor authentication pass their current code along with
+/// the password, enrollment has to be done through the login.
+fn token(
+    req: HttpRequest,
+    auth_data: web::Json<AuthData>,
+    db: web::Data<Database>,
+    lockout: web::Data<LockoutPolicy>,
+    policy: web::Data<PasswordPolicy>,
+    signer: web::Data<TokenSigner>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let auth_data = auth_data.into_inner();
+    let ip = client_ip(&req);
+
+    web::block(move || -> Result<_> {
+        let conn = db.conn()?;
+        let ip = ip.as_ref().map(String::as_str);
+        let user = check_password(&conn, &lockout, &policy, &auth_data, ip)?;
+
+        match two_factor::second_factor(&conn, &user)? {
+            SecondFactor::None => {}
+            SecondFactor::Code => {
+                let code = match auth_data.code {
+                    Some(ref code) => code,
+                    None => Err(Error::from(ErrorKind::Unauthorized)
+                        .with_detail("a two-factor code is required"))?,
+                };
+                if !two_factor::verify(&conn, &user.id, code)? {
+                    lockout.record_failure(&conn, &user.username, ip)?;
+                    Err(ErrorKind::Unauthorized)?
+                }
             }
+            SecondFactor::Enrollment => Err(Error::from(ErrorKind::Forbidden)
+                .with_detail("two-factor enrollment is required"))?,
         }
 
-        Err(ErrorKind::Unauthorized)?
+        lockout.record_success(&conn, &user.username)?;
+        let authentication = membership::authenticate(&conn, &user)?;
+        signer.issue(&authentication)
     })
     .from_err()
-    .map(move |a| {
-        am.remember(a);
-        HttpResponse::Ok().finish()
-    })
+    .map(|token| HttpResponse::Ok().json(token))
+}
+
+/// Checks the password, counting failures against the username and the
+/// client address. Success is left to be recorded once the login completes.
+///
+/// Weaker hashes than the policy asks for are replaced while the password is
+/// at hand.
+fn check_password(
+    conn: &Conn,
+    lockout: &LockoutPolicy,
+    policy: &PasswordPolicy,
+    auth_data: &AuthData,
+    ip: Option<&str>,
+) -> Result<User> {
+    lockout.check(conn, &auth_data.username, ip)?;
+
+    let user = users::find_by_username(conn, &auth_data.username)?;
+    let verified_password = match user {
+        Some(ref user) => {
+            utils::verify_password(&auth_data.password, &user.password)?
+        }
+        None => {
+            utils::dummy_verify_password(&auth_data.password);
+            false
+        }
+    };
+
+    match user {
+        Some(user) if verified_password => {
+            if policy.needs_rehash(&user.password) {
+                let hashed_password = policy.hash(&auth_data.password)?;
+                users::update_password(conn, &user.id, &hashed_password)?;
+                info!("Rehashed the password of user {}", &user.username);
+            }
+            Ok(user)
+        }
+        _ => {
+            lockout.record_failure(conn, &auth_data.username, ip)?;
+            Err(ErrorKind::Unauthorized)?
+        }
+    }
+}
+
+/// Address of the client, forwarded addresses are only taken from trusted
+/// proxies.
+pub(super) fn client_ip(req: &HttpRequest) -> Option<String> {
+    let ip = match req.app_data::<TrustedProxies>() {
+        Some(proxies) => proxies.client_ip(req),
+        None => TrustedProxies::default().client_ip(req),
+    };
+
+    ip.map(|ip| ip.to_string())
 }
 
 fn logout(am: AuthenticationManager) -> HttpResponse {
@@ -64,3 +263,142 @@ fn logout(am: AuthenticationManager) -> HttpResponse {
 
     HttpResponse::Ok().finish()
 }
+
+fn change_password(
+    a: Authentication,
+    data: web::Json<ChangePassword>,
+    db: web::Data<Database>,
+    password_policy: web::Data<PasswordPolicy>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let data = data.into_inner();
+
+    web::block(move || -> Result<_> {
+        policy.check(&input)?;
+        let user_id =
+            Uuid::parse_str(a.identity()).context(ErrorKind::Unauthorized)?;
+        let conn = db.conn()?;
+        let user = match users::find_by_id(&conn, &user_id)? {
+            Some(user) => user,
+            None => Err(ErrorKind::Unauthorized)?,
+        };
+
+        if !utils::verify_password(&data.old_password, &user.password)? {
+            return Err(Error::validation("old_password", "is incorrect"));
+        }
+        password_policy
+            .validate(&data.new_password, &user.username)
+            .map_err(|e| {
+                let message = e.details().iter().map(|d| d.message.clone());
+                Error::validation("new_password", message.collect::<String>())
+            })?;
+
+        let update = UpdateUser {
+            password: Some(password_policy.hash(&data.new_password)?),
+            ..UpdateUser::default()
+        };
+        users::update(&conn, &user.id, update)?;
+
+        Ok(())
+    })
+    .from_err()
+    .map(|_| HttpResponse::NoContent().finish())
+}
+
+/// Mails a reset link if the user exists and has an email address, the
+/// response is the same either way. Requests are throttled per username or
+/// email address and per client address.
+fn request_reset(
+    req: HttpRequest,
+    data: web::Json<ResetRequest>,
+    db: web::Data<Database>,
+    lockout: web::Data<LockoutPolicy>,
+    tokens: web::Data<UserTokens>,
+    mailer: web::Data<Arc<Mailer>>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let ip = client_ip(&req);
+
+    web::block(move || -> Result<_> {
+        let conn = db.conn()?;
+        let ip = ip.as_ref().map(String::as_str);
+        lockout.throttle_reset(&conn, &data.login, ip)?;
+
+        tokens.reset(&conn, &data.login)
+    })
+    .from_err()
+    .map(move |message| {
+        if let Some(message) = message {
+            mail::send_in_background(mailer.get_ref().clone(), message);
+        }
+        HttpResponse::Accepted().finish()
+    })
+}
+
+fn reset_password(
+    data: web::Json<RedeemToken>,
+    db: web::Data<Database>,
+    tokens: web::Data<UserTokens>,
+    policy: web::Data<PasswordPolicy>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    redeem_token(data.into_inner(), db, tokens, policy, TokenPurpose::Reset)
+}
+
+fn accept_invitation(
+    data: web::Json<RedeemToken>,
+    db: web::Data<Database>,
+    tokens: web::Data<UserTokens>,
+    policy: web::Data<PasswordPolicy>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    redeem_token(data.into_inner(), db, tokens, policy, TokenPurpose::Invite)
+}
+
+fn redeem_token(
+    data: RedeemToken,
+    db: web::Data<Database>,
+    tokens: web::Data<UserTokens>,
+    policy: web::Data<PasswordPolicy>,
+    purpose: TokenPurpose,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || {
+        db.transaction(|conn| {
+            tokens.redeem(conn, &policy, purpose, &data.token, &data.password)
+        })
+    })
+    .from_err()
+    .map(|_| HttpResponse::NoContent().finish())
+}
+
+fn get_sessions(
+    a: Authentication,
+    sessions: web::Data<SessionManager>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || sessions.list(a.identity()))
+        .from_err()
+        .map(|res| HttpResponse::Ok().json(res))
+}
+
+/// Revokes every session of the current user, including this one.
+fn del_sessions(
+    a: Authentication,
+    sessions: web::Data<SessionManager>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || sessions.revoke_all(a.identity()))
+        .from_err()
+        .map(|_| HttpResponse::NoContent().finish())
+}
+
+fn del_session(
+    a: Authentication,
+    session_id: web::Path<Uuid>,
+    sessions: web::Data<SessionManager>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        match sessions.revoke(a.identity(), &session_id)? {
+            0 => Err(ErrorKind::NotFound)?,
+            _ => Ok(()),
+        }
+    })
+    .from_err()
+    .map(|_| HttpResponse::NoContent().finish())
+}
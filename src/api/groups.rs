@@ -1,12 +1,20 @@
-use actix_web::{web, Error, HttpResponse, Scope};
+use actix_web::{web, HttpResponse, Scope};
 use futures::Future;
 use uuid::Uuid;
 
+use super::page::PageQuery;
+use crate::auth::acl::{Authorizer, GROUPS};
+use crate::auth::policy::{self, Policy};
+use crate::auth::Authentication;
 use crate::db::{
-    groups::{self, NewGroup, UpdateGroup},
-    Database,
+    groups::{
+        self, GroupMembershipType, NewGroup, NewGroupManager,
+        NewGroupMembership, UpdateGroup,
+    },
+    permissions::{self, NewGroupPermission},
+    users, Conn, Database,
 };
-use crate::error::Result;
+use crate::error::{Error, ErrorKind, Result};
 
 pub fn service(path: &str) -> Scope {
     web::scope(path)
@@ -20,26 +28,75 @@ pub fn service(path: &str) -> Scope {
                 .route(web::put().to_async(update_group))
                 .route(web::delete().to_async(del_group)),
         )
+        .service(
+            web::resource("/{group_id}/members")
+                .route(web::get().to_async(get_members))
+                .route(web::post().to_async(add_member)),
+        )
+        .service(
+            web::resource("/{group_id}/members/{member_id}")
+                .route(web::delete().to_async(del_member)),
+        )
+        .service(
+            web::resource("/{group_id}/managers")
+                .route(web::get().to_async(get_managers))
+                .route(web::post().to_async(add_manager)),
+        )
+        .service(
+            web::resource("/{group_id}/managers/{user_id}")
+                .route(web::delete().to_async(del_manager)),
+        )
+        .service(
+            web::resource("/{group_id}/permissions")
+                .route(web::get().to_async(get_permissions))
+                .route(web::post().to_async(grant_permission)),
+        )
+        .service(
+            web::resource("/{group_id}/permissions/{permission_id}")
+                .route(web::delete().to_async(revoke_permission)),
+        )
 }
 
+/// Lists the groups the caller may read or manages.
 fn get_groups(
+    a: Authentication,
     db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    query: PageQuery,
 ) -> impl Future<Item = HttpResponse, Error = Error> {
+    let request = query.request().clone();
     web::block(move || -> Result<_> {
+        policy.check(&input)?;
         let conn = db.conn()?;
-        let result = groups::find_all(&conn)?;
+        let authorizer = Authorizer::new(&conn, &a);
+        let ids = match authorizer.allowed_ids(GROUPS, "get")? {
+            Some(mut ids) => {
+                ids.extend(authorizer.managed_ids()?);
+                Some(ids)
+            }
+            None => None,
+        };
+        let result = groups::find_page(
+            &conn,
+            &request,
+            ids.as_ref().map(Vec::as_slice),
+        )?;
         Ok(result)
     })
     .from_err()
-    .map(|res| HttpResponse::Ok().json(res))
+    .map(move |res| query.respond(res))
 }
 
 fn add_group(
     db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
     new: web::Json<NewGroup>,
 ) -> impl Future<Item = HttpResponse, Error = Error> {
     let new = new.into_inner();
     web::block(move || -> Result<_> {
+        policy.check(&input)?;
         let conn = db.conn()?;
         let result = groups::create(&conn, new)?;
         Ok(result)
@@ -48,14 +105,31 @@ fn add_group(
     .map(|res| HttpResponse::Created().json(res))
 }
 
+/// Updates a group, managers may only change its description.
 fn update_group(
+    a: Authentication,
     db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
     group_id: web::Path<Uuid>,
     update: web::Json<UpdateGroup>,
 ) -> impl Future<Item = HttpResponse, Error = Error> {
     let update = update.into_inner();
     web::block(move || -> Result<_> {
         let conn = db.conn()?;
+        let description_only = match groups::find_by_id(&conn, &group_id)? {
+            Some(group) => {
+                update.display_name == group.display_name
+                    && update
+                        .require_two_factor
+                        .unwrap_or(group.require_two_factor)
+                        == group.require_two_factor
+            }
+            None => false,
+        };
+        let input = with_access(&Authorizer::new(&conn, &a), input, &group_id)?
+            .resource("description_only", description_only.to_string());
+        policy.check(&input)?;
         let result = groups::update(&conn, &group_id, update)?;
         Ok(result)
     })
@@ -64,18 +138,360 @@ fn update_group(
 }
 
 fn del_group(
+    a: Authentication,
     db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
     group_id: web::Path<Uuid>,
 ) -> impl Future<Item = HttpResponse, Error = Error> {
     web::block(move || -> Result<_> {
         db.transaction(|conn| {
-            groups::del_members_by_member_id(conn, &group_id)?;
-            groups::del_members_by_group_id(conn, &group_id)?;
-            groups::del_by_id(conn, &group_id)?;
+            let authorizer = Authorizer::new(conn, &a);
+            policy.check(&with_access(&authorizer, input, &group_id)?)?;
+            match groups::del_cascade(conn, &group_id)? {
+                0 => Err(ErrorKind::NotFound)?,
+                _ => Ok(()),
+            }
+        })
+    })
+    .from_err()
+    .map(|_| HttpResponse::NoContent().finish())
+}
 
-            Ok(())
+fn get_members(
+    a: Authentication,
+    db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    group_id: web::Path<Uuid>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        let conn = db.conn()?;
+        let authorizer = Authorizer::new(&conn, &a);
+        policy.check(&with_access(&authorizer, input, &group_id)?)?;
+        check_group_exists(&conn, &group_id)?;
+        let result = groups::find_members(&conn, &group_id)?;
+        Ok(result)
+    })
+    .from_err()
+    .map(|res| HttpResponse::Ok().json(res))
+}
+
+fn add_member(
+    a: Authentication,
+    db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    group_id: web::Path<Uuid>,
+    new: web::Json<NewGroupMembership>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let new = new.into_inner();
+    web::block(move || -> Result<_> {
+        db.transaction(|conn| {
+            let may_grant = Authorizer::new(conn, &a).may_grant(&group_id)?;
+            policy
+                .check(&input.resource("may_grant", may_grant.to_string()))?;
+            check_group_exists(conn, &group_id)?;
+            check_member_exists(conn, &new)?;
+            let result = groups::add_member(
+                conn,
+                &group_id,
+                &new.member_id,
+                new.member_type,
+            )?;
+            Ok(result)
         })
     })
     .from_err()
+    .map(|res| HttpResponse::Created().json(res))
+}
+
+fn del_member(
+    a: Authentication,
+    db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    path: web::Path<(Uuid, Uuid)>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        let (group_id, member_id) = path.into_inner();
+        let conn = db.conn()?;
+        let authorizer = Authorizer::new(&conn, &a);
+        policy.check(&with_access(&authorizer, input, &group_id)?)?;
+        match groups::del_member(&conn, &group_id, &member_id)? {
+            0 => Err(ErrorKind::NotFound)?,
+            _ => Ok(()),
+        }
+    })
+    .from_err()
     .map(|_| HttpResponse::NoContent().finish())
 }
+
+fn get_managers(
+    a: Authentication,
+    db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    group_id: web::Path<Uuid>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        let conn = db.conn()?;
+        let authorizer = Authorizer::new(&conn, &a);
+        policy.check(&with_access(&authorizer, input, &group_id)?)?;
+        check_group_exists(&conn, &group_id)?;
+        groups::find_managers(&conn, &group_id)
+    })
+    .from_err()
+    .map(|res| HttpResponse::Ok().json(res))
+}
+
+/// Appoints a manager, managers cannot appoint others.
+fn add_manager(
+    a: Authentication,
+    db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    group_id: web::Path<Uuid>,
+    new: web::Json<NewGroupManager>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let new = new.into_inner();
+    web::block(move || -> Result<_> {
+        db.transaction(|conn| {
+            let authorizer = Authorizer::new(conn, &a);
+            policy.check(&with_access(&authorizer, input, &group_id)?)?;
+            check_group_exists(conn, &group_id)?;
+            if users::find_by_id(conn, &new.user_id)?.is_none() {
+                Err(Error::validation(
+                    "user_id",
+                    format!("User {} does not exist", new.user_id),
+                ))?;
+            }
+            groups::add_manager(conn, &group_id, &new.user_id)
+        })
+    })
+    .from_err()
+    .map(|res| HttpResponse::Created().json(res))
+}
+
+fn del_manager(
+    a: Authentication,
+    db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    path: web::Path<(Uuid, Uuid)>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        let (group_id, user_id) = path.into_inner();
+        let conn = db.conn()?;
+        let authorizer = Authorizer::new(&conn, &a);
+        policy.check(&with_access(&authorizer, input, &group_id)?)?;
+        match groups::del_manager(&conn, &group_id, &user_id)? {
+            0 => Err(ErrorKind::NotFound)?,
+            _ => Ok(()),
+        }
+    })
+    .from_err()
+    .map(|_| HttpResponse::NoContent().finish())
+}
+
+fn get_permissions(
+    a: Authentication,
+    db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    group_id: web::Path<Uuid>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        let conn = db.conn()?;
+        let authorizer = Authorizer::new(&conn, &a);
+        policy.check(&with_access(&authorizer, input, &group_id)?)?;
+        check_group_exists(&conn, &group_id)?;
+        permissions::find_by_group_id(&conn, &group_id)
+    })
+    .from_err()
+    .map(|res| HttpResponse::Ok().json(res))
+}
+
+/// Grants a permission to the group, callers can only pass on permissions
+/// they hold themselves.
+fn grant_permission(
+    db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    group_id: web::Path<Uuid>,
+    new: web::Json<NewGroupPermission>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let new = new.into_inner();
+    web::block(move || -> Result<_> {
+        db.transaction(|conn| {
+            check_group_exists(conn, &group_id)?;
+            let permission =
+                match permissions::find_by_id(conn, &new.permission_id)? {
+                    Some(permission) => permission,
+                    None => Err(Error::validation(
+                        "permission_id",
+                        format!(
+                            "Permission {} does not exist",
+                            new.permission_id
+                        ),
+                    ))?,
+                };
+            policy.check(
+                &input.resource("permission", permission.name.as_str()),
+            )?;
+            permissions::grant(conn, &group_id, &permission.id)?;
+            Ok(permission)
+        })
+    })
+    .from_err()
+    .map(|res| HttpResponse::Created().json(res))
+}
+
+fn revoke_permission(
+    db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    path: web::Path<(Uuid, Uuid)>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        let (group_id, permission_id) = path.into_inner();
+        db.transaction(|conn| {
+            let permission =
+                match permissions::find_by_id(conn, &permission_id)? {
+                    Some(permission) => permission,
+                    None => Err(ErrorKind::NotFound)?,
+                };
+            policy.check(
+                &input.resource("permission", permission.name.as_str()),
+            )?;
+
+            match permissions::revoke(conn, &group_id, &permission.id)? {
+                0 => Err(ErrorKind::NotFound)?,
+                _ => Ok(()),
+            }
+        })
+    })
+    .from_err()
+    .map(|_| HttpResponse::NoContent().finish())
+}
+
+/// Adds whether the caller may read, change and delete the group, as
+/// `resource.allows_<action>`, and whether it manages the group, as
+/// `resource.manager`.
+fn with_access(
+    authorizer: &Authorizer,
+    input: policy::Input,
+    group_id: &Uuid,
+) -> Result<policy::Input> {
+    let mut input = input;
+    for action in &["get", "put", "del"] {
+        let allowed = authorizer.is_allowed(GROUPS, group_id, action)?;
+        input =
+            input.resource(format!("allows_{}", action), allowed.to_string());
+    }
+    let manager = authorizer.manages(group_id)?;
+    Ok(input.resource("manager", manager.to_string()))
+}
+
+fn check_group_exists(conn: &Conn, group_id: &Uuid) -> Result<()> {
+    match groups::find_by_id(conn, group_id)? {
+        Some(_) => Ok(()),
+        None => Err(ErrorKind::NotFound)?,
+    }
+}
+
+fn check_member_exists(conn: &Conn, new: &NewGroupMembership) -> Result<()> {
+    let exists = match new.member_type {
+        GroupMembershipType::User => {
+            users::find_by_id(conn, &new.member_id)?.is_some()
+        }
+        GroupMembershipType::Group => {
+            groups::find_by_id(conn, &new.member_id)?.is_some()
+        }
+    };
+
+    if exists {
+        Ok(())
+    } else {
+        Err(Error::validation(
+            "member_id",
+            format!("{:?} {} does not exist", new.member_type, new.member_id),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{self, TestRequest};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn test_members() {
+        let db = database();
+        let (group, user) = {
+            let conn = db.conn().unwrap();
+            (
+                groups::get_or_create(&conn, "members_team").unwrap(),
+                users::create_or_update(&conn, "bob", "Bob", "password")
+                    .unwrap(),
+            )
+        };
+        let mut app = app(&db);
+        let admin = login(
+            &mut app,
+            &Authentication::new(
+                "admin",
+                vec!["groups.get".to_string(), "groups.put".to_string()],
+            ),
+        );
+        let members = format!("/api/groups/{}/members", group.id);
+        let add = |member_id: Uuid| {
+            TestRequest::post()
+                .uri(&members)
+                .cookie(admin.clone())
+                .set_json(
+                    &json!({"member_id": member_id, "member_type": "user"}),
+                )
+                .to_request()
+        };
+
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::get().uri(&members).to_request(),
+        );
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+
+        let resp = test::call_service(&mut app, add(Uuid::new_v4()));
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let resp = test::call_service(&mut app, add(user.id));
+        assert_eq!(StatusCode::CREATED, resp.status());
+
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::get()
+                .uri(&members)
+                .cookie(admin.clone())
+                .to_request(),
+        );
+        assert_eq!(StatusCode::OK, resp.status());
+        let body: Value =
+            serde_json::from_slice(&test::read_body(resp)).unwrap();
+        assert_eq!(json!(user.id), body[0]["member_id"]);
+
+        let member = format!("{}/{}", members, user.id);
+        for status in &[StatusCode::NO_CONTENT, StatusCode::NOT_FOUND] {
+            let resp = test::call_service(
+                &mut app,
+                TestRequest::delete()
+                    .uri(&member)
+                    .cookie(admin.clone())
+                    .to_request(),
+            );
+            assert_eq!(*status, resp.status());
+        }
+    }
+}
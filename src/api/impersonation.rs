@@ -0,0 +1,194 @@
+use actix_web::{web, HttpResponse, Scope};
+use futures::Future;
+use uuid::Uuid;
+
+use crate::auth::{impersonation, Authentication, AuthenticationManager};
+use crate::db::{users, Database};
+use crate::error::{Error, Result};
+
+#[derive(Debug, Deserialize)]
+struct Impersonate {
+    user_id: Uuid,
+}
+
+pub fn service(path: &str) -> Scope {
+    web::scope(path).service(
+        web::resource("")
+            .route(web::post().to_async(start))
+            .route(web::delete().to_async(stop)),
+    )
+}
+
+/// Swaps the session for one of the user, until stopped.
+fn start(
+    a: Authentication,
+    data: web::Json<Impersonate>,
+    db: web::Data<Database>,
+    am: AuthenticationManager,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        let conn = db.conn()?;
+        let user = match users::find_by_id(&conn, &data.user_id)? {
+            Some(user) => user,
+            None => Err(Error::validation(
+                "user_id",
+                format!("User {} does not exist", data.user_id),
+            ))?,
+        };
+        impersonation::start(&conn, &a, &user)
+    })
+    .from_err()
+    .map(move |a| {
+        am.remember(a);
+        HttpResponse::NoContent().finish()
+    })
+}
+
+fn stop(
+    a: Authentication,
+    db: web::Data<Database>,
+    am: AuthenticationManager,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        let conn = db.conn()?;
+        impersonation::stop(&conn, &a)
+    })
+    .from_err()
+    .map(move |a| {
+        am.remember(a);
+        HttpResponse::NoContent().finish()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{self, TestRequest};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::auth::middleware::IMPERSONATED_BY;
+    use crate::db::groups::{self, GroupMembershipType};
+    use crate::db::permissions;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn test_impersonation() {
+        let db = database();
+        let (admin, user) = {
+            let conn = db.conn().unwrap();
+            let admin =
+                users::create_or_update(&conn, "admin", "Admin", "password")
+                    .unwrap();
+            let user = users::create_or_update(&conn, "bob", "Bob", "password")
+                .unwrap();
+            let group = groups::get_or_create(&conn, "imp_api_staff").unwrap();
+            let permission =
+                permissions::get_or_create(&conn, "imp_api.items.get").unwrap();
+            permissions::grant(&conn, &group.id, &permission.id).unwrap();
+            groups::add_member(
+                &conn,
+                &group.id,
+                &user.id,
+                GroupMembershipType::User,
+            )
+            .unwrap();
+            (admin, user)
+        };
+        let identity = admin.id.simple().to_string();
+        let as_admin = |authorities: &[&str]| {
+            Authentication::new(
+                identity.clone(),
+                authorities
+                    .iter()
+                    .map(|a| a.to_string())
+                    .collect::<Vec<_>>(),
+            )
+        };
+        let start = |cookie| {
+            TestRequest::post()
+                .uri("/api/auth/impersonation")
+                .cookie(cookie)
+                .set_json(&json!({ "user_id": user.id }))
+                .to_request()
+        };
+        let stop = |cookie| {
+            TestRequest::delete()
+                .uri("/api/auth/impersonation")
+                .cookie(cookie)
+                .to_request()
+        };
+        let mut app = app(&db);
+
+        let cookie = login(&mut app, &as_admin(&[]));
+        let resp = test::call_service(&mut app, start(cookie));
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+        let cookie = login(&mut app, &as_admin(&["users.impersonate"]));
+        let resp = test::call_service(&mut app, start(cookie));
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+
+        let cookie =
+            login(&mut app, &as_admin(&["users.impersonate", "imp_api.*"]));
+        let resp = test::call_service(&mut app, start(cookie));
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+        assert_eq!(
+            Some(identity.as_str()),
+            resp.headers()
+                .get(IMPERSONATED_BY)
+                .map(|v| v.to_str().unwrap())
+        );
+        let impersonated = auth_cookie(&resp).unwrap();
+
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::put()
+                .uri("/api/auth/password")
+                .cookie(impersonated.clone())
+                .set_json(&json!({
+                    "old_password": "password",
+                    "new_password": "correct horse battery staple",
+                }))
+                .to_request(),
+        );
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+
+        let resp = test::call_service(&mut app, stop(impersonated));
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+        assert!(resp.headers().get(IMPERSONATED_BY).is_none());
+        let stopped = auth_cookie(&resp).unwrap();
+        let resp = test::call_service(&mut app, stop(stopped));
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::delete()
+                .uri("/api/auth/impersonation")
+                .to_request(),
+        );
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+
+        // Starting, the password change and stopping were recorded.
+        let auditor = login(&mut app, &as_admin(&["audit.get"]));
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::get()
+                .uri("/api/audit-entries")
+                .cookie(auditor)
+                .to_request(),
+        );
+        assert_eq!(StatusCode::OK, resp.status());
+        let body: Value =
+            serde_json::from_slice(&test::read_body(resp)).unwrap();
+        assert_eq!(json!(3), body["total"]);
+        let statuses = body["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["status"].as_i64().unwrap())
+            .collect::<Vec<_>>();
+        assert!(statuses.contains(&403));
+        for entry in body["items"].as_array().unwrap() {
+            assert_eq!(json!(identity), entry["actor"]);
+            assert_eq!(json!(user.id.simple().to_string()), entry["identity"]);
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use actix_web::{web, HttpResponse, Scope};
+use futures::Future;
+use uuid::Uuid;
+
+use crate::db::{lockouts, Database};
+use crate::error::{Error, ErrorKind, Result};
+
+pub fn service(path: &str) -> Scope {
+    web::scope(path)
+        .service(web::resource("").route(web::get().to_async(get_lockouts)))
+        .service(
+            web::resource("/{lockout_id}")
+                .route(web::delete().to_async(del_lockout)),
+        )
+}
+
+fn get_lockouts(
+    db: web::Data<Database>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        let conn = db.conn()?;
+        lockouts::find_all(&conn)
+    })
+    .from_err()
+    .map(|res| HttpResponse::Ok().json(res))
+}
+
+fn del_lockout(
+    db: web::Data<Database>,
+    lockout_id: web::Path<Uuid>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        let conn = db.conn()?;
+        match lockouts::del_by_id(&conn, &lockout_id)? {
+            0 => Err(ErrorKind::NotFound)?,
+            _ => Ok(()),
+        }
+    })
+    .from_err()
+    .map(|_| HttpResponse::NoContent().finish())
+}
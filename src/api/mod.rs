@@ -1,14 +1,103 @@
+mod acl;
+mod audit;
 mod auth;
 mod groups;
+mod impersonation;
+mod lockouts;
+mod oidc_login;
+mod page;
+mod permissions;
+mod two_factor;
 mod users;
 
 use actix_files::Files;
-use actix_web::{web, Scope};
+use actix_web::dev::HttpServiceFactory;
+use actix_web::http::Method;
+use actix_web::web;
 
-pub fn service(path: &str) -> Scope {
+use crate::auth::{middleware::AuthorizationService, AccessRules};
+use crate::error::{Error, ErrorKind};
+
+pub fn service(path: &str) -> impl HttpServiceFactory {
     web::scope(path)
+        .data(web::JsonConfig::default().error_handler(|err, _| {
+            Error::from(ErrorKind::BadRequest)
+                .with_detail(err.to_string())
+                .into()
+        }))
+        .wrap(AuthorizationService::new(access_rules(path)))
+        .service(acl::service("/acl"))
+        .service(audit::service("/audit-entries"))
         .service(auth::service("/auth"))
         .service(groups::service("/groups"))
+        .service(lockouts::service("/lockouts"))
+        .service(permissions::service("/permissions"))
         .service(users::service("/users"))
         .service(Files::new("/images", "./images"))
 }
+
+fn access_rules(path: &str) -> AccessRules {
+    AccessRules::new(path)
+        .authenticated(Method::GET, "/auth")
+        .permit_all(Method::POST, "/auth")
+        .permit_all(Method::DELETE, "/auth")
+        .permit_all(Method::POST, "/auth/token")
+        .permit_all(Method::POST, "/auth/two-factor")
+        .authenticated(Method::DELETE, "/auth/two-factor")
+        .permit_all(Method::GET, "/auth/oidc")
+        .permit_all(Method::POST, "/auth/oidc")
+        .permit_all(Method::POST, "/auth/two-factor/enrollment")
+        .permit_all(Method::PUT, "/auth/two-factor/enrollment")
+        .authenticated(Method::PUT, "/auth/password")
+        .permit_all(Method::POST, "/auth/password-reset")
+        .permit_all(Method::PUT, "/auth/password-reset")
+        .permit_all(Method::PUT, "/auth/invitation")
+        .authenticated(Method::GET, "/auth/sessions")
+        .authenticated(Method::DELETE, "/auth/sessions")
+        .authenticated(Method::DELETE, "/auth/sessions/{session_id}")
+        .has_authority(Method::POST, "/auth/impersonation", "users.impersonate")
+        .authenticated(Method::DELETE, "/auth/impersonation")
+        // ACL, group and user routes are checked by the policy.
+        .authenticated(Method::GET, "/acl/{resource_type}/{resource_id}")
+        .authenticated(Method::POST, "/acl/{resource_type}/{resource_id}")
+        .authenticated(
+            Method::DELETE,
+            "/acl/{resource_type}/{resource_id}/{entry_id}",
+        )
+        .authenticated(Method::GET, "/groups")
+        .authenticated(Method::POST, "/groups")
+        .authenticated(Method::PUT, "/groups/{group_id}")
+        .authenticated(Method::DELETE, "/groups/{group_id}")
+        .authenticated(Method::GET, "/groups/{group_id}/members")
+        .authenticated(Method::POST, "/groups/{group_id}/members")
+        .authenticated(Method::DELETE, "/groups/{group_id}/members/{member_id}")
+        .authenticated(Method::GET, "/groups/{group_id}/managers")
+        .authenticated(Method::POST, "/groups/{group_id}/managers")
+        .authenticated(Method::DELETE, "/groups/{group_id}/managers/{user_id}")
+        .authenticated(Method::GET, "/groups/{group_id}/permissions")
+        .authenticated(Method::POST, "/groups/{group_id}/permissions")
+        .authenticated(
+            Method::DELETE,
+            "/groups/{group_id}/permissions/{permission_id}",
+        )
+        .has_authority(Method::GET, "/audit-entries", "audit.get")
+        .has_authority(Method::GET, "/lockouts", "lockouts.get")
+        .has_authority(Method::DELETE, "/lockouts/{lockout_id}", "lockouts.del")
+        .has_authority(Method::GET, "/permissions", "permissions.get")
+        // Creating and deleting permissions is checked by the policy.
+        .authenticated(Method::POST, "/permissions")
+        .authenticated(Method::DELETE, "/permissions/{permission_id}")
+        .authenticated(Method::GET, "/users")
+        .authenticated(Method::POST, "/users")
+        .authenticated(Method::POST, "/users/invitations")
+        .authenticated(Method::GET, "/users/{user_id}")
+        .authenticated(Method::PATCH, "/users/{user_id}")
+        .authenticated(Method::DELETE, "/users/{user_id}")
+        .authenticated(Method::GET, "/users/{user_id}/sessions")
+        .authenticated(Method::DELETE, "/users/{user_id}/sessions")
+        .authenticated(Method::DELETE, "/users/{user_id}/two-factor")
+        .authenticated(Method::GET, "/users/{user_id}/api-keys")
+        .authenticated(Method::POST, "/users/{user_id}/api-keys")
+        .authenticated(Method::DELETE, "/users/{user_id}/api-keys/{api_key_id}")
+        .permit_all(Method::GET, "/images/{tail:.*}")
+}
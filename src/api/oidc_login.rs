@@ -0,0 +1,179 @@
+//! Login through an external OpenID Connect provider, configured as
+//! described in `oidc::relying_party`.
+//!
+//! The frontend sends the user agent to the `redirect_to` of `GET`, and
+//! posts the `code` and `state` the provider hands back to its redirect uri.
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Scope};
+use futures::future::{self, Either};
+use futures::Future;
+use uuid::Uuid;
+
+use super::auth::{respond, start_login};
+use crate::auth::policy::{self, Policy};
+use crate::auth::{AuthenticationManager, PasswordPolicy, TokenSigner};
+use crate::db::Database;
+use crate::error::{Error, ErrorKind, Result};
+use crate::oidc::RelyingParty;
+
+/// Cookie tying a login to the user agent that started it.
+const BINDING_COOKIE: &str = "hamster-oidc-login";
+
+#[derive(Debug, Serialize)]
+struct Redirect {
+    redirect_to: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Callback {
+    code: String,
+    state: String,
+}
+
+pub fn service(path: &str) -> Scope {
+    web::scope(path).service(
+        web::resource("")
+            .route(web::get().to_async(start))
+            .route(web::post().to_async(complete)),
+    )
+}
+
+fn relying_party(rp: &Option<RelyingParty>) -> Result<RelyingParty> {
+    match rp {
+        Some(rp) => Ok(rp.clone()),
+        None => Err(Error::from(ErrorKind::NotFound)
+            .with_detail("no identity provider is configured")),
+    }
+}
+
+fn binding_cookie(path: &str, value: String, max_age: i64) -> Cookie<'static> {
+    Cookie::build(BINDING_COOKIE, value)
+        .path(path.to_string())
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .max_age(max_age)
+        .finish()
+}
+
+fn start(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    rp: web::Data<Option<RelyingParty>>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let rp = match relying_party(&rp) {
+        Ok(rp) => rp,
+        Err(e) => return Either::A(future::err(e)),
+    };
+    let path = req.path().to_string();
+
+    Either::B(
+        rp.metadata()
+            .and_then(move |metadata| {
+                web::block(move || {
+                    let conn = db.conn()?;
+                    rp.start(&conn, &metadata)
+                })
+                .from_err()
+            })
+            .map(move |login| {
+                HttpResponse::Ok()
+                    .cookie(binding_cookie(&path, login.binding, 10 * 60))
+                    .json(Redirect {
+                        redirect_to: login.redirect_to,
+                    })
+            }),
+    )
+}
+
+/// Completes the login, or links the external identity to the logged in
+/// user. Impersonators can do neither.
+fn complete(
+    req: HttpRequest,
+    callback: web::Json<Callback>,
+    db: web::Data<Database>,
+    rp: web::Data<Option<RelyingParty>>,
+    password_policy: web::Data<PasswordPolicy>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    signer: web::Data<TokenSigner>,
+    am: AuthenticationManager,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    if let Err(e) = policy.check(&input) {
+        return Either::A(future::err(e));
+    }
+    let rp = match relying_party(&rp) {
+        Ok(rp) => rp,
+        Err(e) => return Either::A(future::err(e)),
+    };
+    let callback = callback.into_inner();
+    let path = req.path().to_string();
+    let binding = req.cookie(BINDING_COOKIE).map(|c| c.value().to_string());
+    let current = am
+        .authentication()
+        .and_then(|a| Uuid::parse_str(a.identity()).ok());
+    let (code, state) = (callback.code, callback.state);
+
+    Either::B(
+        web::block(move || -> Result<_> {
+            let conn = db.conn()?;
+            let binding = binding.as_ref().map(String::as_str);
+            let login = rp.take_login(&conn, &state, binding)?;
+            Ok((db, rp, login))
+        })
+        .from_err()
+        .and_then(move |(db, rp, login)| {
+            rp.exchange(&code, &login)
+                .map(move |claims| (db, rp, claims))
+        })
+        .and_then(move |(db, rp, claims)| {
+            web::block(move || {
+                db.transaction(|conn| {
+                    let user = rp.sign_in(
+                        conn,
+                        &password_policy,
+                        &claims,
+                        current.as_ref(),
+                    )?;
+                    start_login(conn, &signer, &user)
+                })
+            })
+            .from_err()
+        })
+        .map(move |login| {
+            let mut res = respond(am, login);
+            let _ = res.add_cookie(&binding_cookie(&path, String::new(), 0));
+            res
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{self, TestRequest};
+    use serde_json::json;
+
+    use crate::auth::Authentication;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn test_complete_while_impersonating() {
+        let db = database();
+        let mut app = app(&db);
+        let bob = Authentication::new("bob", Vec::new());
+        let mut complete = |a: &Authentication| {
+            let cookie = login(&mut app, a);
+            let req = TestRequest::post()
+                .uri("/api/auth/oidc")
+                .cookie(cookie)
+                .set_json(&json!({ "code": "code", "state": "state" }))
+                .to_request();
+            test::call_service(&mut app, req).status()
+        };
+
+        // Gets as far as the missing identity provider.
+        assert_eq!(StatusCode::NOT_FOUND, complete(&bob));
+        let impersonated = bob.with_impersonator(Some("admin".to_string()));
+        assert_eq!(StatusCode::FORBIDDEN, complete(&impersonated));
+    }
+}
@@ -0,0 +1,221 @@
+use actix_web::dev::Payload;
+use actix_web::http::header::LINK;
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse};
+use serde::Serialize;
+
+use crate::db::page::{
+    Direction, Filter, FilterOp, Page, PageRequest, Sort, MAX_PER_PAGE,
+};
+use crate::error::{Error, ErrorKind, Result};
+
+/// Query parameters of a list endpoint.
+///
+/// `page` and `per_page` select the page, `sort` is a comma separated list of
+/// fields where a leading `-` sorts descending, every other parameter is a
+/// filter, `field=value` for equality and `field~=value` for substring match.
+pub struct PageQuery {
+    path: String,
+    params: Vec<(String, String)>,
+    request: PageRequest,
+}
+
+impl PageQuery {
+    pub fn request(&self) -> &PageRequest {
+        &self.request
+    }
+
+    /// Renders the page as a json envelope with rfc 5988 `Link` headers to
+    /// the first, previous, next and last pages.
+    pub fn respond<T: Serialize>(&self, page: Page<T>) -> HttpResponse {
+        let last_page = page.last_page();
+        let mut links = vec![self.link(1, "first")];
+        if page.page > 1 {
+            links.push(self.link((page.page - 1).min(last_page), "prev"));
+        }
+        if page.page < last_page {
+            links.push(self.link(page.page + 1, "next"));
+        }
+        links.push(self.link(last_page, "last"));
+
+        HttpResponse::Ok().header(LINK, links.join(", ")).json(page)
+    }
+
+    fn link(&self, page: i64, rel: &str) -> String {
+        let mut params = self
+            .params
+            .iter()
+            .filter(|(k, _)| k != "page")
+            .cloned()
+            .collect::<Vec<(String, String)>>();
+        params.push(("page".to_string(), page.to_string()));
+
+        let query = serde_urlencoded::to_string(&params).unwrap_or_default();
+        format!("<{}?{}>; rel=\"{}\"", self.path, query, rel)
+    }
+
+    fn parse(params: &[(String, String)]) -> Result<PageRequest> {
+        let mut request = PageRequest::default();
+
+        for (key, value) in params {
+            match key.as_str() {
+                "page" => {
+                    request.page = parse_positive("page", value)?;
+                }
+                "per_page" => {
+                    request.per_page =
+                        parse_positive("per_page", value)?.min(MAX_PER_PAGE);
+                }
+                "sort" => {
+                    request.sort = value
+                        .split(',')
+                        .filter(|s| !s.is_empty())
+                        .map(parse_sort)
+                        .collect();
+                }
+                _ if key.ends_with('~') => request.filters.push(Filter {
+                    field: key.trim_end_matches('~').to_string(),
+                    op: FilterOp::Contains,
+                    value: value.clone(),
+                }),
+                _ => request.filters.push(Filter {
+                    field: key.clone(),
+                    op: FilterOp::Eq,
+                    value: value.clone(),
+                }),
+            }
+        }
+
+        // The offset of the page has to fit the database.
+        if (request.page - 1).checked_mul(request.per_page).is_none() {
+            return Err(Error::validation("page", "is too large"));
+        }
+
+        Ok(request)
+    }
+}
+
+impl FromRequest for PageQuery {
+    type Config = ();
+    type Error = Error;
+    type Future = Result<PageQuery, Error>;
+
+    #[inline]
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let params =
+            web::Query::<Vec<(String, String)>>::from_request(req, payload)
+                .map_err(|e| {
+                    Error::from(ErrorKind::BadRequest)
+                        .with_detail(e.to_string())
+                })?
+                .into_inner();
+        let request = PageQuery::parse(&params)?;
+
+        Ok(PageQuery {
+            path: req.path().to_string(),
+            params,
+            request,
+        })
+    }
+}
+
+fn parse_positive(field: &str, value: &str) -> Result<i64> {
+    match value.parse::<i64>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(Error::validation(field, "must be a positive integer")),
+    }
+}
+
+fn parse_sort(value: &str) -> Sort {
+    if value.starts_with('-') {
+        Sort {
+            field: value[1..].to_string(),
+            direction: Direction::Desc,
+        }
+    } else {
+        Sort {
+            field: value.to_string(),
+            direction: Direction::Asc,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(query: &str) -> Vec<(String, String)> {
+        serde_urlencoded::from_str(query).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let request = PageQuery::parse(&params(
+            "page=2&per_page=500&sort=-username,nickname\
+             &username~=bo&nickname=Bob",
+        ))
+        .unwrap();
+
+        assert_eq!(2, request.page);
+        assert_eq!(MAX_PER_PAGE, request.per_page);
+        assert_eq!(
+            vec![
+                Sort {
+                    field: "username".to_string(),
+                    direction: Direction::Desc,
+                },
+                Sort {
+                    field: "nickname".to_string(),
+                    direction: Direction::Asc,
+                },
+            ],
+            request.sort
+        );
+        assert_eq!(
+            vec![
+                Filter {
+                    field: "username".to_string(),
+                    op: FilterOp::Contains,
+                    value: "bo".to_string(),
+                },
+                Filter {
+                    field: "nickname".to_string(),
+                    op: FilterOp::Eq,
+                    value: "Bob".to_string(),
+                },
+            ],
+            request.filters
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_page() {
+        let err = PageQuery::parse(&params("page=0")).err().unwrap();
+        assert_eq!(ErrorKind::Validation, err.kind());
+
+        let page = format!("page={}&per_page=100", i64::MAX / 50);
+        let err = PageQuery::parse(&params(&page)).err().unwrap();
+        assert_eq!(ErrorKind::Validation, err.kind());
+        let page = format!("page={}&per_page=1", i64::MAX);
+        assert!(PageQuery::parse(&params(&page)).is_ok());
+    }
+
+    #[test]
+    fn test_respond() {
+        let params = params("per_page=2&username~=b");
+        let query = PageQuery {
+            path: "/api/users".to_string(),
+            request: PageQuery::parse(&params).unwrap(),
+            params,
+        };
+        let page = Page::new(vec![1, 2], 5, query.request());
+
+        let resp = query.respond(page);
+
+        assert_eq!(
+            "</api/users?per_page=2&username%7E=b&page=1>; rel=\"first\", \
+             </api/users?per_page=2&username%7E=b&page=2>; rel=\"next\", \
+             </api/users?per_page=2&username%7E=b&page=3>; rel=\"last\"",
+            resp.headers().get(LINK).unwrap()
+        );
+    }
+}
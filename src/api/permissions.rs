@@ -0,0 +1,117 @@
+use actix_web::{web, HttpResponse, Scope};
+use futures::Future;
+use uuid::Uuid;
+
+use crate::auth::authorization;
+use crate::auth::policy::{self, Policy};
+use crate::db::{
+    permissions::{self, NewPermission},
+    Database,
+};
+use crate::error::{Error, ErrorKind, Result};
+
+pub fn service(path: &str) -> Scope {
+    web::scope(path)
+        .service(
+            web::resource("")
+                .route(web::get().to_async(get_permissions))
+                .route(web::post().to_async(add_permission)),
+        )
+        .service(
+            web::resource("/{permission_id}")
+                .route(web::delete().to_async(del_permission)),
+        )
+}
+
+fn get_permissions(
+    db: web::Data<Database>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        let conn = db.conn()?;
+        permissions::find_all(&conn)
+    })
+    .from_err()
+    .map(|res| HttpResponse::Ok().json(res))
+}
+
+/// Creates a permission, callers can only define permissions they hold.
+fn add_permission(
+    db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    new: web::Json<NewPermission>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let new = new.into_inner();
+    web::block(move || -> Result<_> {
+        authorization::validate_permission_name(&new.name)?;
+        policy.check(&input.resource("name", new.name.as_str()))?;
+        let conn = db.conn()?;
+        permissions::create(&conn, new)
+    })
+    .from_err()
+    .map(|res| HttpResponse::Created().json(res))
+}
+
+fn del_permission(
+    db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    permission_id: web::Path<Uuid>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        db.transaction(|conn| {
+            let permission =
+                match permissions::find_by_id(conn, &permission_id)? {
+                    Some(permission) => permission,
+                    None => Err(ErrorKind::NotFound)?,
+                };
+            policy.check(&input.resource("name", permission.name.as_str()))?;
+
+            permissions::del_by_id(conn, &permission.id)?;
+            Ok(())
+        })
+    })
+    .from_err()
+    .map(|_| HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{self, TestRequest};
+    use serde_json::json;
+
+    use crate::auth::Authentication;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn test_add_permission() {
+        let db = database();
+        let mut app = app(&db);
+        let mut add = |authorities: Option<&[&str]>, name: &str| {
+            let mut req = TestRequest::post()
+                .uri("/api/permissions")
+                .set_json(&json!({ "name": name }));
+            if let Some(authorities) = authorities {
+                let a = Authentication::new(
+                    "bob",
+                    authorities
+                        .iter()
+                        .map(|a| a.to_string())
+                        .collect::<Vec<_>>(),
+                );
+                req = req.cookie(login(&mut app, &a));
+            }
+            test::call_service(&mut app, req.to_request()).status()
+        };
+
+        let held = &["permissions.post", "inventory.*"][..];
+        assert_eq!(StatusCode::UNAUTHORIZED, add(None, "inventory.get"));
+        assert_eq!(
+            StatusCode::FORBIDDEN,
+            add(Some(&["inventory.*"]), "inventory.get")
+        );
+        assert_eq!(StatusCode::FORBIDDEN, add(Some(held), "users.get"));
+        assert_eq!(StatusCode::CREATED, add(Some(held), "inventory.get"));
+    }
+}
@@ -0,0 +1,251 @@
+use actix_web::{web, HttpRequest, HttpResponse, Scope};
+use futures::Future;
+use uuid::Uuid;
+
+use super::auth::client_ip;
+use crate::auth::policy::{self, Policy};
+use crate::auth::two_factor;
+use crate::auth::{
+    membership, Authentication, AuthenticationManager, LockoutPolicy,
+    TokenSigner,
+};
+use crate::db::{
+    two_factor as db_two_factor,
+    users::{self, User},
+    Conn, Database,
+};
+use crate::error::{Error, ErrorKind, Result, ResultExt};
+
+/// Second step of a login.
+#[derive(Debug, Deserialize)]
+struct CodeData {
+    challenge: String,
+    code: String,
+}
+
+/// Enrollment of the current user, or of the user of a login challenge.
+#[derive(Debug, Default, Deserialize)]
+struct EnrollmentData {
+    #[serde(default)]
+    challenge: Option<String>,
+    #[serde(default)]
+    code: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DisableData {
+    code: String,
+}
+
+pub fn service(path: &str) -> Scope {
+    web::scope(path)
+        .service(
+            web::resource("")
+                .route(web::post().to_async(verify_code))
+                .route(web::delete().to_async(disable)),
+        )
+        .service(
+            web::resource("/enrollment")
+                .route(web::post().to_async(start_enrollment))
+                .route(web::put().to_async(confirm_enrollment)),
+        )
+}
+
+/// Completes a login with a TOTP or recovery code.
+fn verify_code(
+    req: HttpRequest,
+    data: web::Json<CodeData>,
+    db: web::Data<Database>,
+    lockout: web::Data<LockoutPolicy>,
+    signer: web::Data<TokenSigner>,
+    am: AuthenticationManager,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let data = data.into_inner();
+    let ip = client_ip(&req);
+
+    web::block(move || -> Result<_> {
+        let conn = db.conn()?;
+        let ip = ip.as_ref().map(String::as_str);
+        let identity = signer.verify_challenge(&data.challenge)?;
+        let user = find_user(&conn, &identity)?;
+        lockout.check(&conn, &user.username, ip)?;
+
+        if !two_factor::verify(&conn, &user.id, &data.code)? {
+            lockout.record_failure(&conn, &user.username, ip)?;
+            Err(ErrorKind::Unauthorized)?
+        }
+        lockout.record_success(&conn, &user.username)?;
+
+        membership::authenticate(&conn, &user)
+    })
+    .from_err()
+    .map(move |a| {
+        am.remember(a);
+        HttpResponse::Ok().finish()
+    })
+}
+
+/// Starts an enrollment, for the current user or with a login challenge.
+fn start_enrollment(
+    data: Option<web::Json<EnrollmentData>>,
+    db: web::Data<Database>,
+    signer: web::Data<TokenSigner>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    am: AuthenticationManager,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let data = data.map(web::Json::into_inner).unwrap_or_default();
+    let current = am.authentication();
+
+    web::block(move || -> Result<_> {
+        policy.check(&input)?;
+        let conn = db.conn()?;
+        let user = enrolling_user(&conn, &signer, current, &data)?;
+
+        two_factor::start_enrollment(&conn, &user)
+    })
+    .from_err()
+    .map(|res| HttpResponse::Created().json(res))
+}
+
+/// Confirms the enrollment with a first code and hands out the recovery
+/// codes. Confirming with a login challenge completes the login.
+fn confirm_enrollment(
+    data: web::Json<EnrollmentData>,
+    db: web::Data<Database>,
+    signer: web::Data<TokenSigner>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    am: AuthenticationManager,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let data = data.into_inner();
+    let current = am.authentication();
+
+    web::block(move || -> Result<_> {
+        policy.check(&input)?;
+        let conn = db.conn()?;
+        let user = enrolling_user(&conn, &signer, current, &data)?;
+        let code = match data.code {
+            Some(ref code) => code,
+            None => Err(ErrorKind::BadRequest)?,
+        };
+        let recovery_codes =
+            two_factor::confirm_enrollment(&conn, &user.id, code)?;
+
+        let authentication = match data.challenge {
+            Some(_) => Some(membership::authenticate(&conn, &user)?),
+            None => None,
+        };
+        Ok((recovery_codes, authentication))
+    })
+    .from_err()
+    .map(move |(recovery_codes, authentication)| {
+        if let Some(a) = authentication {
+            am.remember(a);
+        }
+        HttpResponse::Ok().json(recovery_codes)
+    })
+}
+
+/// Turns two-factor authentication off for the current user, which has to
+/// prove possession with a code first. Wrong codes count towards the login
+/// lockout.
+fn disable(
+    req: HttpRequest,
+    a: Authentication,
+    data: web::Json<DisableData>,
+    db: web::Data<Database>,
+    lockout: web::Data<LockoutPolicy>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let ip = client_ip(&req);
+
+    web::block(move || -> Result<_> {
+        policy.check(&input)?;
+        let conn = db.conn()?;
+        let ip = ip.as_ref().map(String::as_str);
+        let user = find_user(&conn, a.identity())?;
+        lockout.check(&conn, &user.username, ip)?;
+
+        if !two_factor::verify(&conn, &user.id, &data.code)? {
+            lockout.record_failure(&conn, &user.username, ip)?;
+            Err(ErrorKind::Unauthorized)?
+        }
+        lockout.record_success(&conn, &user.username)?;
+
+        db_two_factor::del_by_user_id(&conn, &user.id)
+    })
+    .from_err()
+    .map(|_| HttpResponse::NoContent().finish())
+}
+
+fn enrolling_user(
+    conn: &Conn,
+    signer: &TokenSigner,
+    current: Option<Authentication>,
+    data: &EnrollmentData,
+) -> Result<User> {
+    match (&data.challenge, current) {
+        (Some(challenge), _) => {
+            find_user(conn, &signer.verify_challenge(challenge)?)
+        }
+        (None, Some(a)) => find_user(conn, a.identity()),
+        (None, None) => Err(ErrorKind::Unauthorized)?,
+    }
+}
+
+fn find_user(conn: &Conn, identity: &str) -> Result<User> {
+    let user_id = Uuid::parse_str(identity).context(ErrorKind::Unauthorized)?;
+
+    match users::find_by_id(conn, &user_id)? {
+        Some(user) => Ok(user),
+        None => Err(ErrorKind::Unauthorized)?,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{self, TestRequest};
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn test_disable_lockout() {
+        let db = database();
+        let user = {
+            let conn = db.conn().unwrap();
+            let user = users::create_or_update(&conn, "bob", "Bob", "password")
+                .unwrap();
+            two_factor::start_enrollment(&conn, &user).unwrap();
+            user
+        };
+        let mut app = app(&db);
+        let bob = login(
+            &mut app,
+            &Authentication::new(user.id.simple().to_string(), vec![]),
+        );
+        let disable = |code: &str| {
+            TestRequest::delete()
+                .uri("/api/auth/two-factor")
+                .cookie(bob.clone())
+                .set_json(&json!({ "code": code }))
+                .to_request()
+        };
+
+        for _ in 0..5 {
+            let resp = test::call_service(&mut app, disable("000000"));
+            assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+        }
+        let resp = test::call_service(&mut app, disable("000000"));
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, resp.status());
+
+        let conn = db.conn().unwrap();
+        assert!(db_two_factor::find_by_user_id(&conn, &user.id)
+            .unwrap()
+            .is_some());
+    }
+}
@@ -1,13 +1,24 @@
-use actix_web::{web, Error, HttpResponse, Scope};
+use std::sync::Arc;
+
+use actix_web::{web, HttpResponse, Scope};
 use futures::Future;
 use uuid::Uuid;
 
+use super::page::PageQuery;
+use crate::auth::api_keys::NewApiKey;
+use crate::auth::policy::{self, Policy};
+use crate::auth::user_tokens::Invitation;
+use crate::auth::{
+    self, Authentication, PasswordPolicy, SessionManager, UserTokens,
+};
 use crate::db::{
-    groups,
-    users::{self, NewUser},
+    api_keys, two_factor,
+    users::{self, NewUser, UpdateUser},
     Database,
 };
-use crate::error::Result;
+use crate::error::{Error, ErrorKind, Result};
+use crate::mail::{self, Mailer};
+use crate::utils;
 
 pub fn service(path: &str) -> Scope {
     web::scope(path)
@@ -17,28 +28,68 @@ pub fn service(path: &str) -> Scope {
                 .route(web::post().to_async(add_user)),
         )
         .service(
-            web::resource("/{user_id}").route(web::delete().to_async(del_user)),
+            web::resource("/invitations")
+                .route(web::post().to_async(invite_user)),
+        )
+        .service(
+            web::resource("/{user_id}")
+                .route(web::get().to_async(get_user))
+                .route(web::patch().to_async(update_user))
+                .route(web::delete().to_async(del_user)),
+        )
+        .service(
+            web::resource("/{user_id}/sessions")
+                .route(web::get().to_async(get_sessions))
+                .route(web::delete().to_async(del_sessions)),
+        )
+        .service(
+            web::resource("/{user_id}/two-factor")
+                .route(web::delete().to_async(del_two_factor)),
+        )
+        .service(
+            web::resource("/{user_id}/api-keys")
+                .route(web::get().to_async(get_api_keys))
+                .route(web::post().to_async(add_api_key)),
+        )
+        .service(
+            web::resource("/{user_id}/api-keys/{api_key_id}")
+                .route(web::delete().to_async(del_api_key)),
         )
 }
 
 fn get_users(
     db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    query: PageQuery,
 ) -> impl Future<Item = HttpResponse, Error = Error> {
+    let request = query.request().clone();
     web::block(move || -> Result<_> {
+        policy.check(&input)?;
         let conn = db.conn()?;
-        let result = users::find_all(&conn)?;
+        let result = users::find_page(&conn, &request)?;
         Ok(result)
     })
     .from_err()
-    .map(|res| HttpResponse::Ok().json(res))
+    .map(move |res| query.respond(res))
 }
 
 fn add_user(
     db: web::Data<Database>,
+    password_policy: web::Data<PasswordPolicy>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
     new: web::Json<NewUser>,
 ) -> impl Future<Item = HttpResponse, Error = Error> {
-    let new = new.into_inner();
+    let mut new = new.into_inner();
     web::block(move || -> Result<_> {
+        policy.check(&input)?;
+        password_policy.validate(&new.password, &new.username)?;
+        if let Some(ref email) = new.email {
+            utils::validate_email(email)?;
+        }
+        new.password = password_policy.hash(&new.password)?;
+
         let conn = db.conn()?;
         let result = users::create(&conn, new)?;
         Ok(result)
@@ -47,18 +98,265 @@ fn add_user(
     .map(|res| HttpResponse::Created().json(res))
 }
 
-fn del_user(
+/// Creates a user and mails them a link to choose their password.
+fn invite_user(
+    db: web::Data<Database>,
+    password_policy: web::Data<PasswordPolicy>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    tokens: web::Data<UserTokens>,
+    mailer: web::Data<Arc<Mailer>>,
+    invitation: web::Json<Invitation>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let invitation = invitation.into_inner();
+    web::block(move || {
+        policy.check(&input)?;
+        db.transaction(|conn| tokens.invite(conn, &password_policy, invitation))
+    })
+    .from_err()
+    .map(move |(user, message)| {
+        // Only mailed once the user is stored. Should delivery fail, the
+        // user can still ask for a reset link to the same address.
+        mail::send_in_background(mailer.get_ref().clone(), message);
+        HttpResponse::Created().json(user)
+    })
+}
+
+fn get_user(
+    db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    user_id: web::Path<Uuid>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        policy.check(&input)?;
+        let conn = db.conn()?;
+        match users::find_by_id(&conn, &user_id)? {
+            Some(user) => Ok(user),
+            None => Err(ErrorKind::NotFound)?,
+        }
+    })
+    .from_err()
+    .map(|res| HttpResponse::Ok().json(res))
+}
+
+/// Updates a user, impersonators cannot change emails or passwords.
+fn update_user(
     db: web::Data<Database>,
+    password_policy: web::Data<PasswordPolicy>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
     user_id: web::Path<Uuid>,
+    update: web::Json<UpdateUser>,
 ) -> impl Future<Item = HttpResponse, Error = Error> {
+    let mut update = update.into_inner();
     web::block(move || -> Result<_> {
-        db.transaction(|conn| {
-            groups::del_members_by_member_id(conn, &user_id)?;
-            users::del_by_id(conn, &user_id)?;
+        let credentials = update.email.is_some() || update.password.is_some();
+        policy
+            .check(&input.resource("credentials", credentials.to_string()))?;
+        if let Some(Some(ref email)) = update.email {
+            utils::validate_email(email)?;
+        }
+
+        let conn = db.conn()?;
+        if let Some(ref password) = update.password {
+            let username = match update.username {
+                Some(ref username) => username.clone(),
+                None => match users::find_by_id(&conn, &user_id)? {
+                    Some(user) => user.username,
+                    None => Err(ErrorKind::NotFound)?,
+                },
+            };
+            password_policy.validate(password, &username)?;
+            update.password = Some(password_policy.hash(password)?);
+        }
+        let result = users::update(&conn, &user_id, update)?;
+        Ok(result)
+    })
+    .from_err()
+    .map(|res| HttpResponse::Ok().json(res))
+}
 
-            Ok(())
+fn del_user(
+    db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    user_id: web::Path<Uuid>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        policy.check(&input)?;
+        db.transaction(|conn| match users::del_cascade(conn, &user_id)? {
+            0 => Err(ErrorKind::NotFound)?,
+            _ => Ok(()),
         })
     })
     .from_err()
     .map(|_| HttpResponse::NoContent().finish())
 }
+
+fn get_sessions(
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    user_id: web::Path<Uuid>,
+    sessions: web::Data<SessionManager>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || {
+        policy.check(&input)?;
+        sessions.list(&user_id.simple().to_string())
+    })
+    .from_err()
+    .map(|res| HttpResponse::Ok().json(res))
+}
+
+fn del_sessions(
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    user_id: web::Path<Uuid>,
+    sessions: web::Data<SessionManager>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || {
+        policy.check(&input)?;
+        sessions.revoke_all(&user_id.simple().to_string())
+    })
+    .from_err()
+    .map(|_| HttpResponse::NoContent().finish())
+}
+
+/// Resets the two-factor authentication of a user who lost their device,
+/// they have to enroll again if a group requires it.
+fn del_two_factor(
+    db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    user_id: web::Path<Uuid>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        policy.check(&input)?;
+        let conn = db.conn()?;
+        match two_factor::del_by_user_id(&conn, &user_id)? {
+            0 => Err(ErrorKind::NotFound)?,
+            _ => Ok(()),
+        }
+    })
+    .from_err()
+    .map(|_| HttpResponse::NoContent().finish())
+}
+
+/// Lists the API keys of a user, without their secrets.
+fn get_api_keys(
+    db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    user_id: web::Path<Uuid>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        policy
+            .check(&input.resource("user_id", user_id.simple().to_string()))?;
+        let conn = db.conn()?;
+        api_keys::find_by_user_id(&conn, &user_id)
+    })
+    .from_err()
+    .map(|res| HttpResponse::Ok().json(res))
+}
+
+/// Creates an API key, the response is the only time its secret is shown.
+fn add_api_key(
+    a: Authentication,
+    db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    user_id: web::Path<Uuid>,
+    new: web::Json<NewApiKey>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        policy
+            .check(&input.resource("user_id", user_id.simple().to_string()))?;
+        let conn = db.conn()?;
+        let owner = match users::find_by_id(&conn, &user_id)? {
+            Some(user) => user,
+            None => Err(ErrorKind::NotFound)?,
+        };
+        auth::api_keys::create(&conn, &owner, &a, new.into_inner())
+    })
+    .from_err()
+    .map(|res| HttpResponse::Created().json(res))
+}
+
+fn del_api_key(
+    db: web::Data<Database>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    path: web::Path<(Uuid, Uuid)>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let (user_id, api_key_id) = path.into_inner();
+    web::block(move || -> Result<_> {
+        policy
+            .check(&input.resource("user_id", user_id.simple().to_string()))?;
+        let conn = db.conn()?;
+        match api_keys::del_by_id(&conn, &user_id, &api_key_id)? {
+            0 => Err(ErrorKind::NotFound)?,
+            _ => Ok(()),
+        }
+    })
+    .from_err()
+    .map(|_| HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{self, TestRequest};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn test_update_clears_fields() {
+        let db = database();
+        let user = {
+            let conn = db.conn().unwrap();
+            let user = users::create_or_update(&conn, "bob", "Bob", "password")
+                .unwrap();
+            users::update(
+                &conn,
+                &user.id,
+                UpdateUser {
+                    avatar_url: Some(Some("/images/bob.png".to_string())),
+                    email: Some(Some("bob@example.com".to_string())),
+                    ..UpdateUser::default()
+                },
+            )
+            .unwrap()
+        };
+        let mut app = app(&db);
+        let admin = login(
+            &mut app,
+            &Authentication::new("admin", vec!["users.put".to_string()]),
+        );
+        let mut patch = |body: Value| {
+            let resp = test::call_service(
+                &mut app,
+                TestRequest::patch()
+                    .uri(&format!("/api/users/{}", user.id))
+                    .cookie(admin.clone())
+                    .set_json(&body)
+                    .to_request(),
+            );
+            assert_eq!(StatusCode::OK, resp.status());
+            serde_json::from_slice::<Value>(&test::read_body(resp)).unwrap()
+        };
+
+        let body = patch(json!({ "nickname": "Bobby" }));
+        assert_eq!(json!("/images/bob.png"), body["avatar_url"]);
+        assert_eq!(json!("bob@example.com"), body["email"]);
+
+        let body = patch(json!({ "avatar_url": null }));
+        assert_eq!(Value::Null, body["avatar_url"]);
+        assert_eq!(json!("bob@example.com"), body["email"]);
+
+        let body = patch(json!({ "email": null }));
+        assert_eq!(Value::Null, body["email"]);
+        assert_eq!(json!("Bobby"), body["nickname"]);
+    }
+}
@@ -0,0 +1,299 @@
+//! Per-resource access control.
+//!
+//! Callers holding the `<resource type>.<action>` authority may perform the
+//! action on every resource of the type. Everyone else needs an ACL entry
+//! granting the action on the resource to them, or to a group they directly
+//! or indirectly belong to.
+//!
+//! Groups may also be delegated to managers, users that may change their
+//! members and description without any of the above.
+//!
+//! API keys limited to some of their owner's authorities act with those
+//! authorities only, ACL entries and management are not extended to them.
+//!
+//! Handlers pass what the `Authorizer` finds to the policy as resource
+//! attributes, the routes are allowed by its rules.
+use uuid::Uuid;
+
+use super::Authentication;
+use crate::db::{acl, groups, Conn};
+use crate::error::{Error, Result};
+
+/// Resource type of groups.
+pub const GROUPS: &str = "groups";
+
+/// Action that allows managing the ACL of a resource.
+pub const MANAGE_ACL: &str = "acl";
+
+/// Decides which resources the caller of a request may act on.
+pub struct Authorizer<'a> {
+    conn: &'a Conn,
+    authentication: &'a Authentication,
+}
+
+impl<'a> Authorizer<'a> {
+    pub fn new(conn: &'a Conn, authentication: &'a Authentication) -> Self {
+        Authorizer {
+            conn,
+            authentication,
+        }
+    }
+
+    pub fn is_allowed(
+        &self,
+        resource_type: &str,
+        resource_id: &Uuid,
+        action: &str,
+    ) -> Result<bool> {
+        if self.has_type_authority(resource_type, action) {
+            return Ok(true);
+        }
+
+        acl::is_granted(
+            self.conn,
+            resource_type,
+            resource_id,
+            action,
+            &self.principal_ids()?,
+        )
+    }
+
+    /// Ids of the resources the caller may perform the action on, `None` if
+    /// it may perform it on all of them.
+    pub fn allowed_ids(
+        &self,
+        resource_type: &str,
+        action: &str,
+    ) -> Result<Option<Vec<Uuid>>> {
+        if self.has_type_authority(resource_type, action) {
+            return Ok(None);
+        }
+
+        let ids = acl::find_resource_ids(
+            self.conn,
+            resource_type,
+            action,
+            &self.principal_ids()?,
+        )?;
+        Ok(Some(ids))
+    }
+
+    /// Ids of the groups the caller manages.
+    pub fn managed_ids(&self) -> Result<Vec<Uuid>> {
+        if self.authentication.narrowed() {
+            return Ok(Vec::new());
+        }
+
+        match self.user_id() {
+            Some(user_id) => groups::find_managed_ids(self.conn, &user_id),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn manages(&self, group_id: &Uuid) -> Result<bool> {
+        Ok(self.managed_ids()?.contains(group_id))
+    }
+
+    /// Returns `true` if the caller may add members to the group. Members
+    /// also get every group the group is nested in, so without the global
+    /// authority the caller has to be allowed to change or manage all of
+    /// those too.
+    pub fn may_grant(&self, group_id: &Uuid) -> Result<bool> {
+        if self.has_type_authority(GROUPS, "put") {
+            return Ok(true);
+        }
+
+        let managed = self.managed_ids()?;
+        let may_change = |id: &Uuid| -> Result<bool> {
+            Ok(managed.contains(id) || self.is_allowed(GROUPS, id, "put")?)
+        };
+        if !may_change(group_id)? {
+            return Ok(false);
+        }
+        for group in groups::find_by_member_id(self.conn, group_id)? {
+            if !may_change(&group.id)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn has_type_authority(&self, resource_type: &str, action: &str) -> bool {
+        self.authentication
+            .has_authority(&format!("{}.{}", resource_type, action))
+    }
+
+    fn user_id(&self) -> Option<Uuid> {
+        Uuid::parse_str(self.authentication.identity()).ok()
+    }
+
+    /// The user and all groups it belongs to, nothing for callers that are
+    /// not users or narrowed.
+    fn principal_ids(&self) -> Result<Vec<Uuid>> {
+        let user_id = match self.user_id() {
+            Some(user_id) if !self.authentication.narrowed() => user_id,
+            _ => return Ok(Vec::new()),
+        };
+
+        let mut ids = vec![user_id];
+        ids.extend(
+            groups::find_by_member_id(self.conn, &user_id)?
+                .into_iter()
+                .map(|g| g.id),
+        );
+        Ok(ids)
+    }
+}
+
+/// Checks an ACL action is a single permission name segment.
+pub fn validate_action(action: &str) -> Result<()> {
+    let valid = !action.is_empty()
+        && action.chars().all(|c| {
+            c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-'
+        });
+
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::validation("action", "is not a valid action"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::acl::NewAclEntry;
+    use crate::db::groups::GroupMembershipType;
+    use crate::db::users;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn test_authorizer() {
+        let conn = connection();
+        let user =
+            users::create_or_update(&conn, "bob", "Bob", "password").unwrap();
+        let team = groups::get_or_create(&conn, "acl_team").unwrap();
+        let department =
+            groups::get_or_create(&conn, "acl_department").unwrap();
+        let managed = groups::get_or_create(&conn, "acl_managed").unwrap();
+        let other = groups::get_or_create(&conn, "acl_other").unwrap();
+        groups::add_member(
+            &conn,
+            &team.id,
+            &user.id,
+            GroupMembershipType::User,
+        )
+        .unwrap();
+        groups::add_member(
+            &conn,
+            &department.id,
+            &team.id,
+            GroupMembershipType::Group,
+        )
+        .unwrap();
+        acl::create(
+            &conn,
+            GROUPS,
+            &managed.id,
+            NewAclEntry {
+                principal_id: department.id,
+                principal_type: GroupMembershipType::Group,
+                action: "put".to_string(),
+            },
+        )
+        .unwrap();
+
+        let a = Authentication::new(user.id.simple().to_string(), Vec::new());
+        let authorizer = Authorizer::new(&conn, &a);
+        assert!(authorizer.is_allowed(GROUPS, &managed.id, "put").unwrap());
+        assert!(!authorizer.is_allowed(GROUPS, &managed.id, "del").unwrap());
+        assert!(!authorizer.is_allowed(GROUPS, &other.id, "put").unwrap());
+        assert_eq!(
+            Some(vec![managed.id]),
+            authorizer.allowed_ids(GROUPS, "put").unwrap()
+        );
+        assert_eq!(
+            Some(Vec::new()),
+            authorizer.allowed_ids(GROUPS, "get").unwrap()
+        );
+
+        let admin = Authentication::new("admin", vec!["groups.*".to_string()]);
+        let authorizer = Authorizer::new(&conn, &admin);
+        assert!(authorizer.is_allowed(GROUPS, &other.id, "del").unwrap());
+        assert_eq!(None, authorizer.allowed_ids(GROUPS, "get").unwrap());
+
+        let narrowed = a.clone().with_narrowed(true);
+        let authorizer = Authorizer::new(&conn, &narrowed);
+        assert!(!authorizer.is_allowed(GROUPS, &managed.id, "put").unwrap());
+        assert_eq!(
+            Some(Vec::new()),
+            authorizer.allowed_ids(GROUPS, "put").unwrap()
+        );
+
+        let client = Authentication::new("client", Vec::new());
+        assert!(!Authorizer::new(&conn, &client)
+            .is_allowed(GROUPS, &managed.id, "put")
+            .unwrap());
+    }
+
+    #[test]
+    fn test_group_managers() {
+        let conn = connection();
+        let user =
+            users::create_or_update(&conn, "bob", "Bob", "password").unwrap();
+        let team = groups::get_or_create(&conn, "managed_team").unwrap();
+        let department =
+            groups::get_or_create(&conn, "managed_department").unwrap();
+        groups::add_member(
+            &conn,
+            &department.id,
+            &team.id,
+            GroupMembershipType::Group,
+        )
+        .unwrap();
+        groups::add_manager(&conn, &team.id, &user.id).unwrap();
+
+        let a = Authentication::new(user.id.simple().to_string(), Vec::new());
+        let authorizer = Authorizer::new(&conn, &a);
+        assert!(authorizer.manages(&team.id).unwrap());
+        assert!(!authorizer.manages(&department.id).unwrap());
+        // Members of the team would also become members of the department.
+        assert!(!authorizer.may_grant(&team.id).unwrap());
+        assert!(!authorizer.may_grant(&department.id).unwrap());
+
+        // Neither does an ACL entry on the team alone.
+        let entry = |group_id: &Uuid| {
+            acl::create(
+                &conn,
+                GROUPS,
+                group_id,
+                NewAclEntry {
+                    principal_id: user.id,
+                    principal_type: GroupMembershipType::User,
+                    action: "put".to_string(),
+                },
+            )
+            .unwrap()
+        };
+        entry(&team.id);
+        assert!(!authorizer.may_grant(&team.id).unwrap());
+        entry(&department.id);
+        assert!(authorizer.may_grant(&team.id).unwrap());
+        acl::del_by_resource(&conn, GROUPS, &team.id).unwrap();
+        acl::del_by_resource(&conn, GROUPS, &department.id).unwrap();
+        assert!(!authorizer.may_grant(&team.id).unwrap());
+
+        groups::add_manager(&conn, &department.id, &user.id).unwrap();
+        assert!(authorizer.may_grant(&team.id).unwrap());
+        assert!(authorizer.may_grant(&department.id).unwrap());
+
+        let narrowed = a.clone().with_narrowed(true);
+        let authorizer = Authorizer::new(&conn, &narrowed);
+        assert!(!authorizer.manages(&team.id).unwrap());
+        assert!(!authorizer.may_grant(&team.id).unwrap());
+        let authorizer = Authorizer::new(&conn, &a);
+
+        groups::del_managers_by_user_id(&conn, &user.id).unwrap();
+        assert!(authorizer.managed_ids().unwrap().is_empty());
+    }
+}
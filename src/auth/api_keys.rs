@@ -0,0 +1,259 @@
+//! Personal API keys, long-lived credentials for scripts acting as a user.
+//!
+//! A key reads `hk_<prefix>.<secret>` and is sent as a bearer token. The
+//! prefix identifies the key and is shown in listings, only the hash of the
+//! secret is stored. Keys act with the current authorities of their owner,
+//! optionally narrowed down to a subset chosen on creation.
+use chrono::prelude::*;
+use ring::constant_time;
+use uuid::Uuid;
+
+use super::{membership, Authentication};
+use crate::db::api_keys::{self, ApiKey};
+use crate::db::users::{self, User};
+use crate::db::{Conn, Database};
+use crate::error::{Error, ErrorKind, Result};
+use crate::utils;
+
+pub const KEY_PREFIX: &str = "hk_";
+const PREFIX_LENGTH: usize = 8;
+const SECRET_LENGTH: usize = 32;
+
+#[derive(Debug, Deserialize)]
+pub struct NewApiKey {
+    pub name: String,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Authorities to limit the key to, all of the owner's if omitted.
+    #[serde(default)]
+    pub authorities: Option<Vec<String>>,
+}
+
+/// A new key along with its secret, which is only shown once.
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub key: String,
+}
+
+/// Returns `true` if the bearer token looks like an API key rather than a
+/// signed token.
+pub fn is_api_key(token: &str) -> bool {
+    token.starts_with(KEY_PREFIX)
+}
+
+/// Creates a key of `owner` on behalf of `caller`.
+///
+/// Keys are limited to authorities of the owner, and the caller can not hand
+/// out authorities it does not hold itself.
+pub fn create(
+    conn: &Conn,
+    owner: &User,
+    caller: &Authentication,
+    new: NewApiKey,
+) -> Result<CreatedApiKey> {
+    let now = Utc::now();
+    if new.name.trim().is_empty() {
+        return Err(Error::validation("name", "must not be empty"));
+    }
+    if new.expires_at.map(|e| e <= now).unwrap_or(false) {
+        return Err(Error::validation("expires_at", "must be in the future"));
+    }
+
+    let owner_authentication = membership::authenticate(conn, owner)?;
+    let authorities = match new.authorities {
+        Some(mut authorities) => {
+            authorities.sort();
+            authorities.dedup();
+            for authority in &authorities {
+                if !owner_authentication.has_authority(authority) {
+                    return Err(Error::validation(
+                        "authorities",
+                        format!("`{}` is not held by the user", authority),
+                    ));
+                }
+            }
+            Some(authorities)
+        }
+        None => None,
+    };
+    let granted = match authorities {
+        Some(ref authorities) => authorities.iter().collect::<Vec<_>>(),
+        None => owner_authentication.authorities().iter().collect(),
+    };
+    if !granted.iter().all(|a| caller.has_authority(a)) {
+        Err(Error::from(ErrorKind::Forbidden).with_detail(
+            "a key can not be granted authorities the caller does not hold",
+        ))?
+    }
+
+    let prefix =
+        format!("{}{}", KEY_PREFIX, utils::random_string(PREFIX_LENGTH));
+    let secret = utils::random_string(SECRET_LENGTH);
+    let api_key = api_keys::create(
+        conn,
+        &ApiKey {
+            id: Uuid::new_v4(),
+            user_id: owner.id,
+            name: new.name.trim().to_string(),
+            prefix: prefix.clone(),
+            secret_hash: utils::hash_token(&secret),
+            authorities,
+            expires_at: new.expires_at,
+            last_used_at: None,
+            created_at: now,
+        },
+    )?;
+
+    Ok(CreatedApiKey {
+        api_key,
+        key: format!("{}.{}", prefix, secret),
+    })
+}
+
+/// Resolves the authentication of a key, recording its use. Fails with
+/// `Unauthorized` if the key is unknown, expired or its owner is gone.
+pub fn authenticate(
+    conn: &Conn,
+    key: &str,
+    now: DateTime<Utc>,
+) -> Result<Authentication> {
+    let mut parts = key.trim().splitn(2, '.');
+    let (prefix, secret) = match (parts.next(), parts.next()) {
+        (Some(prefix), Some(secret)) if is_api_key(prefix) => (prefix, secret),
+        _ => Err(ErrorKind::Unauthorized)?,
+    };
+
+    let api_key = match api_keys::find_by_prefix(conn, prefix)? {
+        Some(api_key) => api_key,
+        None => Err(ErrorKind::Unauthorized)?,
+    };
+    let verified = constant_time::verify_slices_are_equal(
+        utils::hash_token(secret).as_bytes(),
+        api_key.secret_hash.as_bytes(),
+    )
+    .is_ok();
+    if !verified || api_key.is_expired(now) {
+        Err(ErrorKind::Unauthorized)?
+    }
+
+    let user = match users::find_by_id(conn, &api_key.user_id)? {
+        Some(user) => user,
+        None => Err(ErrorKind::Unauthorized)?,
+    };
+    api_keys::touch(conn, &api_key.id, now)?;
+
+    let authentication = membership::authenticate(conn, &user)?;
+    match api_key.authorities {
+        Some(ref authorities) => {
+            let authorities = authorities
+                .iter()
+                .filter(|a| authentication.has_authority(a))
+                .cloned()
+                .collect::<Vec<String>>();
+            Ok(Authentication::new(authentication.identity(), authorities)
+                .with_epoch(authentication.epoch())
+                .with_narrowed(true))
+        }
+        None => Ok(authentication),
+    }
+}
+
+/// Checks API keys for the authentication backend.
+pub trait ApiKeyVerifier: Send + Sync + 'static {
+    fn verify(&self, key: &str) -> Result<Authentication>;
+}
+
+impl ApiKeyVerifier for Database {
+    fn verify(&self, key: &str) -> Result<Authentication> {
+        let conn = self.conn()?;
+        authenticate(&conn, key, Utc::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::groups::{self, GroupMembershipType};
+    use crate::db::permissions;
+    use crate::test_helpers::*;
+    use chrono::Duration;
+
+    fn new_key(authorities: Option<Vec<&str>>) -> NewApiKey {
+        NewApiKey {
+            name: "deploy".to_string(),
+            expires_at: None,
+            authorities: authorities
+                .map(|a| a.into_iter().map(str::to_string).collect()),
+        }
+    }
+
+    #[test]
+    fn test_api_keys() {
+        let conn = connection();
+        let user =
+            users::create_or_update(&conn, "bob", "Bob", "password").unwrap();
+        for name in &["api_keys.read", "api_keys.write"] {
+            let group = groups::get_or_create(&conn, name).unwrap();
+            let permission = permissions::get_or_create(&conn, name).unwrap();
+            permissions::grant(&conn, &group.id, &permission.id).unwrap();
+            groups::add_member(
+                &conn,
+                &group.id,
+                &user.id,
+                GroupMembershipType::User,
+            )
+            .unwrap();
+        }
+        let user = users::find_by_id(&conn, &user.id).unwrap().unwrap();
+        let owner = membership::authenticate(&conn, &user).unwrap();
+        let now = Utc::now();
+
+        let full = create(&conn, &user, &owner, new_key(None)).unwrap();
+        let a = authenticate(&conn, &full.key, now).unwrap();
+        assert_eq!(owner.authorities(), a.authorities());
+        assert!(!a.narrowed());
+
+        let limited =
+            create(&conn, &user, &owner, new_key(Some(vec!["api_keys.read"])))
+                .unwrap();
+        assert!(limited.key.starts_with(&limited.api_key.prefix));
+        let a = authenticate(&conn, &limited.key, now).unwrap();
+        assert_eq!(user.id.simple().to_string(), a.identity());
+        assert!(a.narrowed());
+        assert_eq!(
+            vec!["api_keys.read"],
+            a.authorities().iter().collect::<Vec<_>>()
+        );
+        let used = api_keys::find_by_prefix(&conn, &limited.api_key.prefix)
+            .unwrap()
+            .unwrap();
+        assert!(used.last_used_at.is_some());
+
+        let wrong = format!("{}.wrong", limited.api_key.prefix);
+        for key in &[wrong.as_str(), "hk_unknown.secret", "hk_nosecret"] {
+            let err = authenticate(&conn, key, now).unwrap_err();
+            assert_eq!(ErrorKind::Unauthorized, err.kind());
+        }
+
+        let expiring = NewApiKey {
+            expires_at: Some(now + Duration::hours(1)),
+            ..new_key(None)
+        };
+        let expiring = create(&conn, &user, &owner, expiring).unwrap();
+        let later = now + Duration::hours(2);
+        let err = authenticate(&conn, &expiring.key, later).unwrap_err();
+        assert_eq!(ErrorKind::Unauthorized, err.kind());
+
+        let err = create(&conn, &user, &owner, new_key(Some(vec!["admin"])))
+            .unwrap_err();
+        assert_eq!(ErrorKind::Validation, err.kind());
+        let reader = Authentication::new(
+            owner.identity(),
+            vec!["api_keys.read".to_string()],
+        );
+        let err = create(&conn, &user, &reader, new_key(None)).unwrap_err();
+        assert_eq!(ErrorKind::Forbidden, err.kind());
+    }
+}
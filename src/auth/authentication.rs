@@ -6,12 +6,25 @@ use std::rc::Rc;
 use actix_web::dev::{Extensions, Payload, ServiceRequest, ServiceResponse};
 use actix_web::{FromRequest, HttpMessage, HttpRequest};
 
+use super::authorization::permission_matches;
 use crate::error::{Error, ErrorKind, Result};
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Authentication {
     identity: String,
     authorities: HashSet<String>,
+    #[serde(default)]
+    epoch: i64,
+    /// Identity of the administrator acting as this user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    impersonator: Option<String>,
+    /// Set for API keys limited to some of their owner's authorities.
+    #[serde(default, skip_serializing_if = "is_false")]
+    narrowed: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
 }
 
 impl Authentication {
@@ -22,9 +35,28 @@ impl Authentication {
         Authentication {
             identity: identity.into(),
             authorities: authorities.into_iter().collect(),
+            epoch: 0,
+            impersonator: None,
+            narrowed: false,
         }
     }
 
+    /// Sets the membership epoch the authorities were resolved at.
+    pub fn with_epoch(mut self, epoch: i64) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    pub fn with_impersonator(mut self, impersonator: Option<String>) -> Self {
+        self.impersonator = impersonator;
+        self
+    }
+
+    pub fn with_narrowed(mut self, narrowed: bool) -> Self {
+        self.narrowed = narrowed;
+        self
+    }
+
     pub fn anonymous() -> Self {
         Self::new("anonymous", vec!["anonymous".to_string()])
     }
@@ -32,6 +64,47 @@ impl Authentication {
     pub fn identity(&self) -> &str {
         &self.identity
     }
+
+    pub fn epoch(&self) -> i64 {
+        self.epoch
+    }
+
+    pub fn impersonator(&self) -> Option<&str> {
+        self.impersonator.as_ref().map(String::as_str)
+    }
+
+    /// Returns `true` if the authorities are narrower than those of the user.
+    pub fn narrowed(&self) -> bool {
+        self.narrowed
+    }
+
+    pub fn authorities(&self) -> &HashSet<String> {
+        &self.authorities
+    }
+
+    /// Returns `true` if a permission held by the authentication covers the
+    /// authority, see `permission_matches`.
+    pub fn has_authority(&self, authority: &str) -> bool {
+        self.authorities
+            .iter()
+            .any(|granted| permission_matches(granted, authority))
+    }
+
+    pub fn has_any_authority<'a, I>(&self, authorities: I) -> bool
+    where
+        I: IntoIterator<Item = &'a String>,
+    {
+        authorities.into_iter().any(|a| self.has_authority(a))
+    }
+
+    /// Fails with `Forbidden` unless the authentication holds the authority.
+    pub fn require_authority(&self, authority: &str) -> Result<()> {
+        if self.has_authority(authority) {
+            Ok(())
+        } else {
+            Err(ErrorKind::Forbidden)?
+        }
+    }
 }
 
 pub struct AuthenticationManager(Rc<RefCell<AuthenticationManagerInner>>);
@@ -75,6 +148,24 @@ impl AuthenticationManager {
         inner.authentication = a;
     }
 
+    pub(crate) fn get_authentication(
+        req: &ServiceRequest,
+    ) -> Option<Authentication> {
+        let am = AuthenticationManager::get_manager(&mut req.extensions_mut());
+        am.authentication()
+    }
+
+    /// Returns the authentication a response was produced with, before it
+    /// is handed to the backend.
+    pub(crate) fn peek_authentication<B>(
+        res: &ServiceResponse<B>,
+    ) -> Option<Authentication> {
+        res.request()
+            .extensions()
+            .get::<Rc<RefCell<AuthenticationManagerInner>>>()
+            .and_then(|inner| inner.borrow().authentication.clone())
+    }
+
     pub(crate) fn get_changed<B>(
         res: &mut ServiceResponse<B>,
     ) -> (bool, Option<Authentication>) {
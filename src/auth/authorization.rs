@@ -0,0 +1,217 @@
+use std::collections::HashSet;
+
+use actix_web::dev::ResourceDef;
+use actix_web::http::Method;
+
+use super::Authentication;
+use crate::error::{Error, ErrorKind, Result};
+
+/// Permission that grants every other permission.
+pub const ALL_PERMISSIONS: &str = "*";
+
+/// Returns `true` if the granted permission covers the required one. A name
+/// ending in `.*` covers every permission below it, `groups.*` covers
+/// `groups.get` but not `groups`.
+pub fn permission_matches(granted: &str, required: &str) -> bool {
+    if granted == required || granted == ALL_PERMISSIONS {
+        return true;
+    }
+
+    granted.ends_with(".*")
+        && required.starts_with(&granted[..granted.len() - 1])
+}
+
+/// Checks a permission name is made of dot separated segments of lowercase
+/// letters, digits, `_` and `-`, where the last segment may be `*`.
+pub fn validate_permission_name(name: &str) -> Result<()> {
+    let segments = name.split('.').collect::<Vec<_>>();
+    let last = segments.len() - 1;
+    let valid = segments.iter().enumerate().all(|(i, segment)| {
+        (i == last && *segment == ALL_PERMISSIONS)
+            || (!segment.is_empty()
+                && segment.chars().all(|c| {
+                    c.is_ascii_lowercase()
+                        || c.is_ascii_digit()
+                        || c == '_'
+                        || c == '-'
+                }))
+    });
+
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::validation("name", "is not a valid permission name"))
+    }
+}
+
+/// Access required by a route.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Access {
+    /// Everyone, including anonymous callers.
+    PermitAll,
+    /// Any authenticated caller.
+    Authenticated,
+    /// Authenticated callers holding at least one of the authorities.
+    HasAnyAuthority(HashSet<String>),
+}
+
+impl Access {
+    pub fn check(&self, a: Option<&Authentication>) -> Result<()> {
+        match (self, a) {
+            (Access::PermitAll, _) => Ok(()),
+            (_, None) => Err(ErrorKind::Unauthorized)?,
+            (Access::Authenticated, Some(_)) => Ok(()),
+            (Access::HasAnyAuthority(authorities), Some(a)) => {
+                if a.has_any_authority(authorities) {
+                    Ok(())
+                } else {
+                    Err(ErrorKind::Forbidden)?
+                }
+            }
+        }
+    }
+}
+
+struct AccessRule {
+    method: Method,
+    path: ResourceDef,
+    access: Access,
+}
+
+/// Maps http routes to the access they require.
+///
+/// Rules are matched in registration order against the request method and
+/// the path below `prefix`. Unmatched routes require an authenticated caller.
+pub struct AccessRules {
+    prefix: String,
+    rules: Vec<AccessRule>,
+    default: Access,
+}
+
+impl AccessRules {
+    pub fn new<S: Into<String>>(prefix: S) -> Self {
+        AccessRules {
+            prefix: prefix.into(),
+            rules: Vec::new(),
+            default: Access::Authenticated,
+        }
+    }
+
+    pub fn rule(mut self, method: Method, path: &str, access: Access) -> Self {
+        let path = ResourceDef::new(&format!("{}{}", self.prefix, path));
+        self.rules.push(AccessRule {
+            method,
+            path,
+            access,
+        });
+        self
+    }
+
+    pub fn permit_all(self, method: Method, path: &str) -> Self {
+        self.rule(method, path, Access::PermitAll)
+    }
+
+    pub fn authenticated(self, method: Method, path: &str) -> Self {
+        self.rule(method, path, Access::Authenticated)
+    }
+
+    pub fn has_authority(
+        self,
+        method: Method,
+        path: &str,
+        authority: &str,
+    ) -> Self {
+        self.has_any_authority(method, path, &[authority])
+    }
+
+    pub fn has_any_authority(
+        self,
+        method: Method,
+        path: &str,
+        authorities: &[&str],
+    ) -> Self {
+        let authorities = authorities.iter().map(|a| a.to_string()).collect();
+        self.rule(method, path, Access::HasAnyAuthority(authorities))
+    }
+
+    pub fn access(&self, method: &Method, path: &str) -> &Access {
+        self.rules
+            .iter()
+            .find(|r| &r.method == method && r.path.is_match(path))
+            .map(|r| &r.access)
+            .unwrap_or(&self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> AccessRules {
+        AccessRules::new("/api")
+            .permit_all(Method::POST, "/auth")
+            .has_authority(Method::GET, "/groups", "groups.get")
+            .has_authority(Method::PUT, "/groups/{group_id}", "groups.put")
+    }
+
+    #[test]
+    fn test_access_rules() {
+        let rules = rules();
+
+        assert_eq!(&Access::PermitAll, rules.access(&Method::POST, "/api/auth"));
+        assert_eq!(
+            &Access::Authenticated,
+            rules.access(&Method::GET, "/api/auth")
+        );
+        assert_eq!(
+            &Access::HasAnyAuthority(vec!["groups.put".to_string()]
+                .into_iter()
+                .collect()),
+            rules.access(&Method::PUT, "/api/groups/1")
+        );
+    }
+
+    #[test]
+    fn test_access_check() {
+        let access = rules().access(&Method::GET, "/api/groups").clone();
+        let user = Authentication::new("bob", vec!["user".to_string()]);
+        let reader = Authentication::new("bob", vec!["groups.get".to_string()]);
+        let admin = Authentication::new("admin", vec!["*".to_string()]);
+        let manager = Authentication::new("bob", vec!["groups.*".to_string()]);
+
+        assert_eq!(
+            ErrorKind::Unauthorized,
+            access.check(None).unwrap_err().kind()
+        );
+        assert_eq!(
+            ErrorKind::Forbidden,
+            access.check(Some(&user)).unwrap_err().kind()
+        );
+        assert!(access.check(Some(&reader)).is_ok());
+        assert!(access.check(Some(&admin)).is_ok());
+        assert!(access.check(Some(&manager)).is_ok());
+    }
+
+    #[test]
+    fn test_permission_matches() {
+        assert!(permission_matches("groups.get", "groups.get"));
+        assert!(permission_matches("*", "groups.get"));
+        assert!(permission_matches("groups.*", "groups.get"));
+        assert!(permission_matches("inventory.*", "inventory.items.get"));
+        assert!(permission_matches("inventory.items.*", "inventory.items.*"));
+        assert!(!permission_matches("groups.*", "groups"));
+        assert!(!permission_matches("groups.*", "groupsx.get"));
+        assert!(!permission_matches("groups.get", "groups.*"));
+        assert!(!permission_matches("inventory.items.*", "inventory.get"));
+    }
+
+    #[test]
+    fn test_validate_permission_name() {
+        for name in &["*", "groups.get", "groups.*", "oauth_clients.del"] {
+            assert!(validate_permission_name(name).is_ok(), "{}", name);
+        }
+        for name in &["", "groups.", ".get", "*.get", "groups.g*", "Groups"] {
+            assert!(validate_permission_name(name).is_err(), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Acting as another user.
+//!
+//! An administrator impersonating a user is authenticated with the user's
+//! identity and authorities, the administrator is remembered as the
+//! impersonator until the impersonation is stopped. Every request made in
+//! the meantime is recorded in the audit log. The impersonation ends as soon
+//! as the administrator no longer holds every authority of the user.
+use uuid::Uuid;
+
+use super::{membership, Authentication};
+use crate::db::audit::{self, NewAuditEntry};
+use crate::db::users::{self, User};
+use crate::db::{Conn, Database};
+use crate::error::{Error, ErrorKind, Result, ResultExt};
+
+/// Returns the authentication of the actor impersonating the target.
+///
+/// The actor has to hold every authority of the target, so impersonating
+/// never grants more than the actor already has.
+pub fn start(
+    conn: &Conn,
+    actor: &Authentication,
+    target: &User,
+) -> Result<Authentication> {
+    if actor.impersonator().is_some() {
+        return Err(Error::from(ErrorKind::BadRequest)
+            .with_detail("already impersonating a user"));
+    }
+    let actor_id =
+        Uuid::parse_str(actor.identity()).context(ErrorKind::Forbidden)?;
+    if actor_id == target.id {
+        return Err(Error::validation("user_id", "can not be yourself"));
+    }
+
+    let authentication = membership::authenticate(conn, target)?;
+    if !holds_all(actor, &authentication) {
+        return Err(Error::from(ErrorKind::Forbidden)
+            .with_detail("the user holds authorities you do not"));
+    }
+
+    Ok(authentication.with_impersonator(Some(actor.identity().to_string())))
+}
+
+/// Returns the authentication of the impersonator.
+pub fn stop(conn: &Conn, a: &Authentication) -> Result<Authentication> {
+    let impersonator = match a.impersonator() {
+        Some(impersonator) => impersonator,
+        None => {
+            return Err(Error::from(ErrorKind::BadRequest)
+                .with_detail("not impersonating a user"))
+        }
+    };
+    let user_id =
+        Uuid::parse_str(impersonator).context(ErrorKind::Unauthorized)?;
+
+    match users::find_by_id(conn, &user_id)? {
+        Some(user) => membership::authenticate(conn, &user),
+        None => Err(ErrorKind::Unauthorized)?,
+    }
+}
+
+/// Checks an impersonation against the current authorities of the
+/// impersonator, returns the impersonator's authentication if they no longer
+/// cover the user's and `None` if the impersonator no longer exists.
+pub fn refresh(
+    conn: &Conn,
+    a: Authentication,
+) -> Result<Option<Authentication>> {
+    if a.impersonator().is_none() {
+        return Ok(Some(a));
+    }
+
+    match stop(conn, &a) {
+        Ok(ref actor) if holds_all(actor, &a) => Ok(Some(a)),
+        Ok(actor) => Ok(Some(actor)),
+        Err(ref e) if e.kind() == ErrorKind::Unauthorized => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn holds_all(actor: &Authentication, a: &Authentication) -> bool {
+    a.authorities()
+        .iter()
+        .all(|authority| actor.has_authority(authority))
+}
+
+/// Records the requests made while impersonating.
+pub trait AuditLog: Send + Sync + 'static {
+    fn record(&self, entry: NewAuditEntry) -> Result<()>;
+}
+
+impl AuditLog for Database {
+    fn record(&self, entry: NewAuditEntry) -> Result<()> {
+        let conn = self.conn()?;
+        audit::create(&conn, entry)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::groups::{self, GroupMembershipType};
+    use crate::db::permissions;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn test_impersonation() {
+        let conn = connection();
+        let admin =
+            users::create_or_update(&conn, "admin", "Admin", "password")
+                .unwrap();
+        let user =
+            users::create_or_update(&conn, "bob", "Bob", "password").unwrap();
+        let group = groups::get_or_create(&conn, "imp_staff").unwrap();
+        let permission =
+            permissions::get_or_create(&conn, "imp.items.get").unwrap();
+        permissions::grant(&conn, &group.id, &permission.id).unwrap();
+        groups::add_member(
+            &conn,
+            &group.id,
+            &user.id,
+            GroupMembershipType::User,
+        )
+        .unwrap();
+        let identity = admin.id.simple().to_string();
+        let actor = |authority: &str| {
+            Authentication::new(identity.clone(), vec![authority.to_string()])
+        };
+
+        let err = start(&conn, &actor("imp.other"), &user).unwrap_err();
+        assert_eq!(ErrorKind::Forbidden, err.kind());
+        let err = start(&conn, &actor("*"), &admin).unwrap_err();
+        assert_eq!(ErrorKind::Validation, err.kind());
+
+        let impersonated = start(&conn, &actor("imp.*"), &user).unwrap();
+        assert_eq!(user.id.simple().to_string(), impersonated.identity());
+        assert_eq!(Some(identity.as_str()), impersonated.impersonator());
+        assert!(impersonated.has_authority("imp.items.get"));
+        let err = start(&conn, &impersonated, &admin).unwrap_err();
+        assert_eq!(ErrorKind::BadRequest, err.kind());
+
+        // The admin holds no authorities of its own.
+        let refreshed = refresh(&conn, impersonated.clone()).unwrap().unwrap();
+        assert_eq!(identity, refreshed.identity());
+        assert_eq!(None, refreshed.impersonator());
+        groups::add_member(
+            &conn,
+            &group.id,
+            &admin.id,
+            GroupMembershipType::User,
+        )
+        .unwrap();
+        let refreshed = refresh(&conn, impersonated.clone()).unwrap().unwrap();
+        assert_eq!(impersonated, refreshed);
+
+        let stopped = stop(&conn, &impersonated).unwrap();
+        assert_eq!(identity, stopped.identity());
+        assert_eq!(None, stopped.impersonator());
+        let err = stop(&conn, &stopped).unwrap_err();
+        assert_eq!(ErrorKind::BadRequest, err.kind());
+    }
+}
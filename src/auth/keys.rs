@@ -0,0 +1,190 @@
+//! Secret keys signing the authentication cookie and bearer tokens.
+//!
+//! Cookies and bearer tokens are signed with separate keys, configured under
+//! the `AUTH` and `TOKEN` prefixes. The current key comes from
+//! `<PREFIX>_SIGNING_KEY` or the first line of the file named by
+//! `<PREFIX>_SIGNING_KEY_FILE`. Retired keys, still accepted until the
+//! credentials they signed expire, follow on the next lines of the key file
+//! or are listed comma separated in `<PREFIX>_PREVIOUS_SIGNING_KEYS`. Keys
+//! are base64 encoded and at least 32 bytes long.
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use crate::error::{Error, ErrorKind, Result, ResultExt};
+
+pub const MIN_KEY_LENGTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SigningKeys {
+    current: Vec<u8>,
+    previous: Vec<Vec<u8>>,
+}
+
+impl SigningKeys {
+    /// Checks the keys, an all-zero key is only accepted in dev mode.
+    pub fn new(
+        current: Vec<u8>,
+        previous: Vec<Vec<u8>>,
+        dev_mode: bool,
+    ) -> Result<SigningKeys> {
+        for key in Some(&current).into_iter().chain(&previous) {
+            if key.len() < MIN_KEY_LENGTH {
+                return Err(config_error(format!(
+                    "signing keys must be at least {} bytes long",
+                    MIN_KEY_LENGTH
+                )));
+            }
+            if !dev_mode && key.iter().all(|b| *b == 0) {
+                return Err(config_error(
+                    "all-zero signing keys are only allowed in dev mode",
+                ));
+            }
+        }
+
+        Ok(SigningKeys { current, previous })
+    }
+
+    /// Loads the keys configured under `prefix` from the environment,
+    /// falling back to an all-zero key in dev mode.
+    pub fn from_env(prefix: &str, dev_mode: bool) -> Result<SigningKeys> {
+        let var = format!("{}_SIGNING_KEY", prefix);
+        let file_var = format!("{}_SIGNING_KEY_FILE", prefix);
+        let mut keys = match env::var(&var) {
+            Ok(key) => vec![decode_key(&key)?],
+            Err(_) => match env::var(&file_var) {
+                Ok(path) => read_key_file(path)?,
+                Err(_) if dev_mode => {
+                    warn!("{} is not set, using an all-zero key", var);
+                    vec![vec![0; MIN_KEY_LENGTH]]
+                }
+                Err(_) => {
+                    return Err(config_error(format!(
+                        "{} or {} must be set",
+                        var, file_var
+                    )))
+                }
+            },
+        };
+
+        let previous_var = format!("{}_PREVIOUS_SIGNING_KEYS", prefix);
+        if let Ok(previous) = env::var(previous_var) {
+            for key in previous.split(',').filter(|k| !k.trim().is_empty()) {
+                keys.push(decode_key(key)?);
+            }
+        }
+
+        let current = keys.remove(0);
+        SigningKeys::new(current, keys, dev_mode)
+    }
+
+    /// Checks that no key is shared with `other`, an all-zero key may be
+    /// shared in dev mode.
+    pub fn distinct_from(
+        self,
+        other: &SigningKeys,
+        dev_mode: bool,
+    ) -> Result<SigningKeys> {
+        let keys = |k: &SigningKeys| {
+            Some(k.current.clone())
+                .into_iter()
+                .chain(k.previous.clone())
+        };
+        let shared = keys(&self)
+            .filter(|key| !dev_mode || key.iter().any(|b| *b != 0))
+            .any(|key| keys(other).any(|k| k == key));
+
+        if shared {
+            return Err(config_error(
+                "cookies and bearer tokens must be signed with separate keys",
+            ));
+        }
+        Ok(self)
+    }
+
+    pub fn current(&self) -> &[u8] {
+        &self.current
+    }
+
+    pub fn previous(&self) -> &[Vec<u8>] {
+        &self.previous
+    }
+}
+
+/// Reads a key file, one key per line, the current key first. Blank lines and
+/// lines starting with `#` are skipped.
+pub fn read_key_file<P: AsRef<Path>>(path: P) -> Result<Vec<Vec<u8>>> {
+    let content = fs::read_to_string(path).context(ErrorKind::ConfigError)?;
+    let keys = parse_keys(&content)?;
+
+    if keys.is_empty() {
+        return Err(config_error("the signing key file contains no key"));
+    }
+    Ok(keys)
+}
+
+fn parse_keys(content: &str) -> Result<Vec<Vec<u8>>> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(decode_key)
+        .collect()
+}
+
+fn decode_key(key: &str) -> Result<Vec<u8>> {
+    base64::decode(key.trim())
+        .map_err(|_| config_error("signing keys must be base64 encoded"))
+}
+
+fn config_error<D: Into<String>>(detail: D) -> Error {
+    Error::from(ErrorKind::ConfigError).with_detail(detail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_keys() {
+        let current = base64::encode(&[1; 32]);
+        let previous = base64::encode(&[2; 32]);
+        let content = format!("# rotated\n{}\n\n{}\n", current, previous);
+
+        let keys = parse_keys(&content).unwrap();
+
+        assert_eq!(vec![vec![1; 32], vec![2; 32]], keys);
+        assert!(parse_keys("not base64!").is_err());
+    }
+
+    #[test]
+    fn test_new_rejects_weak_keys() {
+        let err = SigningKeys::new(vec![0; 32], vec![], false).err().unwrap();
+        assert_eq!(ErrorKind::ConfigError, err.kind());
+
+        let err = SigningKeys::new(vec![1; 32], vec![vec![1; 16]], true)
+            .err()
+            .unwrap();
+        assert_eq!(ErrorKind::ConfigError, err.kind());
+
+        assert!(SigningKeys::new(vec![0; 32], vec![], true).is_ok());
+    }
+
+    #[test]
+    fn test_distinct_from() {
+        let cookie =
+            SigningKeys::new(vec![1; 32], vec![vec![2; 32]], false).unwrap();
+        let token = |current: u8| {
+            SigningKeys::new(vec![current; 32], vec![], false).unwrap()
+        };
+
+        assert!(token(3).distinct_from(&cookie, false).is_ok());
+        for current in &[1, 2] {
+            let err = token(*current).distinct_from(&cookie, true).unwrap_err();
+            assert_eq!(ErrorKind::ConfigError, err.kind());
+        }
+
+        let zero = SigningKeys::new(vec![0; 32], vec![], true).unwrap();
+        assert!(zero.clone().distinct_from(&zero, true).is_ok());
+    }
+}
@@ -0,0 +1,264 @@
+//! Brute-force protection of the login.
+//!
+//! Failed logins are counted per username and per client address. Once a
+//! threshold is reached the subject is locked out for a delay that doubles
+//! with every further failure, up to a maximum. Usernames are tracked whether
+//! they exist or not, so lockouts do not reveal which accounts exist.
+//!
+//! Password reset requests are throttled the same way, counted separately
+//! per requested username or email address and per client address.
+use std::cmp;
+
+use chrono::prelude::*;
+use chrono::Duration;
+
+use crate::db::lockouts::{self, Lockout, LockoutKind};
+use crate::db::Conn;
+use crate::error::{Error, ErrorKind, Result};
+
+/// Lockout thresholds and delays.
+///
+/// By default a username is locked out after 5 failures, an address, which
+/// clients behind a shared proxy count against together, after 20. The first
+/// lockout lasts 30 seconds and doubles up to 15 minutes, failures are
+/// forgotten after a day without any. Resets are throttled after 3 requests
+/// for an address and 10 requests from a client address.
+#[derive(Debug, Clone)]
+pub struct LockoutPolicy {
+    username_threshold: i32,
+    ip_threshold: i32,
+    reset_threshold: i32,
+    reset_ip_threshold: i32,
+    base_delay: Duration,
+    max_delay: Duration,
+    reset_after: Duration,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> LockoutPolicy {
+        LockoutPolicy {
+            username_threshold: 5,
+            ip_threshold: 20,
+            reset_threshold: 3,
+            reset_ip_threshold: 10,
+            base_delay: Duration::seconds(30),
+            max_delay: Duration::minutes(15),
+            reset_after: Duration::hours(24),
+        }
+    }
+}
+
+impl LockoutPolicy {
+    /// Fails with `TooManyRequests` while the username or the address is
+    /// locked out.
+    pub fn check(
+        &self,
+        conn: &Conn,
+        username: &str,
+        ip: Option<&str>,
+    ) -> Result<()> {
+        self.check_subjects(conn, subjects(username, ip), "failed logins")
+    }
+
+    pub fn record_failure(
+        &self,
+        conn: &Conn,
+        username: &str,
+        ip: Option<&str>,
+    ) -> Result<()> {
+        self.record(conn, subjects(username, ip))
+    }
+
+    /// Counts a reset request for the username or email address, failing
+    /// with `TooManyRequests` while either it or the client address is
+    /// throttled.
+    pub fn throttle_reset(
+        &self,
+        conn: &Conn,
+        login: &str,
+        ip: Option<&str>,
+    ) -> Result<()> {
+        let login = login.trim().to_lowercase();
+        self.check_subjects(
+            conn,
+            reset_subjects(&login, ip),
+            "password reset requests",
+        )?;
+        self.record(conn, reset_subjects(&login, ip))
+    }
+
+    fn check_subjects<'a>(
+        &self,
+        conn: &Conn,
+        subjects: impl Iterator<Item = (LockoutKind, &'a str)>,
+        what: &str,
+    ) -> Result<()> {
+        let now = Utc::now();
+
+        for (kind, subject) in subjects {
+            let locked_until = lockouts::find(conn, kind, subject)?
+                .and_then(|lockout| lockout.locked_until)
+                .filter(|locked_until| *locked_until > now);
+
+            if let Some(locked_until) = locked_until {
+                return Err(Error::from(ErrorKind::TooManyRequests)
+                    .with_detail(format!(
+                        "too many {}, try again in {} seconds",
+                        what,
+                        (locked_until - now).num_seconds() + 1
+                    )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn record<'a>(
+        &self,
+        conn: &Conn,
+        subjects: impl Iterator<Item = (LockoutKind, &'a str)>,
+    ) -> Result<()> {
+        let now = Utc::now();
+
+        for (kind, subject) in subjects {
+            let lockout = lockouts::record_failure(
+                conn,
+                kind,
+                subject,
+                now,
+                now - self.reset_after,
+            )?;
+
+            if let Some(delay) = self.delay(&lockout) {
+                lockouts::lock(conn, &lockout.id, now + delay)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Forgets the failures of the username, those of the address are kept
+    /// so one known password can not be used to keep probing others.
+    pub fn record_success(&self, conn: &Conn, username: &str) -> Result<()> {
+        lockouts::del(conn, LockoutKind::Username, username)?;
+        Ok(())
+    }
+
+    fn delay(&self, lockout: &Lockout) -> Option<Duration> {
+        let threshold = match lockout.kind {
+            LockoutKind::Username => self.username_threshold,
+            LockoutKind::Ip => self.ip_threshold,
+            LockoutKind::Reset => self.reset_threshold,
+            LockoutKind::ResetIp => self.reset_ip_threshold,
+        };
+        let exceeded = lockout.failures - threshold;
+        if exceeded < 0 {
+            return None;
+        }
+
+        // Capped so long running attacks can not overflow the delay.
+        let factor = 1i64 << exceeded.min(32);
+        let seconds = self.base_delay.num_seconds().saturating_mul(factor);
+        Some(cmp::min(Duration::seconds(seconds), self.max_delay))
+    }
+}
+
+fn subjects<'a>(
+    username: &'a str,
+    ip: Option<&'a str>,
+) -> impl Iterator<Item = (LockoutKind, &'a str)> {
+    Some((LockoutKind::Username, username))
+        .into_iter()
+        .chain(ip.map(|ip| (LockoutKind::Ip, ip)))
+}
+
+fn reset_subjects<'a>(
+    login: &'a str,
+    ip: Option<&'a str>,
+) -> impl Iterator<Item = (LockoutKind, &'a str)> {
+    Some((LockoutKind::Reset, login))
+        .into_iter()
+        .chain(ip.map(|ip| (LockoutKind::ResetIp, ip)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+    use uuid::Uuid;
+
+    fn lockout(kind: LockoutKind, failures: i32) -> Lockout {
+        Lockout {
+            id: Uuid::new_v4(),
+            kind,
+            subject: "bob".to_string(),
+            failures,
+            last_failed_at: Utc::now(),
+            locked_until: None,
+        }
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy = LockoutPolicy::default();
+        let delay = |kind, failures| {
+            policy
+                .delay(&lockout(kind, failures))
+                .map(|d| d.num_seconds())
+        };
+
+        assert_eq!(None, delay(LockoutKind::Username, 4));
+        assert_eq!(Some(30), delay(LockoutKind::Username, 5));
+        assert_eq!(Some(60), delay(LockoutKind::Username, 6));
+        assert_eq!(Some(900), delay(LockoutKind::Username, 12));
+        assert_eq!(Some(900), delay(LockoutKind::Username, 100));
+        assert_eq!(None, delay(LockoutKind::Ip, 19));
+        assert_eq!(Some(30), delay(LockoutKind::Ip, 20));
+    }
+
+    #[test]
+    fn test_lockout() {
+        let conn = connection();
+        let policy = LockoutPolicy {
+            username_threshold: 2,
+            ..LockoutPolicy::default()
+        };
+        let ip = Some("10.0.0.1");
+
+        policy.record_failure(&conn, "bob", ip).unwrap();
+        assert!(policy.check(&conn, "bob", ip).is_ok());
+
+        policy.record_failure(&conn, "bob", ip).unwrap();
+        let err = policy.check(&conn, "bob", ip).unwrap_err();
+        assert_eq!(ErrorKind::TooManyRequests, err.kind());
+        assert!(policy.check(&conn, "alice", ip).is_ok());
+
+        policy.record_success(&conn, "bob").unwrap();
+        assert!(policy.check(&conn, "bob", ip).is_ok());
+        let ip_lockout =
+            lockouts::find(&conn, LockoutKind::Ip, "10.0.0.1").unwrap();
+        assert_eq!(2, ip_lockout.unwrap().failures);
+    }
+
+    #[test]
+    fn test_throttle_reset() {
+        let conn = connection();
+        let policy = LockoutPolicy::default();
+        let ip = Some("10.0.0.1");
+
+        for _ in 0..3 {
+            policy.throttle_reset(&conn, "Bob", ip).unwrap();
+        }
+        let err = policy.throttle_reset(&conn, "bob", None).unwrap_err();
+        assert_eq!(ErrorKind::TooManyRequests, err.kind());
+        assert!(policy.check(&conn, "bob", ip).is_ok());
+
+        for i in 0..7 {
+            let login = format!("user{}", i);
+            policy.throttle_reset(&conn, &login, ip).unwrap();
+        }
+        let err = policy.throttle_reset(&conn, "alice", ip).unwrap_err();
+        assert_eq!(ErrorKind::TooManyRequests, err.kind());
+        assert!(policy.throttle_reset(&conn, "alice", None).is_ok());
+    }
+}
@@ -0,0 +1,143 @@
+//! Authorities derived from group membership.
+//!
+//! A user holds the permissions granted to every group it directly or
+//! indirectly belongs to. Credentials remember the membership epoch of the
+//! user they were issued at, the epoch is bumped on every membership or
+//! grant change so stale credentials can be detected and their authorities
+//! re-resolved.
+use uuid::Uuid;
+
+use super::{impersonation, Authentication};
+use crate::db::users::{self, User};
+use crate::db::{groups, permissions, Conn, Database};
+use crate::error::Result;
+
+/// Resolves the current authentication of a user.
+pub fn authenticate(conn: &Conn, user: &User) -> Result<Authentication> {
+    let group_ids = groups::find_by_member_id(conn, &user.id)?
+        .into_iter()
+        .map(|g| g.id)
+        .collect::<Vec<Uuid>>();
+    let authorities = permissions::find_names_by_group_ids(conn, &group_ids)?;
+    let identity = user.id.simple().to_string();
+
+    Ok(Authentication::new(identity, authorities)
+        .with_epoch(user.membership_epoch))
+}
+
+/// Brings the authorities of an authentication up to date.
+pub trait AuthorityResolver: Send + Sync + 'static {
+    /// Returns the authentication with current authorities, or `None` if
+    /// the user it belongs to no longer exists.
+    fn refresh(
+        &self,
+        authentication: Authentication,
+    ) -> Result<Option<Authentication>>;
+}
+
+impl AuthorityResolver for Database {
+    fn refresh(
+        &self,
+        authentication: Authentication,
+    ) -> Result<Option<Authentication>> {
+        // Only users have group memberships.
+        let user_id = match Uuid::parse_str(authentication.identity()) {
+            Ok(user_id) => user_id,
+            Err(_) => return Ok(Some(authentication)),
+        };
+
+        let conn = self.conn()?;
+        let authentication = match users::find_by_id(&conn, &user_id)? {
+            Some(ref user)
+                if user.membership_epoch == authentication.epoch() =>
+            {
+                authentication
+            }
+            Some(ref user) => {
+                let impersonator =
+                    authentication.impersonator().map(str::to_string);
+                authenticate(&conn, user)?.with_impersonator(impersonator)
+            }
+            None => return Ok(None),
+        };
+
+        // The impersonator may have lost authorities in the meantime.
+        impersonation::refresh(&conn, authentication)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::groups::GroupMembershipType;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn test_membership_changes_bump_epoch() {
+        let conn = connection();
+        let user =
+            users::create_or_update(&conn, "bob", "Bob", "password").unwrap();
+        let parent = groups::get_or_create(&conn, "epoch_parent").unwrap();
+        let child = groups::get_or_create(&conn, "epoch_child").unwrap();
+        for (group, name) in
+            &[(&parent, "epoch.parent"), (&child, "epoch.child")]
+        {
+            let permission = permissions::get_or_create(&conn, name).unwrap();
+            permissions::grant(&conn, &group.id, &permission.id).unwrap();
+        }
+        let epoch = |conn: &Conn| {
+            users::find_by_id(conn, &user.id)
+                .unwrap()
+                .unwrap()
+                .membership_epoch
+        };
+
+        groups::add_member(
+            &conn,
+            &child.id,
+            &user.id,
+            GroupMembershipType::User,
+        )
+        .unwrap();
+        let before = authenticate(
+            &conn,
+            &users::find_by_id(&conn, &user.id).unwrap().unwrap(),
+        )
+        .unwrap();
+        assert!(before.authorities().contains("epoch.child"));
+        assert!(!before.authorities().contains("epoch.parent"));
+        assert!(!before.authorities().contains("epoch_child"));
+
+        groups::add_member(
+            &conn,
+            &parent.id,
+            &child.id,
+            GroupMembershipType::Group,
+        )
+        .unwrap();
+        assert!(epoch(&conn) > before.epoch());
+
+        let user = users::find_by_id(&conn, &user.id).unwrap().unwrap();
+        let after = authenticate(&conn, &user).unwrap();
+        assert!(after.authorities().contains("epoch.parent"));
+
+        groups::del_member(&conn, &parent.id, &child.id).unwrap();
+        assert!(epoch(&conn) > after.epoch());
+
+        // Grants reach the members of nested groups too.
+        let before = epoch(&conn);
+        let wildcard = permissions::get_or_create(&conn, "epoch.*").unwrap();
+        permissions::grant(&conn, &child.id, &wildcard.id).unwrap();
+        assert!(epoch(&conn) > before);
+        let user = users::find_by_id(&conn, &user.id).unwrap().unwrap();
+        let granted = authenticate(&conn, &user).unwrap();
+        assert!(granted.has_authority("epoch.anything"));
+
+        permissions::del_by_id(&conn, &wildcard.id).unwrap();
+        let user = users::find_by_id(&conn, &user.id).unwrap().unwrap();
+        assert!(user.membership_epoch > granted.epoch());
+        assert!(!authenticate(&conn, &user)
+            .unwrap()
+            .has_authority("epoch.anything"));
+    }
+}
@@ -1,16 +1,24 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::iter;
 use std::rc::Rc;
+use std::sync::Arc;
 
 use actix_service::{Service, Transform};
 use actix_web::cookie::{Cookie, CookieJar, Key, SameSite};
 use actix_web::dev::{ServiceRequest, ServiceResponse};
-use actix_web::http::header::{self, HeaderValue};
-use actix_web::HttpMessage;
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::{web, HttpMessage, HttpRequest};
 use futures::future::{self, Either, FutureResult};
 use futures::{Future, IntoFuture, Poll};
 use time::Duration;
 
-use super::{Authentication, AuthenticationManager};
+use super::api_keys::{self, ApiKeyVerifier};
+use super::impersonation::AuditLog;
+use super::membership::AuthorityResolver;
+use super::session::SessionManager;
+use super::token::TokenSigner;
+use super::{AccessRules, Authentication, AuthenticationManager};
+use crate::db::audit::NewAuditEntry;
 use crate::error::{Error, ErrorKind, Result, ResultExt};
 
 /// Authentication storage backend definition
@@ -25,6 +33,17 @@ pub trait AuthenticationBackend: Sized + 'static {
         authentication: Option<Authentication>,
         res: &mut ServiceResponse<B>,
     ) -> Self::Future;
+
+    /// Chains another backend, tried when this one finds no authentication.
+    fn or<T: AuthenticationBackend>(
+        self,
+        other: T,
+    ) -> ChainedAuthenticationBackend<Self, T> {
+        ChainedAuthenticationBackend {
+            first: self,
+            second: other,
+        }
+    }
 }
 
 pub struct AuthenticationService<T> {
@@ -118,8 +137,196 @@ where
     }
 }
 
+/// Rejects requests whose authentication does not satisfy the access rules.
+pub struct AuthorizationService {
+    rules: Rc<AccessRules>,
+}
+
+impl AuthorizationService {
+    pub fn new(rules: AccessRules) -> Self {
+        AuthorizationService {
+            rules: Rc::new(rules),
+        }
+    }
+}
+
+impl<S, B> Transform<S> for AuthorizationService
+where
+    B: 'static,
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>>
+        + 'static,
+    S::Future: 'static,
+    S::Error: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = S::Error;
+    type InitError = ();
+    type Transform = AuthorizationMiddleware<S>;
+    type Future = FutureResult<Self::Transform, Self::InitError>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        future::ok(AuthorizationMiddleware {
+            rules: self.rules.clone(),
+            service,
+        })
+    }
+}
+
+pub struct AuthorizationMiddleware<S> {
+    service: S,
+    rules: Rc<AccessRules>,
+}
+
+impl<S, B> Service for AuthorizationMiddleware<S>
+where
+    B: 'static,
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>>
+        + 'static,
+    S::Future: 'static,
+    S::Error: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = S::Error;
+    type Future = Either<S::Future, FutureResult<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.service.poll_ready()
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let authentication = AuthenticationManager::get_authentication(&req);
+        let access = self.rules.access(req.method(), req.path());
+
+        match access.check(authentication.as_ref()) {
+            Ok(_) => Either::A(self.service.call(req)),
+            Err(err) => Either::B(future::ok(req.error_response(err))),
+        }
+    }
+}
+
+/// Header naming the administrator impersonating the user of a response.
+pub const IMPERSONATED_BY: &str = "x-impersonated-by";
+
+/// Marks responses made while impersonating and records their requests in
+/// the audit log, including the ones starting and stopping impersonation.
+///
+/// Has to run inside the `AuthenticationService` to see the authentication.
+pub struct ImpersonationService {
+    log: Arc<AuditLog>,
+}
+
+impl ImpersonationService {
+    pub fn new<L: AuditLog>(log: L) -> Self {
+        ImpersonationService { log: Arc::new(log) }
+    }
+}
+
+impl<S, B> Transform<S> for ImpersonationService
+where
+    B: 'static,
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>>
+        + 'static,
+    S::Future: 'static,
+    S::Error: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = S::Error;
+    type InitError = ();
+    type Transform = ImpersonationMiddleware<S>;
+    type Future = FutureResult<Self::Transform, Self::InitError>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        future::ok(ImpersonationMiddleware {
+            log: self.log.clone(),
+            service,
+        })
+    }
+}
+
+pub struct ImpersonationMiddleware<S> {
+    service: S,
+    log: Arc<AuditLog>,
+}
+
+impl<S, B> Service for ImpersonationMiddleware<S>
+where
+    B: 'static,
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>>
+        + 'static,
+    S::Future: 'static,
+    S::Error: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = S::Error;
+    type Future = Box<Future<Item = Self::Response, Error = Self::Error>>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.service.poll_ready()
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let before = AuthenticationManager::get_authentication(&req)
+            .filter(|a| a.impersonator().is_some());
+        let log = self.log.clone();
+
+        Box::new(self.service.call(req).and_then(move |mut res| {
+            let after = AuthenticationManager::peek_authentication(&res)
+                .filter(|a| a.impersonator().is_some());
+            if let Some(ref a) = after {
+                let value = a
+                    .impersonator()
+                    .and_then(|i| HeaderValue::from_str(i).ok());
+                if let Some(value) = value {
+                    res.headers_mut().insert(
+                        HeaderName::from_static(IMPERSONATED_BY),
+                        value,
+                    );
+                }
+            }
+
+            let a = match before.or(after) {
+                Some(a) => a,
+                None => return Either::A(future::ok(res)),
+            };
+            let entry = NewAuditEntry {
+                actor: a.impersonator().unwrap_or_default().to_string(),
+                identity: a.identity().to_string(),
+                method: res.request().method().to_string(),
+                path: res.request().path().to_string(),
+                status: i32::from(res.status().as_u16()),
+            };
+
+            Either::B(web::block(move || log.record(entry)).then(move |r| {
+                if let Err(e) = r {
+                    error!("Failed to record impersonated request: {}", e);
+                }
+                Ok(res)
+            }))
+        }))
+    }
+}
+
+/// Marks a request whose cookie was sealed with a retired key.
+struct StaleCookie;
+
+/// Marks a request whose authentication was changed by a refresh, so it gets
+/// stored back.
+#[derive(Clone, Default)]
+struct Refreshed(Rc<Cell<bool>>);
+
+fn is_refreshed(req: &HttpRequest) -> bool {
+    req.extensions()
+        .get::<Refreshed>()
+        .is_some_and(|r| r.0.get())
+}
+
 struct CookieAuthenticationInner {
     key: Key,
+    previous_keys: Vec<Key>,
     name: String,
     path: String,
     domain: Option<String>,
@@ -132,6 +339,7 @@ impl CookieAuthenticationInner {
     fn new(key: &[u8]) -> CookieAuthenticationInner {
         CookieAuthenticationInner {
             key: Key::from_master(key),
+            previous_keys: Vec::new(),
             name: "hamster-auth".to_owned(),
             path: "/".to_owned(),
             domain: None,
@@ -141,18 +349,15 @@ impl CookieAuthenticationInner {
         }
     }
 
+    /// Seals the value into the cookie, or removes the cookie on `None`.
     fn set_cookie<B>(
         &self,
         resp: &mut ServiceResponse<B>,
-        a: Option<&Authentication>,
+        value: Option<String>,
     ) -> Result<()> {
-        let some = a.is_some();
-        let value = match a {
-            Some(authentication) => serde_json::to_string(authentication)
-                .context(ErrorKind::SerializeJsonError)?,
-            None => String::new(),
-        };
-        let mut cookie = Cookie::new(self.name.clone(), value);
+        let some = value.is_some();
+        let mut cookie =
+            Cookie::new(self.name.clone(), value.unwrap_or_default());
         cookie.set_path(self.path.clone());
         cookie.set_secure(self.secure);
         cookie.set_http_only(true);
@@ -187,23 +392,37 @@ impl CookieAuthenticationInner {
     }
 
     fn load(&self, req: &ServiceRequest) -> Result<Option<Authentication>> {
-        if let Ok(cookies) = req.cookies() {
-            for cookie in cookies.iter() {
-                if cookie.name() == self.name {
-                    let mut jar = CookieJar::new();
-                    jar.add_original(cookie.clone());
-
-                    let cookie_opt = jar.private(&self.key).get(&self.name);
-                    if let Some(cookie) = cookie_opt {
-                        let authentication =
-                            serde_json::from_str(&cookie.value())
-                                .context(ErrorKind::DeserializeJsonError)?;
-                        return Ok(Some(authentication));
-                    }
-                }
-            }
+        let (authentication, stale) = match self.open(req) {
+            Some((value, stale)) => (
+                serde_json::from_str(&value)
+                    .context(ErrorKind::DeserializeJsonError)?,
+                stale,
+            ),
+            None => return Ok(None),
+        };
+
+        if stale {
+            req.extensions_mut().insert(StaleCookie);
         }
-        Ok(None)
+        Ok(Some(authentication))
+    }
+
+    /// Decrypts the cookie value, telling whether a retired key sealed it.
+    fn open<R: HttpMessage>(&self, req: &R) -> Option<(String, bool)> {
+        let cookies = req.cookies().ok()?;
+        let cookie = cookies.iter().find(|c| c.name() == self.name)?;
+
+        let mut jar = CookieJar::new();
+        jar.add_original(cookie.clone());
+
+        iter::once(&self.key)
+            .chain(&self.previous_keys)
+            .enumerate()
+            .filter_map(|(i, key)| {
+                let cookie = jar.private(key).get(&self.name)?;
+                Some((cookie.value().to_string(), i > 0))
+            })
+            .next()
     }
 }
 
@@ -216,6 +435,17 @@ impl CookieAuthenticationBackend {
         )))
     }
 
+    /// Retired keys, cookies sealed with them are still accepted and
+    /// re-issued with the current key.
+    pub fn previous_keys(
+        mut self,
+        keys: &[Vec<u8>],
+    ) -> CookieAuthenticationBackend {
+        Rc::get_mut(&mut self.0).unwrap().previous_keys =
+            keys.iter().map(|key| Key::from_master(key)).collect();
+        self
+    }
+
     pub fn path<S: Into<String>>(
         mut self,
         value: S,
@@ -276,19 +506,353 @@ impl AuthenticationBackend for CookieAuthenticationBackend {
         authentication: Option<Authentication>,
         res: &mut ServiceResponse<B>,
     ) -> Self::Future {
-        if changed {
-            let _ = self.0.set_cookie(res, authentication.as_ref());
+        let stale = res.request().extensions().get::<StaleCookie>().is_some();
+        let refreshed = is_refreshed(res.request());
+        if changed || ((stale || refreshed) && authentication.is_some()) {
+            let value = match authentication {
+                Some(ref a) => Some(
+                    serde_json::to_string(a)
+                        .context(ErrorKind::SerializeJsonError)?,
+                ),
+                None => None,
+            };
+            let _ = self.0.set_cookie(res, value);
         }
         Ok(authentication)
     }
 }
 
+/// Keeps authentications in server-side sessions, the cookie only carries
+/// the session token.
+///
+/// The cookie is configured through a `CookieAuthenticationBackend`, logging
+/// in again replaces the previous session and logging out revokes it.
+pub struct SessionAuthenticationBackend {
+    cookie: Rc<CookieAuthenticationInner>,
+    sessions: SessionManager,
+}
+
+impl SessionAuthenticationBackend {
+    pub fn new(
+        cookie: CookieAuthenticationBackend,
+        sessions: SessionManager,
+    ) -> SessionAuthenticationBackend {
+        SessionAuthenticationBackend {
+            cookie: cookie.0,
+            sessions,
+        }
+    }
+}
+
+impl AuthenticationBackend for SessionAuthenticationBackend {
+    type Future = Box<Future<Item = Option<Authentication>, Error = Error>>;
+
+    fn load(&self, req: &mut ServiceRequest) -> Self::Future {
+        let token = match self.cookie.open(req) {
+            Some((token, stale)) => {
+                if stale {
+                    req.extensions_mut().insert(StaleCookie);
+                }
+                token
+            }
+            None => return Box::new(future::ok(None)),
+        };
+        let sessions = self.sessions.clone();
+
+        Box::new(web::block(move || sessions.load(&token)).from_err())
+    }
+
+    fn store<B>(
+        &self,
+        changed: bool,
+        authentication: Option<Authentication>,
+        res: &mut ServiceResponse<B>,
+    ) -> Self::Future {
+        let current = self.cookie.open(res.request()).map(|(token, _)| token);
+
+        if !changed {
+            let stale =
+                res.request().extensions().get::<StaleCookie>().is_some();
+            if stale && authentication.is_some() {
+                let _ = self.cookie.set_cookie(res, current.clone());
+            }
+            return match (current, authentication) {
+                (Some(token), Some(a)) if is_refreshed(res.request()) => {
+                    let sessions = self.sessions.clone();
+                    Box::new(
+                        web::block(move || -> Result<_> {
+                            sessions.update(&token, &a)?;
+                            Ok(Some(a))
+                        })
+                        .from_err(),
+                    )
+                }
+                (_, authentication) => Box::new(future::ok(authentication)),
+            };
+        }
+
+        let token =
+            authentication.as_ref().map(|_| SessionManager::new_token());
+        if let Err(e) = self.cookie.set_cookie(res, token.clone()) {
+            return Box::new(future::err(e));
+        }
+        let user_agent = res
+            .request()
+            .headers()
+            .get(header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let sessions = self.sessions.clone();
+
+        Box::new(
+            web::block(move || -> Result<_> {
+                if let Some(current) = current {
+                    sessions.revoke_token(&current)?;
+                }
+                if let (Some(token), Some(a)) = (token, &authentication) {
+                    sessions.create(&token, a, user_agent)?;
+                }
+                Ok(authentication)
+            })
+            .from_err(),
+        )
+    }
+}
+
+/// Authenticates requests carrying an `Authorization: Bearer` token.
+///
+/// Tokens are stateless, so storing an authentication is a no-op, new tokens
+/// are handed out by the token endpoint.
+pub struct BearerAuthenticationBackend {
+    signer: TokenSigner,
+    ignored_prefixes: Vec<String>,
+}
+
+impl BearerAuthenticationBackend {
+    pub fn new(signer: TokenSigner) -> BearerAuthenticationBackend {
+        BearerAuthenticationBackend {
+            signer,
+            ignored_prefixes: Vec::new(),
+        }
+    }
+
+    /// Leaves tokens starting with `prefix` to other backends.
+    pub fn ignore_prefix<S: Into<String>>(
+        mut self,
+        prefix: S,
+    ) -> BearerAuthenticationBackend {
+        self.ignored_prefixes.push(prefix.into());
+        self
+    }
+}
+
+impl AuthenticationBackend for BearerAuthenticationBackend {
+    type Future = Result<Option<Authentication>>;
+
+    fn load(&self, req: &mut ServiceRequest) -> Self::Future {
+        let value = match req.headers().get(header::AUTHORIZATION) {
+            Some(value) => value.to_str().context(ErrorKind::Unauthorized)?,
+            None => return Ok(None),
+        };
+        let mut parts = value.splitn(2, ' ');
+        match (parts.next(), parts.next()) {
+            (Some(scheme), Some(token))
+                if scheme.eq_ignore_ascii_case("bearer") =>
+            {
+                let token = token.trim();
+                if self.ignored_prefixes.iter().any(|p| token.starts_with(p)) {
+                    return Ok(None);
+                }
+                Ok(Some(self.signer.verify(token)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn store<B>(
+        &self,
+        _changed: bool,
+        authentication: Option<Authentication>,
+        _res: &mut ServiceResponse<B>,
+    ) -> Self::Future {
+        Ok(authentication)
+    }
+}
+
+/// Authenticates requests carrying a personal API key as bearer token.
+///
+/// Authorities are resolved on every request, so keys need no refreshing.
+/// Other bearer tokens are left to the `BearerAuthenticationBackend`.
+pub struct ApiKeyAuthenticationBackend {
+    verifier: Arc<ApiKeyVerifier>,
+}
+
+impl ApiKeyAuthenticationBackend {
+    pub fn new<V: ApiKeyVerifier>(verifier: V) -> ApiKeyAuthenticationBackend {
+        ApiKeyAuthenticationBackend {
+            verifier: Arc::new(verifier),
+        }
+    }
+}
+
+impl AuthenticationBackend for ApiKeyAuthenticationBackend {
+    type Future = Box<Future<Item = Option<Authentication>, Error = Error>>;
+
+    fn load(&self, req: &mut ServiceRequest) -> Self::Future {
+        let key = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| {
+                let mut parts = value.splitn(2, ' ');
+                match (parts.next(), parts.next()) {
+                    (Some(scheme), Some(token))
+                        if scheme.eq_ignore_ascii_case("bearer")
+                            && api_keys::is_api_key(token.trim()) =>
+                    {
+                        Some(token.trim().to_string())
+                    }
+                    _ => None,
+                }
+            });
+        let key = match key {
+            Some(key) => key,
+            None => return Box::new(future::ok(None)),
+        };
+        let verifier = self.verifier.clone();
+
+        Box::new(web::block(move || verifier.verify(&key).map(Some)).from_err())
+    }
+
+    fn store<B>(
+        &self,
+        _changed: bool,
+        authentication: Option<Authentication>,
+        _res: &mut ServiceResponse<B>,
+    ) -> Self::Future {
+        Box::new(future::ok(authentication))
+    }
+}
+
+/// Authenticates a request with whichever backend succeeds first.
+///
+/// Both backends are consulted, an error of one is only reported if the other
+/// one finds no authentication either.
+pub struct ChainedAuthenticationBackend<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> AuthenticationBackend for ChainedAuthenticationBackend<A, B>
+where
+    A: AuthenticationBackend,
+    B: AuthenticationBackend,
+    <A::Future as IntoFuture>::Future: 'static,
+    <B::Future as IntoFuture>::Future: 'static,
+{
+    type Future = Box<Future<Item = Option<Authentication>, Error = Error>>;
+
+    fn load(&self, req: &mut ServiceRequest) -> Self::Future {
+        let first = self.first.load(req).into_future().then(Ok::<_, Error>);
+        let second = self.second.load(req).into_future().then(Ok);
+
+        Box::new(first.join(second).and_then(|(first, second)| {
+            match (first, second) {
+                (Ok(Some(a)), _) | (_, Ok(Some(a))) => Ok(Some(a)),
+                (Err(e), _) | (_, Err(e)) => Err(e),
+                _ => Ok(None),
+            }
+        }))
+    }
+
+    fn store<R>(
+        &self,
+        changed: bool,
+        authentication: Option<Authentication>,
+        res: &mut ServiceResponse<R>,
+    ) -> Self::Future {
+        let first = self
+            .first
+            .store(changed, authentication.clone(), res)
+            .into_future();
+        let second = self.second.store(changed, authentication, res);
+
+        Box::new(first.join(second).map(|(a, _)| a))
+    }
+}
+
+/// Brings the authorities of loaded authentications up to date with the
+/// current group membership, so handlers never see revoked authorities.
+/// Impersonations end once the impersonator no longer holds every authority
+/// of the user.
+///
+/// Refreshed authentications are stored back into the session or cookie of
+/// the inner backend, bearer tokens stay stateless and are refreshed on every
+/// request until they expire.
+pub struct RefreshingAuthenticationBackend<T> {
+    inner: T,
+    resolver: Arc<AuthorityResolver>,
+}
+
+impl<T: AuthenticationBackend> RefreshingAuthenticationBackend<T> {
+    pub fn new<R: AuthorityResolver>(
+        inner: T,
+        resolver: R,
+    ) -> RefreshingAuthenticationBackend<T> {
+        RefreshingAuthenticationBackend {
+            inner,
+            resolver: Arc::new(resolver),
+        }
+    }
+}
+
+impl<T> AuthenticationBackend for RefreshingAuthenticationBackend<T>
+where
+    T: AuthenticationBackend,
+    <T::Future as IntoFuture>::Future: 'static,
+{
+    type Future = Box<Future<Item = Option<Authentication>, Error = Error>>;
+
+    fn load(&self, req: &mut ServiceRequest) -> Self::Future {
+        let resolver = self.resolver.clone();
+        let refreshed = Refreshed::default();
+        req.extensions_mut().insert(refreshed.clone());
+
+        Box::new(self.inner.load(req).into_future().and_then(
+            move |a| match a {
+                Some(a) => {
+                    let before = a.clone();
+                    Either::A(
+                        web::block(move || resolver.refresh(a)).from_err().map(
+                            move |a| {
+                                refreshed.0.set(a.as_ref() != Some(&before));
+                                a
+                            },
+                        ),
+                    )
+                }
+                None => Either::B(future::ok(None)),
+            },
+        ))
+    }
+
+    fn store<B>(
+        &self,
+        changed: bool,
+        authentication: Option<Authentication>,
+        res: &mut ServiceResponse<B>,
+    ) -> Self::Future {
+        Box::new(self.inner.store(changed, authentication, res).into_future())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use actix_web::http::StatusCode;
+    use actix_web::http::{Method, StatusCode};
     use actix_web::test::{self, TestRequest};
-    use actix_web::{web, App, HttpResponse};
+    use actix_web::{App, HttpResponse};
+    use std::sync::Mutex;
 
     #[test]
     fn test_cookie_authentication() {
@@ -327,4 +891,511 @@ mod tests {
         );
         assert_eq!(resp.status(), StatusCode::OK);
     }
+
+    #[test]
+    fn test_authorization() {
+        let rules = AccessRules::new("")
+            .permit_all(Method::POST, "/login")
+            .has_authority(Method::GET, "/groups", "groups.get");
+        let mut app = test::init_service(
+            App::new()
+                .wrap(AuthorizationService::new(rules))
+                .wrap(AuthenticationService::new(
+                    CookieAuthenticationBackend::new(&[0; 32]).secure(false),
+                ))
+                .service(web::resource("/login").to(
+                    |am: AuthenticationManager| {
+                        am.remember(Authentication::new(
+                            "bob",
+                            vec!["user".to_string()],
+                        ));
+                        HttpResponse::Ok()
+                    },
+                ))
+                .service(web::resource("/groups").to(HttpResponse::Ok))
+                .service(web::resource("/users").to(HttpResponse::Ok)),
+        );
+
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::with_uri("/groups").to_request(),
+        );
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::post().uri("/login").to_request(),
+        );
+        assert_eq!(resp.status(), StatusCode::OK);
+        let cookie = resp
+            .response()
+            .cookies()
+            .find(|c| c.name() == "hamster-auth")
+            .unwrap()
+            .into_owned();
+
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::with_uri("/groups")
+                .cookie(cookie.clone())
+                .to_request(),
+        );
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::with_uri("/users").cookie(cookie).to_request(),
+        );
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_chained_authentication() {
+        let signer = TokenSigner::new(&[0; 32]);
+        let token = signer
+            .issue(&Authentication::new("bob", vec!["user".to_string()]))
+            .unwrap()
+            .access_token;
+        let mut app = test::init_service(
+            App::new()
+                .wrap(AuthenticationService::new(
+                    CookieAuthenticationBackend::new(&[0; 32])
+                        .or(BearerAuthenticationBackend::new(signer)),
+                ))
+                .service(web::resource("/").to(|am: AuthenticationManager| {
+                    let a = am
+                        .authentication()
+                        .unwrap_or_else(Authentication::anonymous);
+
+                    a.identity().to_string()
+                })),
+        );
+
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::with_uri("/")
+                .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                .to_request(),
+        );
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(test::read_body(resp), "bob");
+
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::with_uri("/").to_request(),
+        );
+        assert_eq!(test::read_body(resp), "anonymous");
+
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::with_uri("/")
+                .header(header::AUTHORIZATION, "Bearer invalid")
+                .to_request(),
+        );
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_api_key_authentication() {
+        struct Verifier;
+
+        impl ApiKeyVerifier for Verifier {
+            fn verify(&self, key: &str) -> Result<Authentication> {
+                match key {
+                    "hk_valid.secret" => Ok(Authentication::new("bob", vec![])),
+                    _ => Err(ErrorKind::Unauthorized)?,
+                }
+            }
+        }
+
+        let signer = TokenSigner::new(&[0; 32]);
+        let token = signer
+            .issue(&Authentication::new("alice", vec![]))
+            .unwrap()
+            .access_token;
+        let mut app = test::init_service(
+            App::new()
+                .wrap(AuthenticationService::new(
+                    BearerAuthenticationBackend::new(signer)
+                        .or(ApiKeyAuthenticationBackend::new(Verifier)),
+                ))
+                .service(web::resource("/").to(|am: AuthenticationManager| {
+                    let a = am
+                        .authentication()
+                        .unwrap_or_else(Authentication::anonymous);
+
+                    a.identity().to_string()
+                })),
+        );
+        let mut call = |token: &str| {
+            test::call_service(
+                &mut app,
+                TestRequest::with_uri("/")
+                    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                    .to_request(),
+            )
+        };
+
+        assert_eq!(test::read_body(call("hk_valid.secret")), "bob");
+        assert_eq!(test::read_body(call(&token)), "alice");
+        let resp = call("hk_valid.wrong");
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_refreshing_authentication() {
+        struct Resolver;
+
+        impl AuthorityResolver for Resolver {
+            fn refresh(
+                &self,
+                a: Authentication,
+            ) -> Result<Option<Authentication>> {
+                Ok(match a.identity() {
+                    "gone" => None,
+                    _ if a.epoch() == 1 => Some(a),
+                    identity => Some(
+                        Authentication::new(
+                            identity,
+                            vec!["admin".to_string()],
+                        )
+                        .with_epoch(1),
+                    ),
+                })
+            }
+        }
+
+        let signer = TokenSigner::new(&[0; 32]);
+        let mut app = test::init_service(
+            App::new()
+                .wrap(AuthenticationService::new(
+                    RefreshingAuthenticationBackend::new(
+                        BearerAuthenticationBackend::new(signer.clone()),
+                        Resolver,
+                    ),
+                ))
+                .service(web::resource("/").to(|am: AuthenticationManager| {
+                    let a = am
+                        .authentication()
+                        .unwrap_or_else(Authentication::anonymous);
+                    let mut authorities =
+                        a.authorities().iter().cloned().collect::<Vec<_>>();
+                    authorities.sort();
+
+                    format!("{}:{}", a.identity(), authorities.join(","))
+                })),
+        );
+        let mut call = |a: Authentication| {
+            let token = signer.issue(&a).unwrap().access_token;
+            let resp = test::call_service(
+                &mut app,
+                TestRequest::with_uri("/")
+                    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                    .to_request(),
+            );
+            test::read_body(resp)
+        };
+
+        let stale = Authentication::new("bob", vec!["user".to_string()]);
+        assert_eq!(call(stale.clone()), "bob:admin");
+        assert_eq!(call(stale.with_epoch(1)), "bob:user");
+        assert_eq!(
+            call(Authentication::new("gone", vec![])),
+            "anonymous:anonymous"
+        );
+    }
+
+    #[test]
+    fn test_impersonation() {
+        #[derive(Clone, Default)]
+        struct Log(Arc<Mutex<Vec<NewAuditEntry>>>);
+
+        impl AuditLog for Log {
+            fn record(&self, entry: NewAuditEntry) -> Result<()> {
+                self.0.lock().unwrap().push(entry);
+                Ok(())
+            }
+        }
+
+        let log = Log::default();
+        let mut app = test::init_service(
+            App::new()
+                .wrap(ImpersonationService::new(log.clone()))
+                .wrap(AuthenticationService::new(
+                    CookieAuthenticationBackend::new(&[0; 32]).secure(false),
+                ))
+                .service(web::resource("/").to(HttpResponse::Ok))
+                .service(web::resource("/impersonate").to(
+                    |am: AuthenticationManager| {
+                        am.remember(
+                            Authentication::new("bob", vec![])
+                                .with_impersonator(Some("admin".to_string())),
+                        );
+                        HttpResponse::Ok()
+                    },
+                ))
+                .service(web::resource("/stop").to(
+                    |am: AuthenticationManager| {
+                        am.remember(Authentication::new("admin", vec![]));
+                        HttpResponse::Ok()
+                    },
+                )),
+        );
+        let header = |resp: &ServiceResponse| {
+            resp.headers()
+                .get(IMPERSONATED_BY)
+                .map(|v| v.to_str().unwrap().to_string())
+        };
+
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::with_uri("/").to_request(),
+        );
+        assert_eq!(None, header(&resp));
+        assert!(log.0.lock().unwrap().is_empty());
+
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::post().uri("/impersonate").to_request(),
+        );
+        assert_eq!(Some("admin".to_string()), header(&resp));
+        let cookie = resp
+            .response()
+            .cookies()
+            .find(|c| c.name() == "hamster-auth")
+            .unwrap()
+            .into_owned();
+
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::with_uri("/")
+                .cookie(cookie.clone())
+                .to_request(),
+        );
+        assert_eq!(Some("admin".to_string()), header(&resp));
+
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::post().uri("/stop").cookie(cookie).to_request(),
+        );
+        assert_eq!(None, header(&resp));
+        let entries = log.0.lock().unwrap();
+        assert_eq!(3, entries.len());
+        assert_eq!("admin", entries[2].actor);
+        assert_eq!("bob", entries[2].identity);
+        assert_eq!("POST", entries[2].method);
+        assert_eq!("/stop", entries[2].path);
+        assert_eq!(200, entries[2].status);
+    }
+
+    #[test]
+    fn test_cookie_key_rotation() {
+        let login = |key: &[u8], previous_keys: &[Vec<u8>]| {
+            test::init_service(
+                App::new()
+                    .wrap(AuthenticationService::new(
+                        CookieAuthenticationBackend::new(key)
+                            .previous_keys(previous_keys),
+                    ))
+                    .service(web::resource("/").to(
+                        |am: AuthenticationManager| {
+                            let a = am
+                                .authentication()
+                                .unwrap_or_else(Authentication::anonymous);
+
+                            a.identity().to_string()
+                        },
+                    ))
+                    .service(web::resource("/login").to(
+                        |am: AuthenticationManager| {
+                            am.remember(Authentication::new("bob", vec![]));
+                            HttpResponse::Ok()
+                        },
+                    )),
+            )
+        };
+
+        let mut old_app = login(&[1; 32], &[]);
+        let resp = test::call_service(
+            &mut old_app,
+            TestRequest::with_uri("/login").to_request(),
+        );
+        let old_cookie = resp
+            .response()
+            .cookies()
+            .find(|c| c.name() == "hamster-auth")
+            .unwrap()
+            .into_owned();
+
+        let mut app = login(&[2; 32], &[vec![1; 32]]);
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::with_uri("/")
+                .cookie(old_cookie.clone())
+                .to_request(),
+        );
+        let new_cookie = resp
+            .response()
+            .cookies()
+            .find(|c| c.name() == "hamster-auth")
+            .unwrap()
+            .into_owned();
+        assert_eq!(test::read_body(resp), "bob");
+
+        let mut new_app = login(&[2; 32], &[]);
+        let resp = test::call_service(
+            &mut new_app,
+            TestRequest::with_uri("/").cookie(new_cookie).to_request(),
+        );
+        assert_eq!(test::read_body(resp), "bob");
+
+        let resp = test::call_service(
+            &mut new_app,
+            TestRequest::with_uri("/").cookie(old_cookie).to_request(),
+        );
+        assert_eq!(test::read_body(resp), "anonymous");
+    }
+
+    #[test]
+    fn test_session_authentication() {
+        use crate::auth::session::MemorySessionStore;
+
+        let sessions = SessionManager::new(MemorySessionStore::default());
+        let mut app = test::init_service(
+            App::new()
+                .wrap(AuthenticationService::new(
+                    SessionAuthenticationBackend::new(
+                        CookieAuthenticationBackend::new(&[0; 32]),
+                        sessions.clone(),
+                    ),
+                ))
+                .service(web::resource("/").to(|am: AuthenticationManager| {
+                    let a = am
+                        .authentication()
+                        .unwrap_or_else(Authentication::anonymous);
+
+                    a.identity().to_string()
+                }))
+                .service(web::resource("/login").to(
+                    |am: AuthenticationManager| {
+                        am.remember(Authentication::new("bob", vec![]));
+                        HttpResponse::Ok()
+                    },
+                ))
+                .service(web::resource("/logout").to(
+                    |am: AuthenticationManager| {
+                        am.forget();
+                        HttpResponse::Ok()
+                    },
+                )),
+        );
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::with_uri("/login").to_request(),
+        );
+        let cookie = auth_cookie(&resp);
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::with_uri("/")
+                .cookie(cookie.clone())
+                .to_request(),
+        );
+        assert_eq!(test::read_body(resp), "bob");
+        assert_eq!(1, sessions.list("bob").unwrap().len());
+
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::with_uri("/logout")
+                .cookie(cookie.clone())
+                .to_request(),
+        );
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(sessions.list("bob").unwrap().is_empty());
+
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::with_uri("/").cookie(cookie).to_request(),
+        );
+        assert_eq!(test::read_body(resp), "anonymous");
+
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::with_uri("/login").to_request(),
+        );
+        let cookie = auth_cookie(&resp);
+        sessions.revoke_all("bob").unwrap();
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::with_uri("/").cookie(cookie).to_request(),
+        );
+        assert_eq!(test::read_body(resp), "anonymous");
+    }
+
+    #[test]
+    fn test_refreshed_session() {
+        use crate::auth::session::MemorySessionStore;
+
+        struct Resolver;
+
+        impl AuthorityResolver for Resolver {
+            fn refresh(
+                &self,
+                a: Authentication,
+            ) -> Result<Option<Authentication>> {
+                Ok(Some(
+                    Authentication::new(
+                        a.identity(),
+                        vec!["admin".to_string()],
+                    )
+                    .with_epoch(1),
+                ))
+            }
+        }
+
+        let sessions = SessionManager::new(MemorySessionStore::default());
+        let mut app = test::init_service(
+            App::new()
+                .wrap(AuthenticationService::new(
+                    RefreshingAuthenticationBackend::new(
+                        SessionAuthenticationBackend::new(
+                            CookieAuthenticationBackend::new(&[0; 32]),
+                            sessions.clone(),
+                        ),
+                        Resolver,
+                    ),
+                ))
+                .service(web::resource("/").to(HttpResponse::Ok))
+                .service(web::resource("/login").to(
+                    |am: AuthenticationManager| {
+                        am.remember(Authentication::new("bob", vec![]));
+                        HttpResponse::Ok()
+                    },
+                )),
+        );
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::with_uri("/login").to_request(),
+        );
+        let cookie = auth_cookie(&resp);
+        let session = &sessions.list("bob").unwrap()[0];
+        assert!(session.authorities.is_empty());
+        assert_eq!(0, session.membership_epoch);
+
+        let resp = test::call_service(
+            &mut app,
+            TestRequest::with_uri("/").cookie(cookie).to_request(),
+        );
+        assert_eq!(resp.status(), StatusCode::OK);
+        let session = &sessions.list("bob").unwrap()[0];
+        assert_eq!(vec!["admin".to_string()], session.authorities);
+        assert_eq!(1, session.membership_epoch);
+    }
+
+    fn auth_cookie<B>(resp: &ServiceResponse<B>) -> Cookie<'static> {
+        resp.response()
+            .cookies()
+            .find(|c| c.name() == "hamster-auth")
+            .unwrap()
+            .into_owned()
+    }
 }
@@ -1,4 +1,28 @@
+pub mod acl;
+pub mod api_keys;
 pub mod authentication;
+pub mod authorization;
+pub mod impersonation;
+pub mod keys;
+pub mod lockout;
+pub mod membership;
 pub mod middleware;
+pub mod password;
+pub mod policy;
+pub mod proxies;
+pub mod session;
+pub mod token;
+pub mod totp;
+pub mod two_factor;
+pub mod user_tokens;
 
 pub use self::authentication::{Authentication, AuthenticationManager};
+pub use self::authorization::AccessRules;
+pub use self::keys::SigningKeys;
+pub use self::lockout::LockoutPolicy;
+pub use self::password::PasswordPolicy;
+pub use self::policy::Policy;
+pub use self::proxies::TrustedProxies;
+pub use self::session::SessionManager;
+pub use self::token::TokenSigner;
+pub use self::user_tokens::UserTokens;
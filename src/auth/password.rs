@@ -0,0 +1,229 @@
+//! Password strength and hashing.
+//!
+//! The policy is read from the environment: `PASSWORD_MIN_LENGTH`,
+//! `PASSWORD_MIN_CHARACTER_CLASSES` out of lowercase and uppercase letters,
+//! digits and other characters, `PASSWORD_BANNED_FILE` with further banned
+//! passwords, one per line, and `PASSWORD_BCRYPT_COST`. Hashes below the
+//! configured cost are upgraded on the next successful login.
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::str::FromStr;
+
+use crate::error::{Error, ErrorKind, Result, ResultExt};
+use crate::utils;
+
+/// Passwords that are guessed first, compared case-insensitively.
+const COMMON_PASSWORDS: &[&str] = &[
+    "123456",
+    "12345678",
+    "123456789",
+    "1234567890",
+    "1q2w3e4r",
+    "1qaz2wsx",
+    "abc12345",
+    "abcd1234",
+    "admin123",
+    "baseball",
+    "dragon123",
+    "football",
+    "iloveyou",
+    "letmein1",
+    "passw0rd",
+    "password",
+    "password1",
+    "password123",
+    "princess",
+    "qwerty123",
+    "qwertyuiop",
+    "sunshine",
+    "superman",
+    "trustno1",
+    "welcome1",
+    "zaq12wsx",
+];
+
+/// Versions of bcrypt hashes that are upgraded on login, `$2a$` and `$2x$`
+/// are affected by bugs of some old implementations.
+const LEGACY_VERSIONS: &[&str] = &["2a", "2x"];
+
+/// Password requirements and the bcrypt cost of new hashes.
+///
+/// By default passwords need at least 8 characters out of 2 classes, and
+/// must neither be a common password nor the username.
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    min_length: usize,
+    min_classes: usize,
+    banned: HashSet<String>,
+    cost: u32,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: 8,
+            min_classes: 2,
+            banned: COMMON_PASSWORDS.iter().map(|p| p.to_string()).collect(),
+            cost: utils::DEFAULT_PASSWORD_COST,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    pub fn from_env() -> Result<PasswordPolicy> {
+        let mut policy = PasswordPolicy::default();
+
+        if let Some(min_length) = parse_var("PASSWORD_MIN_LENGTH")? {
+            policy.min_length = min_length;
+        }
+        if let Some(min_classes) = parse_var("PASSWORD_MIN_CHARACTER_CLASSES")?
+        {
+            policy.min_classes = min_classes;
+        }
+        if let Some(cost) = parse_var("PASSWORD_BCRYPT_COST")? {
+            if !(4..=31).contains(&cost) {
+                return Err(Error::from(ErrorKind::ConfigError)
+                    .with_detail("PASSWORD_BCRYPT_COST must be from 4 to 31"));
+            }
+            policy.cost = cost;
+        }
+        if let Ok(path) = env::var("PASSWORD_BANNED_FILE") {
+            let content =
+                fs::read_to_string(&path).context(ErrorKind::ConfigError)?;
+            policy.banned.extend(
+                content
+                    .lines()
+                    .map(|line| line.trim().to_lowercase())
+                    .filter(|line| !line.is_empty()),
+            );
+        }
+
+        Ok(policy)
+    }
+
+    /// Fails with a validation error of the `password` field if the password
+    /// of the user does not meet the policy.
+    pub fn validate(&self, password: &str, username: &str) -> Result<()> {
+        if password.is_empty() {
+            return Err(Error::validation("password", "must not be empty"));
+        }
+
+        if password.chars().count() < self.min_length {
+            return Err(Error::validation(
+                "password",
+                format!("must be at least {} characters", self.min_length),
+            ));
+        }
+
+        if character_classes(password) < self.min_classes {
+            return Err(Error::validation(
+                "password",
+                format!(
+                    "must contain {} of lowercase letters, uppercase \
+                     letters, digits and other characters",
+                    self.min_classes
+                ),
+            ));
+        }
+
+        let lowercase = password.to_lowercase();
+        if lowercase == username.to_lowercase() {
+            return Err(Error::validation(
+                "password",
+                "must not be the username",
+            ));
+        }
+        if self.banned.contains(&lowercase) {
+            return Err(Error::validation("password", "is too common"));
+        }
+
+        Ok(())
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String> {
+        utils::hash_password_with_cost(password, self.cost)
+    }
+
+    /// Returns `true` if the hash should be replaced by a new one, because it
+    /// is cheaper than the configured cost or of a legacy version.
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        if !utils::is_bcrypt_hash(hash) {
+            return true;
+        }
+
+        let parts = hash.split('$').collect::<Vec<&str>>();
+        let cost = parts[2].parse::<u32>().unwrap_or(0);
+        LEGACY_VERSIONS.contains(&parts[1]) || cost < self.cost
+    }
+}
+
+fn character_classes(password: &str) -> usize {
+    let mut classes = [false; 4];
+
+    for c in password.chars() {
+        let class = if c.is_lowercase() {
+            0
+        } else if c.is_uppercase() {
+            1
+        } else if c.is_numeric() {
+            2
+        } else {
+            3
+        };
+        classes[class] = true;
+    }
+
+    classes.iter().filter(|c| **c).count()
+}
+
+fn parse_var<T: FromStr>(name: &str) -> Result<Option<T>> {
+    match env::var(name) {
+        Ok(value) => value.trim().parse().map(Some).map_err(|_| {
+            Error::from(ErrorKind::ConfigError)
+                .with_detail(format!("{} must be a number", name))
+        }),
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let policy = PasswordPolicy::default();
+        let message = |password: &str| {
+            let err = policy.validate(password, "bob").unwrap_err();
+            assert_eq!(ErrorKind::Validation, err.kind());
+            assert_eq!("password", err.details()[0].field);
+            err.details()[0].message.clone()
+        };
+
+        assert_eq!("must not be empty", message(""));
+        assert_eq!("must be at least 8 characters", message("abc123"));
+        assert!(message("abcdefgh").starts_with("must contain 2 of"));
+        assert_eq!("is too common", message("Password1"));
+        assert!(policy.validate("correct horse", "bob").is_ok());
+
+        let err = policy.validate("Bob12345", "bob12345").unwrap_err();
+        assert_eq!("must not be the username", err.details()[0].message);
+    }
+
+    #[test]
+    fn test_needs_rehash() {
+        let policy = PasswordPolicy {
+            cost: 5,
+            ..PasswordPolicy::default()
+        };
+
+        let hashed = policy.hash("correct horse").unwrap();
+        assert!(!policy.needs_rehash(&hashed));
+        assert!(policy.needs_rehash(
+            &utils::hash_password_with_cost("secret", 4).unwrap()
+        ));
+        assert!(policy.needs_rehash(&format!("$2a{}", &hashed[3..])));
+        assert!(policy.needs_rehash("plaintext"));
+    }
+}
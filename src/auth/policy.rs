@@ -0,0 +1,815 @@
+//! Declarative authorization policy.
+//!
+//! A policy is a list of rules read from TOML. A rule applies to requests
+//! matching its methods and paths, and matches them if the caller holds one
+//! of its authorities and all of its conditions hold. A request is denied if
+//! a matching rule denies it, allowed if a matching rule allows it and denied
+//! if no rule matches.
+//!
+//! ```toml
+//! [[rule]]
+//! name = "own-api-keys"
+//! effect = "allow"
+//! methods = ["GET"]
+//! paths = ["/api/users/{user_id}/api-keys"]
+//! authenticated = true
+//! conditions = ["resource.user_id == identity"]
+//! ```
+//!
+//! Conditions compare two operands with `==` or `!=`, or check the caller
+//! holds an authority with `has_authority(operand)`. Operands are quoted
+//! literals, `true`, `false`, numbers or attributes: `identity`,
+//! `impersonating`, `narrowed`, set for API keys limited to some of their
+//! owner's authorities, `method`, `path`, `path.<param>` and
+//! `resource.<name>`.
+//! Conditions on attributes a request does not have never hold.
+//!
+//! Handlers check the policy once they know the resource. Rules depending
+//! on the database, like ACL entries and group managers, get what the
+//! handler looked up through `acl::Authorizer` as resource attributes.
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::fs;
+
+use actix_web::dev::{Path, Payload, ResourceDef};
+use actix_web::http::Method;
+use actix_web::{FromRequest, HttpRequest};
+
+use super::{Authentication, AuthenticationManager};
+use crate::error::{Error, ErrorKind, Result, ResultExt};
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Effect {
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Deserialize)]
+struct PolicyConfig {
+    #[serde(default, rename = "rule")]
+    rules: Vec<RuleConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RuleConfig {
+    name: String,
+    effect: Effect,
+    #[serde(default)]
+    methods: Vec<String>,
+    #[serde(default)]
+    paths: Vec<String>,
+    #[serde(default)]
+    authenticated: bool,
+    #[serde(default)]
+    authorities: Vec<String>,
+    #[serde(default)]
+    conditions: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Attribute(String),
+    Literal(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Condition {
+    Eq(Operand, Operand),
+    Ne(Operand, Operand),
+    HasAuthority(Operand),
+}
+
+#[derive(Clone)]
+struct Rule {
+    name: String,
+    effect: Effect,
+    methods: Vec<Method>,
+    /// Kept as patterns, resource definitions cannot be sent to the threads
+    /// handlers check the policy on.
+    paths: Vec<String>,
+    authenticated: bool,
+    authorities: Vec<String>,
+    conditions: Vec<(String, Condition)>,
+}
+
+/// What a request is checked against.
+#[derive(Debug, Clone)]
+pub struct Input {
+    method: Method,
+    path: String,
+    authentication: Option<Authentication>,
+    resource: HashMap<String, String>,
+}
+
+impl Input {
+    pub fn new<S: Into<String>>(method: Method, path: S) -> Self {
+        Input {
+            method,
+            path: path.into(),
+            authentication: None,
+            resource: HashMap::new(),
+        }
+    }
+
+    pub fn authentication(mut self, authentication: Authentication) -> Self {
+        self.authentication = Some(authentication);
+        self
+    }
+
+    /// Adds an attribute of the resource the request acts on.
+    pub fn resource<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.resource.insert(key.into(), value.into());
+        self
+    }
+}
+
+impl FromRequest for Input {
+    type Config = ();
+    type Error = ();
+    type Future = std::result::Result<Input, ()>;
+
+    #[inline]
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let input = Input::new(req.method().clone(), req.path());
+        let authentication = AuthenticationManager::extract(req)
+            .ok()
+            .and_then(|am| am.authentication());
+
+        Ok(match authentication {
+            Some(a) => input.authentication(a),
+            None => input,
+        })
+    }
+}
+
+/// How a single rule was evaluated.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// The method or path of the request is not covered by the rule.
+    NotApplicable,
+    Unauthenticated,
+    MissingAuthority,
+    ConditionFailed(String),
+    Matched(Effect),
+}
+
+/// Decision on a request and how every rule contributed to it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explanation {
+    pub allowed: bool,
+    /// Rule that made the decision, `None` if no rule matched.
+    pub rule: Option<String>,
+    pub outcomes: Vec<(String, Outcome)>,
+}
+
+impl Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let decision = if self.allowed { "allowed" } else { "denied" };
+        match self.rule {
+            Some(ref rule) => write!(f, "{} by rule `{}`", decision, rule)?,
+            None => write!(f, "{}, no rule matched", decision)?,
+        }
+
+        for (rule, outcome) in &self.outcomes {
+            match outcome {
+                Outcome::NotApplicable => continue,
+                Outcome::Unauthenticated => {
+                    write!(f, "; `{}`: not authenticated", rule)?
+                }
+                Outcome::MissingAuthority => {
+                    write!(f, "; `{}`: missing authority", rule)?
+                }
+                Outcome::ConditionFailed(condition) => {
+                    write!(f, "; `{}`: `{}` does not hold", rule, condition)?
+                }
+                Outcome::Matched(_) => write!(f, "; `{}`: matched", rule)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct Policy {
+    rules: Vec<Rule>,
+    explain: bool,
+}
+
+impl Policy {
+    pub fn from_toml(content: &str) -> Result<Self> {
+        let config = toml::from_str::<PolicyConfig>(content)
+            .context(ErrorKind::ConfigError)?;
+        let rules = config
+            .rules
+            .into_iter()
+            .map(Rule::parse)
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Policy {
+            rules,
+            explain: false,
+        })
+    }
+
+    pub fn from_file(path: &str) -> Result<Self> {
+        let content =
+            fs::read_to_string(path).context(ErrorKind::ConfigError)?;
+        Self::from_toml(&content)
+    }
+
+    /// Reports why a request was denied in the error returned to the caller.
+    pub fn explain(mut self, explain: bool) -> Self {
+        self.explain = explain;
+        self
+    }
+
+    /// Evaluates every rule against the request.
+    pub fn evaluate(&self, input: &Input) -> Explanation {
+        let outcomes = self
+            .rules
+            .iter()
+            .map(|rule| (rule.name.clone(), rule.evaluate(input)))
+            .collect::<Vec<_>>();
+        let matched = |effect| {
+            outcomes
+                .iter()
+                .find(|(_, outcome)| outcome == &Outcome::Matched(effect))
+                .map(|(rule, _)| rule.clone())
+        };
+
+        let (allowed, rule) = match matched(Effect::Deny) {
+            Some(rule) => (false, Some(rule)),
+            None => match matched(Effect::Allow) {
+                Some(rule) => (true, Some(rule)),
+                None => (false, None),
+            },
+        };
+
+        Explanation {
+            allowed,
+            rule,
+            outcomes,
+        }
+    }
+
+    pub fn check(&self, input: &Input) -> Result<()> {
+        let explanation = self.evaluate(input);
+        if explanation.allowed {
+            return Ok(());
+        }
+
+        debug!("{} {} {}", input.method, input.path, explanation);
+        let err = match input.authentication {
+            Some(_) => Error::from(ErrorKind::Forbidden),
+            None => Error::from(ErrorKind::Unauthorized),
+        };
+        if self.explain {
+            Err(err.with_detail(explanation.to_string()))
+        } else {
+            Err(err)
+        }
+    }
+}
+
+impl Rule {
+    fn parse(config: RuleConfig) -> Result<Self> {
+        let invalid = |detail: String| {
+            Error::from(ErrorKind::ConfigError)
+                .with_detail(format!("rule `{}`: {}", config.name, detail))
+        };
+
+        let methods = config
+            .methods
+            .iter()
+            .map(|m| {
+                Method::from_bytes(m.to_uppercase().as_bytes())
+                    .map_err(|_| invalid(format!("invalid method `{}`", m)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let conditions = config
+            .conditions
+            .iter()
+            .map(|c| match parse_condition(c) {
+                Some(condition) => Ok((c.clone(), condition)),
+                None => Err(invalid(format!("invalid condition `{}`", c))),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Rule {
+            methods,
+            conditions,
+            paths: config.paths,
+            name: config.name,
+            effect: config.effect,
+            authenticated: config.authenticated,
+            authorities: config.authorities,
+        })
+    }
+
+    fn evaluate(&self, input: &Input) -> Outcome {
+        if !self.methods.is_empty() && !self.methods.contains(&input.method) {
+            return Outcome::NotApplicable;
+        }
+
+        let mut path = Path::new(input.path.clone());
+        let path_matches = self.paths.is_empty()
+            || self
+                .paths
+                .iter()
+                .any(|p| ResourceDef::new(p).match_path(&mut path));
+        if !path_matches {
+            return Outcome::NotApplicable;
+        }
+
+        let a = match input.authentication {
+            Some(ref a) => Some(a),
+            None if self.authenticated || !self.authorities.is_empty() => {
+                return Outcome::Unauthenticated;
+            }
+            None => None,
+        };
+        if let Some(a) = a {
+            if !self.authorities.is_empty()
+                && !self.authorities.iter().any(|r| a.has_authority(r))
+            {
+                return Outcome::MissingAuthority;
+            }
+        }
+
+        let attributes = Attributes { input, path: &path };
+        for (source, condition) in &self.conditions {
+            if !attributes.holds(condition) {
+                return Outcome::ConditionFailed(source.clone());
+            }
+        }
+
+        Outcome::Matched(self.effect)
+    }
+}
+
+struct Attributes<'a> {
+    input: &'a Input,
+    path: &'a Path<String>,
+}
+
+impl<'a> Attributes<'a> {
+    fn holds(&self, condition: &Condition) -> bool {
+        match condition {
+            Condition::Eq(left, right) => {
+                match (self.value(left), self.value(right)) {
+                    (Some(left), Some(right)) => left == right,
+                    _ => false,
+                }
+            }
+            Condition::Ne(left, right) => {
+                match (self.value(left), self.value(right)) {
+                    (Some(left), Some(right)) => left != right,
+                    _ => false,
+                }
+            }
+            Condition::HasAuthority(operand) => {
+                match (self.input.authentication.as_ref(), self.value(operand))
+                {
+                    (Some(a), Some(authority)) => a.has_authority(authority),
+                    _ => false,
+                }
+            }
+        }
+    }
+
+    fn value<'b>(&'b self, operand: &'b Operand) -> Option<&'b str> {
+        let name = match operand {
+            Operand::Literal(value) => return Some(value),
+            Operand::Attribute(name) => name.as_str(),
+        };
+
+        match name {
+            "identity" => {
+                self.input.authentication.as_ref().map(|a| a.identity())
+            }
+            "impersonating" => self.input.authentication.as_ref().map(|a| {
+                if a.impersonator().is_some() {
+                    "true"
+                } else {
+                    "false"
+                }
+            }),
+            "narrowed" => self.input.authentication.as_ref().map(|a| {
+                if a.narrowed() {
+                    "true"
+                } else {
+                    "false"
+                }
+            }),
+            "method" => Some(self.input.method.as_str()),
+            "path" => Some(&self.input.path),
+            _ if name.starts_with("path.") => self.path.get(&name[5..]),
+            _ if name.starts_with("resource.") => {
+                self.input.resource.get(&name[9..]).map(String::as_str)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn parse_condition(source: &str) -> Option<Condition> {
+    let source = source.trim();
+    if source.starts_with("has_authority(") && source.ends_with(')') {
+        let operand = &source["has_authority(".len()..source.len() - 1];
+        return parse_operand(operand).map(Condition::HasAuthority);
+    }
+
+    let (op, index) = match (source.find("=="), source.find("!=")) {
+        (Some(index), None) => ("==", index),
+        (None, Some(index)) => ("!=", index),
+        _ => return None,
+    };
+    let left = parse_operand(&source[..index])?;
+    let right = parse_operand(&source[index + 2..])?;
+
+    match op {
+        "==" => Some(Condition::Eq(left, right)),
+        _ => Some(Condition::Ne(left, right)),
+    }
+}
+
+fn parse_operand(source: &str) -> Option<Operand> {
+    let source = source.trim();
+    let quoted = source.len() >= 2
+        && ((source.starts_with('\'') && source.ends_with('\''))
+            || (source.starts_with('"') && source.ends_with('"')));
+
+    if quoted {
+        Some(Operand::Literal(source[1..source.len() - 1].to_string()))
+    } else if source == "true"
+        || source == "false"
+        || (!source.is_empty() && source.chars().all(|c| c.is_ascii_digit()))
+    {
+        Some(Operand::Literal(source.to_string()))
+    } else if source == "identity"
+        || source == "impersonating"
+        || source == "narrowed"
+        || source == "method"
+        || source == "path"
+        || is_attribute_path(source, "path.")
+        || is_attribute_path(source, "resource.")
+    {
+        Some(Operand::Attribute(source.to_string()))
+    } else {
+        None
+    }
+}
+
+fn is_attribute_path(source: &str, prefix: &str) -> bool {
+    source.starts_with(prefix)
+        && source.len() > prefix.len()
+        && source[prefix.len()..]
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = include_str!("../../policy.toml");
+
+    fn user(identity: &str, authorities: &[&str]) -> Authentication {
+        Authentication::new(
+            identity,
+            authorities
+                .iter()
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn test_parse_condition() {
+        assert_eq!(
+            Some(Condition::Eq(
+                Operand::Attribute("resource.user_id".to_string()),
+                Operand::Attribute("identity".to_string()),
+            )),
+            parse_condition("resource.user_id == identity")
+        );
+        assert_eq!(
+            Some(Condition::Ne(
+                Operand::Attribute("path.group_id".to_string()),
+                Operand::Literal("admin".to_string()),
+            )),
+            parse_condition("path.group_id != 'admin'")
+        );
+        assert_eq!(
+            Some(Condition::HasAuthority(Operand::Attribute(
+                "resource.permission".to_string()
+            ))),
+            parse_condition("has_authority(resource.permission)")
+        );
+        for source in &["identity", "identity = bob", "unknown == 'a'", ""] {
+            assert_eq!(None, parse_condition(source), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_invalid_policy() {
+        let err = Policy::from_toml(
+            r#"
+            [[rule]]
+            name = "broken"
+            effect = "allow"
+            conditions = ["identity"]
+            "#,
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(ErrorKind::ConfigError, err.kind());
+        assert!(err.detail().unwrap().contains("broken"));
+    }
+
+    #[test]
+    fn test_deny_overrides_allow() {
+        let policy = Policy::from_toml(
+            r#"
+            [[rule]]
+            name = "read-groups"
+            effect = "allow"
+            methods = ["GET"]
+            paths = ["/api/groups/{group_id}"]
+            authorities = ["groups.get"]
+
+            [[rule]]
+            name = "hide-admin"
+            effect = "deny"
+            paths = ["/api/groups/{group_id}"]
+            conditions = ["resource.display_name == 'admin'"]
+            "#,
+        )
+        .unwrap();
+        let reader = user("bob", &["groups.get"]);
+        let input = |name: &str| {
+            Input::new(Method::GET, "/api/groups/1")
+                .authentication(reader.clone())
+                .resource("display_name", name)
+        };
+
+        let explanation = policy.evaluate(&input("staff"));
+        assert!(explanation.allowed);
+        assert_eq!(Some("read-groups".to_string()), explanation.rule);
+        assert_eq!(
+            Outcome::ConditionFailed(
+                "resource.display_name == 'admin'".to_string()
+            ),
+            explanation.outcomes[1].1
+        );
+
+        let explanation = policy.evaluate(&input("admin"));
+        assert!(!explanation.allowed);
+        assert_eq!(Some("hide-admin".to_string()), explanation.rule);
+        assert_eq!(
+            "denied by rule `hide-admin`; `read-groups`: matched; \
+             `hide-admin`: matched",
+            explanation.to_string()
+        );
+
+        let explanation =
+            policy.evaluate(&Input::new(Method::POST, "/api/groups"));
+        assert_eq!("denied, no rule matched", explanation.to_string());
+    }
+
+    #[test]
+    fn test_policy_fixtures() {
+        let policy = Policy::from_toml(POLICY).unwrap().explain(true);
+        let keys = "/api/users/2c1e8e3c5a0e4f0f9f3a8e8b5b0a0b01/api-keys";
+        let owner = "2c1e8e3c5a0e4f0f9f3a8e8b5b0a0b01";
+        let input = |method: Method, a: Option<Authentication>| {
+            let input = Input::new(method, keys).resource("user_id", owner);
+            match a {
+                Some(a) => input.authentication(a),
+                None => input,
+            }
+        };
+
+        let fixtures = vec![
+            (Method::GET, Some(user(owner, &[])), Ok(())),
+            (Method::POST, Some(user(owner, &[])), Ok(())),
+            (Method::GET, Some(user("other", &["users.get"])), Ok(())),
+            (
+                Method::POST,
+                Some(user("other", &["users.get"])),
+                Err(ErrorKind::Forbidden),
+            ),
+            (Method::POST, Some(user("other", &["users.*"])), Ok(())),
+            (
+                Method::GET,
+                Some(user("other", &[])),
+                Err(ErrorKind::Forbidden),
+            ),
+            (Method::GET, None, Err(ErrorKind::Unauthorized)),
+        ];
+        for (method, a, expected) in fixtures {
+            let result = policy.check(&input(method.clone(), a.clone()));
+            assert_eq!(
+                expected,
+                result.as_ref().map(|_| ()).map_err(Error::kind),
+                "{} {:?}: {:?}",
+                method,
+                a,
+                result.err().and_then(|e| e.detail().map(str::to_string))
+            );
+        }
+
+        let grant = |permission: &str| {
+            Input::new(Method::POST, "/api/groups/1/permissions")
+                .authentication(user(
+                    "bob",
+                    &["permissions.grant", "inventory.*"],
+                ))
+                .resource("permission", permission)
+        };
+        assert!(policy.check(&grant("inventory.items.get")).is_ok());
+        let err = policy.check(&grant("users.get")).unwrap_err();
+        assert!(err.detail().unwrap().contains("has_authority"));
+
+        let revoke = |permission: &str| {
+            Input::new(Method::DELETE, "/api/groups/1/permissions/2")
+                .authentication(user(
+                    "bob",
+                    &["permissions.grant", "inventory.*"],
+                ))
+                .resource("permission", permission)
+        };
+        assert!(policy.check(&revoke("inventory.items.get")).is_ok());
+        assert!(policy.check(&revoke("users.get")).is_err());
+        let err = policy
+            .check(
+                &Input::new(Method::DELETE, "/api/groups/1/permissions/2")
+                    .authentication(user("bob", &["inventory.*"]))
+                    .resource("permission", "inventory.items.get"),
+            )
+            .unwrap_err();
+        assert_eq!(ErrorKind::Forbidden, err.kind());
+
+        let create = |authorities: &[&str]| {
+            Input::new(Method::POST, "/api/permissions")
+                .authentication(user("bob", authorities))
+                .resource("name", "inventory.items.put")
+        };
+        assert!(policy
+            .check(&create(&["permissions.post", "inventory.*"]))
+            .is_ok());
+        assert!(policy.check(&create(&["permissions.post"])).is_err());
+        assert!(policy.check(&create(&["inventory.*"])).is_err());
+        let delete = |name: &str| {
+            Input::new(Method::DELETE, "/api/permissions/2")
+                .authentication(user(
+                    "bob",
+                    &["permissions.del", "inventory.*"],
+                ))
+                .resource("name", name)
+        };
+        assert!(policy.check(&delete("inventory.items.get")).is_ok());
+        assert!(policy.check(&delete("users.get")).is_err());
+
+        let impersonated =
+            user(owner, &[]).with_impersonator(Some("admin".to_string()));
+        assert!(policy
+            .check(&input(Method::GET, Some(impersonated.clone())))
+            .is_ok());
+        let err = policy
+            .check(&input(Method::POST, Some(impersonated.clone())))
+            .unwrap_err();
+        assert!(err.detail().unwrap().contains("impersonating"));
+
+        let credentials = |path: &str, a: Authentication| {
+            policy.check(&Input::new(Method::PUT, path).authentication(a))
+        };
+        for path in &[
+            "/api/auth/password",
+            "/api/auth/two-factor",
+            "/api/auth/two-factor/enrollment",
+            "/api/auth/oidc",
+            "/oauth/authorize",
+        ] {
+            assert!(credentials(path, user(owner, &[])).is_ok());
+            let err = credentials(path, impersonated.clone()).unwrap_err();
+            assert_eq!(ErrorKind::Forbidden, err.kind());
+        }
+        assert!(policy
+            .check(&Input::new(Method::POST, "/api/auth/two-factor/enrollment"))
+            .is_ok());
+
+        let update = |a: Authentication, credentials: bool| {
+            policy.check(
+                &Input::new(Method::PATCH, "/api/users/1")
+                    .authentication(a)
+                    .resource("credentials", credentials.to_string()),
+            )
+        };
+        let admin = user(owner, &["users.put"]);
+        assert!(update(admin.clone(), true).is_ok());
+        assert!(update(user(owner, &[]), false).is_err());
+        let admin = admin.with_impersonator(Some("root".to_string()));
+        assert!(update(admin.clone(), false).is_ok());
+        let err = update(admin, true).unwrap_err();
+        assert!(err.detail().unwrap().contains("impersonating"));
+
+        let narrowed = user(owner, &["users.get"]).with_narrowed(true);
+        assert!(policy
+            .check(&input(Method::GET, Some(narrowed.clone())))
+            .is_ok());
+        let err = policy
+            .check(&input(Method::POST, Some(narrowed)))
+            .unwrap_err();
+        assert_eq!(ErrorKind::Forbidden, err.kind());
+    }
+
+    #[test]
+    fn test_user_fixtures() {
+        let policy = Policy::from_toml(POLICY).unwrap().explain(true);
+        let fixtures = vec![
+            (Method::GET, "/api/users", "users.get"),
+            (Method::POST, "/api/users", "users.post"),
+            (Method::POST, "/api/users/invitations", "users.post"),
+            (Method::GET, "/api/users/1", "users.get"),
+            (Method::DELETE, "/api/users/1", "users.del"),
+            (Method::GET, "/api/users/1/sessions", "users.get"),
+            (Method::DELETE, "/api/users/1/sessions", "users.put"),
+            (Method::DELETE, "/api/users/1/two-factor", "users.put"),
+        ];
+
+        for (method, path, authority) in fixtures {
+            let check = |authorities: &[&str]| {
+                policy.check(
+                    &Input::new(method.clone(), path)
+                        .authentication(user("bob", authorities)),
+                )
+            };
+            assert!(check(&[authority]).is_ok(), "{} {}", method, path);
+            let others = ["users.get", "users.post", "users.put", "users.del"]
+                .iter()
+                .cloned()
+                .filter(|a| *a != authority)
+                .collect::<Vec<_>>();
+            let err = check(&others).unwrap_err();
+            assert_eq!(ErrorKind::Forbidden, err.kind(), "{} {}", method, path);
+        }
+    }
+
+    #[test]
+    fn test_group_fixtures() {
+        let policy = Policy::from_toml(POLICY).unwrap().explain(true);
+        let check = |method: Method, path: &str, attributes: &[&str]| {
+            let input = attributes.iter().fold(
+                Input::new(method, path).authentication(user("bob", &[])),
+                |input, name| input.resource(*name, "true"),
+            );
+            policy.check(&input).map_err(|e| e.kind())
+        };
+        let forbidden = Err(ErrorKind::Forbidden);
+
+        assert_eq!(Ok(()), check(Method::GET, "/api/groups", &[]));
+        assert_eq!(forbidden, check(Method::POST, "/api/groups", &[]));
+        for path in &["/api/groups/1/members", "/api/groups/1/managers"] {
+            assert_eq!(Ok(()), check(Method::GET, path, &["allows_get"]));
+            assert_eq!(Ok(()), check(Method::GET, path, &["manager"]));
+            assert_eq!(forbidden, check(Method::GET, path, &["allows_put"]));
+        }
+        let permissions = "/api/groups/1/permissions";
+        assert_eq!(forbidden, check(Method::GET, permissions, &["manager"]));
+
+        let group = "/api/groups/1";
+        assert_eq!(Ok(()), check(Method::PUT, group, &["allows_put"]));
+        assert_eq!(forbidden, check(Method::PUT, group, &["manager"]));
+        assert_eq!(
+            Ok(()),
+            check(Method::PUT, group, &["manager", "description_only"])
+        );
+        assert_eq!(Ok(()), check(Method::DELETE, group, &["allows_del"]));
+        assert_eq!(forbidden, check(Method::DELETE, group, &["allows_put"]));
+
+        let members = "/api/groups/1/members";
+        assert_eq!(Ok(()), check(Method::POST, members, &["may_grant"]));
+        assert_eq!(
+            forbidden,
+            check(Method::POST, members, &["allows_put", "manager"])
+        );
+        let member = "/api/groups/1/members/2";
+        assert_eq!(Ok(()), check(Method::DELETE, member, &["manager"]));
+
+        let manager = "/api/groups/1/managers/2";
+        assert_eq!(Ok(()), check(Method::DELETE, manager, &["allows_put"]));
+        assert_eq!(forbidden, check(Method::DELETE, manager, &["manager"]));
+
+        let acl = "/api/acl/groups/1";
+        assert_eq!(Ok(()), check(Method::POST, acl, &["allows_acl"]));
+        assert_eq!(forbidden, check(Method::POST, acl, &["allows_put"]));
+    }
+}
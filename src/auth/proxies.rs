@@ -0,0 +1,119 @@
+//! Client addresses behind reverse proxies.
+//!
+//! The address of a client is the address of the peer of the connection.
+//! Only if the peer is one of the proxies listed comma separated in
+//! `TRUSTED_PROXIES`, the address it forwarded in `X-Forwarded-For` is used
+//! instead. Proxies append to the header, so it is read from the right and
+//! the first address that is not a trusted proxy is the client's.
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+
+use actix_web::HttpRequest;
+
+use crate::error::{Error, ErrorKind, Result};
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    addrs: Vec<IpAddr>,
+}
+
+impl TrustedProxies {
+    pub fn new(addrs: Vec<IpAddr>) -> TrustedProxies {
+        TrustedProxies { addrs }
+    }
+
+    pub fn from_env() -> Result<TrustedProxies> {
+        let addrs = match env::var("TRUSTED_PROXIES") {
+            Ok(addrs) => addrs
+                .split(',')
+                .map(str::trim)
+                .filter(|addr| !addr.is_empty())
+                .map(|addr| {
+                    addr.parse().map_err(|_| {
+                        Error::from(ErrorKind::ConfigError).with_detail(
+                            format!("invalid trusted proxy `{}`", addr),
+                        )
+                    })
+                })
+                .collect::<Result<Vec<IpAddr>>>()?,
+            Err(_) => Vec::new(),
+        };
+
+        Ok(TrustedProxies::new(addrs))
+    }
+
+    /// Address of the client, `None` if the request has no peer.
+    pub fn client_ip(&self, req: &HttpRequest) -> Option<IpAddr> {
+        let mut ip = req.peer_addr()?.ip();
+        let forwarded = req
+            .headers()
+            .get_all(X_FORWARDED_FOR)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect::<Vec<&str>>();
+
+        for addr in forwarded.into_iter().rev() {
+            if !self.addrs.contains(&ip) {
+                break;
+            }
+            ip = match parse_addr(addr) {
+                Some(addr) => addr,
+                None => break,
+            };
+        }
+
+        Some(ip)
+    }
+}
+
+fn parse_addr(addr: &str) -> Option<IpAddr> {
+    addr.parse::<IpAddr>()
+        .or_else(|_| addr.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn request(peer: &str, forwarded: Option<&str>) -> HttpRequest {
+        let mut req = TestRequest::default();
+        if let Some(forwarded) = forwarded {
+            req = req.header(X_FORWARDED_FOR, forwarded);
+        }
+        let mut req = req.to_srv_request();
+        req.head_mut().peer_addr = Some(peer.parse().unwrap());
+        req.into_parts().0
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let proxies = TrustedProxies::new(vec![
+            "10.0.0.1".parse().unwrap(),
+            "10.0.0.2".parse().unwrap(),
+        ]);
+        let cases = vec![
+            ("192.0.2.1:1234", None, "192.0.2.1"),
+            ("192.0.2.1:1234", Some("198.51.100.1"), "192.0.2.1"),
+            ("10.0.0.1:1234", None, "10.0.0.1"),
+            ("10.0.0.1:1234", Some("198.51.100.1"), "198.51.100.1"),
+            (
+                "10.0.0.1:1234",
+                Some("198.51.100.1, 198.51.100.2, 10.0.0.2"),
+                "198.51.100.2",
+            ),
+            ("10.0.0.1:1234", Some("198.51.100.1:80"), "198.51.100.1"),
+            ("10.0.0.1:1234", Some("unknown"), "10.0.0.1"),
+        ];
+
+        for (peer, forwarded, expected) in cases {
+            let req = request(peer, forwarded);
+            let ip = proxies.client_ip(&req).unwrap();
+            assert_eq!(expected, ip.to_string(), "{} {:?}", peer, forwarded);
+        }
+    }
+}
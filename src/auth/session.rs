@@ -0,0 +1,410 @@
+//! Server-side sessions, the cookie only carries an opaque token.
+//!
+//! Tokens are stored as their SHA-256 hash, so a leaked `sessions` table can
+//! not be replayed. A session ends when it has been idle for longer than the
+//! idle timeout, or at the latest once the absolute timeout is reached.
+use std::sync::Arc;
+#[cfg(test)]
+use std::sync::Mutex;
+
+use chrono::prelude::*;
+use chrono::Duration;
+use uuid::Uuid;
+
+use super::Authentication;
+use crate::db::sessions::{self, Session};
+use crate::db::Database;
+use crate::error::Result;
+use crate::utils::{self, hash_token};
+
+const TOKEN_LENGTH: usize = 43;
+
+/// Storage of sessions, keyed by the hash of their token.
+pub trait SessionStore: Send + Sync + 'static {
+    fn find_by_token_hash(&self, token_hash: &str) -> Result<Option<Session>>;
+
+    fn find_by_identity(&self, identity: &str) -> Result<Vec<Session>>;
+
+    fn create(&self, session: &Session) -> Result<()>;
+
+    fn update(&self, session: &Session) -> Result<()>;
+
+    fn touch(
+        &self,
+        session_id: &Uuid,
+        accessed_at: DateTime<Utc>,
+    ) -> Result<()>;
+
+    fn del_by_id(&self, identity: &str, session_id: &Uuid) -> Result<usize>;
+
+    fn del_by_token_hash(&self, token_hash: &str) -> Result<usize>;
+
+    fn del_by_identity(&self, identity: &str) -> Result<usize>;
+}
+
+impl SessionStore for Database {
+    fn find_by_token_hash(&self, token_hash: &str) -> Result<Option<Session>> {
+        let conn = self.conn()?;
+        sessions::find_by_token_hash(&conn, token_hash)
+    }
+
+    fn find_by_identity(&self, identity: &str) -> Result<Vec<Session>> {
+        let conn = self.conn()?;
+        sessions::find_by_identity(&conn, identity)
+    }
+
+    fn create(&self, session: &Session) -> Result<()> {
+        let conn = self.conn()?;
+        sessions::create(&conn, session)?;
+        Ok(())
+    }
+
+    fn update(&self, session: &Session) -> Result<()> {
+        let conn = self.conn()?;
+        sessions::update(&conn, session)?;
+        Ok(())
+    }
+
+    fn touch(
+        &self,
+        session_id: &Uuid,
+        accessed_at: DateTime<Utc>,
+    ) -> Result<()> {
+        let conn = self.conn()?;
+        sessions::touch(&conn, session_id, accessed_at)?;
+        Ok(())
+    }
+
+    fn del_by_id(&self, identity: &str, session_id: &Uuid) -> Result<usize> {
+        let conn = self.conn()?;
+        sessions::del_by_id(&conn, identity, session_id)
+    }
+
+    fn del_by_token_hash(&self, token_hash: &str) -> Result<usize> {
+        let conn = self.conn()?;
+        sessions::del_by_token_hash(&conn, token_hash)
+    }
+
+    fn del_by_identity(&self, identity: &str) -> Result<usize> {
+        let conn = self.conn()?;
+        sessions::del_by_identity(&conn, identity)
+    }
+}
+
+/// Keeps sessions in memory, for tests.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemorySessionStore {
+    sessions: Mutex<Vec<Session>>,
+}
+
+#[cfg(test)]
+impl MemorySessionStore {
+    fn with_sessions<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(&mut Vec<Session>) -> T,
+    {
+        let mut sessions = self
+            .sessions
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        f(&mut sessions)
+    }
+}
+
+#[cfg(test)]
+impl SessionStore for MemorySessionStore {
+    fn find_by_token_hash(&self, token_hash: &str) -> Result<Option<Session>> {
+        Ok(self.with_sessions(|sessions| {
+            sessions
+                .iter()
+                .find(|s| s.token_hash == token_hash)
+                .cloned()
+        }))
+    }
+
+    fn find_by_identity(&self, identity: &str) -> Result<Vec<Session>> {
+        Ok(self.with_sessions(|sessions| {
+            let mut result = sessions
+                .iter()
+                .filter(|s| s.identity == identity)
+                .cloned()
+                .collect::<Vec<Session>>();
+            result.sort_by(|a, b| b.last_accessed_at.cmp(&a.last_accessed_at));
+            result
+        }))
+    }
+
+    fn create(&self, session: &Session) -> Result<()> {
+        self.with_sessions(|sessions| sessions.push(session.clone()));
+        Ok(())
+    }
+
+    fn update(&self, session: &Session) -> Result<()> {
+        self.with_sessions(|sessions| {
+            for s in sessions.iter_mut().filter(|s| s.id == session.id) {
+                *s = session.clone();
+            }
+        });
+        Ok(())
+    }
+
+    fn touch(
+        &self,
+        session_id: &Uuid,
+        accessed_at: DateTime<Utc>,
+    ) -> Result<()> {
+        self.with_sessions(|sessions| {
+            for session in sessions.iter_mut().filter(|s| s.id == *session_id) {
+                session.last_accessed_at = accessed_at;
+            }
+        });
+        Ok(())
+    }
+
+    fn del_by_id(&self, identity: &str, session_id: &Uuid) -> Result<usize> {
+        Ok(self.with_sessions(|sessions| {
+            remove(sessions, |s| s.identity == identity && s.id == *session_id)
+        }))
+    }
+
+    fn del_by_token_hash(&self, token_hash: &str) -> Result<usize> {
+        Ok(self.with_sessions(|sessions| {
+            remove(sessions, |s| s.token_hash == token_hash)
+        }))
+    }
+
+    fn del_by_identity(&self, identity: &str) -> Result<usize> {
+        Ok(self.with_sessions(|sessions| {
+            remove(sessions, |s| s.identity == identity)
+        }))
+    }
+}
+
+#[cfg(test)]
+fn remove<F: Fn(&Session) -> bool>(sessions: &mut Vec<Session>, f: F) -> usize {
+    let len = sessions.len();
+    sessions.retain(|s| !f(s));
+    len - sessions.len()
+}
+
+/// Creates, resolves and revokes sessions on top of a `SessionStore`.
+#[derive(Clone)]
+pub struct SessionManager {
+    store: Arc<SessionStore>,
+    idle_timeout: Duration,
+    absolute_timeout: Duration,
+}
+
+impl SessionManager {
+    pub fn new<S: SessionStore>(store: S) -> SessionManager {
+        SessionManager {
+            store: Arc::new(store),
+            idle_timeout: Duration::minutes(30),
+            absolute_timeout: Duration::hours(12),
+        }
+    }
+
+    pub fn idle_timeout(mut self, seconds: i64) -> SessionManager {
+        self.idle_timeout = Duration::seconds(seconds);
+        self
+    }
+
+    pub fn absolute_timeout(mut self, seconds: i64) -> SessionManager {
+        self.absolute_timeout = Duration::seconds(seconds);
+        self
+    }
+
+    /// Generates a new, not yet stored, session token.
+    pub fn new_token() -> String {
+        utils::random_string(TOKEN_LENGTH)
+    }
+
+    /// Starts a session for the authentication under the token.
+    pub fn create(
+        &self,
+        token: &str,
+        authentication: &Authentication,
+        user_agent: Option<String>,
+    ) -> Result<Session> {
+        let now = Utc::now();
+        let session = Session {
+            id: Uuid::new_v4(),
+            token_hash: hash_token(token),
+            identity: authentication.identity().to_string(),
+            authorities: sorted_authorities(authentication),
+            user_agent,
+            created_at: now,
+            last_accessed_at: now,
+            expires_at: now + self.absolute_timeout,
+            membership_epoch: authentication.epoch(),
+            impersonator: authentication.impersonator().map(str::to_string),
+        };
+        self.store.create(&session)?;
+
+        Ok(session)
+    }
+
+    /// Replaces the authentication of the session under the token, keeping
+    /// the token and the timeouts.
+    pub fn update(
+        &self,
+        token: &str,
+        authentication: &Authentication,
+    ) -> Result<()> {
+        let session = match self.store.find_by_token_hash(&hash_token(token))? {
+            Some(session) => session,
+            None => return Ok(()),
+        };
+
+        self.store.update(&Session {
+            identity: authentication.identity().to_string(),
+            authorities: sorted_authorities(authentication),
+            membership_epoch: authentication.epoch(),
+            impersonator: authentication.impersonator().map(str::to_string),
+            ..session
+        })
+    }
+
+    /// Resolves the authentication of a live session, ending sessions which
+    /// timed out.
+    pub fn load(&self, token: &str) -> Result<Option<Authentication>> {
+        self.load_at(token, Utc::now())
+    }
+
+    fn load_at(
+        &self,
+        token: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Authentication>> {
+        let token_hash = hash_token(token);
+        let session = match self.store.find_by_token_hash(&token_hash)? {
+            Some(session) => session,
+            None => return Ok(None),
+        };
+
+        if session.expires_at <= now
+            || session.last_accessed_at + self.idle_timeout <= now
+        {
+            self.store.del_by_token_hash(&token_hash)?;
+            return Ok(None);
+        }
+
+        self.store.touch(&session.id, now)?;
+        Ok(Some(
+            Authentication::new(session.identity, session.authorities)
+                .with_epoch(session.membership_epoch)
+                .with_impersonator(session.impersonator),
+        ))
+    }
+
+    /// Lists the sessions of the identity, most recently used first.
+    pub fn list(&self, identity: &str) -> Result<Vec<Session>> {
+        let now = Utc::now();
+        let sessions = self.store.find_by_identity(identity)?;
+
+        Ok(sessions
+            .into_iter()
+            .filter(|s| {
+                s.expires_at > now
+                    && s.last_accessed_at + self.idle_timeout > now
+            })
+            .collect())
+    }
+
+    pub fn revoke(&self, identity: &str, session_id: &Uuid) -> Result<usize> {
+        self.store.del_by_id(identity, session_id)
+    }
+
+    pub fn revoke_token(&self, token: &str) -> Result<usize> {
+        self.store.del_by_token_hash(&hash_token(token))
+    }
+
+    pub fn revoke_all(&self, identity: &str) -> Result<usize> {
+        self.store.del_by_identity(identity)
+    }
+}
+
+fn sorted_authorities(authentication: &Authentication) -> Vec<String> {
+    let mut authorities = authentication
+        .authorities()
+        .iter()
+        .cloned()
+        .collect::<Vec<String>>();
+    authorities.sort();
+    authorities
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn authentication() -> Authentication {
+        Authentication::new("bob", vec!["user".to_string()])
+    }
+
+    #[test]
+    fn test_load() {
+        let manager = SessionManager::new(MemorySessionStore::default());
+        let token = SessionManager::new_token();
+        manager.create(&token, &authentication(), None).unwrap();
+
+        assert_eq!(Some(authentication()), manager.load(&token).unwrap());
+        assert_eq!(None, manager.load("unknown").unwrap());
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let manager = SessionManager::new(MemorySessionStore::default())
+            .idle_timeout(60)
+            .absolute_timeout(150);
+        let token = SessionManager::new_token();
+        let now = manager.create(&token, &authentication(), None).unwrap();
+        let now = now.created_at;
+
+        for seconds in &[50, 100, 140] {
+            let at = now + Duration::seconds(*seconds);
+            assert!(manager.load_at(&token, at).unwrap().is_some());
+        }
+        let at = now + Duration::seconds(150);
+        assert_eq!(None, manager.load_at(&token, at).unwrap());
+
+        let token = SessionManager::new_token();
+        manager.create(&token, &authentication(), None).unwrap();
+        let at = Utc::now() + Duration::seconds(61);
+        assert_eq!(None, manager.load_at(&token, at).unwrap());
+        assert!(manager.list("bob").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_revoke() {
+        let manager = SessionManager::new(MemorySessionStore::default());
+        let first = SessionManager::new_token();
+        let second = SessionManager::new_token();
+        let session = manager.create(&first, &authentication(), None).unwrap();
+        manager.create(&second, &authentication(), None).unwrap();
+        assert_eq!(2, manager.list("bob").unwrap().len());
+
+        assert_eq!(0, manager.revoke("alice", &session.id).unwrap());
+        assert_eq!(1, manager.revoke("bob", &session.id).unwrap());
+        assert_eq!(None, manager.load(&first).unwrap());
+        assert!(manager.load(&second).unwrap().is_some());
+
+        assert_eq!(1, manager.revoke_all("bob").unwrap());
+        assert_eq!(None, manager.load(&second).unwrap());
+    }
+
+    #[test]
+    fn test_update() {
+        let manager = SessionManager::new(MemorySessionStore::default());
+        let token = SessionManager::new_token();
+        let session = manager.create(&token, &authentication(), None).unwrap();
+        let refreshed =
+            Authentication::new("bob", vec!["admin".to_string()]).with_epoch(3);
+
+        manager.update(&token, &refreshed).unwrap();
+        assert_eq!(Some(refreshed), manager.load(&token).unwrap());
+        assert_eq!(session.id, manager.list("bob").unwrap()[0].id);
+        manager.update("unknown", &authentication()).unwrap();
+    }
+}
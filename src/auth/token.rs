@@ -0,0 +1,238 @@
+//! Signed bearer tokens in the compact JWS format (RFC 7515), using
+//! HMAC-SHA256 as the signing algorithm.
+use std::iter;
+use std::sync::Arc;
+
+use chrono::prelude::*;
+use ring::{digest, hmac};
+
+use super::Authentication;
+use crate::error::{ErrorKind, Result, ResultExt};
+
+/// JOSE header of every issued token, `{"alg":"HS256","typ":"JWT"}`.
+const HEADER: &str = "eyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9";
+
+/// Purpose of challenge tokens, handed out after the password when a second
+/// factor is still to be checked.
+const CHALLENGE: &str = "two_factor";
+
+/// Seconds a challenge token is valid.
+const CHALLENGE_MAX_AGE: i64 = 300;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    authorities: Vec<String>,
+    #[serde(default)]
+    epoch: i64,
+    /// Set on tokens that must not be accepted as bearer tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    purpose: Option<String>,
+    iat: i64,
+    exp: i64,
+}
+
+/// Response of the token endpoint, shaped after RFC 6749, section 5.1.
+#[derive(Debug, Serialize)]
+pub struct BearerToken {
+    pub access_token: String,
+    pub token_type: &'static str,
+    pub expires_in: i64,
+}
+
+/// Issues and verifies tokens carrying an `Authentication`.
+#[derive(Clone)]
+pub struct TokenSigner {
+    key: Arc<hmac::SigningKey>,
+    previous_keys: Arc<Vec<hmac::SigningKey>>,
+    max_age: i64,
+}
+
+impl TokenSigner {
+    pub fn new(key: &[u8]) -> TokenSigner {
+        TokenSigner {
+            key: Arc::new(hmac::SigningKey::new(&digest::SHA256, key)),
+            previous_keys: Arc::new(Vec::new()),
+            max_age: 3600,
+        }
+    }
+
+    /// Retired keys, tokens signed with them are accepted until they expire.
+    pub fn previous_keys(mut self, keys: &[Vec<u8>]) -> TokenSigner {
+        self.previous_keys = Arc::new(
+            keys.iter()
+                .map(|key| hmac::SigningKey::new(&digest::SHA256, key))
+                .collect(),
+        );
+        self
+    }
+
+    /// Seconds until issued tokens expire, one hour by default.
+    pub fn max_age(mut self, seconds: i64) -> TokenSigner {
+        self.max_age = seconds;
+        self
+    }
+
+    pub fn issue(
+        &self,
+        authentication: &Authentication,
+    ) -> Result<BearerToken> {
+        let now = Utc::now().timestamp();
+        let mut authorities = authentication
+            .authorities()
+            .iter()
+            .cloned()
+            .collect::<Vec<String>>();
+        authorities.sort();
+
+        let claims = Claims {
+            sub: authentication.identity().to_string(),
+            authorities,
+            epoch: authentication.epoch(),
+            purpose: None,
+            iat: now,
+            exp: now + self.max_age,
+        };
+
+        Ok(BearerToken {
+            access_token: self.sign(&claims)?,
+            token_type: "Bearer",
+            expires_in: self.max_age,
+        })
+    }
+
+    /// Returns the authentication carried by the token, failing with
+    /// `Unauthorized` if it is malformed, forged or expired.
+    pub fn verify(&self, token: &str) -> Result<Authentication> {
+        let claims = self.open(token)?;
+        if claims.purpose.is_some() {
+            Err(ErrorKind::Unauthorized)?
+        }
+
+        Ok(Authentication::new(claims.sub, claims.authorities)
+            .with_epoch(claims.epoch))
+    }
+
+    /// Issues a short-lived token proving the identity passed the first
+    /// login step, it carries no authorities.
+    pub fn issue_challenge(&self, identity: &str) -> Result<String> {
+        let now = Utc::now().timestamp();
+
+        self.sign(&Claims {
+            sub: identity.to_string(),
+            authorities: Vec::new(),
+            epoch: 0,
+            purpose: Some(CHALLENGE.to_string()),
+            iat: now,
+            exp: now + CHALLENGE_MAX_AGE,
+        })
+    }
+
+    /// Returns the identity of a challenge token.
+    pub fn verify_challenge(&self, token: &str) -> Result<String> {
+        let claims = self.open(token)?;
+        match claims.purpose {
+            Some(ref purpose) if purpose == CHALLENGE => Ok(claims.sub),
+            _ => Err(ErrorKind::Unauthorized)?,
+        }
+    }
+
+    fn sign(&self, claims: &Claims) -> Result<String> {
+        let payload = serde_json::to_vec(claims)
+            .context(ErrorKind::SerializeJsonError)?;
+        let message = format!("{}.{}", HEADER, encode(&payload));
+        let signature = hmac::sign(&self.key, message.as_bytes());
+
+        Ok(format!("{}.{}", message, encode(signature.as_ref())))
+    }
+
+    fn open(&self, token: &str) -> Result<Claims> {
+        let mut parts = token.rsplitn(2, '.');
+        let (signature, message) = match (parts.next(), parts.next()) {
+            (Some(signature), Some(message)) => (signature, message),
+            _ => Err(ErrorKind::Unauthorized)?,
+        };
+        let signature = decode(signature)?;
+        let verified = iter::once(&*self.key)
+            .chain(self.previous_keys.iter())
+            .any(|key| {
+                hmac::verify_with_own_key(key, message.as_bytes(), &signature)
+                    .is_ok()
+            });
+        if !verified {
+            Err(ErrorKind::Unauthorized)?
+        }
+
+        let payload = match message.split('.').collect::<Vec<&str>>()[..] {
+            [HEADER, payload] => decode(payload)?,
+            _ => Err(ErrorKind::Unauthorized)?,
+        };
+        let claims: Claims = serde_json::from_slice(&payload)
+            .context(ErrorKind::Unauthorized)?;
+
+        if claims.exp <= Utc::now().timestamp() {
+            Err(ErrorKind::Unauthorized)?
+        }
+
+        Ok(claims)
+    }
+}
+
+fn encode(input: &[u8]) -> String {
+    base64::encode_config(input, base64::URL_SAFE_NO_PAD)
+}
+
+fn decode(input: &str) -> Result<Vec<u8>> {
+    Ok(base64::decode_config(input, base64::URL_SAFE_NO_PAD)
+        .context(ErrorKind::Unauthorized)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issue_and_verify() {
+        let signer = TokenSigner::new(&[1; 32]);
+        let authentication =
+            Authentication::new("admin", vec!["admin".to_string()]);
+
+        let token = signer.issue(&authentication).unwrap();
+
+        assert_eq!("Bearer", token.token_type);
+        assert_eq!(3600, token.expires_in);
+        assert_eq!(authentication, signer.verify(&token.access_token).unwrap());
+    }
+
+    #[test]
+    fn test_verify_rejects_invalid_tokens() {
+        let signer = TokenSigner::new(&[1; 32]);
+        let authentication =
+            Authentication::new("admin", vec!["admin".to_string()]);
+        let token = signer.issue(&authentication).unwrap().access_token;
+
+        let forged = TokenSigner::new(&[2; 32])
+            .issue(&authentication)
+            .unwrap()
+            .access_token;
+        let expired = signer
+            .clone()
+            .max_age(-1)
+            .issue(&authentication)
+            .unwrap()
+            .access_token;
+
+        let rotated = TokenSigner::new(&[2; 32]).previous_keys(&[vec![1; 32]]);
+        assert!(rotated.verify(&token).is_ok());
+
+        let challenge = signer.issue_challenge("admin").unwrap();
+        assert_eq!("admin", signer.verify_challenge(&challenge).unwrap());
+        assert!(signer.verify_challenge(&token).is_err());
+
+        for token in &[&forged, &expired, &challenge, &token[1..], "", "a.b.c"]
+        {
+            let err = signer.verify(token).err().unwrap();
+            assert_eq!(ErrorKind::Unauthorized, err.kind());
+        }
+    }
+}
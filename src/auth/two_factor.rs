@@ -0,0 +1,205 @@
+//! Second login factor, a TOTP code or one of the recovery codes handed out
+//! on enrollment.
+//!
+//! Users opt in by enrolling, members of a group requiring two-factor
+//! authentication have to enroll before their next login completes.
+use chrono::prelude::*;
+use uuid::Uuid;
+
+use super::totp;
+use crate::db::two_factor::{self, TwoFactor};
+use crate::db::users::User;
+use crate::db::{groups, Conn};
+use crate::error::{ErrorKind, Result};
+use crate::utils;
+
+pub const ISSUER: &str = "Hamster";
+pub const RECOVERY_CODES: usize = 10;
+const RECOVERY_CODE_LENGTH: usize = 10;
+
+/// What a user has to present after the password.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SecondFactor {
+    None,
+    /// A code of the confirmed enrollment.
+    Code,
+    /// Nothing yet, but an enrollment has to be confirmed first.
+    Enrollment,
+}
+
+/// Secret of a started enrollment, for the authenticator app.
+#[derive(Debug, Serialize)]
+pub struct Enrollment {
+    pub secret: String,
+    pub uri: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+pub fn second_factor(conn: &Conn, user: &User) -> Result<SecondFactor> {
+    if let Some(enrollment) = two_factor::find_by_user_id(conn, &user.id)? {
+        if enrollment.is_confirmed() {
+            return Ok(SecondFactor::Code);
+        }
+    }
+
+    let required = groups::find_by_member_id(conn, &user.id)?
+        .iter()
+        .any(|g| g.require_two_factor);
+    if required {
+        Ok(SecondFactor::Enrollment)
+    } else {
+        Ok(SecondFactor::None)
+    }
+}
+
+/// Generates a new secret, replacing an unconfirmed enrollment. Fails with
+/// `Conflict` if the user already is enrolled.
+pub fn start_enrollment(conn: &Conn, user: &User) -> Result<Enrollment> {
+    let secret = totp::generate_secret();
+    two_factor::enroll(conn, &user.id, &secret)?;
+
+    Ok(Enrollment {
+        secret: totp::base32_encode(&secret),
+        uri: totp::uri(ISSUER, &user.username, &secret),
+    })
+}
+
+/// Confirms the enrollment with a first code, returning fresh recovery
+/// codes. They are stored hashed and can not be shown again.
+pub fn confirm_enrollment(
+    conn: &Conn,
+    user_id: &Uuid,
+    code: &str,
+) -> Result<RecoveryCodes> {
+    let enrollment = match two_factor::find_by_user_id(conn, user_id)? {
+        Some(ref enrollment) if enrollment.is_confirmed() => {
+            Err(ErrorKind::Conflict)?
+        }
+        Some(enrollment) => enrollment,
+        None => Err(ErrorKind::NotFound)?,
+    };
+
+    let now = Utc::now().timestamp();
+    match totp::verify(&enrollment.secret, code, now, 0) {
+        Some(step) => two_factor::confirm(conn, user_id, step)?,
+        None => Err(ErrorKind::Unauthorized)?,
+    };
+
+    reset_recovery_codes(conn, user_id)
+}
+
+/// Checks a TOTP code or spends a recovery code.
+pub fn verify(conn: &Conn, user_id: &Uuid, code: &str) -> Result<bool> {
+    let enrollment = match two_factor::find_by_user_id(conn, user_id)? {
+        Some(enrollment) => enrollment,
+        None => return Ok(false),
+    };
+    if !enrollment.is_confirmed() {
+        return Ok(false);
+    }
+
+    let code = code.trim();
+    if code.len() == totp::DIGITS && code.chars().all(|c| c.is_ascii_digit()) {
+        verify_totp(conn, &enrollment, code)
+    } else {
+        let code_hash = utils::hash_token(&normalize_recovery_code(code));
+        Ok(two_factor::use_recovery_code(conn, user_id, &code_hash)? > 0)
+    }
+}
+
+pub fn reset_recovery_codes(
+    conn: &Conn,
+    user_id: &Uuid,
+) -> Result<RecoveryCodes> {
+    let recovery_codes = (0..RECOVERY_CODES)
+        .map(|_| {
+            let code =
+                utils::random_string(RECOVERY_CODE_LENGTH).to_lowercase();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect::<Vec<String>>();
+    let code_hashes = recovery_codes
+        .iter()
+        .map(|code| utils::hash_token(&normalize_recovery_code(code)))
+        .collect::<Vec<String>>();
+    two_factor::set_recovery_codes(conn, user_id, &code_hashes)?;
+
+    Ok(RecoveryCodes { recovery_codes })
+}
+
+fn verify_totp(
+    conn: &Conn,
+    enrollment: &TwoFactor,
+    code: &str,
+) -> Result<bool> {
+    let now = Utc::now().timestamp();
+
+    match totp::verify(&enrollment.secret, code, now, enrollment.last_used_step)
+    {
+        // Racing requests with the same code only let one of them pass.
+        Some(step) => {
+            Ok(two_factor::use_step(conn, &enrollment.user_id, step)? > 0)
+        }
+        None => Ok(false),
+    }
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::groups::GroupMembershipType;
+    use crate::db::users;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn test_enrollment() {
+        let conn = connection();
+        let user =
+            users::create_or_update(&conn, "bob", "Bob", "password").unwrap();
+        assert_eq!(SecondFactor::None, second_factor(&conn, &user).unwrap());
+
+        let group = groups::get_or_create(&conn, "two_factor").unwrap();
+        groups::update_require_two_factor(&conn, &group.id, true).unwrap();
+        groups::add_member(
+            &conn,
+            &group.id,
+            &user.id,
+            GroupMembershipType::User,
+        )
+        .unwrap();
+        assert_eq!(
+            SecondFactor::Enrollment,
+            second_factor(&conn, &user).unwrap()
+        );
+
+        start_enrollment(&conn, &user).unwrap();
+        let secret = two_factor::find_by_user_id(&conn, &user.id)
+            .unwrap()
+            .unwrap()
+            .secret;
+        let code = totp::code(&secret, totp::step(Utc::now().timestamp()));
+        let err = confirm_enrollment(&conn, &user.id, "000000").unwrap_err();
+        assert_eq!(ErrorKind::Unauthorized, err.kind());
+        let codes = confirm_enrollment(&conn, &user.id, &code).unwrap();
+        assert_eq!(RECOVERY_CODES, codes.recovery_codes.len());
+        assert_eq!(SecondFactor::Code, second_factor(&conn, &user).unwrap());
+
+        // The code used for confirmation can not be replayed.
+        assert!(!verify(&conn, &user.id, &code).unwrap());
+
+        let recovery_code = codes.recovery_codes[0].to_uppercase();
+        assert!(verify(&conn, &user.id, &recovery_code).unwrap());
+        assert!(!verify(&conn, &user.id, &recovery_code).unwrap());
+    }
+}
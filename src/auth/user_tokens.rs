@@ -0,0 +1,294 @@
+//! Password reset and invitation links.
+//!
+//! Both mail a single-use token to the user, only its hash is stored.
+//! Redeeming a token sets a new password and ends the sessions of the user.
+use chrono::prelude::*;
+use chrono::Duration;
+use uuid::Uuid;
+
+use super::PasswordPolicy;
+use crate::db::lockouts::{self, LockoutKind};
+use crate::db::user_tokens::{self, TokenPurpose, UserToken};
+use crate::db::users::{self, NewUser, User};
+use crate::db::{sessions, Conn};
+use crate::error::{Error, ErrorKind, Result};
+use crate::mail::Message;
+use crate::utils;
+
+const TOKEN_LENGTH: usize = 43;
+
+#[derive(Debug, Deserialize)]
+pub struct Invitation {
+    pub username: String,
+    pub nickname: String,
+    pub email: String,
+}
+
+/// Issues and redeems reset and invitation tokens, linking to pages below
+/// `base_url`.
+#[derive(Debug, Clone)]
+pub struct UserTokens {
+    base_url: String,
+    reset_max_age: Duration,
+    invite_max_age: Duration,
+}
+
+impl UserTokens {
+    pub fn new<S: Into<String>>(base_url: S) -> UserTokens {
+        UserTokens {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            reset_max_age: Duration::hours(1),
+            invite_max_age: Duration::days(7),
+        }
+    }
+
+    /// Prepares a reset link for the user with the username or email
+    /// address. Returns `None` if there is no such user or it has no email
+    /// address, callers should not tell the difference.
+    pub fn reset(&self, conn: &Conn, login: &str) -> Result<Option<Message>> {
+        let user = match users::find_by_username(conn, login)? {
+            Some(user) => Some(user),
+            None => users::find_by_email(conn, login)?,
+        };
+        let (user, email) = match user {
+            Some(user) => match user.email.clone() {
+                Some(email) => (user, email),
+                None => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+
+        let token = self.issue(conn, &user, TokenPurpose::Reset)?;
+        Ok(Some(Message {
+            to: email,
+            subject: "Reset your password".to_string(),
+            body: format!(
+                "Hello {},\n\n\
+                 open the link below to choose a new password, it expires \
+                 in an hour:\n\n\
+                 {}/reset-password?token={}\n\n\
+                 If you did not ask for this, ignore this mail.\n",
+                user.nickname, self.base_url, token
+            ),
+        }))
+    }
+
+    /// Creates a user without a usable password, and the invitation to mail
+    /// to let them choose one.
+    pub fn invite(
+        &self,
+        conn: &Conn,
+        policy: &PasswordPolicy,
+        invitation: Invitation,
+    ) -> Result<(User, Message)> {
+        utils::validate_email(&invitation.email)?;
+
+        // Nobody knows this password, it is replaced on acceptance.
+        let password = policy.hash(&utils::random_string(32))?;
+        let user = users::create(
+            conn,
+            NewUser {
+                username: invitation.username,
+                password,
+                nickname: invitation.nickname,
+                avatar_url: None,
+                email: Some(invitation.email.clone()),
+            },
+        )?;
+
+        let token = self.issue(conn, &user, TokenPurpose::Invite)?;
+        let message = Message {
+            to: invitation.email,
+            subject: "You have been invited".to_string(),
+            body: format!(
+                "Hello {},\n\n\
+                 an account with the username {} has been created for you. \
+                 Open the link below to choose your password, it expires in \
+                 7 days:\n\n\
+                 {}/invitation?token={}\n",
+                user.nickname, user.username, self.base_url, token
+            ),
+        };
+
+        Ok((user, message))
+    }
+
+    /// Sets the password of the user the token was issued to. Fails with
+    /// `Unauthorized` if the token is unknown, used or expired.
+    ///
+    /// The token is spent before the password is validated, the caller has
+    /// to run this in a transaction to keep it when validation fails.
+    pub fn redeem(
+        &self,
+        conn: &Conn,
+        policy: &PasswordPolicy,
+        purpose: TokenPurpose,
+        token: &str,
+        password: &str,
+    ) -> Result<User> {
+        let token_hash = utils::hash_token(token);
+        let token = match user_tokens::redeem(
+            conn,
+            &token_hash,
+            purpose,
+            Utc::now(),
+        )? {
+            Some(token) => token,
+            None => Err(Error::from(ErrorKind::Unauthorized)
+                .with_detail("the token is invalid or expired"))?,
+        };
+        let user = match users::find_by_id(conn, &token.user_id)? {
+            Some(user) => user,
+            None => Err(ErrorKind::Unauthorized)?,
+        };
+        policy.validate(password, &user.username)?;
+
+        users::update_password(conn, &user.id, &policy.hash(password)?)?;
+        user_tokens::del_by_user_id(conn, &user.id)?;
+        sessions::del_by_identity(conn, &user.id.simple().to_string())?;
+        lockouts::del(conn, LockoutKind::Username, &user.username)?;
+
+        Ok(user)
+    }
+
+    fn issue(
+        &self,
+        conn: &Conn,
+        user: &User,
+        purpose: TokenPurpose,
+    ) -> Result<String> {
+        let token = utils::random_string(TOKEN_LENGTH);
+        let now = Utc::now();
+        let max_age = match purpose {
+            TokenPurpose::Reset => self.reset_max_age,
+            TokenPurpose::Invite => self.invite_max_age,
+        };
+
+        user_tokens::create(
+            conn,
+            &UserToken {
+                id: Uuid::new_v4(),
+                token_hash: utils::hash_token(&token),
+                user_id: user.id,
+                purpose,
+                created_at: now,
+                expires_at: now + max_age,
+                used_at: None,
+            },
+        )?;
+
+        Ok(token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mail::{FileMailer, Mailer};
+    use crate::test_helpers::*;
+    use std::{env, fs};
+
+    fn token_of(message: &Message) -> String {
+        let start = message.body.find("token=").unwrap() + "token=".len();
+        message.body[start..]
+            .split_whitespace()
+            .next()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_reset() {
+        let conn = connection();
+        let tokens = UserTokens::new("https://hamster.example.com/");
+        let policy = PasswordPolicy::default();
+        let mut user =
+            users::create_or_update(&conn, "bob", "Bob", "password").unwrap();
+        assert_eq!(None, tokens.reset(&conn, "bob").unwrap());
+
+        user = users::update(
+            &conn,
+            &user.id,
+            users::UpdateUser {
+                email: Some(Some("bob@example.com".to_string())),
+                ..users::UpdateUser::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(None, tokens.reset(&conn, "alice").unwrap());
+
+        let message = tokens.reset(&conn, "bob@example.com").unwrap().unwrap();
+        assert_eq!("bob@example.com", message.to);
+        assert!(message
+            .body
+            .contains("https://hamster.example.com/reset-password?token="));
+
+        let token = token_of(&message);
+        let err = tokens
+            .redeem(
+                &conn,
+                &policy,
+                TokenPurpose::Invite,
+                &token,
+                "new password",
+            )
+            .unwrap_err();
+        assert_eq!(ErrorKind::Unauthorized, err.kind());
+
+        tokens
+            .redeem(&conn, &policy, TokenPurpose::Reset, &token, "new password")
+            .unwrap();
+        let updated = users::find_by_id(&conn, &user.id).unwrap().unwrap();
+        assert!(
+            utils::verify_password("new password", &updated.password).unwrap()
+        );
+
+        let err = tokens
+            .redeem(
+                &conn,
+                &policy,
+                TokenPurpose::Reset,
+                &token,
+                "other password",
+            )
+            .unwrap_err();
+        assert_eq!(ErrorKind::Unauthorized, err.kind());
+    }
+
+    #[test]
+    fn test_invite() {
+        let conn = connection();
+        let tokens = UserTokens::new("http://localhost:8000");
+        let policy = PasswordPolicy::default();
+        let dir = env::temp_dir().join(Uuid::new_v4().simple().to_string());
+        let mailer = FileMailer::new(&dir);
+        let invitation = Invitation {
+            username: "carol".to_string(),
+            nickname: "Carol".to_string(),
+            email: "carol@example.com".to_string(),
+        };
+
+        let (user, message) =
+            tokens.invite(&conn, &policy, invitation).unwrap();
+        mailer.send(&message).unwrap();
+
+        let sent = mailer.messages().unwrap();
+        assert_eq!(1, sent.len());
+        assert_eq!("carol@example.com", sent[0].to);
+        let token = token_of(&sent[0]);
+        tokens
+            .redeem(
+                &conn,
+                &policy,
+                TokenPurpose::Invite,
+                &token,
+                "carol's password",
+            )
+            .unwrap();
+        let user = users::find_by_id(&conn, &user.id).unwrap().unwrap();
+        assert!(
+            utils::verify_password("carol's password", &user.password).unwrap()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
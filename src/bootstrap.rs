@@ -2,28 +2,46 @@ use std::{collections::HashMap, fs};
 
 use diesel::{Connection, PgConnection};
 
+use crate::auth::PasswordPolicy;
 use crate::db::{
     groups::{self, GroupMembershipType},
-    users,
+    permissions, users,
 };
-use crate::error::{ErrorKind, Result, ResultExt};
+use crate::error::{Error, ErrorKind, Result, ResultExt};
 use crate::utils;
 
 #[derive(Debug, PartialEq, Deserialize)]
 pub struct Config {
+    /// Groups whose members have to log in with a second factor.
+    #[serde(default)]
+    pub require_two_factor: Vec<String>,
+    /// Permission names and their descriptions.
+    #[serde(default)]
+    pub permissions: HashMap<String, String>,
     pub groups: HashMap<String, String>,
+    /// Permissions granted to groups, by group name.
+    #[serde(default)]
+    pub group_permissions: HashMap<String, Vec<String>>,
     pub users: HashMap<String, String>,
 }
 
-pub fn run(database_url: &str, config_path: &str) -> Result<()> {
+pub fn run(
+    database_url: &str,
+    config_path: &str,
+    policy: &PasswordPolicy,
+) -> Result<()> {
     let content = fs::read(config_path).context(ErrorKind::BootstrapError)?;
     let config = toml::from_slice::<Config>(&content)
         .context(ErrorKind::BootstrapError)?;
     let conn = PgConnection::establish(database_url)
         .context(ErrorKind::BootstrapError)?;
 
+    init_permissions(&conn, config.permissions)?;
     init_groups(&conn, config.groups)?;
-    init_users(&conn, config.users)?;
+    init_group_permissions(&conn, config.group_permissions)?;
+    init_two_factor(&conn, config.require_two_factor)?;
+    init_users(&conn, policy, config.users)?;
+    rehash_passwords(&conn, policy)?;
 
     Ok(())
 }
@@ -46,14 +64,77 @@ fn init_groups(
     Ok(())
 }
 
+fn init_permissions(
+    conn: &PgConnection,
+    permissions: HashMap<String, String>,
+) -> Result<()> {
+    for (name, desc) in permissions {
+        let permission = permissions::get_or_create(conn, &name)
+            .context(ErrorKind::BootstrapError)?;
+
+        match permission.description {
+            Some(ref description) if description == &desc => continue,
+            _ => permissions::update_desc(conn, &permission.id, &desc)
+                .context(ErrorKind::BootstrapError)?,
+        };
+    }
+
+    Ok(())
+}
+
+/// Grants the configured permissions, grants made at runtime are kept.
+fn init_group_permissions(
+    conn: &PgConnection,
+    group_permissions: HashMap<String, Vec<String>>,
+) -> Result<()> {
+    for (group_name, names) in group_permissions {
+        let group = groups::get_or_create(conn, &group_name)
+            .context(ErrorKind::BootstrapError)?;
+
+        for name in names {
+            let permission = permissions::get_or_create(conn, &name)
+                .context(ErrorKind::BootstrapError)?;
+            permissions::grant(conn, &group.id, &permission.id)
+                .context(ErrorKind::BootstrapError)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn init_two_factor(conn: &PgConnection, groups: Vec<String>) -> Result<()> {
+    for name in groups {
+        let group = groups::get_or_create(conn, &name)
+            .context(ErrorKind::BootstrapError)?;
+
+        if !group.require_two_factor {
+            groups::update_require_two_factor(conn, &group.id, true)
+                .context(ErrorKind::BootstrapError)?;
+        }
+    }
+
+    Ok(())
+}
+
 fn init_users(
     conn: &PgConnection,
+    policy: &PasswordPolicy,
     users: HashMap<String, String>,
 ) -> Result<()> {
     for (ref username, ref user_info) in users {
         let (nickname, password, groups) = parse_user_info(user_info)?;
-        let hashed_password = utils::hash_password(password)
-            .context(ErrorKind::BootstrapError)?;
+        if let Err(e) = policy.validate(password, username) {
+            let message = e.details().iter().map(|d| d.message.clone());
+            return Err(Error::from(ErrorKind::BootstrapError).with_detail(
+                format!(
+                    "password of user {} does not meet the policy: {}",
+                    username,
+                    message.collect::<String>()
+                ),
+            ));
+        }
+        let hashed_password =
+            policy.hash(password).context(ErrorKind::BootstrapError)?;
         let user =
             users::create_or_update(conn, username, nickname, &hashed_password)
                 .context(ErrorKind::BootstrapError)?;
@@ -76,6 +157,30 @@ fn init_users(
     Ok(())
 }
 
+/// Hashes passwords that were stored in plain text before user creation
+/// hashed them.
+fn rehash_passwords(
+    conn: &PgConnection,
+    policy: &PasswordPolicy,
+) -> Result<()> {
+    let users = users::find_all(conn).context(ErrorKind::BootstrapError)?;
+
+    for user in users {
+        if utils::is_bcrypt_hash(&user.password) {
+            continue;
+        }
+
+        warn!("Rehashing plain text password of user {}", &user.username);
+        let hashed_password = policy
+            .hash(&user.password)
+            .context(ErrorKind::BootstrapError)?;
+        users::update_password(conn, &user.id, &hashed_password)
+            .context(ErrorKind::BootstrapError)?;
+    }
+
+    Ok(())
+}
+
 fn parse_user_info(user_info: &str) -> Result<(&str, &str, Vec<&str>)> {
     let info = user_info.split('|').collect::<Vec<&str>>();
 
@@ -97,7 +202,10 @@ mod tests {
     impl Config {
         pub fn new() -> Self {
             Config {
+                require_two_factor: Vec::new(),
+                permissions: HashMap::new(),
                 groups: HashMap::new(),
+                group_permissions: HashMap::new(),
                 users: HashMap::new(),
             }
         }
@@ -107,6 +215,24 @@ mod tests {
             self
         }
 
+        pub fn insert_permission(
+            &mut self,
+            key: String,
+            val: String,
+        ) -> &mut Self {
+            self.permissions.insert(key, val);
+            self
+        }
+
+        pub fn insert_group_permissions(
+            &mut self,
+            key: String,
+            val: Vec<String>,
+        ) -> &mut Self {
+            self.group_permissions.insert(key, val);
+            self
+        }
+
         pub fn insert_user(&mut self, key: String, val: String) -> &mut Self {
             self.users.insert(key, val);
             self
@@ -115,12 +241,19 @@ mod tests {
 
     fn input_config() -> Config {
         let config: Config = toml::from_str(r#"
+                                            [permissions]
+                                            "*" = "Every permission"
+                                            "users.get" = "Read users"
+
                                             [groups]
                                             user  = "Act as a user in the system"
                                             admin = "Act as an administrator throughout the system"
 
+                                            [group_permissions]
+                                            admin = ["*"]
+
                                             [users]
-                                            bob = "Bob|123456|admin,user"
+                                            bob = "Bob|bobs-secret-42|admin,user"
                                             "#).unwrap();
 
         config
@@ -128,6 +261,12 @@ mod tests {
 
     fn expected_config() -> Config {
         let mut config = Config::new();
+        config
+            .insert_permission("*".to_string(), "Every permission".to_string())
+            .insert_permission(
+                "users.get".to_string(),
+                "Read users".to_string(),
+            );
         config.insert_group(
             "user".to_string(),
             "Act as a user in the system".to_string(),
@@ -137,9 +276,14 @@ mod tests {
             "Act as an administrator throughout the system".to_string(),
         );
 
+        config.insert_group_permissions(
+            "admin".to_string(),
+            vec!["*".to_string()],
+        );
+
         config.insert_user(
             "bob".to_string(),
-            "Bob|123456|admin,user".to_string(),
+            "Bob|bobs-secret-42|admin,user".to_string(),
         );
 
         config
@@ -168,13 +312,38 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_init_group_permissions() {
+        let conn = connection();
+        let input_config = input_config();
+
+        init_permissions(&conn, input_config.permissions).unwrap();
+        init_group_permissions(&conn, input_config.group_permissions.clone())
+            .unwrap();
+        // Running bootstrap again leaves the grants as they are.
+        init_group_permissions(&conn, input_config.group_permissions).unwrap();
+
+        let permission = permissions::find_by_name(&conn, "users.get")
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some("Read users".to_string()), permission.description);
+        let admin = groups::find_by_name(&conn, "admin").unwrap().unwrap();
+        let granted = permissions::find_by_group_id(&conn, &admin.id)
+            .unwrap()
+            .into_iter()
+            .map(|p| p.name)
+            .collect::<Vec<String>>();
+        assert_eq!(vec!["*"], granted);
+    }
+
     #[test]
     fn test_init_users() {
         let conn = connection();
         let input_config = input_config();
         let expected_users = expected_config().users;
 
-        init_users(&conn, input_config.users).unwrap();
+        init_users(&conn, &PasswordPolicy::default(), input_config.users)
+            .unwrap();
 
         for (username, user_info) in expected_users {
             let user =
@@ -197,6 +366,43 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_init_users_weak_password() {
+        let conn = connection();
+        let mut users = HashMap::new();
+        users.insert("bob".to_string(), "Bob|123456|user".to_string());
+
+        let err =
+            init_users(&conn, &PasswordPolicy::default(), users).unwrap_err();
+
+        assert_eq!(ErrorKind::BootstrapError, err.kind());
+        assert!(users::find_by_username(&conn, "bob").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_rehash_passwords() {
+        let conn = connection();
+        let user = users::create(
+            &conn,
+            users::NewUser {
+                username: "alice".to_string(),
+                password: "plaintext".to_string(),
+                nickname: "Alice".to_string(),
+                avatar_url: None,
+                email: None,
+            },
+        )
+        .unwrap();
+
+        rehash_passwords(&conn, &PasswordPolicy::default()).unwrap();
+
+        let user = users::find_by_username(&conn, &user.username)
+            .unwrap()
+            .unwrap();
+        assert!(utils::is_bcrypt_hash(&user.password));
+        assert!(utils::verify_password("plaintext", &user.password).unwrap());
+    }
+
     #[test]
     fn test_parse_user_info() {
         let (nickname, password, groups) =
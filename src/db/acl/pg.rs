@@ -0,0 +1,127 @@
+use chrono::prelude::*;
+use diesel::dsl::any;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use super::types::{AclEntry, NewAclEntry};
+use crate::db::Conn;
+use crate::error::{ErrorKind, Result, ResultExt};
+
+pub fn find_by_resource(
+    conn: &Conn,
+    resource_type: &str,
+    resource_id: &Uuid,
+) -> Result<Vec<AclEntry>> {
+    use crate::schema::acl_entries;
+
+    Ok(acl_entries::table
+        .filter(acl_entries::resource_type.eq(resource_type))
+        .filter(acl_entries::resource_id.eq(resource_id))
+        .order((acl_entries::action, acl_entries::created_at))
+        .load(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+/// Ids of the resources any of the principals may perform the action on.
+pub fn find_resource_ids(
+    conn: &Conn,
+    resource_type: &str,
+    action: &str,
+    principal_ids: &[Uuid],
+) -> Result<Vec<Uuid>> {
+    use crate::schema::acl_entries;
+
+    Ok(acl_entries::table
+        .select(acl_entries::resource_id)
+        .distinct()
+        .filter(acl_entries::resource_type.eq(resource_type))
+        .filter(acl_entries::action.eq(action))
+        .filter(acl_entries::principal_id.eq(any(principal_ids)))
+        .load(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+/// Returns `true` if any of the principals may perform the action on the
+/// resource.
+pub fn is_granted(
+    conn: &Conn,
+    resource_type: &str,
+    resource_id: &Uuid,
+    action: &str,
+    principal_ids: &[Uuid],
+) -> Result<bool> {
+    use crate::schema::acl_entries;
+    use diesel::dsl::exists;
+
+    Ok(diesel::select(exists(
+        acl_entries::table
+            .filter(acl_entries::resource_type.eq(resource_type))
+            .filter(acl_entries::resource_id.eq(resource_id))
+            .filter(acl_entries::action.eq(action))
+            .filter(acl_entries::principal_id.eq(any(principal_ids))),
+    ))
+    .get_result(conn)
+    .context(ErrorKind::DbError)?)
+}
+
+pub fn create(
+    conn: &Conn,
+    resource_type: &str,
+    resource_id: &Uuid,
+    new: NewAclEntry,
+) -> Result<AclEntry> {
+    use crate::schema::acl_entries;
+
+    Ok(diesel::insert_into(acl_entries::table)
+        .values(&AclEntry {
+            id: Uuid::new_v4(),
+            resource_type: resource_type.to_string(),
+            resource_id: *resource_id,
+            principal_id: new.principal_id,
+            principal_type: new.principal_type,
+            action: new.action,
+            created_at: Utc::now(),
+        })
+        .get_result(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn del_by_id(
+    conn: &Conn,
+    resource_type: &str,
+    resource_id: &Uuid,
+    id: &Uuid,
+) -> Result<usize> {
+    use crate::schema::acl_entries;
+
+    Ok(diesel::delete(acl_entries::table.find(id))
+        .filter(acl_entries::resource_type.eq(resource_type))
+        .filter(acl_entries::resource_id.eq(resource_id))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+/// Deletes the entries of a resource, before it is deleted.
+pub fn del_by_resource(
+    conn: &Conn,
+    resource_type: &str,
+    resource_id: &Uuid,
+) -> Result<usize> {
+    use crate::schema::acl_entries;
+
+    Ok(diesel::delete(acl_entries::table)
+        .filter(acl_entries::resource_type.eq(resource_type))
+        .filter(acl_entries::resource_id.eq(resource_id))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+/// Deletes the entries of a user or group, before it is deleted.
+pub fn del_by_principal_id(conn: &Conn, principal_id: &Uuid) -> Result<usize> {
+    use crate::schema::acl_entries;
+
+    Ok(diesel::delete(acl_entries::table)
+        .filter(acl_entries::principal_id.eq(principal_id))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
@@ -0,0 +1,26 @@
+use chrono::prelude::*;
+use uuid::Uuid;
+
+use crate::db::groups::GroupMembershipType;
+use crate::schema::acl_entries;
+
+/// Allows a user, or the members of a group, to perform an action on a
+/// single resource.
+#[derive(Debug, Clone, PartialEq, Serialize, Queryable, Insertable)]
+#[table_name = "acl_entries"]
+pub struct AclEntry {
+    pub id: Uuid,
+    pub resource_type: String,
+    pub resource_id: Uuid,
+    pub principal_id: Uuid,
+    pub principal_type: GroupMembershipType,
+    pub action: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct NewAclEntry {
+    pub principal_id: Uuid,
+    pub principal_type: GroupMembershipType,
+    pub action: String,
+}
@@ -0,0 +1,63 @@
+use chrono::prelude::*;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use super::types::ApiKey;
+use crate::db::Conn;
+use crate::error::{ErrorKind, Result, ResultExt};
+
+pub fn find_by_user_id(conn: &Conn, user_id: &Uuid) -> Result<Vec<ApiKey>> {
+    use crate::schema::api_keys;
+
+    Ok(api_keys::table
+        .filter(api_keys::user_id.eq(user_id))
+        .order(api_keys::created_at)
+        .load(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn find_by_prefix(conn: &Conn, prefix: &str) -> Result<Option<ApiKey>> {
+    use crate::schema::api_keys;
+
+    Ok(api_keys::table
+        .filter(api_keys::prefix.eq(prefix))
+        .first(conn)
+        .optional()
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn create(conn: &Conn, api_key: &ApiKey) -> Result<ApiKey> {
+    use crate::schema::api_keys;
+
+    Ok(diesel::insert_into(api_keys::table)
+        .values(api_key)
+        .get_result(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn touch(conn: &Conn, id: &Uuid, used_at: DateTime<Utc>) -> Result<usize> {
+    use crate::schema::api_keys;
+
+    Ok(diesel::update(api_keys::table.find(id))
+        .set(api_keys::last_used_at.eq(used_at))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn del_by_id(conn: &Conn, user_id: &Uuid, id: &Uuid) -> Result<usize> {
+    use crate::schema::api_keys;
+
+    Ok(diesel::delete(api_keys::table.find(id))
+        .filter(api_keys::user_id.eq(user_id))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn del_by_user_id(conn: &Conn, user_id: &Uuid) -> Result<usize> {
+    use crate::schema::api_keys;
+
+    Ok(diesel::delete(api_keys::table)
+        .filter(api_keys::user_id.eq(user_id))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
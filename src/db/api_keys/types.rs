@@ -0,0 +1,26 @@
+use chrono::prelude::*;
+use uuid::Uuid;
+
+use crate::schema::api_keys;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Insertable, Queryable)]
+#[table_name = "api_keys"]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    #[serde(skip_serializing)]
+    pub secret_hash: String,
+    /// Authorities the key is limited to, all of the owner's if `None`.
+    pub authorities: Option<Vec<String>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ApiKey {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.map(|e| e <= now).unwrap_or(false)
+    }
+}
@@ -0,0 +1,74 @@
+use chrono::prelude::*;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use super::types::{AuditEntry, NewAuditEntry};
+use crate::db::page::{self, FilterOp, Page, PageRequest};
+use crate::db::Conn;
+use crate::error::{ErrorKind, Result, ResultExt};
+use crate::schema;
+
+/// Finds a page of entries, most recent first.
+pub fn find_page(
+    conn: &Conn,
+    request: &PageRequest,
+) -> Result<Page<AuditEntry>> {
+    use crate::schema::audit_entries;
+
+    if let Some(sort) = request.sort.first() {
+        return Err(page::unknown_sort(sort));
+    }
+
+    let total = filtered(request)?
+        .count()
+        .get_result(conn)
+        .context(ErrorKind::DbError)?;
+    let items = filtered(request)?
+        .order((audit_entries::created_at.desc(), audit_entries::id.asc()))
+        .limit(request.per_page)
+        .offset(request.offset())
+        .load(conn)
+        .context(ErrorKind::DbError)?;
+
+    Ok(Page::new(items, total, request))
+}
+
+pub fn create(conn: &Conn, new: NewAuditEntry) -> Result<AuditEntry> {
+    use crate::schema::audit_entries;
+
+    Ok(diesel::insert_into(audit_entries::table)
+        .values(&AuditEntry {
+            id: Uuid::new_v4(),
+            actor: new.actor,
+            identity: new.identity,
+            method: new.method,
+            path: new.path,
+            status: new.status,
+            created_at: Utc::now(),
+        })
+        .get_result(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+fn filtered(
+    request: &PageRequest,
+) -> Result<schema::audit_entries::BoxedQuery<'static, Pg>> {
+    use crate::schema::audit_entries;
+
+    let mut query = audit_entries::table.into_boxed();
+    for filter in &request.filters {
+        let value = filter.value.clone();
+        query = match (filter.field.as_str(), filter.op) {
+            ("actor", FilterOp::Eq) => {
+                query.filter(audit_entries::actor.eq(value))
+            }
+            ("identity", FilterOp::Eq) => {
+                query.filter(audit_entries::identity.eq(value))
+            }
+            _ => return Err(page::unknown_filter(filter)),
+        };
+    }
+
+    Ok(query)
+}
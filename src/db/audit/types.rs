@@ -0,0 +1,28 @@
+use chrono::prelude::*;
+use uuid::Uuid;
+
+use crate::schema::audit_entries;
+
+/// Request an administrator made while impersonating a user.
+#[derive(Debug, Clone, PartialEq, Serialize, Queryable, Insertable)]
+#[table_name = "audit_entries"]
+pub struct AuditEntry {
+    pub id: Uuid,
+    /// Identity of the administrator.
+    pub actor: String,
+    /// Identity of the impersonated user.
+    pub identity: String,
+    pub method: String,
+    pub path: String,
+    pub status: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewAuditEntry {
+    pub actor: String,
+    pub identity: String,
+    pub method: String,
+    pub path: String,
+    pub status: i32,
+}
@@ -0,0 +1,90 @@
+use chrono::prelude::*;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use super::types::{ExternalIdentity, ExternalLogin};
+use crate::db::Conn;
+use crate::error::{ErrorKind, Result, ResultExt};
+
+pub fn find(
+    conn: &Conn,
+    issuer: &str,
+    subject: &str,
+) -> Result<Option<ExternalIdentity>> {
+    use crate::schema::external_identities;
+
+    Ok(external_identities::table
+        .filter(external_identities::issuer.eq(issuer))
+        .filter(external_identities::subject.eq(subject))
+        .first(conn)
+        .optional()
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn create(
+    conn: &Conn,
+    identity: &ExternalIdentity,
+) -> Result<ExternalIdentity> {
+    use crate::schema::external_identities;
+
+    Ok(diesel::insert_into(external_identities::table)
+        .values(identity)
+        .get_result(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+/// Records a login along with the groups now synced from the provider.
+pub fn touch(
+    conn: &Conn,
+    id: &Uuid,
+    synced_group_ids: &[Uuid],
+    login_at: DateTime<Utc>,
+) -> Result<usize> {
+    use crate::schema::external_identities;
+
+    Ok(diesel::update(external_identities::table.find(id))
+        .set((
+            external_identities::synced_group_ids.eq(synced_group_ids),
+            external_identities::last_login_at.eq(login_at),
+        ))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn del_by_user_id(conn: &Conn, user_id: &Uuid) -> Result<usize> {
+    use crate::schema::external_identities;
+
+    Ok(diesel::delete(external_identities::table)
+        .filter(external_identities::user_id.eq(user_id))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn create_login(conn: &Conn, login: &ExternalLogin) -> Result<usize> {
+    use crate::schema::external_logins;
+
+    Ok(diesel::insert_into(external_logins::table)
+        .values(login)
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+/// Removes and returns the unexpired login with the state, so that each can
+/// only be completed once. Expired logins are cleaned up on the way.
+pub fn take_login(
+    conn: &Conn,
+    state_hash: &str,
+    now: DateTime<Utc>,
+) -> Result<Option<ExternalLogin>> {
+    use crate::schema::external_logins;
+
+    diesel::delete(external_logins::table)
+        .filter(external_logins::expires_at.le(now))
+        .execute(conn)
+        .context(ErrorKind::DbError)?;
+
+    Ok(diesel::delete(external_logins::table.find(state_hash))
+        .get_result(conn)
+        .optional()
+        .context(ErrorKind::DbError)?)
+}
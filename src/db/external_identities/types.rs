@@ -0,0 +1,33 @@
+use chrono::prelude::*;
+use uuid::Uuid;
+
+use crate::schema::{external_identities, external_logins};
+
+/// Link of a subject at an external identity provider to a user.
+#[derive(Debug, Clone, PartialEq, Serialize, Insertable, Queryable)]
+#[table_name = "external_identities"]
+pub struct ExternalIdentity {
+    pub id: Uuid,
+    pub issuer: String,
+    pub subject: String,
+    pub user_id: Uuid,
+    /// Groups joined because of the groups claim, left again once the claim
+    /// no longer names them.
+    pub synced_group_ids: Vec<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub last_login_at: Option<DateTime<Utc>>,
+}
+
+/// Login started at an external identity provider, waiting for the user
+/// agent to come back with the code.
+#[derive(Debug, Clone, PartialEq, Insertable, Queryable)]
+#[table_name = "external_logins"]
+pub struct ExternalLogin {
+    pub state_hash: String,
+    /// Hash of the cookie tying the login to the user agent that started it.
+    pub binding_hash: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
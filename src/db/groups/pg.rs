@@ -1,19 +1,67 @@
 use chrono::prelude::*;
+use diesel::pg::Pg;
 use diesel::prelude::*;
 use uuid::Uuid;
 
 use super::types::{
-    Group, GroupMembership, GroupMembershipType, NewGroup, UpdateGroup,
+    Group, GroupManager, GroupMembership, GroupMembershipType, NewGroup,
+    UpdateGroup,
 };
+use crate::db::page::{self, Direction, FilterOp, Page, PageRequest};
 use crate::db::{self, Conn};
-use crate::error::{ErrorKind, Result, ResultExt};
+use crate::error::{Error, ErrorKind, Result, ResultExt};
+use crate::schema;
 
-pub fn find_all(conn: &Conn) -> Result<Vec<Group>> {
-    use crate::schema::groups::dsl::*;
+/// Finds a page of groups, only among `ids` if given.
+pub fn find_page(
+    conn: &Conn,
+    request: &PageRequest,
+    ids: Option<&[Uuid]>,
+) -> Result<Page<Group>> {
+    use crate::schema::groups;
 
-    let result = groups.load::<Group>(conn).context(ErrorKind::DbError)?;
+    let total = filtered(request, ids)?
+        .count()
+        .get_result(conn)
+        .context(ErrorKind::DbError)?;
 
-    Ok(result)
+    let mut query = filtered(request, ids)?;
+    for sort in &request.sort {
+        query = match (sort.field.as_str(), sort.direction) {
+            ("display_name", Direction::Asc) => {
+                query.then_order_by(groups::display_name.asc())
+            }
+            ("display_name", Direction::Desc) => {
+                query.then_order_by(groups::display_name.desc())
+            }
+            ("created_at", Direction::Asc) => {
+                query.then_order_by(groups::created_at.asc())
+            }
+            ("created_at", Direction::Desc) => {
+                query.then_order_by(groups::created_at.desc())
+            }
+            _ => return Err(page::unknown_sort(sort)),
+        };
+    }
+
+    let items = query
+        .then_order_by(groups::id.asc())
+        .limit(request.per_page)
+        .offset(request.offset())
+        .load(conn)
+        .context(ErrorKind::DbError)?;
+
+    Ok(Page::new(items, total, request))
+}
+
+pub fn find_by_id(conn: &Conn, group_id: &Uuid) -> Result<Option<Group>> {
+    use crate::schema::groups::dsl::*;
+
+    Ok(groups
+        .find(group_id)
+        .first::<Group>(conn)
+        .optional()
+        .context(ErrorKind::DbError)?)
 }
 
 pub fn find_by_name(conn: &Conn, name: &str) -> Result<Option<Group>> {
@@ -26,19 +74,53 @@ pub fn find_by_name(conn: &Conn, name: &str) -> Result<Option<Group>> {
         .context(ErrorKind::DbError)?)
 }
 
-pub fn find_by_member_id(conn: &Conn, member_id: &Uuid) -> Result<Vec<Group>> {
-    let mut result = Vec::new();
+/// Maximum depth of nested group membership that is resolved.
+pub const MAX_GROUP_DEPTH: i32 = 32;
 
-    let mut member_ids = vec![*member_id];
-    let mut groups = find_groups_by_member_ids(conn, &member_ids)?;
+/// Finds the groups the member belongs to, directly or through nested groups.
+///
+/// The transitive closure is resolved in a single recursive query, every
+/// path stops at a group it has already visited or at `MAX_GROUP_DEPTH`.
+pub fn find_by_member_id(conn: &Conn, member_id: &Uuid) -> Result<Vec<Group>> {
+    use diesel::sql_types::{Integer, Uuid as SqlUuid};
+
+    Ok(diesel::sql_query(
+        "with recursive member_groups(id, path) as (
+             select group_id, array[group_id]
+             from group_membership
+             where member_id = $1
+           union all
+             select gm.group_id, mg.path || gm.group_id
+             from group_membership gm
+             join member_groups mg on gm.member_id = mg.id
+             where gm.group_id <> all(mg.path)
+               and cardinality(mg.path) < $2
+         )
+         select g.* from groups g
+         where g.id in (select id from member_groups)
+         order by g.display_name",
+    )
+    .bind::<SqlUuid, _>(member_id)
+    .bind::<Integer, _>(MAX_GROUP_DEPTH)
+    .load(conn)
+    .context(ErrorKind::DbError)?)
+}
 
-    while !groups.is_empty() {
-        member_ids = groups.iter().map(|g| g.id).collect();
-        result.append(&mut groups);
-        groups = find_groups_by_member_ids(conn, &member_ids)?;
-    }
+/// Finds the groups the members directly belong to, as `(member_id, group)`.
+pub fn find_direct_by_member_ids(
+    conn: &Conn,
+    member_ids: &[Uuid],
+) -> Result<Vec<(Uuid, Group)>> {
+    use crate::schema::{group_membership, groups};
+    use diesel::dsl::any;
 
-    Ok(result)
+    Ok(group_membership::table
+        .inner_join(groups::table)
+        .select((group_membership::member_id, groups::all_columns))
+        .filter(group_membership::member_id.eq(any(member_ids)))
+        .order(groups::display_name)
+        .load(conn)
+        .context(ErrorKind::DbError)?)
 }
 
 pub fn get_or_create(conn: &Conn, name: &str) -> Result<Group> {
@@ -85,17 +167,25 @@ pub fn update(
     conn: &Conn,
     group_id: &Uuid,
     update: UpdateGroup,
-) -> Result<usize> {
+) -> Result<Group> {
     use crate::schema::groups::dsl::*;
 
-    Ok(diesel::update(groups.find(group_id))
+    let mut result: Group = diesel::update(groups.find(group_id))
         .set((
             display_name.eq(&update.display_name),
             description.eq(&update.description),
             updated_at.eq(Utc::now()),
         ))
-        .execute(conn)
-        .context(ErrorKind::DbError)?)
+        .get_result(conn)
+        .context(ErrorKind::DbError)?;
+    if let Some(required) = update.require_two_factor {
+        update_require_two_factor(conn, group_id, required)?;
+        result.require_two_factor = required;
+    }
+    // The display name is the authority granted to members.
+    bump_membership_epochs(conn, group_id)?;
+
+    Ok(result)
 }
 
 pub fn update_desc(conn: &Conn, group_id: &Uuid, desc: &str) -> Result<usize> {
@@ -107,6 +197,35 @@ pub fn update_desc(conn: &Conn, group_id: &Uuid, desc: &str) -> Result<usize> {
         .context(ErrorKind::DbError)?)
 }
 
+pub fn update_require_two_factor(
+    conn: &Conn,
+    group_id: &Uuid,
+    required: bool,
+) -> Result<usize> {
+    use crate::schema::groups::dsl::*;
+
+    Ok(diesel::update(groups.find(group_id))
+        .set(require_two_factor.eq(required))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+/// Deletes a group and everything referring to it, returns `0` if the group
+/// does not exist. Has to run in a transaction.
+pub fn del_cascade(conn: &Conn, group_id: &Uuid) -> Result<usize> {
+    use crate::auth::acl::GROUPS;
+    use crate::db::{acl, permissions};
+
+    acl::del_by_resource(conn, GROUPS, group_id)?;
+    acl::del_by_principal_id(conn, group_id)?;
+    del_managers_by_group_id(conn, group_id)?;
+    permissions::revoke_by_group_id(conn, group_id)?;
+    del_members_by_member_id(conn, group_id)?;
+    del_members_by_group_id(conn, group_id)?;
+
+    del_by_id(conn, group_id)
+}
+
 pub fn del_by_id(conn: &Conn, group_id: &Uuid) -> Result<usize> {
     use crate::schema::groups;
 
@@ -133,6 +252,10 @@ pub fn add_member(
     if let Some(result) = member {
         Ok(result)
     } else {
+        if member_type == GroupMembershipType::Group {
+            check_cycle(conn, group_id, member_id)?;
+        }
+
         let result = diesel::insert_into(group_membership::table)
             .values((
                 group_membership::group_id.eq(&group_id),
@@ -141,14 +264,61 @@ pub fn add_member(
             ))
             .get_result(conn)
             .context(ErrorKind::DbError)?;
+        bump_membership_epochs(conn, member_id)?;
 
         Ok(result)
     }
 }
 
+pub fn find_members(
+    conn: &Conn,
+    group_id: &Uuid,
+) -> Result<Vec<GroupMembership>> {
+    use crate::schema::group_membership;
+
+    Ok(group_membership::table
+        .filter(group_membership::group_id.eq(group_id))
+        .order(group_membership::added)
+        .load(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn find_members_by_group_ids(
+    conn: &Conn,
+    group_ids: &[Uuid],
+) -> Result<Vec<GroupMembership>> {
+    use crate::schema::group_membership;
+    use diesel::dsl::any;
+
+    Ok(group_membership::table
+        .filter(group_membership::group_id.eq(any(group_ids)))
+        .order(group_membership::added)
+        .load(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn del_member(
+    conn: &Conn,
+    group_id: &Uuid,
+    member_id: &Uuid,
+) -> Result<usize> {
+    use crate::schema::group_membership;
+
+    let result =
+        diesel::delete(group_membership::table.find((group_id, member_id)))
+            .execute(conn)
+            .context(ErrorKind::DbError)?;
+    if result > 0 {
+        bump_membership_epochs(conn, member_id)?;
+    }
+
+    Ok(result)
+}
+
 pub fn del_members_by_group_id(conn: &Conn, group_id: &Uuid) -> Result<usize> {
     use crate::schema::group_membership;
 
+    bump_membership_epochs(conn, group_id)?;
     Ok(diesel::delete(group_membership::table)
         .filter(group_membership::group_id.eq(group_id))
         .execute(conn)
@@ -161,23 +331,254 @@ pub fn del_members_by_member_id(
 ) -> Result<usize> {
     use crate::schema::group_membership;
 
+    bump_membership_epochs(conn, member_id)?;
     Ok(diesel::delete(group_membership::table)
         .filter(group_membership::member_id.eq(member_id))
         .execute(conn)
         .context(ErrorKind::DbError)?)
 }
 
-fn find_groups_by_member_ids(
+pub fn find_managers(
     conn: &Conn,
-    member_ids: &[Uuid],
-) -> Result<Vec<Group>> {
-    use crate::schema::{group_membership, groups};
-    use diesel::dsl::any;
+    group_id: &Uuid,
+) -> Result<Vec<GroupManager>> {
+    use crate::schema::group_managers;
 
-    Ok(group_membership::table
-        .inner_join(groups::table)
-        .select(groups::all_columns)
-        .filter(group_membership::member_id.eq(any(member_ids)))
+    Ok(group_managers::table
+        .filter(group_managers::group_id.eq(group_id))
+        .order(group_managers::added)
+        .load(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+/// Ids of the groups the user manages.
+pub fn find_managed_ids(conn: &Conn, user_id: &Uuid) -> Result<Vec<Uuid>> {
+    use crate::schema::group_managers;
+
+    Ok(group_managers::table
+        .select(group_managers::group_id)
+        .filter(group_managers::user_id.eq(user_id))
         .load(conn)
         .context(ErrorKind::DbError)?)
 }
+
+pub fn add_manager(
+    conn: &Conn,
+    group_id: &Uuid,
+    user_id: &Uuid,
+) -> Result<GroupManager> {
+    use crate::schema::group_managers;
+
+    let manager = group_managers::table
+        .find((group_id, user_id))
+        .first(conn)
+        .optional()
+        .context(ErrorKind::DbError)?;
+
+    match manager {
+        Some(manager) => Ok(manager),
+        None => Ok(diesel::insert_into(group_managers::table)
+            .values((
+                group_managers::group_id.eq(group_id),
+                group_managers::user_id.eq(user_id),
+            ))
+            .get_result(conn)
+            .context(ErrorKind::DbError)?),
+    }
+}
+
+pub fn del_manager(
+    conn: &Conn,
+    group_id: &Uuid,
+    user_id: &Uuid,
+) -> Result<usize> {
+    use crate::schema::group_managers;
+
+    Ok(
+        diesel::delete(group_managers::table.find((group_id, user_id)))
+            .execute(conn)
+            .context(ErrorKind::DbError)?,
+    )
+}
+
+pub fn del_managers_by_group_id(conn: &Conn, group_id: &Uuid) -> Result<usize> {
+    use crate::schema::group_managers;
+
+    Ok(diesel::delete(group_managers::table)
+        .filter(group_managers::group_id.eq(group_id))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn del_managers_by_user_id(conn: &Conn, user_id: &Uuid) -> Result<usize> {
+    use crate::schema::group_managers;
+
+    Ok(diesel::delete(group_managers::table)
+        .filter(group_managers::user_id.eq(user_id))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+fn filtered(
+    request: &PageRequest,
+    ids: Option<&[Uuid]>,
+) -> Result<schema::groups::BoxedQuery<'static, Pg>> {
+    use crate::schema::groups;
+    use diesel::dsl::any;
+
+    let mut query = groups::table.into_boxed();
+    if let Some(ids) = ids {
+        query = query.filter(groups::id.eq(any(ids.to_vec())));
+    }
+    for filter in &request.filters {
+        let value = filter.value.clone();
+        query = match (filter.field.as_str(), filter.op) {
+            ("display_name", FilterOp::Eq) => {
+                query.filter(groups::display_name.eq(value))
+            }
+            ("display_name", FilterOp::Contains) => query.filter(
+                groups::display_name.ilike(page::contains_pattern(&value)),
+            ),
+            _ => return Err(page::unknown_filter(filter)),
+        };
+    }
+
+    Ok(query)
+}
+
+/// Bumps the membership epoch of the member, if it is a user, or of all users
+/// directly or indirectly belonging to it, if it is a group.
+pub fn bump_membership_epochs(conn: &Conn, member_id: &Uuid) -> Result<usize> {
+    use diesel::sql_types::{Integer, Uuid as SqlUuid};
+
+    Ok(diesel::sql_query(
+        "with recursive members(id, path) as (
+             select $1, array[$1]
+             union all
+             select gm.member_id, m.path || gm.member_id
+             from group_membership gm
+             join members m on gm.group_id = m.id
+             where gm.member_id <> all(m.path)
+               and cardinality(m.path) < $2
+         )
+         update users set membership_epoch = membership_epoch + 1
+         where id in (select id from members)",
+    )
+    .bind::<SqlUuid, _>(member_id)
+    .bind::<Integer, _>(MAX_GROUP_DEPTH)
+    .execute(conn)
+    .context(ErrorKind::DbError)?)
+}
+
+/// Fails if making `member_id` a member of `group_id` creates a cycle, that is
+/// `group_id` already belongs to `member_id`.
+fn check_cycle(conn: &Conn, group_id: &Uuid, member_id: &Uuid) -> Result<()> {
+    let creates_cycle = group_id == member_id
+        || find_by_member_id(conn, group_id)?
+            .iter()
+            .any(|g| &g.id == member_id);
+
+    if creates_cycle {
+        Err(Error::validation(
+            "member_id",
+            "group membership would create a cycle",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+
+    fn add_group_member(
+        conn: &Conn,
+        group: &Group,
+        member: &Group,
+    ) -> Result<()> {
+        add_member(conn, &group.id, &member.id, GroupMembershipType::Group)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_by_member_id() {
+        let conn = connection();
+        let a = get_or_create(&conn, "a").unwrap();
+        let b = get_or_create(&conn, "b").unwrap();
+        let c = get_or_create(&conn, "c").unwrap();
+        let user_id = Uuid::new_v4();
+
+        add_member(&conn, &a.id, &user_id, GroupMembershipType::User).unwrap();
+        add_group_member(&conn, &b, &a).unwrap();
+        add_group_member(&conn, &c, &b).unwrap();
+
+        let groups = find_by_member_id(&conn, &user_id)
+            .unwrap()
+            .into_iter()
+            .map(|g| g.display_name)
+            .collect::<Vec<String>>();
+
+        assert_eq!(vec!["a", "b", "c"], groups);
+    }
+
+    #[test]
+    fn test_update() {
+        let conn = connection();
+        let group = get_or_create(&conn, "a").unwrap();
+        let update_group = || UpdateGroup {
+            display_name: "b".to_string(),
+            description: Some("B".to_string()),
+            require_two_factor: None,
+        };
+
+        let updated = update(&conn, &group.id, update_group()).unwrap();
+        assert_eq!("b", updated.display_name);
+
+        let err = update(&conn, &Uuid::new_v4(), update_group()).unwrap_err();
+        assert_eq!(ErrorKind::NotFound, err.kind());
+    }
+
+    #[test]
+    fn test_add_member_rejects_cycle() {
+        let conn = connection();
+        let a = get_or_create(&conn, "a").unwrap();
+        let b = get_or_create(&conn, "b").unwrap();
+        let c = get_or_create(&conn, "c").unwrap();
+
+        add_group_member(&conn, &b, &a).unwrap();
+        add_group_member(&conn, &c, &b).unwrap();
+
+        let err = add_group_member(&conn, &a, &c).unwrap_err();
+        assert_eq!(ErrorKind::Validation, err.kind());
+
+        let err = add_group_member(&conn, &a, &a).unwrap_err();
+        assert_eq!(ErrorKind::Validation, err.kind());
+    }
+
+    #[test]
+    fn test_find_by_member_id_with_cycle() {
+        use crate::schema::group_membership;
+
+        let conn = connection();
+        let a = get_or_create(&conn, "a").unwrap();
+        let b = get_or_create(&conn, "b").unwrap();
+        let user_id = Uuid::new_v4();
+
+        add_member(&conn, &a.id, &user_id, GroupMembershipType::User).unwrap();
+        add_group_member(&conn, &b, &a).unwrap();
+        diesel::insert_into(group_membership::table)
+            .values((
+                group_membership::group_id.eq(&a.id),
+                group_membership::member_id.eq(&b.id),
+                group_membership::member_type.eq(GroupMembershipType::Group),
+            ))
+            .execute(&conn)
+            .unwrap();
+
+        let groups = find_by_member_id(&conn, &user_id).unwrap();
+
+        assert_eq!(2, groups.len());
+    }
+}
@@ -9,7 +9,15 @@ use uuid::Uuid;
 
 use crate::schema::groups;
 
-#[derive(Debug, PartialEq, Deserialize, Serialize, Insertable, Queryable)]
+#[derive(
+    Debug,
+    PartialEq,
+    Deserialize,
+    Serialize,
+    Insertable,
+    Queryable,
+    QueryableByName,
+)]
 #[table_name = "groups"]
 pub struct Group {
     pub id: Uuid,
@@ -17,6 +25,9 @@ pub struct Group {
     pub description: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// Members have to log in with a second factor.
+    #[serde(default)]
+    pub require_two_factor: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -29,6 +40,9 @@ pub struct NewGroup {
 pub struct UpdateGroup {
     pub display_name: String,
     pub description: Option<String>,
+    /// Left unchanged if absent.
+    #[serde(default)]
+    pub require_two_factor: Option<bool>,
 }
 
 #[derive(Debug, PartialEq, Deserialize, Serialize, Queryable)]
@@ -39,7 +53,36 @@ pub struct GroupMembership {
     pub added: DateTime<Utc>,
 }
 
-#[derive(Debug, PartialEq, Deserialize, Serialize, FromSqlRow, AsExpression)]
+#[derive(Debug, Deserialize, Serialize)]
+pub struct NewGroupMembership {
+    pub member_id: Uuid,
+    pub member_type: GroupMembershipType,
+}
+
+/// User allowed to manage the members and description of a group.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Queryable)]
+pub struct GroupManager {
+    pub group_id: Uuid,
+    pub user_id: Uuid,
+    pub added: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct NewGroupManager {
+    pub user_id: Uuid,
+}
+
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Deserialize,
+    Serialize,
+    FromSqlRow,
+    AsExpression,
+)]
+#[serde(rename_all = "lowercase")]
 #[sql_type = "Text"]
 pub enum GroupMembershipType {
     User,
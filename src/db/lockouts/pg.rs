@@ -0,0 +1,134 @@
+use chrono::prelude::*;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use super::types::{Lockout, LockoutKind};
+use crate::db::Conn;
+use crate::error::{ErrorKind, Result, ResultExt};
+
+pub fn find_all(conn: &Conn) -> Result<Vec<Lockout>> {
+    use crate::schema::login_failures;
+
+    Ok(login_failures::table
+        .order(login_failures::last_failed_at.desc())
+        .load(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn find(
+    conn: &Conn,
+    kind: LockoutKind,
+    subject: &str,
+) -> Result<Option<Lockout>> {
+    use crate::schema::login_failures;
+
+    Ok(login_failures::table
+        .filter(login_failures::kind.eq(kind))
+        .filter(login_failures::subject.eq(subject))
+        .first(conn)
+        .optional()
+        .context(ErrorKind::DbError)?)
+}
+
+/// Counts a failed login, failures older than `reset_before` are forgotten.
+pub fn record_failure(
+    conn: &Conn,
+    kind: LockoutKind,
+    subject: &str,
+    failed_at: DateTime<Utc>,
+    reset_before: DateTime<Utc>,
+) -> Result<Lockout> {
+    use diesel::sql_types::{Text, Timestamptz, Uuid as SqlUuid};
+
+    Ok(diesel::sql_query(
+        "insert into login_failures
+           (id, kind, subject, failures, last_failed_at)
+         values ($1, $2, $3, 1, $4)
+         on conflict (kind, subject) do update set
+           failures = case
+             when login_failures.last_failed_at < $5 then 1
+             else login_failures.failures + 1
+           end,
+           last_failed_at = excluded.last_failed_at
+         returning *",
+    )
+    .bind::<SqlUuid, _>(Uuid::new_v4())
+    .bind::<Text, _>(kind)
+    .bind::<Text, _>(subject)
+    .bind::<Timestamptz, _>(failed_at)
+    .bind::<Timestamptz, _>(reset_before)
+    .get_result(conn)
+    .context(ErrorKind::DbError)?)
+}
+
+pub fn lock(
+    conn: &Conn,
+    lockout_id: &Uuid,
+    locked_until: DateTime<Utc>,
+) -> Result<usize> {
+    use crate::schema::login_failures;
+
+    Ok(diesel::update(login_failures::table.find(lockout_id))
+        .set(login_failures::locked_until.eq(locked_until))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn del_by_id(conn: &Conn, lockout_id: &Uuid) -> Result<usize> {
+    use crate::schema::login_failures;
+
+    Ok(diesel::delete(login_failures::table.find(lockout_id))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn del(conn: &Conn, kind: LockoutKind, subject: &str) -> Result<usize> {
+    use crate::schema::login_failures;
+
+    Ok(diesel::delete(login_failures::table)
+        .filter(login_failures::kind.eq(kind))
+        .filter(login_failures::subject.eq(subject))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_record_failure() {
+        let conn = connection();
+        let now = Utc::now();
+        let reset_before = now - Duration::hours(1);
+
+        record_failure(&conn, LockoutKind::Username, "bob", now, reset_before)
+            .unwrap();
+        let lockout = record_failure(
+            &conn,
+            LockoutKind::Username,
+            "bob",
+            now,
+            reset_before,
+        )
+        .unwrap();
+        assert_eq!(2, lockout.failures);
+
+        let later = now + Duration::hours(2);
+        let lockout = record_failure(
+            &conn,
+            LockoutKind::Username,
+            "bob",
+            later,
+            later - Duration::hours(1),
+        )
+        .unwrap();
+        assert_eq!(1, lockout.failures);
+        assert_eq!(None, find(&conn, LockoutKind::Ip, "bob").unwrap());
+
+        assert_eq!(1, del(&conn, LockoutKind::Username, "bob").unwrap());
+        assert!(find_all(&conn).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,74 @@
+use std::io;
+
+use chrono::prelude::*;
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::Pg;
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::Text;
+use uuid::Uuid;
+
+use crate::schema::login_failures;
+
+/// Failed logins, or reset requests, of a username or a client address.
+#[derive(Debug, Clone, PartialEq, Serialize, Queryable, QueryableByName)]
+#[table_name = "login_failures"]
+pub struct Lockout {
+    pub id: Uuid,
+    pub kind: LockoutKind,
+    pub subject: String,
+    pub failures: i32,
+    pub last_failed_at: DateTime<Utc>,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Deserialize,
+    Serialize,
+    FromSqlRow,
+    AsExpression,
+)]
+#[serde(rename_all = "lowercase")]
+#[sql_type = "Text"]
+pub enum LockoutKind {
+    Username,
+    Ip,
+    /// Password reset requests for a username or email address.
+    #[serde(rename = "reset")]
+    Reset,
+    /// Password reset requests from a client address.
+    #[serde(rename = "reset_ip")]
+    ResetIp,
+}
+
+impl ToSql<Text, Pg> for LockoutKind {
+    fn to_sql<W: io::Write>(
+        &self,
+        out: &mut Output<W, Pg>,
+    ) -> serialize::Result {
+        use self::LockoutKind::*;
+        match *self {
+            Username => out.write_all(b"username")?,
+            Ip => out.write_all(b"ip")?,
+            Reset => out.write_all(b"reset")?,
+            ResetIp => out.write_all(b"reset_ip")?,
+        }
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for LockoutKind {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        use self::LockoutKind::*;
+        match not_none!(bytes) {
+            b"username" => Ok(Username),
+            b"ip" => Ok(Ip),
+            b"reset" => Ok(Reset),
+            b"reset_ip" => Ok(ResetIp),
+            _ => Err("Unrecognized enum variant".into()),
+        }
+    }
+}
@@ -1,5 +1,16 @@
+pub mod acl;
+pub mod api_keys;
+pub mod audit;
 pub mod database;
+pub mod external_identities;
 pub mod groups;
+pub mod lockouts;
+pub mod oauth;
+pub mod page;
+pub mod permissions;
+pub mod sessions;
+pub mod two_factor;
+pub mod user_tokens;
 pub mod users;
 
 pub use self::database::{Conn, Database, DatabaseBuilder};
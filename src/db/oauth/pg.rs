@@ -0,0 +1,127 @@
+use chrono::prelude::*;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use super::types::{AccessToken, AuthorizationCode, OAuthClient};
+use crate::db::Conn;
+use crate::error::{ErrorKind, Result, ResultExt};
+
+pub fn find_clients(conn: &Conn) -> Result<Vec<OAuthClient>> {
+    use crate::schema::oauth_clients;
+
+    Ok(oauth_clients::table
+        .order(oauth_clients::name)
+        .load(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn find_client(conn: &Conn, id: &Uuid) -> Result<Option<OAuthClient>> {
+    use crate::schema::oauth_clients;
+
+    Ok(oauth_clients::table
+        .find(id)
+        .first(conn)
+        .optional()
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn create_client(conn: &Conn, client: &OAuthClient) -> Result<OAuthClient> {
+    use crate::schema::oauth_clients;
+
+    Ok(diesel::insert_into(oauth_clients::table)
+        .values(client)
+        .get_result(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+/// Deletes the client along with its codes and tokens.
+pub fn del_client(conn: &Conn, id: &Uuid) -> Result<usize> {
+    use crate::schema::{oauth_access_tokens, oauth_clients, oauth_codes};
+
+    diesel::delete(oauth_codes::table)
+        .filter(oauth_codes::client_id.eq(id))
+        .execute(conn)
+        .context(ErrorKind::DbError)?;
+    diesel::delete(oauth_access_tokens::table)
+        .filter(oauth_access_tokens::client_id.eq(id))
+        .execute(conn)
+        .context(ErrorKind::DbError)?;
+
+    Ok(diesel::delete(oauth_clients::table.find(id))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn create_code(
+    conn: &Conn,
+    code: &AuthorizationCode,
+) -> Result<AuthorizationCode> {
+    use crate::schema::oauth_codes;
+
+    Ok(diesel::insert_into(oauth_codes::table)
+        .values(code)
+        .get_result(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+/// Spends an unused, unexpired code, returns `None` if there is none.
+pub fn redeem_code(
+    conn: &Conn,
+    code_hash: &str,
+    now: DateTime<Utc>,
+) -> Result<Option<AuthorizationCode>> {
+    use crate::schema::oauth_codes;
+
+    Ok(diesel::update(oauth_codes::table)
+        .filter(oauth_codes::code_hash.eq(code_hash))
+        .filter(oauth_codes::used_at.is_null())
+        .filter(oauth_codes::expires_at.gt(now))
+        .set(oauth_codes::used_at.eq(now))
+        .get_result(conn)
+        .optional()
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn create_access_token(
+    conn: &Conn,
+    token: &AccessToken,
+) -> Result<AccessToken> {
+    use crate::schema::oauth_access_tokens;
+
+    Ok(diesel::insert_into(oauth_access_tokens::table)
+        .values(token)
+        .get_result(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+/// Finds an unexpired access token.
+pub fn find_access_token(
+    conn: &Conn,
+    token_hash: &str,
+    now: DateTime<Utc>,
+) -> Result<Option<AccessToken>> {
+    use crate::schema::oauth_access_tokens;
+
+    Ok(oauth_access_tokens::table
+        .filter(oauth_access_tokens::token_hash.eq(token_hash))
+        .filter(oauth_access_tokens::expires_at.gt(now))
+        .first(conn)
+        .optional()
+        .context(ErrorKind::DbError)?)
+}
+
+/// Deletes the codes and tokens issued to a user.
+pub fn del_by_user_id(conn: &Conn, user_id: &Uuid) -> Result<usize> {
+    use crate::schema::{oauth_access_tokens, oauth_codes};
+
+    let codes = diesel::delete(oauth_codes::table)
+        .filter(oauth_codes::user_id.eq(user_id))
+        .execute(conn)
+        .context(ErrorKind::DbError)?;
+    let tokens = diesel::delete(oauth_access_tokens::table)
+        .filter(oauth_access_tokens::user_id.eq(user_id))
+        .execute(conn)
+        .context(ErrorKind::DbError)?;
+
+    Ok(codes + tokens)
+}
@@ -0,0 +1,51 @@
+use chrono::prelude::*;
+use uuid::Uuid;
+
+use crate::schema::{oauth_access_tokens, oauth_clients, oauth_codes};
+
+/// Application logging its users in against hamster.
+#[derive(Debug, Clone, PartialEq, Serialize, Insertable, Queryable)]
+#[table_name = "oauth_clients"]
+pub struct OAuthClient {
+    pub id: Uuid,
+    pub name: String,
+    /// `None` for public clients, which can not keep a secret.
+    #[serde(skip_serializing)]
+    pub secret_hash: Option<String>,
+    pub redirect_uris: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl OAuthClient {
+    pub fn is_confidential(&self) -> bool {
+        self.secret_hash.is_some()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Insertable, Queryable)]
+#[table_name = "oauth_codes"]
+pub struct AuthorizationCode {
+    pub id: Uuid,
+    pub code_hash: String,
+    pub client_id: Uuid,
+    pub user_id: Uuid,
+    pub redirect_uri: String,
+    pub scope: String,
+    pub nonce: Option<String>,
+    pub code_challenge: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Insertable, Queryable)]
+#[table_name = "oauth_access_tokens"]
+pub struct AccessToken {
+    pub id: Uuid,
+    pub token_hash: String,
+    pub client_id: Uuid,
+    pub user_id: Uuid,
+    pub scope: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
@@ -0,0 +1,101 @@
+use crate::error::Error;
+
+pub const DEFAULT_PER_PAGE: i64 = 20;
+pub const MAX_PER_PAGE: i64 = 100;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Direction {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sort {
+    pub field: String,
+    pub direction: Direction,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FilterOp {
+    /// `field=value`
+    Eq,
+    /// `field~=value`, case insensitive substring match.
+    Contains,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub field: String,
+    pub op: FilterOp,
+    pub value: String,
+}
+
+/// A page of a list query, pages are numbered from 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageRequest {
+    pub page: i64,
+    pub per_page: i64,
+    /// Rows to skip instead of the ones before `page`, for lists that may
+    /// start at any index.
+    pub start: Option<i64>,
+    pub sort: Vec<Sort>,
+    pub filters: Vec<Filter>,
+}
+
+impl Default for PageRequest {
+    fn default() -> Self {
+        PageRequest {
+            page: 1,
+            per_page: DEFAULT_PER_PAGE,
+            start: None,
+            sort: Vec::new(),
+            filters: Vec::new(),
+        }
+    }
+}
+
+impl PageRequest {
+    pub fn offset(&self) -> i64 {
+        self.start.unwrap_or((self.page - 1) * self.per_page)
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+impl<T> Page<T> {
+    pub fn new(items: Vec<T>, total: i64, request: &PageRequest) -> Self {
+        Page {
+            items,
+            total,
+            page: request.page,
+            per_page: request.per_page,
+        }
+    }
+
+    pub fn last_page(&self) -> i64 {
+        ((self.total + self.per_page - 1) / self.per_page).max(1)
+    }
+}
+
+/// Builds a `LIKE` pattern matching values that contain `value`.
+pub fn contains_pattern(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+pub fn unknown_sort(sort: &Sort) -> Error {
+    Error::validation("sort", format!("unknown field `{}`", sort.field))
+}
+
+pub fn unknown_filter(filter: &Filter) -> Error {
+    Error::validation(filter.field.clone(), "unknown filter")
+}
@@ -0,0 +1,183 @@
+use chrono::prelude::*;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use super::types::{NewPermission, Permission};
+use crate::db::{groups, Conn};
+use crate::error::{ErrorKind, Result, ResultExt};
+
+pub fn find_all(conn: &Conn) -> Result<Vec<Permission>> {
+    use crate::schema::permissions;
+
+    Ok(permissions::table
+        .order(permissions::name)
+        .load(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn find_by_id(conn: &Conn, id: &Uuid) -> Result<Option<Permission>> {
+    use crate::schema::permissions;
+
+    Ok(permissions::table
+        .find(id)
+        .first(conn)
+        .optional()
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn find_by_name(conn: &Conn, name: &str) -> Result<Option<Permission>> {
+    use crate::schema::permissions;
+
+    Ok(permissions::table
+        .filter(permissions::name.eq(name))
+        .first(conn)
+        .optional()
+        .context(ErrorKind::DbError)?)
+}
+
+/// Permissions granted directly to the group.
+pub fn find_by_group_id(
+    conn: &Conn,
+    group_id: &Uuid,
+) -> Result<Vec<Permission>> {
+    use crate::schema::{group_permissions, permissions};
+
+    Ok(permissions::table
+        .inner_join(
+            group_permissions::table
+                .on(group_permissions::permission_id.eq(permissions::id)),
+        )
+        .filter(group_permissions::group_id.eq(group_id))
+        .select(permissions::all_columns)
+        .order(permissions::name)
+        .load(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+/// Names of the permissions granted to any of the groups.
+pub fn find_names_by_group_ids(
+    conn: &Conn,
+    group_ids: &[Uuid],
+) -> Result<Vec<String>> {
+    use crate::schema::{group_permissions, permissions};
+    use diesel::dsl::any;
+
+    Ok(permissions::table
+        .inner_join(
+            group_permissions::table
+                .on(group_permissions::permission_id.eq(permissions::id)),
+        )
+        .filter(group_permissions::group_id.eq(any(group_ids)))
+        .select(permissions::name)
+        .distinct()
+        .load(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn create(conn: &Conn, new: NewPermission) -> Result<Permission> {
+    use crate::schema::permissions;
+
+    Ok(diesel::insert_into(permissions::table)
+        .values(&Permission {
+            id: Uuid::new_v4(),
+            name: new.name,
+            description: new.description,
+            created_at: Utc::now(),
+        })
+        .get_result(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn get_or_create(conn: &Conn, name: &str) -> Result<Permission> {
+    match find_by_name(conn, name)? {
+        Some(permission) => Ok(permission),
+        None => create(
+            conn,
+            NewPermission {
+                name: name.to_string(),
+                description: None,
+            },
+        ),
+    }
+}
+
+pub fn update_desc(conn: &Conn, id: &Uuid, desc: &str) -> Result<usize> {
+    use crate::schema::permissions;
+
+    Ok(diesel::update(permissions::table.find(id))
+        .set(permissions::description.eq(desc))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+/// Deletes the permission, taking it from the groups it was granted to.
+pub fn del_by_id(conn: &Conn, id: &Uuid) -> Result<usize> {
+    use crate::schema::{group_permissions, permissions};
+
+    let group_ids = diesel::delete(group_permissions::table)
+        .filter(group_permissions::permission_id.eq(id))
+        .returning(group_permissions::group_id)
+        .get_results::<Uuid>(conn)
+        .context(ErrorKind::DbError)?;
+    for group_id in &group_ids {
+        groups::bump_membership_epochs(conn, group_id)?;
+    }
+
+    Ok(diesel::delete(permissions::table.find(id))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+/// Grants the permission to the members of the group, returns `false` if
+/// it already was.
+pub fn grant(
+    conn: &Conn,
+    group_id: &Uuid,
+    permission_id: &Uuid,
+) -> Result<bool> {
+    use crate::schema::group_permissions;
+
+    let inserted = diesel::insert_into(group_permissions::table)
+        .values((
+            group_permissions::group_id.eq(group_id),
+            group_permissions::permission_id.eq(permission_id),
+        ))
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .context(ErrorKind::DbError)?;
+    if inserted > 0 {
+        groups::bump_membership_epochs(conn, group_id)?;
+    }
+
+    Ok(inserted > 0)
+}
+
+pub fn revoke(
+    conn: &Conn,
+    group_id: &Uuid,
+    permission_id: &Uuid,
+) -> Result<usize> {
+    use crate::schema::group_permissions;
+
+    let result = diesel::delete(
+        group_permissions::table.find((group_id, permission_id)),
+    )
+    .execute(conn)
+    .context(ErrorKind::DbError)?;
+    if result > 0 {
+        groups::bump_membership_epochs(conn, group_id)?;
+    }
+
+    Ok(result)
+}
+
+/// Takes all permissions from the group, before it is deleted.
+pub fn revoke_by_group_id(conn: &Conn, group_id: &Uuid) -> Result<usize> {
+    use crate::schema::group_permissions;
+
+    groups::bump_membership_epochs(conn, group_id)?;
+    Ok(diesel::delete(group_permissions::table)
+        .filter(group_permissions::group_id.eq(group_id))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
@@ -0,0 +1,26 @@
+use chrono::prelude::*;
+use uuid::Uuid;
+
+use crate::schema::permissions;
+
+/// Permission granted to the members of groups. Names ending in `.*` grant
+/// every permission below them, `*` grants all permissions.
+#[derive(Debug, Clone, PartialEq, Serialize, Queryable, Insertable)]
+#[table_name = "permissions"]
+pub struct Permission {
+    pub id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct NewPermission {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct NewGroupPermission {
+    pub permission_id: Uuid,
+}
@@ -0,0 +1,155 @@
+use chrono::prelude::*;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use super::types::Session;
+use crate::db::Conn;
+use crate::error::{ErrorKind, Result, ResultExt};
+
+pub fn find_by_token_hash(
+    conn: &Conn,
+    token_hash: &str,
+) -> Result<Option<Session>> {
+    use crate::schema::sessions;
+
+    Ok(sessions::table
+        .filter(sessions::token_hash.eq(token_hash))
+        .first(conn)
+        .optional()
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn find_by_identity(conn: &Conn, identity: &str) -> Result<Vec<Session>> {
+    use crate::schema::sessions;
+
+    Ok(sessions::table
+        .filter(sessions::identity.eq(identity))
+        .order(sessions::last_accessed_at.desc())
+        .load(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn create(conn: &Conn, session: &Session) -> Result<Session> {
+    use crate::schema::sessions;
+
+    Ok(diesel::insert_into(sessions::table)
+        .values(session)
+        .get_result(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn touch(
+    conn: &Conn,
+    session_id: &Uuid,
+    accessed_at: DateTime<Utc>,
+) -> Result<usize> {
+    use crate::schema::sessions;
+
+    Ok(diesel::update(sessions::table.find(session_id))
+        .set(sessions::last_accessed_at.eq(accessed_at))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+/// Replaces the authentication stored in the session.
+pub fn update(conn: &Conn, session: &Session) -> Result<usize> {
+    use crate::schema::sessions;
+
+    Ok(diesel::update(sessions::table.find(session.id))
+        .set((
+            sessions::identity.eq(&session.identity),
+            sessions::authorities.eq(&session.authorities),
+            sessions::membership_epoch.eq(session.membership_epoch),
+            sessions::impersonator.eq(&session.impersonator),
+        ))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn del_by_id(
+    conn: &Conn,
+    identity: &str,
+    session_id: &Uuid,
+) -> Result<usize> {
+    use crate::schema::sessions;
+
+    Ok(diesel::delete(sessions::table.find(session_id))
+        .filter(sessions::identity.eq(identity))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn del_by_token_hash(conn: &Conn, token_hash: &str) -> Result<usize> {
+    use crate::schema::sessions;
+
+    Ok(diesel::delete(sessions::table)
+        .filter(sessions::token_hash.eq(token_hash))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn del_by_identity(conn: &Conn, identity: &str) -> Result<usize> {
+    use crate::schema::sessions;
+
+    Ok(diesel::delete(sessions::table)
+        .filter(sessions::identity.eq(identity))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+    use chrono::Duration;
+
+    fn session(identity: &str, token_hash: &str) -> Session {
+        let now = Utc::now();
+        Session {
+            id: Uuid::new_v4(),
+            token_hash: token_hash.to_string(),
+            identity: identity.to_string(),
+            authorities: vec!["user".to_string()],
+            user_agent: None,
+            created_at: now,
+            last_accessed_at: now,
+            expires_at: now + Duration::hours(1),
+            membership_epoch: 0,
+            impersonator: None,
+        }
+    }
+
+    #[test]
+    fn test_revoke() {
+        let conn = connection();
+        let first = create(&conn, &session("bob", "first")).unwrap();
+        create(&conn, &session("bob", "second")).unwrap();
+        create(&conn, &session("alice", "third")).unwrap();
+
+        assert_eq!(
+            Some(first.clone()),
+            find_by_token_hash(&conn, "first").unwrap()
+        );
+        assert_eq!(0, del_by_id(&conn, "alice", &first.id).unwrap());
+        assert_eq!(1, del_by_id(&conn, "bob", &first.id).unwrap());
+        assert_eq!(1, find_by_identity(&conn, "bob").unwrap().len());
+
+        assert_eq!(1, del_by_identity(&conn, "bob").unwrap());
+        assert!(find_by_identity(&conn, "bob").unwrap().is_empty());
+        assert_eq!(1, find_by_identity(&conn, "alice").unwrap().len());
+    }
+
+    #[test]
+    fn test_update() {
+        let conn = connection();
+        let session = create(&conn, &session("bob", "first")).unwrap();
+        let updated = Session {
+            authorities: vec!["admin".to_string()],
+            membership_epoch: 2,
+            ..session.clone()
+        };
+
+        assert_eq!(1, update(&conn, &updated).unwrap());
+        assert_eq!(Some(updated), find_by_token_hash(&conn, "first").unwrap());
+    }
+}
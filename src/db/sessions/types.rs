@@ -0,0 +1,22 @@
+use chrono::prelude::*;
+use uuid::Uuid;
+
+use crate::schema::sessions;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Insertable, Queryable)]
+#[table_name = "sessions"]
+pub struct Session {
+    pub id: Uuid,
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    pub identity: String,
+    pub authorities: Vec<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_accessed_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    #[serde(skip_serializing)]
+    pub membership_epoch: i64,
+    /// Administrator impersonating the user in this session.
+    pub impersonator: Option<String>,
+}
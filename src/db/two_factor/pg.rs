@@ -0,0 +1,171 @@
+use chrono::prelude::*;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use super::types::{RecoveryCode, TwoFactor};
+use crate::db::Conn;
+use crate::error::{ErrorKind, Result, ResultExt};
+
+pub fn find_by_user_id(
+    conn: &Conn,
+    user_id: &Uuid,
+) -> Result<Option<TwoFactor>> {
+    use crate::schema::two_factor;
+
+    Ok(two_factor::table
+        .find(user_id)
+        .first(conn)
+        .optional()
+        .context(ErrorKind::DbError)?)
+}
+
+/// Starts an enrollment, replacing an unconfirmed one. Fails with
+/// `Conflict` if the user already confirmed an enrollment.
+pub fn enroll(conn: &Conn, user_id: &Uuid, secret: &[u8]) -> Result<TwoFactor> {
+    use crate::schema::two_factor;
+
+    diesel::delete(two_factor::table.find(user_id))
+        .filter(two_factor::confirmed_at.is_null())
+        .execute(conn)
+        .context(ErrorKind::DbError)?;
+
+    let enrollment = TwoFactor {
+        user_id: *user_id,
+        secret: secret.to_vec(),
+        confirmed_at: None,
+        last_used_step: 0,
+        created_at: Utc::now(),
+    };
+
+    Ok(diesel::insert_into(two_factor::table)
+        .values(&enrollment)
+        .get_result(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn confirm(conn: &Conn, user_id: &Uuid, step: i64) -> Result<usize> {
+    use crate::schema::two_factor;
+
+    Ok(diesel::update(two_factor::table.find(user_id))
+        .filter(two_factor::confirmed_at.is_null())
+        .set((
+            two_factor::confirmed_at.eq(Utc::now()),
+            two_factor::last_used_step.eq(step),
+        ))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+/// Marks the time step as used, returns `0` if it, or a later one, already
+/// was.
+pub fn use_step(conn: &Conn, user_id: &Uuid, step: i64) -> Result<usize> {
+    use crate::schema::two_factor;
+
+    Ok(diesel::update(two_factor::table.find(user_id))
+        .filter(two_factor::last_used_step.lt(step))
+        .set(two_factor::last_used_step.eq(step))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn del_by_user_id(conn: &Conn, user_id: &Uuid) -> Result<usize> {
+    use crate::schema::{recovery_codes, two_factor};
+
+    diesel::delete(recovery_codes::table)
+        .filter(recovery_codes::user_id.eq(user_id))
+        .execute(conn)
+        .context(ErrorKind::DbError)?;
+
+    Ok(diesel::delete(two_factor::table.find(user_id))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+/// Replaces the recovery codes of the user.
+pub fn set_recovery_codes(
+    conn: &Conn,
+    user_id: &Uuid,
+    code_hashes: &[String],
+) -> Result<usize> {
+    use crate::schema::recovery_codes;
+
+    diesel::delete(recovery_codes::table)
+        .filter(recovery_codes::user_id.eq(user_id))
+        .execute(conn)
+        .context(ErrorKind::DbError)?;
+
+    let codes = code_hashes
+        .iter()
+        .map(|code_hash| RecoveryCode {
+            id: Uuid::new_v4(),
+            user_id: *user_id,
+            code_hash: code_hash.clone(),
+            used_at: None,
+        })
+        .collect::<Vec<RecoveryCode>>();
+
+    Ok(diesel::insert_into(recovery_codes::table)
+        .values(&codes)
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+/// Spends an unused recovery code, returns `0` if there is none.
+pub fn use_recovery_code(
+    conn: &Conn,
+    user_id: &Uuid,
+    code_hash: &str,
+) -> Result<usize> {
+    use crate::schema::recovery_codes;
+
+    Ok(diesel::update(recovery_codes::table)
+        .filter(recovery_codes::user_id.eq(user_id))
+        .filter(recovery_codes::code_hash.eq(code_hash))
+        .filter(recovery_codes::used_at.is_null())
+        .set(recovery_codes::used_at.eq(Utc::now()))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn test_enroll() {
+        let conn = connection();
+        let user_id = Uuid::new_v4();
+
+        enroll(&conn, &user_id, b"first").unwrap();
+        let enrollment = enroll(&conn, &user_id, b"second").unwrap();
+        assert_eq!(b"second".to_vec(), enrollment.secret);
+        assert!(!enrollment.is_confirmed());
+
+        assert_eq!(1, confirm(&conn, &user_id, 10).unwrap());
+        assert_eq!(0, use_step(&conn, &user_id, 10).unwrap());
+        assert_eq!(1, use_step(&conn, &user_id, 11).unwrap());
+
+        let enrollment = find_by_user_id(&conn, &user_id).unwrap().unwrap();
+        assert_eq!(b"second".to_vec(), enrollment.secret);
+        assert!(enrollment.is_confirmed());
+
+        let err = enroll(&conn, &user_id, b"third").unwrap_err();
+        assert_eq!(ErrorKind::Conflict, err.kind());
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let conn = connection();
+        let user_id = Uuid::new_v4();
+        let hashes = vec!["a".to_string(), "b".to_string()];
+
+        assert_eq!(2, set_recovery_codes(&conn, &user_id, &hashes).unwrap());
+        assert_eq!(1, use_recovery_code(&conn, &user_id, "a").unwrap());
+        assert_eq!(0, use_recovery_code(&conn, &user_id, "a").unwrap());
+        assert_eq!(0, use_recovery_code(&conn, &Uuid::new_v4(), "b").unwrap());
+
+        del_by_user_id(&conn, &user_id).unwrap();
+        assert_eq!(0, use_recovery_code(&conn, &user_id, "b").unwrap());
+    }
+}
@@ -0,0 +1,30 @@
+use chrono::prelude::*;
+use uuid::Uuid;
+
+use crate::schema::{recovery_codes, two_factor};
+
+/// TOTP enrollment of a user, only enforced once confirmed.
+#[derive(Debug, Clone, PartialEq, Insertable, Queryable)]
+#[table_name = "two_factor"]
+pub struct TwoFactor {
+    pub user_id: Uuid,
+    pub secret: Vec<u8>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+    pub last_used_step: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+impl TwoFactor {
+    pub fn is_confirmed(&self) -> bool {
+        self.confirmed_at.is_some()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Insertable, Queryable)]
+#[table_name = "recovery_codes"]
+pub struct RecoveryCode {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub code_hash: String,
+    pub used_at: Option<DateTime<Utc>>,
+}
@@ -0,0 +1,5 @@
+pub mod pg;
+pub mod types;
+
+pub use self::pg::*;
+pub use self::types::*;
@@ -0,0 +1,79 @@
+use chrono::prelude::*;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use super::types::{TokenPurpose, UserToken};
+use crate::db::Conn;
+use crate::error::{ErrorKind, Result, ResultExt};
+
+pub fn create(conn: &Conn, token: &UserToken) -> Result<UserToken> {
+    use crate::schema::user_tokens;
+
+    Ok(diesel::insert_into(user_tokens::table)
+        .values(token)
+        .get_result(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+/// Spends an unused, unexpired token, returns `None` if there is none.
+pub fn redeem(
+    conn: &Conn,
+    token_hash: &str,
+    purpose: TokenPurpose,
+    now: DateTime<Utc>,
+) -> Result<Option<UserToken>> {
+    use crate::schema::user_tokens;
+
+    Ok(diesel::update(user_tokens::table)
+        .filter(user_tokens::token_hash.eq(token_hash))
+        .filter(user_tokens::purpose.eq(purpose))
+        .filter(user_tokens::used_at.is_null())
+        .filter(user_tokens::expires_at.gt(now))
+        .set(user_tokens::used_at.eq(now))
+        .get_result(conn)
+        .optional()
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn del_by_user_id(conn: &Conn, user_id: &Uuid) -> Result<usize> {
+    use crate::schema::user_tokens;
+
+    Ok(diesel::delete(user_tokens::table)
+        .filter(user_tokens::user_id.eq(user_id))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+    use chrono::Duration;
+
+    fn token(token_hash: &str, expires_in: i64) -> UserToken {
+        let now = Utc::now();
+        UserToken {
+            id: Uuid::new_v4(),
+            token_hash: token_hash.to_string(),
+            user_id: Uuid::new_v4(),
+            purpose: TokenPurpose::Reset,
+            created_at: now,
+            expires_at: now + Duration::seconds(expires_in),
+            used_at: None,
+        }
+    }
+
+    #[test]
+    fn test_redeem() {
+        let conn = connection();
+        let now = Utc::now();
+        create(&conn, &token("valid", 60)).unwrap();
+        create(&conn, &token("expired", -60)).unwrap();
+
+        let redeem = |hash, purpose| redeem(&conn, hash, purpose, now).unwrap();
+        assert_eq!(None, redeem("valid", TokenPurpose::Invite));
+        assert_eq!(None, redeem("expired", TokenPurpose::Reset));
+        assert!(redeem("valid", TokenPurpose::Reset).is_some());
+        assert_eq!(None, redeem("valid", TokenPurpose::Reset));
+    }
+}
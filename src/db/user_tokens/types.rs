@@ -0,0 +1,65 @@
+use std::io;
+
+use chrono::prelude::*;
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::Pg;
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::Text;
+use uuid::Uuid;
+
+use crate::schema::user_tokens;
+
+/// Single-use token mailed to a user, stored as the hash of the token.
+#[derive(Debug, Clone, PartialEq, Insertable, Queryable)]
+#[table_name = "user_tokens"]
+pub struct UserToken {
+    pub id: Uuid,
+    pub token_hash: String,
+    pub user_id: Uuid,
+    pub purpose: TokenPurpose,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Deserialize,
+    Serialize,
+    FromSqlRow,
+    AsExpression,
+)]
+#[serde(rename_all = "lowercase")]
+#[sql_type = "Text"]
+pub enum TokenPurpose {
+    Reset,
+    Invite,
+}
+
+impl ToSql<Text, Pg> for TokenPurpose {
+    fn to_sql<W: io::Write>(
+        &self,
+        out: &mut Output<W, Pg>,
+    ) -> serialize::Result {
+        use self::TokenPurpose::*;
+        match *self {
+            Reset => out.write_all(b"reset")?,
+            Invite => out.write_all(b"invite")?,
+        }
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for TokenPurpose {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        use self::TokenPurpose::*;
+        match not_none!(bytes) {
+            b"reset" => Ok(Reset),
+            b"invite" => Ok(Invite),
+            _ => Err("Unrecognized enum variant".into()),
+        }
+    }
+}
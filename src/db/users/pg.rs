@@ -1,10 +1,13 @@
 use chrono::prelude::*;
+use diesel::pg::Pg;
 use diesel::prelude::*;
 use uuid::Uuid;
 
-use super::types::{NewUser, User};
+use super::types::{NewUser, UpdateUser, User};
+use crate::db::page::{self, Direction, FilterOp, Page, PageRequest};
 use crate::db::{self, Conn};
 use crate::error::{ErrorKind, Result, ResultExt};
+use crate::schema;
 use crate::utils;
 
 pub fn find_all(conn: &Conn) -> Result<Vec<User>> {
@@ -13,6 +16,59 @@ pub fn find_all(conn: &Conn) -> Result<Vec<User>> {
     Ok(users::table.load(conn).context(ErrorKind::DbError)?)
 }
 
+pub fn find_page(conn: &Conn, request: &PageRequest) -> Result<Page<User>> {
+    use crate::schema::users;
+
+    let total = filtered(request)?
+        .count()
+        .get_result(conn)
+        .context(ErrorKind::DbError)?;
+
+    let mut query = filtered(request)?;
+    for sort in &request.sort {
+        query = match (sort.field.as_str(), sort.direction) {
+            ("username", Direction::Asc) => {
+                query.then_order_by(users::username.asc())
+            }
+            ("username", Direction::Desc) => {
+                query.then_order_by(users::username.desc())
+            }
+            ("nickname", Direction::Asc) => {
+                query.then_order_by(users::nickname.asc())
+            }
+            ("nickname", Direction::Desc) => {
+                query.then_order_by(users::nickname.desc())
+            }
+            ("created_at", Direction::Asc) => {
+                query.then_order_by(users::created_at.asc())
+            }
+            ("created_at", Direction::Desc) => {
+                query.then_order_by(users::created_at.desc())
+            }
+            _ => return Err(page::unknown_sort(sort)),
+        };
+    }
+
+    let items = query
+        .then_order_by(users::id.asc())
+        .limit(request.per_page)
+        .offset(request.offset())
+        .load(conn)
+        .context(ErrorKind::DbError)?;
+
+    Ok(Page::new(items, total, request))
+}
+
+pub fn find_by_id(conn: &Conn, user_id: &Uuid) -> Result<Option<User>> {
+    use crate::schema::users;
+
+    Ok(users::table
+        .find(user_id)
+        .first(conn)
+        .optional()
+        .context(ErrorKind::DbError)?)
+}
+
 pub fn find_by_username(conn: &Conn, username: &str) -> Result<Option<User>> {
     use crate::schema::users;
 
@@ -23,6 +79,16 @@ pub fn find_by_username(conn: &Conn, username: &str) -> Result<Option<User>> {
         .context(ErrorKind::DbError)?)
 }
 
+pub fn find_by_email(conn: &Conn, email: &str) -> Result<Option<User>> {
+    use crate::schema::users;
+
+    Ok(users::table
+        .filter(users::email.eq(email))
+        .first(conn)
+        .optional()
+        .context(ErrorKind::DbError)?)
+}
+
 pub fn create_or_update(
     conn: &Conn,
     username: &str,
@@ -44,6 +110,7 @@ pub fn create_or_update(
                 password: password.to_string(),
                 nickname: nickname.to_string(),
                 avatar_url: None,
+                email: None,
             },
         )?,
         Some(user) => change_user(conn, user, nickname, password)?,
@@ -66,6 +133,7 @@ pub fn create(conn: &Conn, new_user: NewUser) -> Result<User> {
             users::password.eq(&new_user.password),
             users::nickname.eq(&new_user.nickname),
             users::avatar_url.eq(&avatar_url),
+            users::email.eq(&new_user.email),
             users::created_at.eq(&now),
             users::updated_at.eq(&now),
         ))
@@ -73,6 +141,35 @@ pub fn create(conn: &Conn, new_user: NewUser) -> Result<User> {
         .context(ErrorKind::DbError)?)
 }
 
+pub fn update(conn: &Conn, user_id: &Uuid, update: UpdateUser) -> Result<User> {
+    use crate::schema::users;
+
+    if update.is_empty() {
+        return Ok(users::table
+            .find(user_id)
+            .first(conn)
+            .context(ErrorKind::DbError)?);
+    }
+
+    Ok(diesel::update(users::table.find(user_id))
+        .set((&update, users::updated_at.eq(Utc::now())))
+        .get_result(conn)
+        .context(ErrorKind::DbError)?)
+}
+
+pub fn update_password(
+    conn: &Conn,
+    user_id: &Uuid,
+    password: &str,
+) -> Result<usize> {
+    use crate::schema::users;
+
+    Ok(diesel::update(users::table.find(user_id))
+        .set((users::password.eq(password), users::updated_at.eq(Utc::now())))
+        .execute(conn)
+        .context(ErrorKind::DbError)?)
+}
+
 pub fn del_by_id(conn: &Conn, user_id: &Uuid) -> Result<usize> {
     use crate::schema::users;
 
@@ -81,6 +178,53 @@ pub fn del_by_id(conn: &Conn, user_id: &Uuid) -> Result<usize> {
         .context(ErrorKind::DbError)?)
 }
 
+/// Deletes a user and everything referring to it, returns `0` if the user
+/// does not exist. Has to run in a transaction.
+pub fn del_cascade(conn: &Conn, user_id: &Uuid) -> Result<usize> {
+    use crate::db::{
+        acl, api_keys, external_identities, groups, oauth, sessions,
+        two_factor, user_tokens,
+    };
+
+    groups::del_members_by_member_id(conn, user_id)?;
+    sessions::del_by_identity(conn, &user_id.simple().to_string())?;
+    two_factor::del_by_user_id(conn, user_id)?;
+    user_tokens::del_by_user_id(conn, user_id)?;
+    api_keys::del_by_user_id(conn, user_id)?;
+    oauth::del_by_user_id(conn, user_id)?;
+    external_identities::del_by_user_id(conn, user_id)?;
+    acl::del_by_principal_id(conn, user_id)?;
+    groups::del_managers_by_user_id(conn, user_id)?;
+
+    del_by_id(conn, user_id)
+}
+
+fn filtered(
+    request: &PageRequest,
+) -> Result<schema::users::BoxedQuery<'static, Pg>> {
+    use crate::schema::users;
+
+    let mut query = users::table.into_boxed();
+    for filter in &request.filters {
+        let value = filter.value.clone();
+        query = match (filter.field.as_str(), filter.op) {
+            ("username", FilterOp::Eq) => {
+                query.filter(users::username.eq(value))
+            }
+            ("username", FilterOp::Contains) => query
+                .filter(users::username.ilike(page::contains_pattern(&value))),
+            ("nickname", FilterOp::Eq) => {
+                query.filter(users::nickname.eq(value))
+            }
+            ("nickname", FilterOp::Contains) => query
+                .filter(users::nickname.ilike(page::contains_pattern(&value))),
+            _ => return Err(page::unknown_filter(filter)),
+        };
+    }
+
+    Ok(query)
+}
+
 fn change_user(
     conn: &Conn,
     mut user: User,
@@ -95,3 +239,90 @@ fn change_user(
         .save_changes::<User>(conn)
         .context(ErrorKind::DbError)?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn test_find_page() {
+        use crate::db::page::{Filter, Sort};
+
+        let conn = connection();
+        for name in &["page_a", "page_b", "page_c"] {
+            create_or_update(&conn, name, name, "password").unwrap();
+        }
+
+        let request = PageRequest {
+            page: 2,
+            per_page: 2,
+            start: None,
+            sort: vec![Sort {
+                field: "username".to_string(),
+                direction: Direction::Desc,
+            }],
+            filters: vec![Filter {
+                field: "username".to_string(),
+                op: FilterOp::Contains,
+                value: "PAGE_".to_string(),
+            }],
+        };
+        let page = find_page(&conn, &request).unwrap();
+
+        assert_eq!(3, page.total);
+        assert_eq!(2, page.last_page());
+        assert_eq!(
+            vec!["page_a"],
+            page.items.iter().map(|u| &u.username).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_update() {
+        let conn = connection();
+        let user = create_or_update(&conn, "bob", "Bob", "password").unwrap();
+
+        let updated = update(
+            &conn,
+            &user.id,
+            UpdateUser {
+                nickname: Some("Bobby".to_string()),
+                ..UpdateUser::default()
+            },
+        )
+        .unwrap();
+        assert_eq!("Bobby", updated.nickname);
+        assert_eq!(user.avatar_url, updated.avatar_url);
+
+        let unchanged = update(&conn, &user.id, UpdateUser::default()).unwrap();
+        assert_eq!(updated, unchanged);
+
+        let err =
+            update(&conn, &Uuid::new_v4(), UpdateUser::default()).unwrap_err();
+        assert_eq!(ErrorKind::NotFound, err.kind());
+    }
+
+    #[test]
+    fn test_del_cascade() {
+        use crate::db::groups::{self, GroupMembershipType};
+
+        let conn = connection();
+        let user = create_or_update(&conn, "bob", "Bob", "password").unwrap();
+        let group = groups::get_or_create(&conn, "cascade_team").unwrap();
+        groups::add_member(
+            &conn,
+            &group.id,
+            &user.id,
+            GroupMembershipType::User,
+        )
+        .unwrap();
+        groups::add_manager(&conn, &group.id, &user.id).unwrap();
+
+        assert_eq!(1, del_cascade(&conn, &user.id).unwrap());
+        assert_eq!(None, find_by_id(&conn, &user.id).unwrap());
+        assert!(groups::find_members(&conn, &group.id).unwrap().is_empty());
+        assert!(groups::find_managers(&conn, &group.id).unwrap().is_empty());
+        assert_eq!(0, del_cascade(&conn, &user.id).unwrap());
+    }
+}
@@ -2,6 +2,7 @@ use chrono::prelude::*;
 use uuid::Uuid;
 
 use crate::schema::users;
+use crate::utils;
 
 #[derive(
     Debug,
@@ -20,9 +21,12 @@ pub struct User {
     #[serde(skip_serializing)]
     pub password: String,
     pub nickname: String,
-    pub avatar_url: String,
+    pub avatar_url: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    #[serde(skip_serializing)]
+    pub membership_epoch: i64,
+    pub email: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -31,4 +35,30 @@ pub struct NewUser {
     pub password: String,
     pub nickname: String,
     pub avatar_url: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, AsChangeset)]
+#[table_name = "users"]
+pub struct UpdateUser {
+    pub username: Option<String>,
+    pub nickname: Option<String>,
+    /// `Some(None)`, `null` in json, removes the avatar.
+    #[serde(default, deserialize_with = "utils::deserialize_some")]
+    pub avatar_url: Option<Option<String>>,
+    pub password: Option<String>,
+    /// `Some(None)`, `null` in json, removes the email address.
+    #[serde(default, deserialize_with = "utils::deserialize_some")]
+    pub email: Option<Option<String>>,
+}
+
+impl UpdateUser {
+    pub fn is_empty(&self) -> bool {
+        self.username.is_none()
+            && self.nickname.is_none()
+            && self.avatar_url.is_none()
+            && self.password.is_none()
+            && self.email.is_none()
+    }
 }
@@ -1,7 +1,9 @@
 use std::fmt::{self, Display};
 
+use actix_web::error::BlockingError;
 use actix_web::http::StatusCode;
 use actix_web::{HttpResponse, ResponseError};
+use diesel::result::{DatabaseErrorInformation, DatabaseErrorKind};
 pub use failure::ResultExt;
 use failure::{Backtrace, Context, Fail};
 
@@ -21,9 +23,30 @@ pub enum ErrorKind {
     #[fail(display = "Application bootstrap error")]
     BootstrapError,
 
+    #[fail(display = "Invalid configuration")]
+    ConfigError,
+
     #[fail(display = "Unauthorized")]
     Unauthorized,
 
+    #[fail(display = "Forbidden")]
+    Forbidden,
+
+    #[fail(display = "Validation failed")]
+    Validation,
+
+    #[fail(display = "Not found")]
+    NotFound,
+
+    #[fail(display = "Conflict")]
+    Conflict,
+
+    #[fail(display = "Bad request")]
+    BadRequest,
+
+    #[fail(display = "Too many requests")]
+    TooManyRequests,
+
     #[fail(display = "Serialize json error")]
     SerializeJsonError,
 
@@ -35,26 +58,130 @@ pub enum ErrorKind {
 
     #[fail(display = "Failed to bcrypt password")]
     HashPasswordFailure,
+
+    #[fail(display = "Failed to send mail")]
+    MailError,
+
+    #[fail(display = "Identity provider request failed")]
+    IdentityProviderError,
+
+    #[fail(display = "Blocking operation canceled")]
+    BlockingCanceled,
+}
+
+impl ErrorKind {
+    pub fn status(self) -> StatusCode {
+        use self::ErrorKind::*;
+
+        match self {
+            Unauthorized => StatusCode::UNAUTHORIZED,
+            Forbidden => StatusCode::FORBIDDEN,
+            Validation => StatusCode::UNPROCESSABLE_ENTITY,
+            NotFound => StatusCode::NOT_FOUND,
+            Conflict => StatusCode::CONFLICT,
+            BadRequest => StatusCode::BAD_REQUEST,
+            TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            IdentityProviderError => StatusCode::BAD_GATEWAY,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Stable, machine readable error code.
+    pub fn code(self) -> &'static str {
+        use self::ErrorKind::*;
+
+        match self {
+            Unauthorized => "unauthorized",
+            Forbidden => "forbidden",
+            Validation => "validation_failed",
+            NotFound => "not_found",
+            Conflict => "conflict",
+            BadRequest => "bad_request",
+            TooManyRequests => "too_many_requests",
+            IdentityProviderError => "identity_provider_error",
+            _ => "internal_error",
+        }
+    }
+}
+
+/// Validation failure of a single request field.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// RFC 7807 problem details body.
+#[derive(Debug, Serialize)]
+struct Problem<'a> {
+    #[serde(rename = "type")]
+    problem_type: &'static str,
+    title: String,
+    status: u16,
+    code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<&'a str>,
+    #[serde(skip_serializing_if = "<[FieldError]>::is_empty")]
+    errors: &'a [FieldError],
 }
 
 #[derive(Debug)]
 pub struct Error {
     inner: Context<ErrorKind>,
+    detail: Option<String>,
+    details: Vec<FieldError>,
 }
 
 impl Error {
     pub fn kind(&self) -> ErrorKind {
         *self.inner.get_context()
     }
+
+    pub fn detail(&self) -> Option<&str> {
+        self.detail.as_ref().map(String::as_str)
+    }
+
+    pub fn details(&self) -> &[FieldError] {
+        &self.details
+    }
+
+    pub fn with_detail<D: Into<String>>(mut self, detail: D) -> Error {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn validation<F, M>(field: F, message: M) -> Error
+    where
+        F: Into<String>,
+        M: Into<String>,
+    {
+        let mut error = Error::from(ErrorKind::Validation);
+        error.details.push(FieldError {
+            field: field.into(),
+            message: message.into(),
+        });
+        error
+    }
 }
 
 impl ResponseError for Error {
     fn error_response(&self) -> HttpResponse {
-        use self::ErrorKind::*;
+        let kind = self.kind();
+        let status = kind.status();
+        let problem = Problem {
+            problem_type: "about:blank",
+            title: kind.to_string(),
+            status: status.as_u16(),
+            code: kind.code(),
+            detail: self.detail(),
+            errors: &self.details,
+        };
 
-        match self.kind() {
-            Unauthorized => HttpResponse::new(StatusCode::UNAUTHORIZED),
-            _ => HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR),
+        match serde_json::to_string(&problem) {
+            Ok(body) => HttpResponse::build(status)
+                .content_type("application/problem+json")
+                .body(body),
+            Err(_) => HttpResponse::new(status),
         }
     }
 }
@@ -71,7 +198,10 @@ impl Fail for Error {
 
 impl Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        Display::fmt(&self.inner, f)
+        match self.detail {
+            Some(ref detail) => write!(f, "{}: {}", self.inner, detail),
+            None => Display::fmt(&self.inner, f),
+        }
     }
 }
 
@@ -79,12 +209,121 @@ impl From<ErrorKind> for Error {
     fn from(kind: ErrorKind) -> Error {
         Error {
             inner: Context::new(kind),
+            detail: None,
+            details: Vec::new(),
+        }
+    }
+}
+
+impl From<BlockingError<Error>> for Error {
+    fn from(err: BlockingError<Error>) -> Error {
+        match err {
+            BlockingError::Error(e) => e,
+            BlockingError::Canceled => Error::from(ErrorKind::BlockingCanceled),
         }
     }
 }
 
 impl From<Context<ErrorKind>> for Error {
     fn from(inner: Context<ErrorKind>) -> Error {
-        Error { inner }
+        let db_error = match inner.get_context() {
+            ErrorKind::DbError => inner
+                .cause()
+                .and_then(|c| c.downcast_ref::<diesel::result::Error>()),
+            _ => None,
+        };
+
+        match db_error.map(from_db_error) {
+            Some((kind, details)) => Error {
+                inner: inner.map(|_| kind),
+                detail: None,
+                details,
+            },
+            None => Error {
+                inner,
+                detail: None,
+                details: Vec::new(),
+            },
+        }
+    }
+}
+
+/// Refines a database error into a client error where the cause is known.
+fn from_db_error(err: &diesel::result::Error) -> (ErrorKind, Vec<FieldError>) {
+    use diesel::result::Error::*;
+
+    match err {
+        NotFound => (ErrorKind::NotFound, Vec::new()),
+        DatabaseError(DatabaseErrorKind::UniqueViolation, info) => {
+            let details = constraint_field(info.as_ref())
+                .map(|field| FieldError {
+                    field,
+                    message: "already exists".to_string(),
+                })
+                .into_iter()
+                .collect();
+            (ErrorKind::Conflict, details)
+        }
+        DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => {
+            (ErrorKind::Conflict, Vec::new())
+        }
+        _ => (ErrorKind::DbError, Vec::new()),
+    }
+}
+
+/// Extracts the column of a postgres `<table>_<column>_key` constraint.
+fn constraint_field(info: &DatabaseErrorInformation) -> Option<String> {
+    let table = info.table_name()?;
+    let constraint = info.constraint_name()?;
+
+    if constraint.starts_with(table) && constraint.ends_with("_key") {
+        let field = &constraint[table.len()..constraint.len() - 4];
+        Some(field.trim_start_matches('_').to_string())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_response() {
+        let err = Error::validation("password", "must not be empty");
+        let resp = err.error_response();
+
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        assert_eq!(
+            "application/problem+json",
+            resp.headers().get("content-type").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_from_db_error() {
+        let err: Result<()> = Err(diesel::result::Error::NotFound)
+            .context(ErrorKind::DbError)
+            .map_err(Error::from);
+
+        assert_eq!(ErrorKind::NotFound, err.unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_from_unique_violation() {
+        use crate::db::groups::{self, NewGroup};
+        use crate::test_helpers::*;
+
+        let conn = connection();
+        let new_group = || NewGroup {
+            display_name: "duplicate".to_string(),
+            description: None,
+        };
+
+        groups::create(&conn, new_group()).unwrap();
+        let err = groups::create(&conn, new_group()).unwrap_err();
+
+        assert_eq!(ErrorKind::Conflict, err.kind());
+        assert_eq!("display_name", err.details()[0].field);
     }
 }
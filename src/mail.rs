@@ -0,0 +1,212 @@
+//! Outgoing mail.
+//!
+//! Mail goes out over SMTP when `SMTP_HOST` is set, using implicit TLS on the
+//! submissions port and the `SMTP_USERNAME` and `SMTP_PASSWORD` credentials,
+//! from the address in `MAIL_FROM`. Otherwise every message is written to a
+//! file in `MAIL_DIR`, `./mail` by default, which suits development and tests.
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+
+use chrono::prelude::*;
+use lettre::smtp::authentication::Credentials;
+use lettre::{SmtpClient, Transport};
+use lettre_email::EmailBuilder;
+use uuid::Uuid;
+
+use crate::error::{Error, ErrorKind, Result, ResultExt};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+pub trait Mailer: Send + Sync + 'static {
+    fn send(&self, message: &Message) -> Result<()>;
+}
+
+/// Picks the mailer configured in the environment.
+pub fn from_env() -> Result<Arc<Mailer>> {
+    match env::var("SMTP_HOST") {
+        Ok(host) => {
+            let from = env::var("MAIL_FROM").map_err(|_| {
+                Error::from(ErrorKind::ConfigError)
+                    .with_detail("MAIL_FROM must be set to send mail over SMTP")
+            })?;
+            let mut mailer = SmtpMailer::new(host, from);
+            if let (Ok(username), Ok(password)) =
+                (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD"))
+            {
+                mailer = mailer.credentials(username, password);
+            }
+            Ok(Arc::new(mailer))
+        }
+        Err(_) => {
+            let dir = env::var("MAIL_DIR").unwrap_or_else(|_| "mail".into());
+            warn!("No SMTP_HOST configured, writing mail to {}", &dir);
+            Ok(Arc::new(FileMailer::new(dir)))
+        }
+    }
+}
+
+/// Sends the message on another thread, so the response does not wait for
+/// the mail server. Failures are only logged.
+pub fn send_in_background(mailer: Arc<Mailer>, message: Message) {
+    thread::spawn(move || {
+        if let Err(e) = mailer.send(&message) {
+            error!("Failed to send mail to {}: {}", &message.to, e);
+        }
+    });
+}
+
+pub struct SmtpMailer {
+    host: String,
+    from: String,
+    credentials: Option<(String, String)>,
+}
+
+impl SmtpMailer {
+    pub fn new<H: Into<String>, F: Into<String>>(host: H, from: F) -> Self {
+        SmtpMailer {
+            host: host.into(),
+            from: from.into(),
+            credentials: None,
+        }
+    }
+
+    pub fn credentials(mut self, username: String, password: String) -> Self {
+        self.credentials = Some((username, password));
+        self
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, message: &Message) -> Result<()> {
+        let email = EmailBuilder::new()
+            .from(self.from.as_str())
+            .to(message.to.as_str())
+            .subject(message.subject.as_str())
+            .text(message.body.as_str())
+            .build()
+            .context(ErrorKind::MailError)?;
+
+        let mut client =
+            SmtpClient::new_simple(&self.host).context(ErrorKind::MailError)?;
+        if let Some((ref username, ref password)) = self.credentials {
+            client = client.credentials(Credentials::new(
+                username.clone(),
+                password.clone(),
+            ));
+        }
+        client
+            .transport()
+            .send(email.into())
+            .context(ErrorKind::MailError)?;
+
+        Ok(())
+    }
+}
+
+/// Writes every message to its own file in a directory.
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> FileMailer {
+        FileMailer { dir: dir.into() }
+    }
+
+    /// Reads back the messages written so far, oldest first.
+    #[cfg(test)]
+    pub fn messages(&self) -> Result<Vec<Message>> {
+        let mut paths = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .collect::<Vec<PathBuf>>(),
+            Err(_) => return Ok(Vec::new()),
+        };
+        paths.sort();
+
+        paths
+            .iter()
+            .map(|path| {
+                let content =
+                    fs::read_to_string(path).context(ErrorKind::MailError)?;
+                parse_message(&content)
+            })
+            .collect()
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, message: &Message) -> Result<()> {
+        fs::create_dir_all(&self.dir).context(ErrorKind::MailError)?;
+
+        // Timestamp first, so file names sort by the time they were sent.
+        let name = format!(
+            "{}-{}.txt",
+            Utc::now().format("%Y%m%d%H%M%S%6f"),
+            Uuid::new_v4().simple()
+        );
+        let content = format!(
+            "To: {}\nSubject: {}\n\n{}",
+            message.to, message.subject, message.body
+        );
+        fs::write(self.dir.join(name), content)
+            .context(ErrorKind::MailError)?;
+
+        info!("Wrote mail to {} into {}", &message.to, self.dir.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn parse_message(content: &str) -> Result<Message> {
+    let mut parts = content.splitn(2, "\n\n");
+    let headers = parts.next().unwrap_or_default();
+    let body = parts.next().unwrap_or_default();
+    let header = |name: &str| {
+        headers
+            .lines()
+            .find(|line| line.starts_with(name))
+            .map(|line| line[name.len()..].trim().to_string())
+            .ok_or_else(|| Error::from(ErrorKind::MailError))
+    };
+
+    Ok(Message {
+        to: header("To:")?,
+        subject: header("Subject:")?,
+        body: body.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_mailer() {
+        let dir = env::temp_dir().join(Uuid::new_v4().simple().to_string());
+        let mailer = FileMailer::new(&dir);
+        let message = |to: &str| Message {
+            to: to.to_string(),
+            subject: "Hello".to_string(),
+            body: "Hello,\n\nworld".to_string(),
+        };
+
+        assert!(mailer.messages().unwrap().is_empty());
+        mailer.send(&message("bob@example.com")).unwrap();
+        mailer.send(&message("alice@example.com")).unwrap();
+
+        assert_eq!(
+            vec![message("bob@example.com"), message("alice@example.com")],
+            mailer.messages().unwrap()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -10,7 +10,10 @@ mod auth;
 mod bootstrap;
 mod db;
 mod error;
+mod mail;
+mod oidc;
 mod schema;
+mod scim;
 mod utils;
 
 #[cfg(test)]
@@ -23,10 +26,20 @@ use actix_web::{App, HttpServer};
 use failure::Error;
 
 use crate::auth::middleware::{
-    AuthenticationService, CookieAuthenticationBackend,
+    ApiKeyAuthenticationBackend, AuthenticationBackend, AuthenticationService,
+    BearerAuthenticationBackend, CookieAuthenticationBackend,
+    ImpersonationService, RefreshingAuthenticationBackend,
+    SessionAuthenticationBackend,
+};
+use crate::auth::{
+    api_keys, LockoutPolicy, PasswordPolicy, Policy, SessionManager,
+    SigningKeys, TokenSigner, TrustedProxies, UserTokens,
 };
 
-static AUTH_SIGNING_KEY: &[u8] = &[0; 32];
+/// Seconds a session may stay unused before it ends.
+const SESSION_IDLE_TIMEOUT: i64 = 30 * 60;
+/// Seconds after which a session ends however active it is.
+const SESSION_ABSOLUTE_TIMEOUT: i64 = 12 * 60 * 60;
 
 fn main() -> Result<(), Error> {
     env::set_var("RUST_LOG", "hamster=debug,actix_web=info");
@@ -43,22 +56,77 @@ fn main() -> Result<(), Error> {
         .pool_idle_timeout(Some(time::Duration::from_secs(10 * 60)))
         .open(&database_url);
 
-    bootstrap::run(&database_url, "bootstrap.toml")?;
+    let dev_mode = env::var("DEV_MODE")
+        .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+    let signing_keys = SigningKeys::from_env("AUTH", dev_mode)?;
+    let token_keys = SigningKeys::from_env("TOKEN", dev_mode)?
+        .distinct_from(&signing_keys, dev_mode)?;
+
+    let sessions = SessionManager::new(db.clone())
+        .idle_timeout(SESSION_IDLE_TIMEOUT)
+        .absolute_timeout(SESSION_ABSOLUTE_TIMEOUT);
+
+    let mailer = mail::from_env()?;
+    let public_url = env::var("PUBLIC_URL")
+        .unwrap_or_else(|_| "http://localhost:8000".to_string());
+    let user_tokens = UserTokens::new(public_url.clone());
+    let relying_party = oidc::RelyingParty::from_env(&public_url)?;
+    let provider =
+        oidc::Provider::new(public_url, oidc::IdTokenSigner::from_env()?);
+
+    let password_policy = PasswordPolicy::from_env()?;
+    let trusted_proxies = TrustedProxies::from_env()?;
+    let policy_path =
+        env::var("POLICY_PATH").unwrap_or_else(|_| "policy.toml".to_string());
+    let policy = Policy::from_file(&policy_path)?.explain(dev_mode);
+
+    bootstrap::run(&database_url, "bootstrap.toml", &password_policy)?;
     let app = move || {
         let domain =
             env::var("DOMAIN").unwrap_or_else(|_| "localhost".to_string());
-        let auth_backend = CookieAuthenticationBackend::new(AUTH_SIGNING_KEY)
+        let cookie = CookieAuthenticationBackend::new(signing_keys.current())
+            .previous_keys(signing_keys.previous())
             .name("hamster-auth")
             .path("/")
             .domain(domain.clone())
-            .max_age(3600)
+            .max_age(SESSION_ABSOLUTE_TIMEOUT)
             .secure(false);
+        let session_backend =
+            SessionAuthenticationBackend::new(cookie, sessions.clone());
+        let token_signer = TokenSigner::new(token_keys.current())
+            .previous_keys(token_keys.previous())
+            .max_age(3600);
+        let token_backend =
+            BearerAuthenticationBackend::new(token_signer.clone())
+                .ignore_prefix(api_keys::KEY_PREFIX)
+                .ignore_prefix(oidc::ACCESS_TOKEN_PREFIX);
 
         App::new()
             .data(db.clone())
-            .wrap(AuthenticationService::new(auth_backend))
+            .data(token_signer)
+            .data(sessions.clone())
+            .data(LockoutPolicy::default())
+            .data(trusted_proxies.clone())
+            .data(password_policy.clone())
+            .data(policy.clone())
+            .data(user_tokens.clone())
+            .data(mailer.clone())
+            .data(provider.clone())
+            .data(relying_party.clone())
+            .wrap(ImpersonationService::new(db.clone()))
+            .wrap(AuthenticationService::new(
+                RefreshingAuthenticationBackend::new(
+                    session_backend.or(token_backend),
+                    db.clone(),
+                )
+                .or(ApiKeyAuthenticationBackend::new(db.clone())),
+            ))
             .wrap(Logger::default())
             .service(api::service("/api"))
+            .service(scim::service("/scim/v2"))
+            .service(oidc::service(oidc::PATH))
+            .service(oidc::discovery())
     };
 
     let port = env::var("PORT").unwrap_or_else(|_| "8000".to_string());
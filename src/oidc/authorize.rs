@@ -0,0 +1,127 @@
+//! Consent step of the authorization endpoint, called by the frontend page
+//! the user agent is sent to with the authorization request. Impersonators
+//! cannot authorize clients on behalf of the user.
+use actix_web::{web, HttpResponse};
+use futures::Future;
+use uuid::Uuid;
+
+use super::provider::{AuthorizeRequest, Provider};
+use crate::auth::policy::{self, Policy};
+use crate::auth::Authentication;
+use crate::db::Database;
+use crate::error::{Error, ErrorKind, Result, ResultExt};
+
+#[derive(Debug, Deserialize)]
+pub struct Decision {
+    #[serde(flatten)]
+    request: AuthorizeRequest,
+    approved: bool,
+}
+
+pub fn get_authorization(
+    db: web::Data<Database>,
+    provider: web::Data<Provider>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    request: web::Query<AuthorizeRequest>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        policy.check(&input)?;
+        let conn = db.conn()?;
+        provider.authorize(&conn, &request)
+    })
+    .from_err()
+    .map(|res| HttpResponse::Ok().json(res))
+}
+
+pub fn post_authorization(
+    a: Authentication,
+    db: web::Data<Database>,
+    provider: web::Data<Provider>,
+    policy: web::Data<Policy>,
+    input: policy::Input,
+    decision: web::Json<Decision>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        policy.check(&input)?;
+        let user_id =
+            Uuid::parse_str(a.identity()).context(ErrorKind::Unauthorized)?;
+        let conn = db.conn()?;
+        provider.decide(&conn, &decision.request, &user_id, decision.approved)
+    })
+    .from_err()
+    .map(|res| HttpResponse::Ok().json(res))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{self, TestRequest};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::db::users;
+    use crate::oidc::clients::{self, NewClient};
+    use crate::test_helpers::*;
+
+    #[test]
+    fn test_authorize_while_impersonating() {
+        let db = database();
+        let (user, client_id) = {
+            let conn = db.conn().unwrap();
+            let user = users::create_or_update(&conn, "bob", "Bob", "password")
+                .unwrap();
+            let client = clients::register(
+                &conn,
+                NewClient {
+                    name: "App".to_string(),
+                    redirect_uris: vec![
+                        "https://app.example.com/callback".to_string()
+                    ],
+                    confidential: true,
+                },
+            )
+            .unwrap();
+            (user, client.client.id)
+        };
+        let request = json!({
+            "response_type": "code",
+            "client_id": client_id.to_string(),
+            "redirect_uri": "https://app.example.com/callback",
+            "scope": "openid",
+            "code_challenge": "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM",
+            "code_challenge_method": "S256",
+        });
+        let query = serde_urlencoded::to_string(&request).unwrap();
+        let mut decision = request.clone();
+        decision["approved"] = json!(true);
+        let mut app = app(&db);
+        let bob = Authentication::new(user.id.simple().to_string(), vec![]);
+        let impersonated =
+            bob.clone().with_impersonator(Some("admin".to_string()));
+        let mut call = |a: &Authentication, method: &str| {
+            let cookie = login(&mut app, a);
+            let uri = format!("/oauth/authorize?{}", query);
+            let req = match method {
+                "GET" => TestRequest::get().uri(&uri),
+                _ => TestRequest::post().uri(&uri).set_json(&decision),
+            };
+            let resp =
+                test::call_service(&mut app, req.cookie(cookie).to_request());
+            let status = resp.status();
+            let body: Value = serde_json::from_slice(&test::read_body(resp))
+                .unwrap_or(Value::Null);
+            (status, body)
+        };
+
+        assert_eq!(StatusCode::FORBIDDEN, call(&impersonated, "GET").0);
+        assert_eq!(StatusCode::FORBIDDEN, call(&impersonated, "POST").0);
+
+        let (status, body) = call(&bob, "GET");
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(json!("App"), body["client_name"]);
+        let (status, body) = call(&bob, "POST");
+        assert_eq!(StatusCode::OK, status);
+        assert!(body["redirect_to"].as_str().unwrap().contains("code="));
+    }
+}
@@ -0,0 +1,123 @@
+//! Registration of relying parties by administrators.
+use actix_web::{web, HttpResponse, Scope};
+use chrono::prelude::*;
+use futures::Future;
+use uuid::Uuid;
+
+use crate::db::oauth::{self, OAuthClient};
+use crate::db::{Conn, Database};
+use crate::error::{Error, ErrorKind, Result};
+use crate::utils;
+
+const SECRET_LENGTH: usize = 43;
+
+#[derive(Debug, Deserialize)]
+pub struct NewClient {
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    /// Public clients, such as single page apps, get no secret.
+    #[serde(default = "default_confidential")]
+    pub confidential: bool,
+}
+
+fn default_confidential() -> bool {
+    true
+}
+
+/// A new client along with its secret, which is only shown once.
+#[derive(Debug, Serialize)]
+pub struct CreatedClient {
+    #[serde(flatten)]
+    pub client: OAuthClient,
+    pub client_secret: Option<String>,
+}
+
+pub fn service(path: &str) -> Scope {
+    web::scope(path)
+        .service(
+            web::resource("")
+                .route(web::get().to_async(get_clients))
+                .route(web::post().to_async(add_client)),
+        )
+        .service(
+            web::resource("/{client_id}")
+                .route(web::delete().to_async(del_client)),
+        )
+}
+
+pub fn register(conn: &Conn, new: NewClient) -> Result<CreatedClient> {
+    if new.name.trim().is_empty() {
+        return Err(Error::validation("name", "must not be empty"));
+    }
+    if new.redirect_uris.is_empty() {
+        return Err(Error::validation("redirect_uris", "must not be empty"));
+    }
+    for uri in &new.redirect_uris {
+        let scheme_valid =
+            uri.starts_with("https://") || uri.starts_with("http://");
+        if !scheme_valid || uri.contains('#') {
+            return Err(Error::validation(
+                "redirect_uris",
+                format!("`{}` is not an absolute http(s) uri", uri),
+            ));
+        }
+    }
+
+    let client_secret = if new.confidential {
+        Some(utils::random_string(SECRET_LENGTH))
+    } else {
+        None
+    };
+    let client = oauth::create_client(
+        conn,
+        &OAuthClient {
+            id: Uuid::new_v4(),
+            name: new.name.trim().to_string(),
+            secret_hash: client_secret.as_ref().map(|s| utils::hash_token(s)),
+            redirect_uris: new.redirect_uris,
+            created_at: Utc::now(),
+        },
+    )?;
+
+    Ok(CreatedClient {
+        client,
+        client_secret,
+    })
+}
+
+fn get_clients(
+    db: web::Data<Database>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        let conn = db.conn()?;
+        oauth::find_clients(&conn)
+    })
+    .from_err()
+    .map(|res| HttpResponse::Ok().json(res))
+}
+
+fn add_client(
+    db: web::Data<Database>,
+    new: web::Json<NewClient>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        let conn = db.conn()?;
+        register(&conn, new.into_inner())
+    })
+    .from_err()
+    .map(|res| HttpResponse::Created().json(res))
+}
+
+fn del_client(
+    db: web::Data<Database>,
+    client_id: web::Path<Uuid>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    web::block(move || -> Result<_> {
+        db.transaction(|conn| match oauth::del_client(conn, &client_id)? {
+            0 => Err(ErrorKind::NotFound)?,
+            _ => Ok(()),
+        })
+    })
+    .from_err()
+    .map(|_| HttpResponse::NoContent().finish())
+}
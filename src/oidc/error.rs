@@ -0,0 +1,136 @@
+use std::fmt::{self, Display};
+
+use actix_web::error::BlockingError;
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpResponse, ResponseError};
+use failure::Context;
+
+use crate::error::{Error, ErrorKind};
+
+/// Error of the token and userinfo endpoints, rendered as described in
+/// RFC 6749, section 5.2 and RFC 6750, section 3.
+#[derive(Debug)]
+pub struct OAuthError {
+    status: StatusCode,
+    error: &'static str,
+    description: Option<String>,
+}
+
+impl OAuthError {
+    pub fn new(status: StatusCode, error: &'static str) -> Self {
+        OAuthError {
+            status,
+            error,
+            description: None,
+        }
+    }
+
+    pub fn with_description<D: Into<String>>(mut self, description: D) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn invalid_request<D: Into<String>>(description: D) -> Self {
+        OAuthError::new(StatusCode::BAD_REQUEST, "invalid_request")
+            .with_description(description)
+    }
+
+    pub fn invalid_client() -> Self {
+        OAuthError::new(StatusCode::UNAUTHORIZED, "invalid_client")
+    }
+
+    pub fn invalid_grant<D: Into<String>>(description: D) -> Self {
+        OAuthError::new(StatusCode::BAD_REQUEST, "invalid_grant")
+            .with_description(description)
+    }
+
+    pub fn unsupported_grant_type() -> Self {
+        OAuthError::new(StatusCode::BAD_REQUEST, "unsupported_grant_type")
+    }
+
+    pub fn invalid_token() -> Self {
+        OAuthError::new(StatusCode::UNAUTHORIZED, "invalid_token")
+    }
+
+    pub fn error(&self) -> &'static str {
+        self.error
+    }
+}
+
+impl ResponseError for OAuthError {
+    fn error_response(&self) -> HttpResponse {
+        let mut builder = HttpResponse::build(self.status);
+        match self.error {
+            "invalid_client" => {
+                builder.header(header::WWW_AUTHENTICATE, "Basic");
+            }
+            "invalid_token" => {
+                builder.header(
+                    header::WWW_AUTHENTICATE,
+                    "Bearer error=\"invalid_token\"",
+                );
+            }
+            _ => {}
+        }
+
+        builder.header(header::CACHE_CONTROL, "no-store").json(
+            serde_json::json!({
+                "error": self.error,
+                "error_description": self.description,
+            }),
+        )
+    }
+}
+
+impl Display for OAuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.description {
+            Some(ref description) => {
+                write!(f, "{}: {}", self.error, description)
+            }
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+impl From<Error> for OAuthError {
+    fn from(error: Error) -> Self {
+        let description = error
+            .detail()
+            .map(str::to_string)
+            .unwrap_or_else(|| error.kind().to_string());
+
+        match error.kind() {
+            ErrorKind::BadRequest | ErrorKind::Validation => {
+                OAuthError::invalid_request(description)
+            }
+            kind => {
+                error!("{}", error);
+                OAuthError::new(kind.status(), "server_error")
+            }
+        }
+    }
+}
+
+impl From<ErrorKind> for OAuthError {
+    fn from(kind: ErrorKind) -> Self {
+        OAuthError::from(Error::from(kind))
+    }
+}
+
+impl From<Context<ErrorKind>> for OAuthError {
+    fn from(inner: Context<ErrorKind>) -> Self {
+        OAuthError::from(Error::from(inner))
+    }
+}
+
+impl From<BlockingError<OAuthError>> for OAuthError {
+    fn from(err: BlockingError<OAuthError>) -> Self {
+        match err {
+            BlockingError::Error(e) => e,
+            BlockingError::Canceled => {
+                OAuthError::from(ErrorKind::BlockingCanceled)
+            }
+        }
+    }
+}
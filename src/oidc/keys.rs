@@ -0,0 +1,171 @@
+//! Key signing ID tokens with ES256, published as JWKS for relying parties.
+//!
+//! The key is a P-256 key in unencrypted PKCS#8 form, base64 encoded in
+//! `OIDC_SIGNING_KEY` or in the file named by `OIDC_SIGNING_KEY_FILE`. One
+//! can be created with
+//!
+//! ```text
+//! openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:P-256 \
+//!     | openssl pkcs8 -topk8 -nocrypt -outform DER | base64 -w0
+//! ```
+//!
+//! Without a configured key an ephemeral one is generated, ID tokens issued
+//! before a restart can then no longer be verified.
+use std::env;
+use std::fs;
+use std::sync::Arc;
+
+use ring::digest;
+use ring::rand::SystemRandom;
+use ring::signature::{self, EcdsaKeyPair, KeyPair};
+use serde::Serialize;
+use untrusted::Input;
+
+use crate::error::{Error, ErrorKind, Result, ResultExt};
+
+pub const ALGORITHM: &str = "ES256";
+
+/// Signs JWS tokens with the provider key.
+#[derive(Clone)]
+pub struct IdTokenSigner {
+    key_pair: Arc<EcdsaKeyPair>,
+    kid: String,
+    rng: Arc<SystemRandom>,
+}
+
+impl IdTokenSigner {
+    pub fn from_pkcs8(pkcs8: &[u8]) -> Result<IdTokenSigner> {
+        let key_pair = EcdsaKeyPair::from_pkcs8(
+            &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+            Input::from(pkcs8),
+        )
+        .map_err(|e| {
+            Error::from(ErrorKind::ConfigError).with_detail(format!(
+                "the OIDC signing key is not a P-256 PKCS#8 key: {}",
+                e
+            ))
+        })?;
+        let hash =
+            digest::digest(&digest::SHA256, key_pair.public_key().as_ref());
+
+        Ok(IdTokenSigner {
+            key_pair: Arc::new(key_pair),
+            kid: encode(&hash.as_ref()[..8]),
+            rng: Arc::new(SystemRandom::new()),
+        })
+    }
+
+    /// Generates a key that lives as long as the process.
+    pub fn generate() -> Result<IdTokenSigner> {
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(
+            &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+            &SystemRandom::new(),
+        )
+        .map_err(|_| Error::from(ErrorKind::ConfigError))?;
+
+        IdTokenSigner::from_pkcs8(pkcs8.as_ref())
+    }
+
+    pub fn from_env() -> Result<IdTokenSigner> {
+        let key = match env::var("OIDC_SIGNING_KEY") {
+            Ok(key) => key,
+            Err(_) => match env::var("OIDC_SIGNING_KEY_FILE") {
+                Ok(path) => {
+                    fs::read_to_string(path).context(ErrorKind::ConfigError)?
+                }
+                Err(_) => {
+                    warn!("No OIDC signing key configured, using an ephemeral key");
+                    return IdTokenSigner::generate();
+                }
+            },
+        };
+        let pkcs8 = base64::decode(key.trim()).map_err(|_| {
+            Error::from(ErrorKind::ConfigError)
+                .with_detail("the OIDC signing key must be base64 encoded")
+        })?;
+
+        IdTokenSigner::from_pkcs8(&pkcs8)
+    }
+
+    pub fn kid(&self) -> &str {
+        &self.kid
+    }
+
+    /// Signs the claims as a compact JWS.
+    pub fn sign<T: Serialize>(&self, claims: &T) -> Result<String> {
+        let header = serde_json::json!({
+            "alg": ALGORITHM,
+            "typ": "JWT",
+            "kid": self.kid,
+        });
+        let header = serde_json::to_vec(&header)
+            .context(ErrorKind::SerializeJsonError)?;
+        let payload = serde_json::to_vec(claims)
+            .context(ErrorKind::SerializeJsonError)?;
+        let message = format!("{}.{}", encode(&header), encode(&payload));
+        let signature = self
+            .key_pair
+            .sign(&*self.rng, Input::from(message.as_bytes()))
+            .map_err(|_| Error::from(ErrorKind::SerializeJsonError))?;
+
+        Ok(format!("{}.{}", message, encode(signature.as_ref())))
+    }
+
+    /// The public key as JSON Web Key Set (RFC 7517).
+    pub fn jwks(&self) -> serde_json::Value {
+        // Uncompressed point, 0x04 followed by the coordinates.
+        let point = self.key_pair.public_key().as_ref();
+
+        serde_json::json!({
+            "keys": [{
+                "kty": "EC",
+                "crv": "P-256",
+                "use": "sig",
+                "alg": ALGORITHM,
+                "kid": self.kid,
+                "x": encode(&point[1..33]),
+                "y": encode(&point[33..65]),
+            }],
+        })
+    }
+}
+
+fn encode(input: &[u8]) -> String {
+    base64::encode_config(input, base64::URL_SAFE_NO_PAD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(input: &str) -> Vec<u8> {
+        base64::decode_config(input, base64::URL_SAFE_NO_PAD).unwrap()
+    }
+
+    #[test]
+    fn test_sign() {
+        let signer = IdTokenSigner::generate().unwrap();
+        let token = signer.sign(&serde_json::json!({ "sub": "bob" })).unwrap();
+        let parts = token.split('.').collect::<Vec<&str>>();
+        assert_eq!(3, parts.len());
+
+        let header: serde_json::Value =
+            serde_json::from_slice(&decode(parts[0])).unwrap();
+        assert_eq!("ES256", header["alg"]);
+        assert_eq!(signer.kid(), header["kid"]);
+
+        let jwks = signer.jwks();
+        let key = &jwks["keys"][0];
+        let mut point = vec![4];
+        point.extend(decode(key["x"].as_str().unwrap()));
+        point.extend(decode(key["y"].as_str().unwrap()));
+        let message = format!("{}.{}", parts[0], parts[1]);
+        signature::verify(
+            &signature::ECDSA_P256_SHA256_FIXED,
+            Input::from(&point),
+            Input::from(message.as_bytes()),
+            Input::from(&decode(parts[2])),
+        )
+        .unwrap();
+    }
+}
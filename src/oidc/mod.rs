@@ -0,0 +1,102 @@
+//! OpenID Connect provider, letting other applications log users in with
+//! their hamster account (authorization code flow with PKCE only).
+//!
+//! The authorization endpoint announced by discovery is a frontend page at
+//! `{issuer}/authorize`. It asks the logged in user for consent using
+//! `GET` and `POST` of `/oauth/authorize` with the query of the request.
+mod authorize;
+mod clients;
+mod error;
+pub mod keys;
+mod provider;
+pub mod relying_party;
+mod token;
+
+use actix_web::dev::HttpServiceFactory;
+use actix_web::http::Method;
+use actix_web::{web, HttpResponse, Resource};
+
+pub use self::error::OAuthError;
+pub use self::keys::IdTokenSigner;
+pub use self::provider::{Provider, ACCESS_TOKEN_PREFIX, SCOPES};
+pub use self::relying_party::RelyingParty;
+use crate::auth::{middleware::AuthorizationService, AccessRules};
+use crate::error::{Error, ErrorKind};
+
+/// Where the provider endpoints are mounted.
+pub const PATH: &str = "/oauth";
+
+pub fn service(path: &str) -> impl HttpServiceFactory {
+    web::scope(path)
+        .data(web::JsonConfig::default().error_handler(|err, _| {
+            Error::from(ErrorKind::BadRequest)
+                .with_detail(err.to_string())
+                .into()
+        }))
+        .data(web::FormConfig::default().error_handler(|err, _| {
+            OAuthError::invalid_request(err.to_string()).into()
+        }))
+        .wrap(AuthorizationService::new(access_rules(path)))
+        .service(
+            web::resource("/authorize")
+                .route(web::get().to_async(authorize::get_authorization))
+                .route(web::post().to_async(authorize::post_authorization)),
+        )
+        .service(
+            web::resource("/token").route(web::post().to_async(token::token)),
+        )
+        .service(
+            web::resource("/userinfo")
+                .route(web::get().to_async(token::userinfo))
+                .route(web::post().to_async(token::userinfo)),
+        )
+        .service(web::resource("/jwks").route(web::get().to(token::jwks)))
+        .service(clients::service("/clients"))
+}
+
+fn access_rules(path: &str) -> AccessRules {
+    AccessRules::new(path)
+        .authenticated(Method::GET, "/authorize")
+        .authenticated(Method::POST, "/authorize")
+        .permit_all(Method::POST, "/token")
+        .permit_all(Method::GET, "/userinfo")
+        .permit_all(Method::POST, "/userinfo")
+        .permit_all(Method::GET, "/jwks")
+        .has_authority(Method::GET, "/clients", "oauth_clients.get")
+        .has_authority(Method::POST, "/clients", "oauth_clients.post")
+        .has_authority(
+            Method::DELETE,
+            "/clients/{client_id}",
+            "oauth_clients.del",
+        )
+}
+
+/// OpenID Provider Metadata, served at the root of the issuer.
+pub fn discovery() -> Resource {
+    web::resource("/.well-known/openid-configuration")
+        .route(web::get().to(configuration))
+}
+
+fn configuration(provider: web::Data<Provider>) -> HttpResponse {
+    let issuer = provider.issuer();
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{}/authorize", issuer),
+        "token_endpoint": format!("{}{}/token", issuer, PATH),
+        "userinfo_endpoint": format!("{}{}/userinfo", issuer, PATH),
+        "jwks_uri": format!("{}{}/jwks", issuer, PATH),
+        "response_types_supported": ["code"],
+        "grant_types_supported": ["authorization_code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": [keys::ALGORITHM],
+        "scopes_supported": SCOPES,
+        "token_endpoint_auth_methods_supported":
+            ["client_secret_basic", "client_secret_post", "none"],
+        "code_challenge_methods_supported": ["S256"],
+        "claims_supported": [
+            "iss", "sub", "aud", "iat", "exp", "nonce",
+            "username", "nickname", "avatar_url", "groups",
+        ],
+    }))
+}
@@ -0,0 +1,678 @@
+//! Authorization code flow with PKCE (RFC 6749, RFC 7636) and the OpenID
+//! Connect claims of hamster users.
+use chrono::prelude::*;
+use chrono::Duration;
+use ring::{constant_time, digest};
+use uuid::Uuid;
+
+use super::error::OAuthError;
+use super::keys::IdTokenSigner;
+use crate::db::oauth::{self, AccessToken, AuthorizationCode, OAuthClient};
+use crate::db::users::{self, User};
+use crate::db::{groups, Conn};
+use crate::error::{Error, ErrorKind, Result};
+use crate::utils;
+
+/// Prefix of access tokens, telling them apart from hamster's own bearer
+/// tokens.
+pub const ACCESS_TOKEN_PREFIX: &str = "hat_";
+pub const SCOPES: &[&str] = &["openid", "profile", "groups"];
+const CODE_LENGTH: usize = 43;
+const TOKEN_LENGTH: usize = 43;
+
+/// Query of the authorization endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthorizeRequest {
+    pub response_type: String,
+    pub client_id: String,
+    pub redirect_uri: String,
+    #[serde(default)]
+    pub scope: String,
+    #[serde(default)]
+    pub state: Option<String>,
+    #[serde(default)]
+    pub nonce: Option<String>,
+    #[serde(default)]
+    pub code_challenge: Option<String>,
+    #[serde(default)]
+    pub code_challenge_method: Option<String>,
+}
+
+/// Outcome of an authorization request.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Authorization {
+    /// A valid request, for the user to approve or deny.
+    Consent {
+        client_id: Uuid,
+        client_name: String,
+        scopes: Vec<String>,
+    },
+    /// The user agent is sent back to the client.
+    Redirect { redirect_to: String },
+}
+
+/// Form of the token endpoint.
+#[derive(Debug, Default, Deserialize)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub code_verifier: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: &'static str,
+    pub expires_in: i64,
+    pub id_token: String,
+    pub scope: String,
+}
+
+/// Claims about a user, returned by the userinfo endpoint and carried by ID
+/// tokens. Only `sub` is always present, the `profile` scope adds the
+/// username, nickname and avatar and the `groups` scope the groups.
+#[derive(Debug, Serialize)]
+pub struct UserClaims {
+    pub sub: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nickname: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avatar_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub groups: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+struct IdTokenClaims<'a> {
+    iss: &'a str,
+    aud: String,
+    iat: i64,
+    exp: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nonce: Option<String>,
+    #[serde(flatten)]
+    user: UserClaims,
+}
+
+/// Issues codes, access tokens and ID tokens on behalf of `issuer`.
+#[derive(Clone)]
+pub struct Provider {
+    issuer: String,
+    signer: IdTokenSigner,
+    code_max_age: Duration,
+    token_max_age: Duration,
+}
+
+impl Provider {
+    pub fn new<S: Into<String>>(issuer: S, signer: IdTokenSigner) -> Provider {
+        Provider {
+            issuer: issuer.into().trim_end_matches('/').to_string(),
+            signer,
+            code_max_age: Duration::minutes(1),
+            token_max_age: Duration::hours(1),
+        }
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn signer(&self) -> &IdTokenSigner {
+        &self.signer
+    }
+
+    /// Checks an authorization request before asking the user for consent.
+    ///
+    /// Fails with `BadRequest` if the client or the redirect uri are
+    /// unknown, nothing is sent to an unverified uri. Other problems are
+    /// reported to the client by redirecting back to it.
+    pub fn authorize(
+        &self,
+        conn: &Conn,
+        request: &AuthorizeRequest,
+    ) -> Result<Authorization> {
+        let client = find_client(conn, request)?;
+        if let Err(redirect) = check(request) {
+            return Ok(redirect);
+        }
+
+        Ok(Authorization::Consent {
+            client_id: client.id,
+            client_name: client.name,
+            scopes: scopes(&request.scope),
+        })
+    }
+
+    /// Completes an authorization request the user approved or denied,
+    /// handing a code to the client on approval.
+    pub fn decide(
+        &self,
+        conn: &Conn,
+        request: &AuthorizeRequest,
+        user_id: &Uuid,
+        approved: bool,
+    ) -> Result<Authorization> {
+        let client = find_client(conn, request)?;
+        if let Err(redirect) = check(request) {
+            return Ok(redirect);
+        }
+        if !approved {
+            return Ok(redirect(request, &[("error", "access_denied")]));
+        }
+
+        let code = utils::random_string(CODE_LENGTH);
+        let now = Utc::now();
+        oauth::create_code(
+            conn,
+            &AuthorizationCode {
+                id: Uuid::new_v4(),
+                code_hash: utils::hash_token(&code),
+                client_id: client.id,
+                user_id: *user_id,
+                redirect_uri: request.redirect_uri.clone(),
+                scope: scopes(&request.scope).join(" "),
+                nonce: request.nonce.clone(),
+                code_challenge: request
+                    .code_challenge
+                    .clone()
+                    .unwrap_or_default(),
+                created_at: now,
+                expires_at: now + self.code_max_age,
+                used_at: None,
+            },
+        )?;
+
+        Ok(redirect(request, &[("code", &code)]))
+    }
+
+    /// Exchanges an authorization code for an access token and an ID token.
+    ///
+    /// Confidential clients authenticate with their secret, either from the
+    /// `Authorization: Basic` header passed as `basic` or from the form.
+    pub fn exchange(
+        &self,
+        conn: &Conn,
+        request: TokenRequest,
+        basic: Option<(String, String)>,
+    ) -> std::result::Result<TokenResponse, OAuthError> {
+        let (client_id, secret) = match basic {
+            Some((client_id, secret)) => {
+                if request.client_id.iter().any(|id| id != &client_id) {
+                    return Err(OAuthError::invalid_request(
+                        "client_id does not match the authenticated client",
+                    ));
+                }
+                (client_id, Some(secret))
+            }
+            None => match request.client_id {
+                Some(ref client_id) => {
+                    (client_id.clone(), request.client_secret.clone())
+                }
+                None => return Err(OAuthError::invalid_client()),
+            },
+        };
+        let client = match Uuid::parse_str(&client_id) {
+            Ok(client_id) => oauth::find_client(conn, &client_id)?,
+            Err(_) => None,
+        };
+        let client = match client {
+            Some(client) => client,
+            None => return Err(OAuthError::invalid_client()),
+        };
+        let authenticated = match (&client.secret_hash, secret) {
+            (Some(secret_hash), Some(secret)) => {
+                constant_time::verify_slices_are_equal(
+                    utils::hash_token(&secret).as_bytes(),
+                    secret_hash.as_bytes(),
+                )
+                .is_ok()
+            }
+            (None, None) => true,
+            _ => false,
+        };
+        if !authenticated {
+            return Err(OAuthError::invalid_client());
+        }
+
+        if request.grant_type != "authorization_code" {
+            return Err(OAuthError::unsupported_grant_type());
+        }
+        let (code, code_verifier) = match (request.code, request.code_verifier)
+        {
+            (Some(code), Some(code_verifier)) => (code, code_verifier),
+            _ => {
+                return Err(OAuthError::invalid_request(
+                    "code and code_verifier are required",
+                ))
+            }
+        };
+
+        let now = Utc::now();
+        let code =
+            match oauth::redeem_code(conn, &utils::hash_token(&code), now)? {
+                Some(code) if code.client_id == client.id => code,
+                _ => {
+                    return Err(OAuthError::invalid_grant(
+                        "the code is invalid or expired",
+                    ))
+                }
+            };
+        if request.redirect_uri.as_ref() != Some(&code.redirect_uri) {
+            return Err(OAuthError::invalid_grant(
+                "redirect_uri does not match the authorization request",
+            ));
+        }
+        if !verify_pkce(&code.code_challenge, &code_verifier) {
+            return Err(OAuthError::invalid_grant(
+                "code_verifier does not match the code challenge",
+            ));
+        }
+        let user = match users::find_by_id(conn, &code.user_id)? {
+            Some(user) => user,
+            None => return Err(OAuthError::invalid_grant("the user is gone")),
+        };
+
+        let access_token = format!(
+            "{}{}",
+            ACCESS_TOKEN_PREFIX,
+            utils::random_string(TOKEN_LENGTH)
+        );
+        oauth::create_access_token(
+            conn,
+            &AccessToken {
+                id: Uuid::new_v4(),
+                token_hash: utils::hash_token(&access_token),
+                client_id: client.id,
+                user_id: user.id,
+                scope: code.scope.clone(),
+                created_at: now,
+                expires_at: now + self.token_max_age,
+            },
+        )?;
+        let id_token = self.signer.sign(&IdTokenClaims {
+            iss: &self.issuer,
+            aud: client.id.to_string(),
+            iat: now.timestamp(),
+            exp: (now + self.token_max_age).timestamp(),
+            nonce: code.nonce,
+            user: claims(conn, &user, &code.scope)?,
+        })?;
+
+        Ok(TokenResponse {
+            access_token,
+            token_type: "Bearer",
+            expires_in: self.token_max_age.num_seconds(),
+            id_token,
+            scope: code.scope,
+        })
+    }
+
+    /// Claims about the user an access token was issued for.
+    pub fn userinfo(
+        &self,
+        conn: &Conn,
+        access_token: &str,
+    ) -> std::result::Result<UserClaims, OAuthError> {
+        let token_hash = utils::hash_token(access_token);
+        let token = oauth::find_access_token(conn, &token_hash, Utc::now())?;
+        let token = match token {
+            Some(token) => token,
+            None => return Err(OAuthError::invalid_token()),
+        };
+
+        match users::find_by_id(conn, &token.user_id)? {
+            Some(user) => Ok(claims(conn, &user, &token.scope)?),
+            None => Err(OAuthError::invalid_token()),
+        }
+    }
+}
+
+/// Returns the client if it exists and registered the redirect uri.
+fn find_client(conn: &Conn, request: &AuthorizeRequest) -> Result<OAuthClient> {
+    let client = match Uuid::parse_str(&request.client_id) {
+        Ok(client_id) => oauth::find_client(conn, &client_id)?,
+        Err(_) => None,
+    };
+
+    match client {
+        Some(ref client)
+            if client.redirect_uris.contains(&request.redirect_uri) =>
+        {
+            Ok(client.clone())
+        }
+        Some(_) => Err(Error::from(ErrorKind::BadRequest)
+            .with_detail("redirect_uri is not registered for the client")),
+        None => Err(Error::from(ErrorKind::BadRequest)
+            .with_detail("the client is unknown")),
+    }
+}
+
+/// Checks the parameters a client has to be told about by redirect.
+fn check(request: &AuthorizeRequest) -> std::result::Result<(), Authorization> {
+    let error = |error: &str, description: &str| {
+        redirect(
+            request,
+            &[("error", error), ("error_description", description)],
+        )
+    };
+
+    if request.response_type != "code" {
+        return Err(error(
+            "unsupported_response_type",
+            "only the code response type is supported",
+        ));
+    }
+    if !scopes(&request.scope).iter().any(|s| s == "openid") {
+        return Err(error("invalid_scope", "the openid scope is required"));
+    }
+
+    let challenge_valid = match request.code_challenge {
+        Some(ref challenge) => {
+            challenge.len() == 43
+                && challenge
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        }
+        None => false,
+    };
+    let method_valid = match request.code_challenge_method {
+        Some(ref method) => method == "S256",
+        None => false,
+    };
+    if !challenge_valid || !method_valid {
+        return Err(error(
+            "invalid_request",
+            "a S256 code_challenge is required",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Redirect to the client with the parameters and the state of the request.
+fn redirect(
+    request: &AuthorizeRequest,
+    params: &[(&str, &str)],
+) -> Authorization {
+    let mut params = params.to_vec();
+    if let Some(ref state) = request.state {
+        params.push(("state", state));
+    }
+    let separator = if request.redirect_uri.contains('?') {
+        '&'
+    } else {
+        '?'
+    };
+
+    Authorization::Redirect {
+        redirect_to: format!(
+            "{}{}{}",
+            request.redirect_uri,
+            separator,
+            serde_urlencoded::to_string(params).unwrap_or_default()
+        ),
+    }
+}
+
+/// The supported scopes out of a requested scope, others are ignored.
+fn scopes(scope: &str) -> Vec<String> {
+    let requested = scope.split_whitespace().collect::<Vec<&str>>();
+
+    SCOPES
+        .iter()
+        .filter(|s| requested.contains(s))
+        .map(|s| s.to_string())
+        .collect()
+}
+
+fn verify_pkce(code_challenge: &str, code_verifier: &str) -> bool {
+    if code_verifier.len() < 43 || code_verifier.len() > 128 {
+        return false;
+    }
+    let hash = digest::digest(&digest::SHA256, code_verifier.as_bytes());
+    let expected =
+        base64::encode_config(hash.as_ref(), base64::URL_SAFE_NO_PAD);
+
+    constant_time::verify_slices_are_equal(
+        expected.as_bytes(),
+        code_challenge.as_bytes(),
+    )
+    .is_ok()
+}
+
+/// Claims about the user released by the granted scope.
+fn claims(conn: &Conn, user: &User, scope: &str) -> Result<UserClaims> {
+    let granted = scopes(scope);
+    let profile = granted.iter().any(|s| s == "profile");
+    let groups = if granted.iter().any(|s| s == "groups") {
+        let mut groups = groups::find_by_member_id(conn, &user.id)?
+            .into_iter()
+            .map(|g| g.display_name)
+            .collect::<Vec<String>>();
+        groups.sort();
+        Some(groups)
+    } else {
+        None
+    };
+
+    Ok(UserClaims {
+        sub: user.id.simple().to_string(),
+        username: Some(user.username.clone()).filter(|_| profile),
+        nickname: Some(user.nickname.clone()).filter(|_| profile),
+        avatar_url: user.avatar_url.clone().filter(|_| profile),
+        groups,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::groups::GroupMembershipType;
+    use crate::oidc::clients::{self, NewClient};
+    use crate::test_helpers::*;
+    use serde_json::json;
+
+    const VERIFIER: &str = "dBjftJeZ4CVP-mJ92K27uhbUJU1p1r_wW1gFWFOEjXk";
+    const CHALLENGE: &str = "ngF5GsXcbwljx6u133FFr3Xht9xooA_DuaX_3QwODtc";
+
+    fn request(client_id: Uuid) -> AuthorizeRequest {
+        AuthorizeRequest {
+            response_type: "code".to_string(),
+            client_id: client_id.to_string(),
+            redirect_uri: "https://app.example.com/callback".to_string(),
+            scope: "openid profile groups unknown".to_string(),
+            state: Some("xyz".to_string()),
+            nonce: Some("n-0S6_WzA2Mj".to_string()),
+            code_challenge: Some(CHALLENGE.to_string()),
+            code_challenge_method: Some("S256".to_string()),
+        }
+    }
+
+    fn code_of(authorization: Authorization) -> String {
+        match authorization {
+            Authorization::Redirect { redirect_to } => {
+                let query = &redirect_to[redirect_to.find('?').unwrap() + 1..];
+                let params: Vec<(String, String)> =
+                    serde_urlencoded::from_str(query).unwrap();
+                assert!(params.contains(&("state".into(), "xyz".into())));
+                params
+                    .into_iter()
+                    .find(|(name, _)| name == "code")
+                    .map(|(_, value)| value)
+                    .unwrap()
+            }
+            _ => panic!("expected a redirect"),
+        }
+    }
+
+    #[test]
+    fn test_authorization_code_flow() {
+        let conn = connection();
+        let provider = Provider::new(
+            "https://hamster.example.com",
+            IdTokenSigner::generate().unwrap(),
+        );
+        let user =
+            users::create_or_update(&conn, "bob", "Bob", "password").unwrap();
+        let group = groups::get_or_create(&conn, "oidc_group").unwrap();
+        groups::add_member(
+            &conn,
+            &group.id,
+            &user.id,
+            GroupMembershipType::User,
+        )
+        .unwrap();
+        let client = clients::register(
+            &conn,
+            NewClient {
+                name: "App".to_string(),
+                redirect_uris: vec![
+                    "https://app.example.com/callback".to_string()
+                ],
+                confidential: true,
+            },
+        )
+        .unwrap();
+        let client_id = client.client.id;
+        let secret = client.client_secret.unwrap();
+
+        assert_eq!(
+            Authorization::Consent {
+                client_id,
+                client_name: "App".to_string(),
+                scopes: vec![
+                    "openid".to_string(),
+                    "profile".to_string(),
+                    "groups".to_string(),
+                ],
+            },
+            provider.authorize(&conn, &request(client_id)).unwrap()
+        );
+        let without_pkce = AuthorizeRequest {
+            code_challenge: None,
+            ..request(client_id)
+        };
+        match provider.authorize(&conn, &without_pkce).unwrap() {
+            Authorization::Redirect { redirect_to } => {
+                assert!(redirect_to.contains("error=invalid_request"))
+            }
+            _ => panic!("expected a redirect"),
+        }
+
+        let code = code_of(
+            provider
+                .decide(&conn, &request(client_id), &user.id, true)
+                .unwrap(),
+        );
+        let token_request = |code: &str, verifier: &str| TokenRequest {
+            grant_type: "authorization_code".to_string(),
+            code: Some(code.to_string()),
+            redirect_uri: Some("https://app.example.com/callback".to_string()),
+            client_id: Some(client_id.to_string()),
+            client_secret: Some(secret.clone()),
+            code_verifier: Some(verifier.to_string()),
+        };
+        let wrong_secret = TokenRequest {
+            client_secret: Some("wrong".to_string()),
+            ..token_request(&code, VERIFIER)
+        };
+        let err = provider.exchange(&conn, wrong_secret, None).unwrap_err();
+        assert_eq!("invalid_client", err.error());
+
+        let response = provider
+            .exchange(&conn, token_request(&code, VERIFIER), None)
+            .unwrap();
+        assert_eq!("openid profile groups", response.scope);
+        let payload = response.id_token.split('.').nth(1).unwrap();
+        let payload: serde_json::Value = serde_json::from_slice(
+            &base64::decode_config(payload, base64::URL_SAFE_NO_PAD).unwrap(),
+        )
+        .unwrap();
+        assert_eq!("https://hamster.example.com", payload["iss"]);
+        assert_eq!(client_id.to_string(), payload["aud"]);
+        assert_eq!("n-0S6_WzA2Mj", payload["nonce"]);
+        assert_eq!("bob", payload["username"]);
+        assert_eq!(serde_json::json!(["oidc_group"]), payload["groups"]);
+
+        let claims = provider.userinfo(&conn, &response.access_token).unwrap();
+        assert_eq!(user.id.simple().to_string(), claims.sub);
+        assert_eq!(Some(vec!["oidc_group".to_string()]), claims.groups);
+        let err = provider.userinfo(&conn, "hat_unknown").unwrap_err();
+        assert_eq!("invalid_token", err.error());
+
+        // Codes are single use and bound to the verifier.
+        let err = provider
+            .exchange(&conn, token_request(&code, VERIFIER), None)
+            .unwrap_err();
+        assert_eq!("invalid_grant", err.error());
+        let code = code_of(
+            provider
+                .decide(&conn, &request(client_id), &user.id, true)
+                .unwrap(),
+        );
+        let err = provider
+            .exchange(&conn, token_request(&code, &"x".repeat(43)), None)
+            .unwrap_err();
+        assert_eq!("invalid_grant", err.error());
+    }
+
+    #[test]
+    fn test_openid_scope_only() {
+        let conn = connection();
+        let provider = Provider::new(
+            "https://hamster.example.com",
+            IdTokenSigner::generate().unwrap(),
+        );
+        let user =
+            users::create_or_update(&conn, "bob", "Bob", "password").unwrap();
+        let client = clients::register(
+            &conn,
+            NewClient {
+                name: "App".to_string(),
+                redirect_uris: vec![
+                    "https://app.example.com/callback".to_string()
+                ],
+                confidential: true,
+            },
+        )
+        .unwrap();
+        let request = AuthorizeRequest {
+            scope: "openid".to_string(),
+            ..request(client.client.id)
+        };
+
+        let code =
+            code_of(provider.decide(&conn, &request, &user.id, true).unwrap());
+        let token_request = TokenRequest {
+            grant_type: "authorization_code".to_string(),
+            code: Some(code),
+            redirect_uri: Some(request.redirect_uri.clone()),
+            client_id: Some(client.client.id.to_string()),
+            client_secret: client.client_secret,
+            code_verifier: Some(VERIFIER.to_string()),
+        };
+        let response = provider.exchange(&conn, token_request, None).unwrap();
+        assert_eq!("openid", response.scope);
+        let payload = response.id_token.split('.').nth(1).unwrap();
+        let payload: serde_json::Value = serde_json::from_slice(
+            &base64::decode_config(payload, base64::URL_SAFE_NO_PAD).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(json!(user.id.simple().to_string()), payload["sub"]);
+        for claim in &["username", "nickname", "avatar_url", "groups"] {
+            assert!(payload.get(claim).is_none(), "{}", claim);
+        }
+
+        let claims = provider.userinfo(&conn, &response.access_token).unwrap();
+        assert_eq!(
+            json!({ "sub": user.id.simple().to_string() }),
+            serde_json::to_value(claims).unwrap()
+        );
+    }
+}
@@ -0,0 +1,855 @@
+//! Login with an external OpenID Connect provider, hamster acting as relying
+//! party with the authorization code flow and PKCE.
+//!
+//! Configured with `OIDC_LOGIN_ISSUER` and `OIDC_LOGIN_CLIENT_ID`, and
+//! optionally
+//!
+//! * `OIDC_LOGIN_CLIENT_SECRET`, sent with `client_secret_basic`
+//! * `OIDC_LOGIN_REDIRECT_URI`, the frontend page completing the login,
+//!   `{PUBLIC_URL}/login/oidc` by default
+//! * `OIDC_LOGIN_AUTO_PROVISION`, creating users for unknown subjects
+//! * `OIDC_LOGIN_GROUPS_CLAIM`, a claim listing group names. The user joins
+//!   the hamster groups of those names, and leaves them again once the claim
+//!   no longer lists them.
+//!
+//! Unknown subjects are linked to the logged in user if there is one.
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, RwLock};
+use std::time::Duration as StdDuration;
+
+use actix_web::client::Client;
+use chrono::prelude::*;
+use chrono::Duration;
+use futures::future::{self, Either};
+use futures::Future;
+use ring::{constant_time, digest, signature};
+use serde::de::DeserializeOwned;
+use untrusted::Input;
+use uuid::Uuid;
+
+use crate::auth::PasswordPolicy;
+use crate::db::external_identities::{self, ExternalIdentity, ExternalLogin};
+use crate::db::groups::{self, GroupMembershipType};
+use crate::db::users::{self, NewUser, User};
+use crate::db::Conn;
+use crate::error::{Error, ErrorKind, Result};
+use crate::utils;
+
+/// Seconds to wait for the provider.
+const TIMEOUT: u64 = 10;
+/// Seconds of clock difference tolerated when checking ID tokens.
+const LEEWAY: i64 = 60;
+const SECRET_LENGTH: usize = 43;
+
+/// OpenID Provider Metadata, as far as needed.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Metadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+/// JSON Web Key of the provider, RSA and P-256 keys are supported.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Jwk {
+    pub kty: String,
+    #[serde(default)]
+    pub kid: Option<String>,
+    #[serde(default)]
+    pub crv: Option<String>,
+    #[serde(default)]
+    pub n: Option<String>,
+    #[serde(default)]
+    pub e: Option<String>,
+    #[serde(default)]
+    pub x: Option<String>,
+    #[serde(default)]
+    pub y: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwsHeader {
+    alg: String,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Verified claims of an ID token.
+#[derive(Debug, Deserialize)]
+pub struct ExternalClaims {
+    pub iss: String,
+    pub sub: String,
+    aud: Audience,
+    exp: i64,
+    #[serde(default)]
+    nonce: Option<String>,
+    #[serde(default)]
+    pub preferred_username: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: Option<bool>,
+    #[serde(default)]
+    pub picture: Option<String>,
+    #[serde(flatten)]
+    pub other: HashMap<String, serde_json::Value>,
+}
+
+/// A login waiting for the user agent to come back from the provider.
+#[derive(Debug)]
+pub struct StartedLogin {
+    pub redirect_to: String,
+    /// Secret for the user agent to keep in a cookie until it comes back.
+    pub binding: String,
+}
+
+/// Client of the external provider.
+#[derive(Clone)]
+pub struct RelyingParty {
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_uri: String,
+    auto_provision: bool,
+    groups_claim: Option<String>,
+    login_max_age: Duration,
+    metadata: Arc<RwLock<Option<Metadata>>>,
+    keys: Arc<RwLock<Vec<Jwk>>>,
+}
+
+impl RelyingParty {
+    pub fn new<I, C, R>(
+        issuer: I,
+        client_id: C,
+        redirect_uri: R,
+    ) -> RelyingParty
+    where
+        I: Into<String>,
+        C: Into<String>,
+        R: Into<String>,
+    {
+        RelyingParty {
+            issuer: issuer.into().trim_end_matches('/').to_string(),
+            client_id: client_id.into(),
+            client_secret: None,
+            redirect_uri: redirect_uri.into(),
+            auto_provision: false,
+            groups_claim: None,
+            login_max_age: Duration::minutes(10),
+            metadata: Arc::new(RwLock::new(None)),
+            keys: Arc::new(RwLock::new(Vec::new())),
+        }
+    }
+
+    pub fn client_secret<S: Into<String>>(mut self, secret: S) -> Self {
+        self.client_secret = Some(secret.into());
+        self
+    }
+
+    pub fn auto_provision(mut self, auto_provision: bool) -> Self {
+        self.auto_provision = auto_provision;
+        self
+    }
+
+    pub fn groups_claim<S: Into<String>>(mut self, claim: S) -> Self {
+        self.groups_claim = Some(claim.into());
+        self
+    }
+
+    /// Reads the configuration, `None` if no provider is configured.
+    pub fn from_env(public_url: &str) -> Result<Option<RelyingParty>> {
+        let issuer = match env::var("OIDC_LOGIN_ISSUER") {
+            Ok(issuer) => issuer,
+            Err(_) => return Ok(None),
+        };
+        let client_id = env::var("OIDC_LOGIN_CLIENT_ID").map_err(|_| {
+            Error::from(ErrorKind::ConfigError)
+                .with_detail("OIDC_LOGIN_CLIENT_ID must be set")
+        })?;
+        let redirect_uri = env::var("OIDC_LOGIN_REDIRECT_URI")
+            .unwrap_or_else(|_| format!("{}/login/oidc", public_url));
+
+        let mut rp = RelyingParty::new(issuer, client_id, redirect_uri)
+            .auto_provision(
+                env::var("OIDC_LOGIN_AUTO_PROVISION")
+                    .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                    .unwrap_or(false),
+            );
+        if let Ok(secret) = env::var("OIDC_LOGIN_CLIENT_SECRET") {
+            rp = rp.client_secret(secret);
+        }
+        if let Ok(claim) = env::var("OIDC_LOGIN_GROUPS_CLAIM") {
+            rp = rp.groups_claim(claim);
+        }
+
+        Ok(Some(rp))
+    }
+
+    /// The provider metadata, discovered on first use.
+    pub fn metadata(&self) -> impl Future<Item = Metadata, Error = Error> {
+        if let Some(ref metadata) = *self.metadata.read().unwrap() {
+            return Either::A(future::ok(metadata.clone()));
+        }
+        let url = format!("{}/.well-known/openid-configuration", self.issuer);
+        let issuer = self.issuer.clone();
+        let cache = self.metadata.clone();
+
+        Either::B(get_json::<Metadata>(&url).and_then(move |metadata| {
+            if metadata.issuer.trim_end_matches('/') != issuer {
+                return Err(Error::from(ErrorKind::IdentityProviderError)
+                    .with_detail("the discovered issuer does not match"));
+            }
+            *cache.write().unwrap() = Some(metadata.clone());
+            Ok(metadata)
+        }))
+    }
+
+    /// Records a new login and returns where to send the user agent.
+    pub fn start(
+        &self,
+        conn: &Conn,
+        metadata: &Metadata,
+    ) -> Result<StartedLogin> {
+        let state = utils::random_string(SECRET_LENGTH);
+        let nonce = utils::random_string(SECRET_LENGTH);
+        let code_verifier = utils::random_string(SECRET_LENGTH);
+        let binding = utils::random_string(SECRET_LENGTH);
+        let now = Utc::now();
+        external_identities::create_login(
+            conn,
+            &ExternalLogin {
+                state_hash: utils::hash_token(&state),
+                binding_hash: utils::hash_token(&binding),
+                nonce: nonce.clone(),
+                code_verifier: code_verifier.clone(),
+                created_at: now,
+                expires_at: now + self.login_max_age,
+            },
+        )?;
+
+        let challenge =
+            digest::digest(&digest::SHA256, code_verifier.as_bytes());
+        let challenge = encode(challenge.as_ref());
+        let query = serde_urlencoded::to_string([
+            ("response_type", "code"),
+            ("client_id", &self.client_id),
+            ("redirect_uri", &self.redirect_uri),
+            ("scope", "openid profile email"),
+            ("state", &state),
+            ("nonce", &nonce),
+            ("code_challenge", &challenge),
+            ("code_challenge_method", "S256"),
+        ])
+        .unwrap_or_default();
+        let separator = if metadata.authorization_endpoint.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+
+        Ok(StartedLogin {
+            redirect_to: format!(
+                "{}{}{}",
+                metadata.authorization_endpoint, separator, query
+            ),
+            binding,
+        })
+    }
+
+    /// Takes the login the user agent comes back to, which must be the one
+    /// that started it. Fails with `Unauthorized` otherwise.
+    pub fn take_login(
+        &self,
+        conn: &Conn,
+        state: &str,
+        binding: Option<&str>,
+    ) -> Result<ExternalLogin> {
+        let login = external_identities::take_login(
+            conn,
+            &utils::hash_token(state),
+            Utc::now(),
+        )?;
+        let binding_hash = binding.map(utils::hash_token).unwrap_or_default();
+
+        match login {
+            Some(login)
+                if constant_time::verify_slices_are_equal(
+                    binding_hash.as_bytes(),
+                    login.binding_hash.as_bytes(),
+                )
+                .is_ok() =>
+            {
+                Ok(login)
+            }
+            _ => Err(Error::from(ErrorKind::Unauthorized)
+                .with_detail("the login is unknown or expired")),
+        }
+    }
+
+    /// Redeems the code at the provider and verifies the returned ID token.
+    pub fn exchange(
+        &self,
+        code: &str,
+        login: &ExternalLogin,
+    ) -> impl Future<Item = ExternalClaims, Error = Error> {
+        let form = vec![
+            ("grant_type", "authorization_code".to_string()),
+            ("code", code.to_string()),
+            ("redirect_uri", self.redirect_uri.clone()),
+            ("client_id", self.client_id.clone()),
+            ("code_verifier", login.code_verifier.clone()),
+        ];
+        let nonce = login.nonce.clone();
+        let rp = self.clone();
+
+        self.metadata().and_then(move |metadata| {
+            let mut request = client()
+                .post(&metadata.token_endpoint)
+                .header("Accept", "application/json");
+            if let Some(ref secret) = rp.client_secret {
+                request =
+                    request.basic_auth(&rp.client_id, Some(secret.as_str()));
+            }
+
+            read_json(request.send_form(&form)).and_then(
+                move |response: TokenResponse| {
+                    let kid = match header(&response.id_token) {
+                        Ok(header) => header.kid,
+                        Err(e) => return Either::A(future::err(e)),
+                    };
+                    Either::B(rp.keys(&metadata, kid).and_then(move |keys| {
+                        rp.verify(&response.id_token, &keys, &nonce, Utc::now())
+                    }))
+                },
+            )
+        })
+    }
+
+    /// Keys of the provider, fetched again when signed with an unknown key.
+    fn keys(
+        &self,
+        metadata: &Metadata,
+        kid: Option<String>,
+    ) -> impl Future<Item = Vec<Jwk>, Error = Error> {
+        {
+            let keys = self.keys.read().unwrap();
+            let known = match kid {
+                Some(ref kid) => {
+                    keys.iter().any(|k| k.kid.as_ref() == Some(kid))
+                }
+                None => !keys.is_empty(),
+            };
+            if known {
+                return Either::A(future::ok(keys.clone()));
+            }
+        }
+        let cache = self.keys.clone();
+
+        Either::B(get_json::<JwkSet>(&metadata.jwks_uri).map(move |set| {
+            *cache.write().unwrap() = set.keys.clone();
+            set.keys
+        }))
+    }
+
+    /// Checks signature, issuer, audience, expiry and nonce of an ID token.
+    pub fn verify(
+        &self,
+        id_token: &str,
+        keys: &[Jwk],
+        nonce: &str,
+        now: DateTime<Utc>,
+    ) -> Result<ExternalClaims> {
+        let invalid = |detail: &str| {
+            Error::from(ErrorKind::Unauthorized)
+                .with_detail(format!("invalid ID token: {}", detail))
+        };
+
+        let parts = id_token.split('.').collect::<Vec<&str>>();
+        if parts.len() != 3 {
+            return Err(invalid("malformed"));
+        }
+        let header = header(id_token)?;
+        let key = keys.iter().find(|k| match header.kid {
+            Some(ref kid) => k.kid.as_ref() == Some(kid),
+            None => true,
+        });
+        let message = format!("{}.{}", parts[0], parts[1]);
+        let signature = decode(parts[2]).ok_or_else(|| invalid("malformed"))?;
+        let verified = key
+            .map(|key| {
+                verify_signature(
+                    &header.alg,
+                    key,
+                    message.as_bytes(),
+                    &signature,
+                )
+            })
+            .unwrap_or(false);
+        if !verified {
+            return Err(invalid("bad signature"));
+        }
+
+        let claims = decode(parts[1])
+            .and_then(|payload| {
+                serde_json::from_slice::<ExternalClaims>(&payload).ok()
+            })
+            .ok_or_else(|| invalid("malformed claims"))?;
+        if claims.iss.trim_end_matches('/') != self.issuer {
+            return Err(invalid("wrong issuer"));
+        }
+        let audience_valid = match claims.aud {
+            Audience::One(ref aud) => aud == &self.client_id,
+            Audience::Many(ref aud) => aud.contains(&self.client_id),
+        };
+        if !audience_valid {
+            return Err(invalid("wrong audience"));
+        }
+        if claims.exp + LEEWAY < now.timestamp() {
+            return Err(invalid("expired"));
+        }
+        if claims.nonce.as_ref().map(String::as_str) != Some(nonce) {
+            return Err(invalid("wrong nonce"));
+        }
+
+        Ok(claims)
+    }
+
+    /// Finds, links or provisions the user of verified claims, and syncs
+    /// their groups. `current` is the user already logged in, if any.
+    pub fn sign_in(
+        &self,
+        conn: &Conn,
+        policy: &PasswordPolicy,
+        claims: &ExternalClaims,
+        current: Option<&Uuid>,
+    ) -> Result<User> {
+        let identity =
+            match external_identities::find(conn, &self.issuer, &claims.sub)? {
+                Some(identity) => {
+                    if current.iter().any(|id| **id != identity.user_id) {
+                        return Err(Error::from(ErrorKind::Conflict)
+                            .with_detail(
+                            "the external identity is linked to another user",
+                        ));
+                    }
+                    identity
+                }
+                None => {
+                    let user_id = match current {
+                        Some(user_id) => *user_id,
+                        None if self.auto_provision => {
+                            self.provision(conn, policy, claims)?.id
+                        }
+                        None => {
+                            return Err(Error::from(ErrorKind::Forbidden)
+                                .with_detail(
+                                "no user is linked to the external identity",
+                            ))
+                        }
+                    };
+                    external_identities::create(
+                        conn,
+                        &ExternalIdentity {
+                            id: Uuid::new_v4(),
+                            issuer: self.issuer.clone(),
+                            subject: claims.sub.clone(),
+                            user_id,
+                            synced_group_ids: Vec::new(),
+                            created_at: Utc::now(),
+                            last_login_at: None,
+                        },
+                    )?
+                }
+            };
+        let user = match users::find_by_id(conn, &identity.user_id)? {
+            Some(user) => user,
+            None => Err(ErrorKind::Unauthorized)?,
+        };
+
+        let synced_group_ids = self.sync_groups(conn, &identity, claims)?;
+        external_identities::touch(
+            conn,
+            &identity.id,
+            &synced_group_ids,
+            Utc::now(),
+        )?;
+
+        Ok(user)
+    }
+
+    fn provision(
+        &self,
+        conn: &Conn,
+        policy: &PasswordPolicy,
+        claims: &ExternalClaims,
+    ) -> Result<User> {
+        let username = claims
+            .preferred_username
+            .clone()
+            .unwrap_or_else(|| claims.sub.clone());
+        if users::find_by_username(conn, &username)?.is_some() {
+            return Err(Error::from(ErrorKind::Conflict).with_detail(format!(
+                "the username `{}` is taken by a local user",
+                username
+            )));
+        }
+        // Only addresses the provider vouches for, and not yet in use.
+        let email = match claims.email {
+            Some(ref email) if claims.email_verified == Some(true) => {
+                match users::find_by_email(conn, email)? {
+                    Some(_) => None,
+                    None => Some(email.clone()),
+                }
+            }
+            _ => None,
+        };
+
+        info!("Provisioning user `{}` from {}", username, self.issuer);
+        users::create(
+            conn,
+            NewUser {
+                nickname: claims
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| username.clone()),
+                username,
+                // Nobody knows this password, the user logs in externally.
+                password: policy.hash(&utils::random_string(32))?,
+                avatar_url: claims.picture.clone(),
+                email,
+            },
+        )
+    }
+
+    /// Adjusts the memberships in the groups named by the groups claim,
+    /// returning the groups now synced.
+    fn sync_groups(
+        &self,
+        conn: &Conn,
+        identity: &ExternalIdentity,
+        claims: &ExternalClaims,
+    ) -> Result<Vec<Uuid>> {
+        let claim = match self.groups_claim {
+            Some(ref claim) => claim,
+            None => return Ok(identity.synced_group_ids.clone()),
+        };
+        let names = match claims.other.get(claim) {
+            Some(serde_json::Value::Array(values)) => {
+                values.iter().filter_map(|v| v.as_str()).collect()
+            }
+            Some(serde_json::Value::String(value)) => vec![value.as_str()],
+            _ => Vec::new(),
+        };
+
+        let mut group_ids = Vec::new();
+        for name in names {
+            if let Some(group) = groups::find_by_name(conn, name)? {
+                groups::add_member(
+                    conn,
+                    &group.id,
+                    &identity.user_id,
+                    GroupMembershipType::User,
+                )?;
+                group_ids.push(group.id);
+            }
+        }
+        for group_id in &identity.synced_group_ids {
+            if !group_ids.contains(group_id) {
+                groups::del_member(conn, group_id, &identity.user_id)?;
+            }
+        }
+        group_ids.sort();
+        group_ids.dedup();
+
+        Ok(group_ids)
+    }
+}
+
+fn client() -> Client {
+    Client::build()
+        .timeout(StdDuration::from_secs(TIMEOUT))
+        .finish()
+}
+
+fn get_json<T: DeserializeOwned + 'static>(
+    url: &str,
+) -> impl Future<Item = T, Error = Error> {
+    read_json(
+        client()
+            .get(url)
+            .header("Accept", "application/json")
+            .send(),
+    )
+}
+
+/// Reads a successful JSON response of the provider.
+fn read_json<T, F, S>(request: F) -> impl Future<Item = T, Error = Error>
+where
+    T: DeserializeOwned + 'static,
+    F: Future<
+        Item = actix_web::client::ClientResponse<S>,
+        Error = actix_web::client::SendRequestError,
+    >,
+    S: futures::Stream<
+            Item = actix_web::web::Bytes,
+            Error = actix_web::error::PayloadError,
+        > + 'static,
+{
+    let failed = |detail: String| {
+        Error::from(ErrorKind::IdentityProviderError).with_detail(detail)
+    };
+
+    request
+        .map_err(move |e| failed(e.to_string()))
+        .and_then(move |mut res| {
+            if !res.status().is_success() {
+                return Either::A(future::err(failed(format!(
+                    "the provider responded with {}",
+                    res.status()
+                ))));
+            }
+            Either::B(res.json::<T>().map_err(move |e| failed(e.to_string())))
+        })
+}
+
+fn header(token: &str) -> Result<JwsHeader> {
+    token
+        .split('.')
+        .next()
+        .and_then(decode)
+        .and_then(|header| serde_json::from_slice(&header).ok())
+        .ok_or_else(|| {
+            Error::from(ErrorKind::Unauthorized)
+                .with_detail("invalid ID token: malformed")
+        })
+}
+
+fn verify_signature(alg: &str, key: &Jwk, message: &[u8], sig: &[u8]) -> bool {
+    match (alg, key.kty.as_str()) {
+        ("RS256", "RSA") => {
+            let (n, e) = match (
+                key.n.as_ref().and_then(|n| decode(n)),
+                key.e.as_ref().and_then(|e| decode(e)),
+            ) {
+                (Some(n), Some(e)) => (n, e),
+                _ => return false,
+            };
+            signature::primitive::verify_rsa(
+                &signature::RSA_PKCS1_2048_8192_SHA256,
+                (Input::from(&n), Input::from(&e)),
+                Input::from(message),
+                Input::from(sig),
+            )
+            .is_ok()
+        }
+        ("ES256", "EC")
+            if key.crv.as_ref().map(String::as_str) == Some("P-256") =>
+        {
+            let mut point = vec![4];
+            match (
+                key.x.as_ref().and_then(|x| decode(x)),
+                key.y.as_ref().and_then(|y| decode(y)),
+            ) {
+                (Some(x), Some(y)) => {
+                    point.extend(x);
+                    point.extend(y);
+                }
+                _ => return false,
+            }
+            signature::verify(
+                &signature::ECDSA_P256_SHA256_FIXED,
+                Input::from(&point),
+                Input::from(message),
+                Input::from(sig),
+            )
+            .is_ok()
+        }
+        _ => false,
+    }
+}
+
+fn encode(input: &[u8]) -> String {
+    base64::encode_config(input, base64::URL_SAFE_NO_PAD)
+}
+
+fn decode(input: &str) -> Option<Vec<u8>> {
+    base64::decode_config(input, base64::URL_SAFE_NO_PAD).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use actix_web::{test, web, App, HttpResponse, HttpServer};
+
+    use super::*;
+    use crate::oidc::IdTokenSigner;
+    use crate::test_helpers::*;
+
+    const REDIRECT_URI: &str = "https://hamster.example.com/login/oidc";
+
+    /// Starts a provider issuing ID tokens with `claims` to the client
+    /// `hamster`. The code it is given is taken as the nonce to include.
+    fn mock_issuer(claims: serde_json::Value) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let signer = IdTokenSigner::generate().unwrap();
+
+        let base = issuer.clone();
+        thread::spawn(move || {
+            HttpServer::new(move || {
+                let (base, signer, claims) =
+                    (base.clone(), signer.clone(), claims.clone());
+                let jwks = signer.jwks();
+                let discovery = serde_json::json!({
+                    "issuer": base,
+                    "authorization_endpoint": format!("{}/authorize", base),
+                    "token_endpoint": format!("{}/token", base),
+                    "jwks_uri": format!("{}/jwks", base),
+                });
+                App::new()
+                    .route(
+                        "/.well-known/openid-configuration",
+                        web::get()
+                            .to(move || HttpResponse::Ok().json(&discovery)),
+                    )
+                    .route(
+                        "/jwks",
+                        web::get().to(move || HttpResponse::Ok().json(&jwks)),
+                    )
+                    .route(
+                        "/token",
+                        web::post().to(
+                            move |form: web::Form<HashMap<String, String>>| {
+                                assert_eq!(
+                                    "authorization_code",
+                                    form["grant_type"]
+                                );
+                                assert_eq!(43, form["code_verifier"].len());
+                                let mut claims = claims.clone();
+                                claims["iss"] = base.clone().into();
+                                claims["aud"] = "hamster".into();
+                                claims["exp"] =
+                                    (Utc::now().timestamp() + 60).into();
+                                claims["nonce"] = form["code"].clone().into();
+                                HttpResponse::Ok().json(serde_json::json!({
+                                    "access_token": "unused",
+                                    "token_type": "Bearer",
+                                    "id_token": signer.sign(&claims).unwrap(),
+                                }))
+                            },
+                        ),
+                    )
+            })
+            .listen(listener)
+            .unwrap()
+            .run()
+            .unwrap();
+        });
+
+        issuer
+    }
+
+    fn param(url: &str, name: &str) -> String {
+        let query = &url[url.find('?').unwrap() + 1..];
+        let params: Vec<(String, String)> =
+            serde_urlencoded::from_str(query).unwrap();
+        params.into_iter().find(|(n, _)| n == name).unwrap().1
+    }
+
+    #[test]
+    fn test_login_with_mock_issuer() {
+        let conn = connection();
+        let issuer = mock_issuer(serde_json::json!({
+            "sub": "external-1",
+            "preferred_username": "erin",
+            "name": "Erin",
+            "email": "erin@example.com",
+            "email_verified": true,
+            "roles": ["rp_staff", "unknown"],
+        }));
+        let rp = RelyingParty::new(issuer.clone(), "hamster", REDIRECT_URI)
+            .auto_provision(true)
+            .groups_claim("roles");
+        let group = groups::get_or_create(&conn, "rp_staff").unwrap();
+        let policy = PasswordPolicy::default();
+
+        let metadata = test::block_on(rp.metadata()).unwrap();
+        let started = rp.start(&conn, &metadata).unwrap();
+        assert!(started
+            .redirect_to
+            .starts_with(&format!("{}/authorize?", issuer)));
+        assert_eq!(REDIRECT_URI, param(&started.redirect_to, "redirect_uri"));
+        let state = param(&started.redirect_to, "state");
+        let nonce = param(&started.redirect_to, "nonce");
+
+        let login = rp
+            .take_login(&conn, &state, Some(&started.binding))
+            .unwrap();
+        let claims = test::block_on(rp.exchange(&nonce, &login)).unwrap();
+        let user = rp.sign_in(&conn, &policy, &claims, None).unwrap();
+        assert_eq!("erin", user.username);
+        assert_eq!(Some("erin@example.com".to_string()), user.email);
+        let member_of = |user_id| {
+            groups::find_by_member_id(&conn, user_id)
+                .unwrap()
+                .iter()
+                .any(|g| g.id == group.id)
+        };
+        assert!(member_of(&user.id));
+
+        // Later logins find the linked user, and follow the groups claim.
+        let mut claims = claims;
+        claims
+            .other
+            .insert("roles".to_string(), serde_json::json!([]));
+        let again = rp.sign_in(&conn, &policy, &claims, None).unwrap();
+        assert_eq!(user.id, again.id);
+        assert!(!member_of(&user.id));
+
+        // Logins are single use, bound to the user agent and the nonce.
+        let err = rp
+            .take_login(&conn, &state, Some(&started.binding))
+            .unwrap_err();
+        assert_eq!(ErrorKind::Unauthorized, err.kind());
+        let started = rp.start(&conn, &metadata).unwrap();
+        let state = param(&started.redirect_to, "state");
+        let err = rp.take_login(&conn, &state, Some("other")).unwrap_err();
+        assert_eq!(ErrorKind::Unauthorized, err.kind());
+        let started = rp.start(&conn, &metadata).unwrap();
+        let state = param(&started.redirect_to, "state");
+        let login = rp
+            .take_login(&conn, &state, Some(&started.binding))
+            .unwrap();
+        let err =
+            test::block_on(rp.exchange("other nonce", &login)).unwrap_err();
+        assert_eq!(ErrorKind::Unauthorized, err.kind());
+
+        let closed = RelyingParty::new(issuer, "hamster", REDIRECT_URI);
+        claims.sub = "external-2".to_string();
+        let err = closed.sign_in(&conn, &policy, &claims, None).unwrap_err();
+        assert_eq!(ErrorKind::Forbidden, err.kind());
+    }
+}
@@ -0,0 +1,78 @@
+//! Token and userinfo endpoints, called by relying parties.
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures::Future;
+
+use super::error::OAuthError;
+use super::provider::{Provider, TokenRequest};
+use crate::db::Database;
+
+pub fn token(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    provider: web::Data<Provider>,
+    form: web::Form<TokenRequest>,
+) -> impl Future<Item = HttpResponse, Error = OAuthError> {
+    let basic = basic_credentials(&req);
+
+    web::block(move || {
+        let conn = db.conn()?;
+        provider.exchange(&conn, form.into_inner(), basic)
+    })
+    .from_err()
+    .map(|res| {
+        HttpResponse::Ok()
+            .header(header::CACHE_CONTROL, "no-store")
+            .header(header::PRAGMA, "no-cache")
+            .json(res)
+    })
+}
+
+pub fn userinfo(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    provider: web::Data<Provider>,
+) -> impl Future<Item = HttpResponse, Error = OAuthError> {
+    let token = authorization(&req, "bearer");
+
+    web::block(move || {
+        let token = token.ok_or_else(OAuthError::invalid_token)?;
+        let conn = db.conn()?;
+        provider.userinfo(&conn, &token)
+    })
+    .from_err()
+    .map(|res| HttpResponse::Ok().json(res))
+}
+
+pub fn jwks(provider: web::Data<Provider>) -> HttpResponse {
+    HttpResponse::Ok().json(provider.signer().jwks())
+}
+
+/// Credentials of the `Authorization` header with the given scheme.
+fn authorization(req: &HttpRequest, scheme: &str) -> Option<String> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let mut parts = value.splitn(2, ' ');
+    match (parts.next(), parts.next()) {
+        (Some(s), Some(credentials)) if s.eq_ignore_ascii_case(scheme) => {
+            Some(credentials.trim().to_string())
+        }
+        _ => None,
+    }
+}
+
+/// Client id and secret of `Authorization: Basic`, form encoded as required
+/// by RFC 6749, section 2.3.1.
+fn basic_credentials(req: &HttpRequest) -> Option<(String, String)> {
+    let credentials = base64::decode(&authorization(req, "basic")?).ok()?;
+    let credentials = String::from_utf8(credentials).ok()?;
+    let mut parts = credentials.splitn(2, ':');
+    let (client_id, secret) = (parts.next()?, parts.next()?);
+    let decode = |s: &str| {
+        serde_urlencoded::from_str::<Vec<(String, String)>>(&format!("v={}", s))
+            .ok()
+            .and_then(|mut v| v.pop())
+            .map(|(_, v)| v)
+    };
+
+    Some((decode(client_id)?, decode(secret)?))
+}
@@ -1,3 +1,72 @@
+table! {
+    acl_entries (id) {
+        id -> Uuid,
+        resource_type -> Text,
+        resource_id -> Uuid,
+        principal_id -> Uuid,
+        principal_type -> Text,
+        action -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    api_keys (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        name -> Text,
+        prefix -> Text,
+        secret_hash -> Text,
+        authorities -> Nullable<Array<Text>>,
+        expires_at -> Nullable<Timestamptz>,
+        last_used_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    audit_entries (id) {
+        id -> Uuid,
+        actor -> Text,
+        identity -> Text,
+        method -> Text,
+        path -> Text,
+        status -> Int4,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    external_identities (id) {
+        id -> Uuid,
+        issuer -> Text,
+        subject -> Text,
+        user_id -> Uuid,
+        synced_group_ids -> Array<Uuid>,
+        created_at -> Timestamptz,
+        last_login_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    external_logins (state_hash) {
+        state_hash -> Text,
+        binding_hash -> Text,
+        nonce -> Text,
+        code_verifier -> Text,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+    }
+}
+
+table! {
+    group_managers (group_id, user_id) {
+        group_id -> Uuid,
+        user_id -> Uuid,
+        added -> Timestamptz,
+    }
+}
+
 table! {
     group_membership (group_id, member_id) {
         group_id -> Uuid,
@@ -7,6 +76,14 @@ table! {
     }
 }
 
+table! {
+    group_permissions (group_id, permission_id) {
+        group_id -> Uuid,
+        permission_id -> Uuid,
+        added -> Timestamptz,
+    }
+}
+
 table! {
     groups (id) {
         id -> Uuid,
@@ -14,6 +91,111 @@ table! {
         description -> Nullable<Text>,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        require_two_factor -> Bool,
+    }
+}
+
+table! {
+    login_failures (id) {
+        id -> Uuid,
+        kind -> Text,
+        subject -> Text,
+        failures -> Int4,
+        last_failed_at -> Timestamptz,
+        locked_until -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    oauth_access_tokens (id) {
+        id -> Uuid,
+        token_hash -> Text,
+        client_id -> Uuid,
+        user_id -> Uuid,
+        scope -> Text,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+    }
+}
+
+table! {
+    oauth_clients (id) {
+        id -> Uuid,
+        name -> Text,
+        secret_hash -> Nullable<Text>,
+        redirect_uris -> Array<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    oauth_codes (id) {
+        id -> Uuid,
+        code_hash -> Text,
+        client_id -> Uuid,
+        user_id -> Uuid,
+        redirect_uri -> Text,
+        scope -> Text,
+        nonce -> Nullable<Text>,
+        code_challenge -> Text,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        used_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    permissions (id) {
+        id -> Uuid,
+        name -> Text,
+        description -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    recovery_codes (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        code_hash -> Text,
+        used_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    sessions (id) {
+        id -> Uuid,
+        token_hash -> Text,
+        identity -> Text,
+        authorities -> Array<Text>,
+        user_agent -> Nullable<Text>,
+        created_at -> Timestamptz,
+        last_accessed_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        membership_epoch -> Int8,
+        impersonator -> Nullable<Text>,
+    }
+}
+
+table! {
+    two_factor (user_id) {
+        user_id -> Uuid,
+        secret -> Bytea,
+        confirmed_at -> Nullable<Timestamptz>,
+        last_used_step -> Int8,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    user_tokens (id) {
+        id -> Uuid,
+        token_hash -> Text,
+        user_id -> Uuid,
+        purpose -> Text,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        used_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -23,16 +205,35 @@ table! {
         username -> Text,
         password -> Text,
         nickname -> Text,
-        avatar_url -> Text,
+        avatar_url -> Nullable<Text>,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        membership_epoch -> Int8,
+        email -> Nullable<Text>,
     }
 }
 
+joinable!(group_managers -> groups (group_id));
 joinable!(group_membership -> groups (group_id));
 
 allow_tables_to_appear_in_same_query!(
+    acl_entries,
+    api_keys,
+    audit_entries,
+    external_identities,
+    external_logins,
+    group_managers,
     group_membership,
+    group_permissions,
     groups,
+    login_failures,
+    oauth_access_tokens,
+    oauth_clients,
+    oauth_codes,
+    permissions,
+    recovery_codes,
+    sessions,
+    two_factor,
+    user_tokens,
     users,
 );
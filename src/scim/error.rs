@@ -0,0 +1,112 @@
+use std::fmt::{self, Display};
+
+use actix_web::error::BlockingError;
+use actix_web::{HttpResponse, ResponseError};
+use failure::Context;
+
+use super::types::ERROR_SCHEMA;
+use crate::error::{Error, ErrorKind};
+
+/// Error rendered in the SCIM error format (RFC 7644, section 3.12).
+#[derive(Debug)]
+pub struct ScimError {
+    error: Error,
+    scim_type: Option<&'static str>,
+}
+
+impl ScimError {
+    pub fn new(error: Error, scim_type: &'static str) -> Self {
+        ScimError {
+            error,
+            scim_type: Some(scim_type),
+        }
+    }
+
+    pub fn invalid_path<D: Into<String>>(detail: D) -> Self {
+        ScimError::new(
+            Error::from(ErrorKind::BadRequest).with_detail(detail),
+            "invalidPath",
+        )
+    }
+
+    pub fn invalid_value<D: Into<String>>(detail: D) -> Self {
+        ScimError::new(
+            Error::from(ErrorKind::BadRequest).with_detail(detail),
+            "invalidValue",
+        )
+    }
+
+    pub fn scim_type(&self) -> Option<&'static str> {
+        self.scim_type.or_else(|| match self.error.kind() {
+            ErrorKind::Conflict => Some("uniqueness"),
+            ErrorKind::Validation => Some("invalidValue"),
+            _ => None,
+        })
+    }
+}
+
+impl ResponseError for ScimError {
+    fn error_response(&self) -> HttpResponse {
+        let status = self.error.kind().status();
+        let detail = self
+            .error
+            .detail()
+            .map(str::to_string)
+            .or_else(|| {
+                self.error
+                    .details()
+                    .iter()
+                    .map(|d| format!("{} {}", d.field, d.message))
+                    .next()
+            })
+            .unwrap_or_else(|| self.error.kind().to_string());
+        let body = serde_json::json!({
+            "schemas": [ERROR_SCHEMA],
+            "status": status.as_str(),
+            "scimType": self.scim_type(),
+            "detail": detail,
+        });
+
+        HttpResponse::build(status)
+            .content_type("application/scim+json")
+            .body(body.to_string())
+    }
+}
+
+impl Display for ScimError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Display::fmt(&self.error, f)
+    }
+}
+
+impl From<Error> for ScimError {
+    fn from(error: Error) -> Self {
+        ScimError {
+            error,
+            scim_type: None,
+        }
+    }
+}
+
+impl From<ErrorKind> for ScimError {
+    fn from(kind: ErrorKind) -> Self {
+        ScimError::from(Error::from(kind))
+    }
+}
+
+impl From<Context<ErrorKind>> for ScimError {
+    fn from(inner: Context<ErrorKind>) -> Self {
+        ScimError::from(Error::from(inner))
+    }
+}
+
+impl From<BlockingError<ScimError>> for ScimError {
+    fn from(err: BlockingError<ScimError>) -> Self {
+        match err {
+            BlockingError::Error(e) => e,
+            BlockingError::Canceled => {
+                ScimError::from(ErrorKind::BlockingCanceled)
+            }
+        }
+    }
+}
@@ -0,0 +1,489 @@
+//! SCIM filter expressions (RFC 7644, section 3.4.2.2).
+use std::cmp::Ordering;
+
+use serde_json::Value;
+
+use crate::db::page::{self, FilterOp};
+use crate::error::{Error, ErrorKind, Result};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Co,
+    Sw,
+    Ew,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// `attr pr`
+    Present(String),
+    /// `attr op value`
+    Compare(String, CompareOp, Value),
+    /// `attr[filter]`, matches if an element of `attr` matches the filter.
+    ValuePath(String, Box<Filter>),
+    Not(Box<Filter>),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+}
+
+impl Filter {
+    pub fn parse(input: &str) -> Result<Filter> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let filter = parser.parse_or()?;
+
+        match parser.next() {
+            None => Ok(filter),
+            Some(token) => {
+                Err(invalid_filter(format!("unexpected token `{:?}`", token)))
+            }
+        }
+    }
+
+    /// Translates the filter into database filters on the mapped
+    /// attributes. Only `eq` and `co` comparisons joined by `and` translate,
+    /// anything else returns `None`.
+    pub fn page_filters(
+        &self,
+        fields: &[(&str, &str)],
+    ) -> Option<Vec<page::Filter>> {
+        match self {
+            Filter::Compare(path, op, Value::String(value)) => {
+                let op = match op {
+                    CompareOp::Eq => FilterOp::Eq,
+                    CompareOp::Co => FilterOp::Contains,
+                    _ => return None,
+                };
+                let (_, field) = fields.iter().find(|(attr, _)| {
+                    attr_name(path).eq_ignore_ascii_case(attr)
+                })?;
+                Some(vec![page::Filter {
+                    field: field.to_string(),
+                    op,
+                    value: value.clone(),
+                }])
+            }
+            Filter::And(a, b) => {
+                let mut filters = a.page_filters(fields)?;
+                filters.extend(b.page_filters(fields)?);
+                Some(filters)
+            }
+            _ => None,
+        }
+    }
+
+    /// Evaluates the filter against the json representation of a resource.
+    pub fn matches(&self, resource: &Value) -> bool {
+        match self {
+            Filter::Present(path) => {
+                lookup(resource, path).into_iter().any(is_present)
+            }
+            Filter::Compare(path, CompareOp::Ne, value) => {
+                !lookup(resource, path)
+                    .into_iter()
+                    .any(|v| compare(v, CompareOp::Eq, value))
+            }
+            Filter::Compare(path, op, value) => lookup(resource, path)
+                .into_iter()
+                .any(|v| compare(v, *op, value)),
+            Filter::ValuePath(path, filter) => lookup(resource, path)
+                .into_iter()
+                .any(|v| filter.matches(v)),
+            Filter::Not(filter) => !filter.matches(resource),
+            Filter::And(a, b) => a.matches(resource) && b.matches(resource),
+            Filter::Or(a, b) => a.matches(resource) || b.matches(resource),
+        }
+    }
+}
+
+pub fn invalid_filter<D: Into<String>>(detail: D) -> Error {
+    Error::from(ErrorKind::BadRequest).with_detail(detail)
+}
+
+/// Strips the schema urn from an attribute path.
+pub fn attr_name(path: &str) -> &str {
+    match path.rfind(':') {
+        Some(i) => &path[i + 1..],
+        None => path,
+    }
+}
+
+/// Collects the values of an attribute path, flattening multi-valued
+/// attributes along the way.
+fn lookup<'a>(resource: &'a Value, path: &str) -> Vec<&'a Value> {
+    let mut values = vec![resource];
+
+    for name in attr_name(path).split('.') {
+        values = values
+            .into_iter()
+            .flat_map(|v| match v {
+                Value::Array(items) => items.iter().collect(),
+                v => vec![v],
+            })
+            .filter_map(|v| match v {
+                Value::Object(map) => map
+                    .iter()
+                    .find(|(k, _)| k.eq_ignore_ascii_case(name))
+                    .map(|(_, v)| v),
+                _ => None,
+            })
+            .collect();
+    }
+
+    values
+        .into_iter()
+        .flat_map(|v| match v {
+            Value::Array(items) => items.iter().collect(),
+            v => vec![v],
+        })
+        .collect()
+}
+
+fn is_present(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        _ => true,
+    }
+}
+
+fn compare(actual: &Value, op: CompareOp, expected: &Value) -> bool {
+    use self::CompareOp::*;
+
+    match (actual, expected) {
+        (Value::String(a), Value::String(e)) => {
+            let (a, e) = (a.to_lowercase(), e.to_lowercase());
+            match op {
+                Co => a.contains(&e),
+                Sw => a.starts_with(&e),
+                Ew => a.ends_with(&e),
+                _ => ordering_matches(op, a.cmp(&e)),
+            }
+        }
+        (Value::Number(a), Value::Number(e)) => {
+            match (a.as_f64(), e.as_f64()) {
+                (Some(a), Some(e)) => {
+                    a.partial_cmp(&e).map_or(false, |o| ordering_matches(op, o))
+                }
+                _ => false,
+            }
+        }
+        (Value::Bool(a), Value::Bool(e)) => {
+            ordering_matches(op, a.cmp(e)) && (op == Eq || op == Ne)
+        }
+        (Value::Null, Value::Null) => op == Eq,
+        _ => false,
+    }
+}
+
+fn ordering_matches(op: CompareOp, ordering: Ordering) -> bool {
+    use self::CompareOp::*;
+
+    match op {
+        Eq => ordering == Ordering::Equal,
+        Ne => ordering != Ordering::Equal,
+        Gt => ordering == Ordering::Greater,
+        Ge => ordering != Ordering::Less,
+        Lt => ordering == Ordering::Less,
+        Le => ordering != Ordering::Greater,
+        Co | Sw | Ew => false,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Str(String),
+    Word(String),
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => tokens.push(Token::LParen),
+            ')' => tokens.push(Token::RParen),
+            '[' => tokens.push(Token::LBracket),
+            ']' => tokens.push(Token::RBracket),
+            '"' => {
+                let mut end = None;
+                let mut escaped = false;
+                for (i, c) in &mut chars {
+                    match c {
+                        '\\' if !escaped => escaped = true,
+                        '"' if !escaped => {
+                            end = Some(i);
+                            break;
+                        }
+                        _ => escaped = false,
+                    }
+                }
+                let end = end.ok_or_else(|| {
+                    invalid_filter("unterminated string literal")
+                })?;
+                let value = serde_json::from_str(&input[start..=end])
+                    .map_err(|e| invalid_filter(e.to_string()))?;
+                tokens.push(Token::Str(value));
+            }
+            _ => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(i, c)) = chars.peek() {
+                    if c.is_whitespace() || "()[]\"".contains(c) {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                tokens.push(Token::Word(input[start..end].to_string()));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(w)) => w.eq_ignore_ascii_case(keyword),
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<()> {
+        match self.next() {
+            Some(ref token) if token == &expected => Ok(()),
+            token => Err(invalid_filter(format!(
+                "expected `{:?}`, found `{:?}`",
+                expected, token
+            ))),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Filter> {
+        let mut filter = self.parse_and()?;
+        while self.peek_keyword("or") {
+            self.pos += 1;
+            let rhs = self.parse_and()?;
+            filter = Filter::Or(Box::new(filter), Box::new(rhs));
+        }
+        Ok(filter)
+    }
+
+    fn parse_and(&mut self) -> Result<Filter> {
+        let mut filter = self.parse_not()?;
+        while self.peek_keyword("and") {
+            self.pos += 1;
+            let rhs = self.parse_not()?;
+            filter = Filter::And(Box::new(filter), Box::new(rhs));
+        }
+        Ok(filter)
+    }
+
+    fn parse_not(&mut self) -> Result<Filter> {
+        if self.peek_keyword("not") {
+            self.pos += 1;
+            self.expect(Token::LParen)?;
+            let filter = self.parse_or()?;
+            self.expect(Token::RParen)?;
+            Ok(Filter::Not(Box::new(filter)))
+        } else {
+            self.parse_primary()
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Filter> {
+        let path = match self.next() {
+            Some(Token::LParen) => {
+                let filter = self.parse_or()?;
+                self.expect(Token::RParen)?;
+                return Ok(filter);
+            }
+            Some(Token::Word(path)) => path,
+            token => {
+                return Err(invalid_filter(format!(
+                    "expected attribute path, found `{:?}`",
+                    token
+                )))
+            }
+        };
+
+        if self.peek() == Some(&Token::LBracket) {
+            self.pos += 1;
+            let filter = self.parse_or()?;
+            self.expect(Token::RBracket)?;
+            return Ok(Filter::ValuePath(path, Box::new(filter)));
+        }
+
+        let op = match self.next() {
+            Some(Token::Word(op)) => op.to_lowercase(),
+            token => {
+                return Err(invalid_filter(format!(
+                    "expected operator, found `{:?}`",
+                    token
+                )))
+            }
+        };
+        let op = match op.as_str() {
+            "pr" => return Ok(Filter::Present(path)),
+            "eq" => CompareOp::Eq,
+            "ne" => CompareOp::Ne,
+            "co" => CompareOp::Co,
+            "sw" => CompareOp::Sw,
+            "ew" => CompareOp::Ew,
+            "gt" => CompareOp::Gt,
+            "ge" => CompareOp::Ge,
+            "lt" => CompareOp::Lt,
+            "le" => CompareOp::Le,
+            _ => {
+                return Err(invalid_filter(format!(
+                    "unknown operator `{}`",
+                    op
+                )))
+            }
+        };
+
+        let value = match self.next() {
+            Some(Token::Str(s)) => Value::String(s),
+            Some(Token::Word(w)) => serde_json::from_str(&w).map_err(|_| {
+                invalid_filter(format!("invalid value `{}`", w))
+            })?,
+            token => {
+                return Err(invalid_filter(format!(
+                    "expected value, found `{:?}`",
+                    token
+                )))
+            }
+        };
+
+        Ok(Filter::Compare(path, op, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user() -> Value {
+        serde_json::json!({
+            "userName": "bob",
+            "displayName": "Bob Smith",
+            "groups": [
+                { "value": "1", "display": "admin" },
+                { "value": "2", "display": "user" }
+            ],
+            "meta": { "created": "2019-04-17T00:00:00Z" }
+        })
+    }
+
+    #[test]
+    fn test_parse() {
+        let filter =
+            Filter::parse(r#"userName eq "bob" and not (displayName pr)"#)
+                .unwrap();
+
+        assert_eq!(
+            Filter::And(
+                Box::new(Filter::Compare(
+                    "userName".to_string(),
+                    CompareOp::Eq,
+                    Value::String("bob".to_string())
+                )),
+                Box::new(Filter::Not(Box::new(Filter::Present(
+                    "displayName".to_string()
+                ))))
+            ),
+            filter
+        );
+    }
+
+    #[test]
+    fn test_page_filters() {
+        let fields = [("userName", "username"), ("displayName", "nickname")];
+        let filter =
+            Filter::parse(r#"USERNAME eq "bob" and displayName co "Smith""#)
+                .unwrap();
+
+        assert_eq!(
+            Some(vec![
+                page::Filter {
+                    field: "username".to_string(),
+                    op: FilterOp::Eq,
+                    value: "bob".to_string(),
+                },
+                page::Filter {
+                    field: "nickname".to_string(),
+                    op: FilterOp::Contains,
+                    value: "Smith".to_string(),
+                },
+            ]),
+            filter.page_filters(&fields)
+        );
+
+        for input in &[r#"userName sw "b""#, r#"title eq "x""#, "userName pr"] {
+            let filter = Filter::parse(input).unwrap();
+            assert_eq!(None, filter.page_filters(&fields), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for input in &["userName", r#"userName zz "bob""#, "(userName pr", ""] {
+            let err = Filter::parse(input).unwrap_err();
+            assert_eq!(ErrorKind::BadRequest, err.kind());
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let user = user();
+        let cases = vec![
+            (r#"userName eq "BOB""#, true),
+            (r#"userName ne "bob""#, false),
+            (r#"displayName co "smith""#, true),
+            (r#"displayName sw "bob" and userName ew "b""#, true),
+            (r#"nickName pr or userName eq "alice""#, false),
+            (r#"groups.display eq "admin""#, true),
+            (r#"groups[value eq "2" and display eq "user"]"#, true),
+            (r#"groups[value eq "1" and display eq "user"]"#, false),
+            (r#"meta.created gt "2019-01-01T00:00:00Z""#, true),
+            (
+                r#"urn:ietf:params:scim:schemas:core:2.0:User:userName eq "bob""#,
+                true,
+            ),
+        ];
+
+        for (input, expected) in cases {
+            let filter = Filter::parse(input).unwrap();
+            assert_eq!(expected, filter.matches(&user), "{}", input);
+        }
+    }
+}
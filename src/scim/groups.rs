@@ -0,0 +1,475 @@
+use std::collections::HashMap;
+
+use actix_web::http::header::LOCATION;
+use actix_web::{web, HttpRequest, HttpResponse, Scope};
+use futures::Future;
+use serde_json::Value;
+use uuid::Uuid;
+
+use super::error::ScimError;
+use super::filter::{attr_name, Filter};
+use super::types::{
+    ListParams, ListResponse, PatchOp, PatchRequest, Reference, ScimGroup,
+};
+use crate::db::{
+    groups::{
+        self, Group, GroupMembership, GroupMembershipType, NewGroup,
+        UpdateGroup,
+    },
+    users, Conn, Database,
+};
+use crate::error::{Error, ErrorKind, Result};
+
+pub fn service(path: &str) -> Scope {
+    web::scope(path)
+        .service(
+            web::resource("")
+                .route(web::get().to_async(get_groups))
+                .route(web::post().to_async(add_group)),
+        )
+        .service(
+            web::resource("/{group_id}")
+                .route(web::get().to_async(get_group))
+                .route(web::put().to_async(replace_group))
+                .route(web::patch().to_async(patch_group))
+                .route(web::delete().to_async(del_group)),
+        )
+}
+
+fn get_groups(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    params: web::Query<ListParams>,
+) -> impl Future<Item = HttpResponse, Error = ScimError> {
+    let base = super::base_url(&req, "/Groups");
+    web::block(move || -> std::result::Result<_, ScimError> {
+        let request = super::page_request(
+            &params,
+            &[("displayName", "display_name")],
+            "display_name",
+        )?;
+
+        let conn = db.conn()?;
+        let page = groups::find_page(&conn, &request, None)?;
+        let resources = to_resources(&conn, page.items, &base)?;
+
+        Ok(ListResponse::new(resources, page.total, &params))
+    })
+    .from_err()
+    .and_then(|res| super::respond(&mut HttpResponse::Ok(), &res))
+}
+
+fn add_group(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    group: web::Json<ScimGroup>,
+) -> impl Future<Item = HttpResponse, Error = ScimError> {
+    let base = super::base_url(&req, "/Groups");
+    let group = group.into_inner();
+    web::block(move || -> std::result::Result<_, ScimError> {
+        let result = db.transaction(|conn| {
+            let new = NewGroup {
+                display_name: group.display_name.clone(),
+                description: None,
+            };
+            let result = groups::create(conn, new)?;
+            set_members(conn, &result.id, &group.members)?;
+            to_resource(conn, result, &base)
+        })?;
+        Ok(result)
+    })
+    .from_err()
+    .and_then(|res| {
+        let location = res.meta.as_ref().map(|m| m.location.clone());
+        super::respond(
+            HttpResponse::Created()
+                .header(LOCATION, location.unwrap_or_default()),
+            &res,
+        )
+    })
+}
+
+fn get_group(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    group_id: web::Path<String>,
+) -> impl Future<Item = HttpResponse, Error = ScimError> {
+    let base = super::base_url(&req, "/Groups");
+    web::block(move || -> std::result::Result<_, ScimError> {
+        let group_id = super::parse_id(&group_id)?;
+        let conn = db.conn()?;
+        let group = find_group(&conn, &group_id)?;
+        Ok(to_resource(&conn, group, &base)?)
+    })
+    .from_err()
+    .and_then(|res| super::respond(&mut HttpResponse::Ok(), &res))
+}
+
+fn replace_group(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    group_id: web::Path<String>,
+    group: web::Json<ScimGroup>,
+) -> impl Future<Item = HttpResponse, Error = ScimError> {
+    let base = super::base_url(&req, "/Groups");
+    web::block(move || -> std::result::Result<_, ScimError> {
+        let group_id = super::parse_id(&group_id)?;
+        let result = db.transaction(|conn| {
+            let result = replace(conn, &group_id, &group)?;
+            to_resource(conn, result, &base)
+        })?;
+        Ok(result)
+    })
+    .from_err()
+    .and_then(|res| super::respond(&mut HttpResponse::Ok(), &res))
+}
+
+fn patch_group(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    group_id: web::Path<String>,
+    patch: web::Json<PatchRequest>,
+) -> impl Future<Item = HttpResponse, Error = ScimError> {
+    let base = super::base_url(&req, "/Groups");
+    web::block(move || -> std::result::Result<_, ScimError> {
+        let group_id = super::parse_id(&group_id)?;
+        let mut group = {
+            let conn = db.conn()?;
+            let group = find_group(&conn, &group_id)?;
+            to_resource(&conn, group, &base)?
+        };
+        apply_patch(&mut group, &patch)?;
+
+        let result = db.transaction(|conn| {
+            let result = replace(conn, &group_id, &group)?;
+            to_resource(conn, result, &base)
+        })?;
+        Ok(result)
+    })
+    .from_err()
+    .and_then(|res| super::respond(&mut HttpResponse::Ok(), &res))
+}
+
+fn del_group(
+    db: web::Data<Database>,
+    group_id: web::Path<String>,
+) -> impl Future<Item = HttpResponse, Error = ScimError> {
+    web::block(move || -> std::result::Result<_, ScimError> {
+        let group_id = super::parse_id(&group_id)?;
+        db.transaction(|conn| match groups::del_cascade(conn, &group_id)? {
+            0 => Err(ErrorKind::NotFound)?,
+            _ => Ok(()),
+        })?;
+        Ok(())
+    })
+    .from_err()
+    .map(|_| HttpResponse::NoContent().finish())
+}
+
+fn find_group(conn: &Conn, group_id: &Uuid) -> Result<Group> {
+    match groups::find_by_id(conn, group_id)? {
+        Some(group) => Ok(group),
+        None => Err(ErrorKind::NotFound)?,
+    }
+}
+
+/// Renames the group, keeping its description, and replaces its members.
+fn replace(conn: &Conn, group_id: &Uuid, group: &ScimGroup) -> Result<Group> {
+    let current = find_group(conn, group_id)?;
+    let update = UpdateGroup {
+        display_name: group.display_name.clone(),
+        description: current.description,
+        require_two_factor: None,
+    };
+    let result = groups::update(conn, group_id, update)?;
+    set_members(conn, group_id, &group.members)?;
+
+    Ok(result)
+}
+
+fn set_members(
+    conn: &Conn,
+    group_id: &Uuid,
+    members: &[Reference],
+) -> Result<()> {
+    let mut wanted = Vec::new();
+    for member in members {
+        let (member_id, member_type) = resolve_member(conn, member)?;
+        if !wanted.iter().any(|(id, _)| *id == member_id) {
+            wanted.push((member_id, member_type));
+        }
+    }
+
+    for current in groups::find_members(conn, group_id)? {
+        if !wanted.iter().any(|(id, _)| *id == current.member_id) {
+            groups::del_member(conn, group_id, &current.member_id)?;
+        }
+    }
+    for (member_id, member_type) in wanted {
+        groups::add_member(conn, group_id, &member_id, member_type)?;
+    }
+
+    Ok(())
+}
+
+/// Looks up whether a member reference points at a user or a group.
+fn resolve_member(
+    conn: &Conn,
+    member: &Reference,
+) -> Result<(Uuid, GroupMembershipType)> {
+    let member_id = Uuid::parse_str(&member.value).map_err(|_| {
+        Error::validation("members", format!("`{}` is not an id", member.value))
+    })?;
+
+    if users::find_by_id(conn, &member_id)?.is_some() {
+        Ok((member_id, GroupMembershipType::User))
+    } else if groups::find_by_id(conn, &member_id)?.is_some() {
+        Ok((member_id, GroupMembershipType::Group))
+    } else {
+        Err(Error::validation(
+            "members",
+            format!("`{}` does not exist", member.value),
+        ))
+    }
+}
+
+fn to_resource(conn: &Conn, group: Group, base: &str) -> Result<ScimGroup> {
+    let mut resources = to_resources(conn, vec![group], base)?;
+    Ok(resources.remove(0))
+}
+
+fn to_resources(
+    conn: &Conn,
+    groups: Vec<Group>,
+    base: &str,
+) -> Result<Vec<ScimGroup>> {
+    let ids = groups.iter().map(|g| g.id).collect::<Vec<Uuid>>();
+    let mut memberships: HashMap<Uuid, Vec<GroupMembership>> = HashMap::new();
+    for member in groups::find_members_by_group_ids(conn, &ids)? {
+        memberships.entry(member.group_id).or_default().push(member);
+    }
+
+    Ok(groups
+        .into_iter()
+        .map(|group| {
+            let members = memberships.remove(&group.id).unwrap_or_default();
+            ScimGroup::new(group, members, base)
+        })
+        .collect())
+}
+
+fn apply_patch(
+    group: &mut ScimGroup,
+    patch: &PatchRequest,
+) -> std::result::Result<(), ScimError> {
+    for operation in &patch.operations {
+        let op = operation.op().ok_or_else(|| {
+            ScimError::invalid_value(format!(
+                "unsupported operation `{}`",
+                operation.op
+            ))
+        })?;
+        let value = operation.value.clone().unwrap_or(Value::Null);
+
+        match operation.path {
+            Some(ref path) if path.contains('[') => {
+                remove_matching(group, op, path)?
+            }
+            Some(ref path) => set_attr(group, op, path, value)?,
+            None => match value {
+                Value::Object(map) => {
+                    for (name, value) in map {
+                        set_attr(group, op, &name, value)?;
+                    }
+                }
+                _ => {
+                    return Err(ScimError::invalid_value(
+                        "operations without a path require an object value",
+                    ))
+                }
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Handles `remove` with a value filter such as `members[value eq "id"]`.
+fn remove_matching(
+    group: &mut ScimGroup,
+    op: PatchOp,
+    path: &str,
+) -> std::result::Result<(), ScimError> {
+    let filter = Filter::parse(path).map_err(|_| {
+        ScimError::invalid_path(format!("`{}` is not a valid path", path))
+    })?;
+    let filter = match filter {
+        Filter::ValuePath(ref attr, ref filter)
+            if op == PatchOp::Remove
+                && attr_name(attr).eq_ignore_ascii_case("members") =>
+        {
+            filter
+        }
+        _ => {
+            return Err(ScimError::invalid_path(format!(
+                "`{}` is not supported",
+                path
+            )))
+        }
+    };
+
+    group.members.retain(|member| {
+        !serde_json::to_value(member)
+            .map(|value| filter.matches(&value))
+            .unwrap_or(false)
+    });
+
+    Ok(())
+}
+
+fn set_attr(
+    group: &mut ScimGroup,
+    op: PatchOp,
+    path: &str,
+    value: Value,
+) -> std::result::Result<(), ScimError> {
+    match (attr_name(path).to_lowercase().as_str(), op) {
+        ("displayname", PatchOp::Remove) => {
+            return Err(ScimError::invalid_path(format!(
+                "`{}` can not be removed",
+                path
+            )))
+        }
+        ("displayname", _) => match value {
+            Value::String(name) => group.display_name = name,
+            _ => {
+                return Err(ScimError::invalid_value(format!(
+                    "`{}` must be a string",
+                    path
+                )))
+            }
+        },
+        ("members", op) => {
+            let members = match value {
+                Value::Null => Vec::new(),
+                Value::Array(_) => serde_json::from_value(value)
+                    .map_err(|e| ScimError::invalid_value(e.to_string()))?,
+                _ => {
+                    return Err(ScimError::invalid_value(format!(
+                        "`{}` must be an array",
+                        path
+                    )))
+                }
+            };
+            match op {
+                PatchOp::Add => group.members.extend(members),
+                PatchOp::Replace => group.members = members,
+                // Without a value every member is removed.
+                PatchOp::Remove if members.is_empty() => group.members.clear(),
+                PatchOp::Remove => group.members.retain(|m| {
+                    !members.iter().any(|r: &Reference| same_id(r, m))
+                }),
+            }
+        }
+        _ => {
+            return Err(ScimError::invalid_path(format!(
+                "`{}` is not a mutable attribute",
+                path
+            )))
+        }
+    }
+
+    Ok(())
+}
+
+fn same_id(a: &Reference, b: &Reference) -> bool {
+    match (Uuid::parse_str(&a.value), Uuid::parse_str(&b.value)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a.value == b.value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(id: &str) -> Reference {
+        Reference {
+            value: id.to_string(),
+            reference: None,
+            display: None,
+            kind: Some("User".to_string()),
+        }
+    }
+
+    fn group() -> ScimGroup {
+        ScimGroup {
+            schemas: Vec::new(),
+            id: None,
+            display_name: "admin".to_string(),
+            members: vec![
+                member("4a9a1bd0e6a94ca6a0d6a8ad7c0a3b21"),
+                member("9e1f6a0c1f4d4b8e8d0a1c6b2e7f3d40"),
+            ],
+            meta: None,
+        }
+    }
+
+    fn patch(operations: Value) -> PatchRequest {
+        serde_json::from_value(serde_json::json!({ "Operations": operations }))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_apply_patch() {
+        let mut group = group();
+
+        apply_patch(
+            &mut group,
+            &patch(serde_json::json!([
+                {
+                    "op": "remove",
+                    "path": "members[value eq \"4a9a1bd0e6a94ca6a0d6a8ad7c0a3b21\"]",
+                },
+                {
+                    "op": "add",
+                    "path": "members",
+                    "value": [{ "value": "0b5d1c7e-2f3a-4c8e-9a1d-6e4f2b7c8d90" }],
+                },
+                {
+                    "op": "remove",
+                    "path": "members",
+                    "value": [{ "value": "9e1f6a0c-1f4d-4b8e-8d0a-1c6b2e7f3d40" }],
+                },
+                { "op": "replace", "value": { "displayName": "admins" } },
+            ])),
+        )
+        .unwrap();
+
+        assert_eq!("admins", group.display_name);
+        assert_eq!(
+            vec!["0b5d1c7e-2f3a-4c8e-9a1d-6e4f2b7c8d90"],
+            group
+                .members
+                .iter()
+                .map(|m| m.value.as_str())
+                .collect::<Vec<&str>>()
+        );
+    }
+
+    #[test]
+    fn test_apply_patch_invalid_path() {
+        let mut group = group();
+
+        let err = apply_patch(
+            &mut group,
+            &patch(serde_json::json!([
+                { "op": "replace", "path": "members[value eq \"x\"]" },
+            ])),
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(Some("invalidPath"), err.scim_type());
+    }
+}
@@ -0,0 +1,221 @@
+//! SCIM 2.0 provisioning endpoints (RFC 7643, RFC 7644).
+mod error;
+mod filter;
+mod groups;
+mod types;
+mod users;
+
+use actix_web::dev::{HttpResponseBuilder, HttpServiceFactory};
+use actix_web::http::Method;
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Serialize;
+use uuid::Uuid;
+
+use self::error::ScimError;
+use self::filter::Filter;
+use self::types::{GROUP_SCHEMA, USER_SCHEMA};
+use crate::auth::{middleware::AuthorizationService, AccessRules};
+use crate::db::page::PageRequest;
+use crate::error::{Error, ErrorKind, ResultExt};
+
+pub fn service(path: &str) -> impl HttpServiceFactory {
+    web::scope(path)
+        .data(web::JsonConfig::default().error_handler(|err, _| {
+            let error =
+                Error::from(ErrorKind::BadRequest).with_detail(err.to_string());
+            ScimError::new(error, "invalidSyntax").into()
+        }))
+        .wrap(AuthorizationService::new(access_rules(path)))
+        .service(users::service("/Users"))
+        .service(groups::service("/Groups"))
+        .service(
+            web::resource("/ServiceProviderConfig")
+                .route(web::get().to(service_provider_config)),
+        )
+        .service(web::resource("/Schemas").route(web::get().to(schemas)))
+        .service(
+            web::resource("/ResourceTypes")
+                .route(web::get().to(resource_types)),
+        )
+}
+
+fn access_rules(path: &str) -> AccessRules {
+    AccessRules::new(path)
+        .permit_all(Method::GET, "/ServiceProviderConfig")
+        .permit_all(Method::GET, "/Schemas")
+        .permit_all(Method::GET, "/ResourceTypes")
+        .has_authority(Method::GET, "/Users", "users.get")
+        .has_authority(Method::POST, "/Users", "users.post")
+        .has_authority(Method::GET, "/Users/{user_id}", "users.get")
+        .has_authority(Method::PUT, "/Users/{user_id}", "users.put")
+        .has_authority(Method::PATCH, "/Users/{user_id}", "users.put")
+        .has_authority(Method::DELETE, "/Users/{user_id}", "users.del")
+        .has_authority(Method::GET, "/Groups", "groups.get")
+        .has_authority(Method::POST, "/Groups", "groups.post")
+        .has_authority(Method::GET, "/Groups/{group_id}", "groups.get")
+        .has_authority(Method::PUT, "/Groups/{group_id}", "groups.put")
+        .has_authority(Method::PATCH, "/Groups/{group_id}", "groups.put")
+        .has_authority(Method::DELETE, "/Groups/{group_id}", "groups.del")
+}
+
+/// Absolute url of the SCIM root for a request to `endpoint`.
+fn base_url(req: &HttpRequest, endpoint: &str) -> String {
+    let path = req.path();
+    let base = &path[..path.rfind(endpoint).unwrap_or(path.len())];
+    let info = req.connection_info();
+
+    format!("{}://{}{}", info.scheme(), info.host(), base)
+}
+
+/// Returns the database request for a list, filters are limited to `eq`
+/// and `co` comparisons of the attributes in `fields` joined by `and`.
+fn page_request(
+    params: &types::ListParams,
+    fields: &[(&str, &str)],
+    sort_field: &str,
+) -> Result<PageRequest, ScimError> {
+    let filters = match params.filter {
+        Some(ref filter) => Filter::parse(filter)
+            .and_then(|filter| {
+                filter.page_filters(fields).ok_or_else(|| {
+                    let attrs = fields.iter().map(|(attr, _)| *attr);
+                    filter::invalid_filter(format!(
+                        "only eq and co comparisons of {} are supported",
+                        attrs.collect::<Vec<_>>().join(", ")
+                    ))
+                })
+            })
+            .map_err(|e| ScimError::new(e, "invalidFilter"))?,
+        None => Vec::new(),
+    };
+
+    Ok(params.page_request(filters, sort_field))
+}
+
+/// Parses a resource id, malformed ids are reported as missing resources.
+fn parse_id(id: &str) -> Result<Uuid, ScimError> {
+    Uuid::parse_str(id).map_err(|_| ScimError::from(ErrorKind::NotFound))
+}
+
+fn respond<T: Serialize>(
+    builder: &mut HttpResponseBuilder,
+    body: &T,
+) -> Result<HttpResponse, ScimError> {
+    let body =
+        serde_json::to_string(body).context(ErrorKind::SerializeJsonError)?;
+
+    Ok(builder.content_type("application/scim+json").body(body))
+}
+
+fn service_provider_config(req: HttpRequest) -> HttpResponse {
+    let base = base_url(&req, "/ServiceProviderConfig");
+    let body = serde_json::json!({
+        "schemas": [
+            "urn:ietf:params:scim:schemas:core:2.0:ServiceProviderConfig"
+        ],
+        "patch": { "supported": true },
+        "bulk": { "supported": false, "maxOperations": 0, "maxPayloadSize": 0 },
+        "filter": { "supported": true, "maxResults": types::MAX_COUNT },
+        "changePassword": { "supported": true },
+        "sort": { "supported": false },
+        "etag": { "supported": false },
+        "authenticationSchemes": [{
+            "type": "oauthbearertoken",
+            "name": "Bearer token",
+            "description":
+                "Access token or API key in the Authorization header",
+            "primary": true,
+        }],
+        "meta": {
+            "resourceType": "ServiceProviderConfig",
+            "location": format!("{}/ServiceProviderConfig", base),
+        },
+    });
+
+    HttpResponse::Ok()
+        .content_type("application/scim+json")
+        .body(body.to_string())
+}
+
+fn schemas(req: HttpRequest) -> HttpResponse {
+    let base = base_url(&req, "/Schemas");
+    let attribute = |name: &str, kind: &str, multi: bool, mutability: &str| {
+        serde_json::json!({
+            "name": name,
+            "type": kind,
+            "multiValued": multi,
+            "required": name == "userName" || name == "displayName",
+            "caseExact": false,
+            "mutability": mutability,
+            "returned": if name == "password" { "never" } else { "default" },
+            "uniqueness": if name == "userName" { "server" } else { "none" },
+        })
+    };
+    let schemas = vec![
+        serde_json::json!({
+            "id": USER_SCHEMA,
+            "name": "User",
+            "description": "User Account",
+            "attributes": [
+                attribute("userName", "string", false, "readWrite"),
+                attribute("displayName", "string", false, "readWrite"),
+                attribute("password", "string", false, "writeOnly"),
+                attribute("emails", "complex", true, "readWrite"),
+                attribute("photos", "complex", true, "readWrite"),
+                attribute("groups", "complex", true, "readOnly"),
+            ],
+            "meta": {
+                "resourceType": "Schema",
+                "location": format!("{}/Schemas/{}", base, USER_SCHEMA),
+            },
+        }),
+        serde_json::json!({
+            "id": GROUP_SCHEMA,
+            "name": "Group",
+            "description": "Group",
+            "attributes": [
+                attribute("displayName", "string", false, "readWrite"),
+                attribute("members", "complex", true, "readWrite"),
+            ],
+            "meta": {
+                "resourceType": "Schema",
+                "location": format!("{}/Schemas/{}", base, GROUP_SCHEMA),
+            },
+        }),
+    ];
+
+    list(schemas)
+}
+
+fn resource_types(req: HttpRequest) -> HttpResponse {
+    let base = base_url(&req, "/ResourceTypes");
+    let resource_type = |name: &str, endpoint: &str, schema: &str| {
+        serde_json::json!({
+            "schemas": [
+                "urn:ietf:params:scim:schemas:core:2.0:ResourceType"
+            ],
+            "id": name,
+            "name": name,
+            "endpoint": endpoint,
+            "schema": schema,
+            "meta": {
+                "resourceType": "ResourceType",
+                "location": format!("{}/ResourceTypes/{}", base, name),
+            },
+        })
+    };
+
+    list(vec![
+        resource_type("User", "/Users", USER_SCHEMA),
+        resource_type("Group", "/Groups", GROUP_SCHEMA),
+    ])
+}
+
+fn list(resources: Vec<serde_json::Value>) -> HttpResponse {
+    let total = resources.len() as i64;
+    let body = types::ListResponse::new(resources, total, &Default::default());
+
+    HttpResponse::Ok()
+        .content_type("application/scim+json")
+        .body(serde_json::to_string(&body).unwrap_or_default())
+}
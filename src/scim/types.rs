@@ -0,0 +1,276 @@
+use chrono::prelude::*;
+use serde_json::Value;
+
+use crate::db::groups::{Group, GroupMembership, GroupMembershipType};
+use crate::db::page::{self, Direction, PageRequest, Sort};
+use crate::db::users::User;
+
+pub const USER_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+pub const GROUP_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:Group";
+pub const LIST_RESPONSE_SCHEMA: &str =
+    "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+pub const ERROR_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+
+pub const DEFAULT_COUNT: i64 = 100;
+pub const MAX_COUNT: i64 = 200;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Meta {
+    pub resource_type: String,
+    pub created: DateTime<Utc>,
+    pub last_modified: DateTime<Utc>,
+    pub location: String,
+}
+
+/// Value of a multi-valued attribute such as `photos` or `emails`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct MultiValue {
+    pub value: String,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub primary: Option<bool>,
+}
+
+/// Reference to a user or group, as in `members` and `groups`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Reference {
+    pub value: String,
+    #[serde(rename = "$ref", skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display: Option<String>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimUser {
+    #[serde(default)]
+    pub schemas: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub user_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(default, skip_serializing)]
+    pub password: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub emails: Vec<MultiValue>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub photos: Vec<MultiValue>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<Reference>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Meta>,
+}
+
+impl ScimUser {
+    pub fn new(user: User, groups: Vec<Group>, base: &str) -> Self {
+        let id = user.id.simple().to_string();
+        let groups = groups
+            .into_iter()
+            .map(|g| {
+                let id = g.id.simple().to_string();
+                Reference {
+                    reference: Some(format!("{}/Groups/{}", base, id)),
+                    value: id,
+                    display: Some(g.display_name),
+                    kind: Some("direct".to_string()),
+                }
+            })
+            .collect();
+
+        ScimUser {
+            schemas: vec![USER_SCHEMA.to_string()],
+            meta: Some(Meta {
+                resource_type: "User".to_string(),
+                created: user.created_at,
+                last_modified: user.updated_at,
+                location: format!("{}/Users/{}", base, id),
+            }),
+            id: Some(id),
+            user_name: user.username,
+            display_name: Some(user.nickname),
+            password: None,
+            emails: primary(user.email, "work"),
+            photos: primary(user.avatar_url, "photo"),
+            groups,
+        }
+    }
+
+    pub fn email(&self) -> Option<String> {
+        primary_value(&self.emails)
+    }
+
+    pub fn photo(&self) -> Option<String> {
+        primary_value(&self.photos)
+    }
+}
+
+fn primary(value: Option<String>, kind: &str) -> Vec<MultiValue> {
+    value
+        .into_iter()
+        .map(|value| MultiValue {
+            value,
+            kind: Some(kind.to_string()),
+            primary: Some(true),
+        })
+        .collect()
+}
+
+/// Returns the primary value, or the first one if none is marked primary.
+fn primary_value(values: &[MultiValue]) -> Option<String> {
+    values
+        .iter()
+        .find(|v| v.primary == Some(true))
+        .or_else(|| values.first())
+        .map(|v| v.value.clone())
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimGroup {
+    #[serde(default)]
+    pub schemas: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub display_name: String,
+    #[serde(default)]
+    pub members: Vec<Reference>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Meta>,
+}
+
+impl ScimGroup {
+    pub fn new(
+        group: Group,
+        members: Vec<GroupMembership>,
+        base: &str,
+    ) -> Self {
+        let id = group.id.simple().to_string();
+        let members = members
+            .into_iter()
+            .map(|m| {
+                let id = m.member_id.simple().to_string();
+                let (kind, endpoint) = match m.member_type {
+                    GroupMembershipType::User => ("User", "Users"),
+                    GroupMembershipType::Group => ("Group", "Groups"),
+                };
+                Reference {
+                    reference: Some(format!("{}/{}/{}", base, endpoint, id)),
+                    value: id,
+                    display: None,
+                    kind: Some(kind.to_string()),
+                }
+            })
+            .collect();
+
+        ScimGroup {
+            schemas: vec![GROUP_SCHEMA.to_string()],
+            meta: Some(Meta {
+                resource_type: "Group".to_string(),
+                created: group.created_at,
+                last_modified: group.updated_at,
+                location: format!("{}/Groups/{}", base, id),
+            }),
+            id: Some(id),
+            display_name: group.display_name,
+            members,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListResponse<T> {
+    pub schemas: Vec<String>,
+    pub total_results: i64,
+    pub start_index: i64,
+    pub items_per_page: i64,
+    #[serde(rename = "Resources")]
+    pub resources: Vec<T>,
+}
+
+impl<T> ListResponse<T> {
+    /// Wraps the page of resources selected by `params` out of
+    /// `total_results` matching ones.
+    pub fn new(
+        resources: Vec<T>,
+        total_results: i64,
+        params: &ListParams,
+    ) -> Self {
+        ListResponse {
+            schemas: vec![LIST_RESPONSE_SCHEMA.to_string()],
+            total_results,
+            start_index: params.start_index(),
+            items_per_page: resources.len() as i64,
+            resources,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListParams {
+    pub filter: Option<String>,
+    pub start_index: Option<i64>,
+    pub count: Option<i64>,
+}
+
+impl ListParams {
+    pub fn start_index(&self) -> i64 {
+        self.start_index.unwrap_or(1).max(1)
+    }
+
+    /// Returns the database request for the page, sorted by `sort_field`.
+    pub fn page_request(
+        &self,
+        filters: Vec<page::Filter>,
+        sort_field: &str,
+    ) -> PageRequest {
+        PageRequest {
+            page: 1,
+            per_page: self.count.unwrap_or(DEFAULT_COUNT).min(MAX_COUNT).max(0),
+            start: Some(self.start_index() - 1),
+            sort: vec![Sort {
+                field: sort_field.to_string(),
+                direction: Direction::Asc,
+            }],
+            filters,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PatchRequest {
+    #[serde(rename = "Operations")]
+    pub operations: Vec<PatchOperation>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PatchOperation {
+    pub op: String,
+    pub path: Option<String>,
+    pub value: Option<Value>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PatchOp {
+    Add,
+    Remove,
+    Replace,
+}
+
+impl PatchOperation {
+    pub fn op(&self) -> Option<PatchOp> {
+        match self.op.to_lowercase().as_str() {
+            "add" => Some(PatchOp::Add),
+            "remove" => Some(PatchOp::Remove),
+            "replace" => Some(PatchOp::Replace),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,440 @@
+use std::collections::HashMap;
+
+use actix_web::http::header::LOCATION;
+use actix_web::{web, HttpRequest, HttpResponse, Scope};
+use futures::Future;
+use serde_json::Value;
+use uuid::Uuid;
+
+use super::error::ScimError;
+use super::filter::attr_name;
+use super::types::{
+    ListParams, ListResponse, MultiValue, PatchOp, PatchRequest, ScimUser,
+};
+use crate::auth::PasswordPolicy;
+use crate::db::{
+    groups::{self, Group},
+    users::{self, NewUser, UpdateUser, User},
+    Conn, Database,
+};
+use crate::error::{ErrorKind, Result};
+use crate::utils;
+
+pub fn service(path: &str) -> Scope {
+    web::scope(path)
+        .service(
+            web::resource("")
+                .route(web::get().to_async(get_users))
+                .route(web::post().to_async(add_user)),
+        )
+        .service(
+            web::resource("/{user_id}")
+                .route(web::get().to_async(get_user))
+                .route(web::put().to_async(replace_user))
+                .route(web::patch().to_async(patch_user))
+                .route(web::delete().to_async(del_user)),
+        )
+}
+
+fn get_users(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    params: web::Query<ListParams>,
+) -> impl Future<Item = HttpResponse, Error = ScimError> {
+    let base = super::base_url(&req, "/Users");
+    web::block(move || -> std::result::Result<_, ScimError> {
+        let request = super::page_request(
+            &params,
+            &[("userName", "username"), ("displayName", "nickname")],
+            "username",
+        )?;
+
+        let conn = db.conn()?;
+        let page = users::find_page(&conn, &request)?;
+        let resources = to_resources(&conn, page.items, &base)?;
+
+        Ok(ListResponse::new(resources, page.total, &params))
+    })
+    .from_err()
+    .and_then(|res| super::respond(&mut HttpResponse::Ok(), &res))
+}
+
+fn add_user(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    policy: web::Data<PasswordPolicy>,
+    user: web::Json<ScimUser>,
+) -> impl Future<Item = HttpResponse, Error = ScimError> {
+    let base = super::base_url(&req, "/Users");
+    let user = user.into_inner();
+    web::block(move || -> std::result::Result<_, ScimError> {
+        // Provisioned accounts may sign in through another system and come
+        // without a password, they get a random one nobody knows.
+        let password = match user.password {
+            Some(ref password) => {
+                policy.validate(password, &user.user_name)?;
+                password.clone()
+            }
+            None => utils::random_string(32),
+        };
+        let email = user.email();
+        if let Some(ref email) = email {
+            utils::validate_email(email)?;
+        }
+        let new = NewUser {
+            nickname: user
+                .display_name
+                .clone()
+                .unwrap_or_else(|| user.user_name.clone()),
+            avatar_url: user.photo(),
+            username: user.user_name,
+            password: policy.hash(&password)?,
+            email,
+        };
+
+        let conn = db.conn()?;
+        let result = users::create(&conn, new)?;
+        Ok(ScimUser::new(result, Vec::new(), &base))
+    })
+    .from_err()
+    .and_then(|res| {
+        let location = res.meta.as_ref().map(|m| m.location.clone());
+        super::respond(
+            HttpResponse::Created()
+                .header(LOCATION, location.unwrap_or_default()),
+            &res,
+        )
+    })
+}
+
+fn get_user(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    user_id: web::Path<String>,
+) -> impl Future<Item = HttpResponse, Error = ScimError> {
+    let base = super::base_url(&req, "/Users");
+    web::block(move || -> std::result::Result<_, ScimError> {
+        let user_id = super::parse_id(&user_id)?;
+        let conn = db.conn()?;
+        let user = find_user(&conn, &user_id)?;
+        Ok(to_resource(&conn, user, &base)?)
+    })
+    .from_err()
+    .and_then(|res| super::respond(&mut HttpResponse::Ok(), &res))
+}
+
+fn replace_user(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    policy: web::Data<PasswordPolicy>,
+    user_id: web::Path<String>,
+    user: web::Json<ScimUser>,
+) -> impl Future<Item = HttpResponse, Error = ScimError> {
+    let base = super::base_url(&req, "/Users");
+    web::block(move || -> std::result::Result<_, ScimError> {
+        let user_id = super::parse_id(&user_id)?;
+        let conn = db.conn()?;
+        let result = replace(&conn, &policy, &user_id, user.into_inner())?;
+        Ok(to_resource(&conn, result, &base)?)
+    })
+    .from_err()
+    .and_then(|res| super::respond(&mut HttpResponse::Ok(), &res))
+}
+
+fn patch_user(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    policy: web::Data<PasswordPolicy>,
+    user_id: web::Path<String>,
+    patch: web::Json<PatchRequest>,
+) -> impl Future<Item = HttpResponse, Error = ScimError> {
+    let base = super::base_url(&req, "/Users");
+    web::block(move || -> std::result::Result<_, ScimError> {
+        let user_id = super::parse_id(&user_id)?;
+        let conn = db.conn()?;
+        let mut user = ScimUser::new(find_user(&conn, &user_id)?, vec![], "");
+        apply_patch(&mut user, &patch)?;
+
+        let result = replace(&conn, &policy, &user_id, user)?;
+        Ok(to_resource(&conn, result, &base)?)
+    })
+    .from_err()
+    .and_then(|res| super::respond(&mut HttpResponse::Ok(), &res))
+}
+
+fn del_user(
+    db: web::Data<Database>,
+    user_id: web::Path<String>,
+) -> impl Future<Item = HttpResponse, Error = ScimError> {
+    web::block(move || -> std::result::Result<_, ScimError> {
+        let user_id = super::parse_id(&user_id)?;
+        db.transaction(|conn| match users::del_cascade(conn, &user_id)? {
+            0 => Err(ErrorKind::NotFound)?,
+            _ => Ok(()),
+        })?;
+        Ok(())
+    })
+    .from_err()
+    .map(|_| HttpResponse::NoContent().finish())
+}
+
+fn find_user(conn: &Conn, user_id: &Uuid) -> Result<User> {
+    match users::find_by_id(conn, user_id)? {
+        Some(user) => Ok(user),
+        None => Err(ErrorKind::NotFound)?,
+    }
+}
+
+fn replace(
+    conn: &Conn,
+    policy: &PasswordPolicy,
+    user_id: &Uuid,
+    user: ScimUser,
+) -> Result<User> {
+    let password = match user.password {
+        Some(ref password) => {
+            policy.validate(password, &user.user_name)?;
+            Some(policy.hash(password)?)
+        }
+        None => None,
+    };
+    let email = user.email();
+    if let Some(ref email) = email {
+        utils::validate_email(email)?;
+    }
+    let update = UpdateUser {
+        nickname: Some(
+            user.display_name
+                .clone()
+                .unwrap_or_else(|| user.user_name.clone()),
+        ),
+        avatar_url: Some(user.photo()),
+        username: Some(user.user_name),
+        password,
+        email: Some(email),
+    };
+
+    users::update(conn, user_id, update)
+}
+
+fn to_resource(conn: &Conn, user: User, base: &str) -> Result<ScimUser> {
+    let mut resources = to_resources(conn, vec![user], base)?;
+    Ok(resources.remove(0))
+}
+
+fn to_resources(
+    conn: &Conn,
+    users: Vec<User>,
+    base: &str,
+) -> Result<Vec<ScimUser>> {
+    let ids = users.iter().map(|u| u.id).collect::<Vec<Uuid>>();
+    let mut memberships: HashMap<Uuid, Vec<Group>> = HashMap::new();
+    for (member_id, group) in groups::find_direct_by_member_ids(conn, &ids)? {
+        memberships.entry(member_id).or_default().push(group);
+    }
+
+    Ok(users
+        .into_iter()
+        .map(|user| {
+            let groups = memberships.remove(&user.id).unwrap_or_default();
+            ScimUser::new(user, groups, base)
+        })
+        .collect())
+}
+
+fn apply_patch(
+    user: &mut ScimUser,
+    patch: &PatchRequest,
+) -> std::result::Result<(), ScimError> {
+    for operation in &patch.operations {
+        let op = operation.op().ok_or_else(|| {
+            ScimError::invalid_value(format!(
+                "unsupported operation `{}`",
+                operation.op
+            ))
+        })?;
+        let value = operation.value.clone().unwrap_or(Value::Null);
+
+        match (op, &operation.path) {
+            (PatchOp::Remove, Some(path)) => {
+                match attr_name(path).to_lowercase().as_str() {
+                    "emails" => user.emails.clear(),
+                    "photos" => user.photos.clear(),
+                    _ => {
+                        return Err(ScimError::invalid_path(format!(
+                            "`{}` can not be removed",
+                            path
+                        )))
+                    }
+                }
+            }
+            (PatchOp::Remove, None) => {
+                return Err(ScimError::invalid_path(
+                    "remove operations require a path",
+                ))
+            }
+            (op, Some(path)) => set_attr(user, op, path, value)?,
+            (op, None) => match value {
+                Value::Object(map) => {
+                    for (name, value) in map {
+                        set_attr(user, op, &name, value)?;
+                    }
+                }
+                _ => {
+                    return Err(ScimError::invalid_value(
+                        "operations without a path require an object value",
+                    ))
+                }
+            },
+        }
+    }
+
+    Ok(())
+}
+
+fn set_attr(
+    user: &mut ScimUser,
+    op: PatchOp,
+    path: &str,
+    value: Value,
+) -> std::result::Result<(), ScimError> {
+    let string = |value: Value| match value {
+        Value::String(s) => Ok(s),
+        _ => Err(ScimError::invalid_value(format!(
+            "`{}` must be a string",
+            path
+        ))),
+    };
+
+    match attr_name(path).to_lowercase().as_str() {
+        "username" => user.user_name = string(value)?,
+        "displayname" => user.display_name = Some(string(value)?),
+        "password" => user.password = Some(string(value)?),
+        "emails" => set_values(&mut user.emails, op, value)?,
+        "photos" => set_values(&mut user.photos, op, value)?,
+        _ => {
+            return Err(ScimError::invalid_path(format!(
+                "`{}` is not a mutable attribute",
+                path
+            )))
+        }
+    }
+
+    Ok(())
+}
+
+/// Replaces or extends a multi-valued attribute with one or more values.
+fn set_values(
+    values: &mut Vec<MultiValue>,
+    op: PatchOp,
+    value: Value,
+) -> std::result::Result<(), ScimError> {
+    let new = match value {
+        Value::Array(_) => serde_json::from_value(value),
+        value => serde_json::from_value(value).map(|v| vec![v]),
+    }
+    .map_err(|e| ScimError::invalid_value(e.to_string()))?;
+    if op == PatchOp::Replace {
+        *values = new;
+    } else {
+        values.extend::<Vec<MultiValue>>(new);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+
+    fn user() -> ScimUser {
+        serde_json::from_value(serde_json::json!({
+            "userName": "bob",
+            "displayName": "Bob",
+            "photos": [{ "value": "/a.png", "primary": true }],
+        }))
+        .unwrap()
+    }
+
+    fn patch(operations: Value) -> PatchRequest {
+        serde_json::from_value(serde_json::json!({ "Operations": operations }))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_apply_patch() {
+        let mut user = user();
+
+        apply_patch(
+            &mut user,
+            &patch(serde_json::json!([
+                { "op": "replace", "path": "displayName", "value": "Robert" },
+                { "op": "Replace", "value": { "userName": "robert" } },
+                { "op": "remove", "path": "photos" },
+            ])),
+        )
+        .unwrap();
+
+        assert_eq!("robert", user.user_name);
+        assert_eq!(Some("Robert".to_string()), user.display_name);
+        assert_eq!(None, user.photo());
+    }
+
+    #[test]
+    fn test_patch_persists() {
+        let conn = connection();
+        let bob =
+            users::create_or_update(&conn, "bob", "Bob", "password").unwrap();
+        let mut user = ScimUser::new(bob, vec![], "");
+        assert!(user.photo().is_some());
+
+        apply_patch(
+            &mut user,
+            &patch(serde_json::json!([
+                { "op": "remove", "path": "photos" },
+                { "op": "add", "path": "emails", "value": [
+                    { "value": "bob@example.com", "primary": true },
+                ]},
+            ])),
+        )
+        .unwrap();
+        let policy = PasswordPolicy::default();
+        let id = Uuid::parse_str(user.id.as_ref().unwrap()).unwrap();
+        replace(&conn, &policy, &id, user).unwrap();
+
+        let bob = users::find_by_id(&conn, &id).unwrap().unwrap();
+        assert_eq!(None, bob.avatar_url);
+        assert_eq!(Some("bob@example.com".to_string()), bob.email);
+
+        let mut user = ScimUser::new(bob, vec![], "");
+        assert_eq!(Some("bob@example.com".to_string()), user.email());
+        apply_patch(
+            &mut user,
+            &patch(serde_json::json!([
+                { "op": "remove", "path": "emails" },
+            ])),
+        )
+        .unwrap();
+        replace(&conn, &policy, &id, user).unwrap();
+
+        let bob = users::find_by_id(&conn, &id).unwrap().unwrap();
+        assert_eq!(None, bob.email);
+    }
+
+    #[test]
+    fn test_apply_patch_invalid_path() {
+        let mut user = user();
+
+        let err = apply_patch(
+            &mut user,
+            &patch(serde_json::json!([
+                { "op": "remove", "path": "userName" },
+            ])),
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(Some("invalidPath"), err.scim_type());
+    }
+}
@@ -1,4 +1,22 @@
+use actix_http::Request;
+use actix_service::Service;
+use actix_web::cookie::Cookie;
+use actix_web::dev::{Body, ServiceResponse};
+use actix_web::test::{self, TestRequest};
+use actix_web::{web, App, HttpResponse};
 use diesel::prelude::*;
+use diesel::r2d2::{self, ConnectionManager, CustomizeConnection, Pool};
+
+use crate::api;
+use crate::auth::middleware::{
+    AuthenticationService, CookieAuthenticationBackend, ImpersonationService,
+};
+use crate::auth::{
+    Authentication, AuthenticationManager, LockoutPolicy, PasswordPolicy,
+    Policy, TokenSigner,
+};
+use crate::db::Database;
+use crate::oidc::{self, IdTokenSigner, Provider, RelyingParty};
 
 pub fn connection() -> PgConnection {
     let database_url =
@@ -7,3 +25,88 @@ pub fn connection() -> PgConnection {
     conn.begin_test_transaction().unwrap();
     conn
 }
+
+#[derive(Debug)]
+struct TestTransaction;
+
+impl CustomizeConnection<PgConnection, r2d2::Error> for TestTransaction {
+    fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), r2d2::Error> {
+        conn.begin_test_transaction()
+            .map_err(r2d2::Error::QueryError)
+    }
+}
+
+/// Database of a single connection whose changes are never committed.
+///
+/// Handlers share the connection, so tests have to drop the ones they take
+/// before calling the app.
+pub fn database() -> Database {
+    let database_url =
+        dotenv::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = Pool::builder()
+        .max_size(1)
+        .connection_customizer(Box::new(TestTransaction))
+        .build(ConnectionManager::new(database_url))
+        .unwrap();
+
+    Database { pool }
+}
+
+/// Serves the api and the OpenID Connect provider, callers log in through
+/// `login`. No identity provider is configured.
+pub fn app(
+    db: &Database,
+) -> impl Service<
+    Request = Request,
+    Response = ServiceResponse<Body>,
+    Error = actix_web::Error,
+> {
+    test::init_service(
+        App::new()
+            .data(db.clone())
+            .data(LockoutPolicy::default())
+            .data(PasswordPolicy::default())
+            .data(TokenSigner::new(&[0; 32]))
+            .data(None::<RelyingParty>)
+            .data(Provider::new(
+                "http://localhost",
+                IdTokenSigner::generate().unwrap(),
+            ))
+            .data(Policy::from_toml(include_str!("../policy.toml")).unwrap())
+            .wrap(ImpersonationService::new(db.clone()))
+            .wrap(AuthenticationService::new(
+                CookieAuthenticationBackend::new(&[0; 32]).secure(false),
+            ))
+            .service(web::resource("/login").to(
+                |a: web::Json<Authentication>, am: AuthenticationManager| {
+                    am.remember(a.into_inner());
+                    HttpResponse::Ok()
+                },
+            ))
+            .service(api::service("/api"))
+            .service(oidc::service(oidc::PATH)),
+    )
+}
+
+/// Returns the authentication cookie of a response.
+pub fn auth_cookie<B>(resp: &ServiceResponse<B>) -> Option<Cookie<'static>> {
+    resp.response()
+        .cookies()
+        .find(|c| c.name() == "hamster-auth")
+        .map(|c| c.into_owned())
+}
+
+pub fn login<S>(app: &mut S, a: &Authentication) -> Cookie<'static>
+where
+    S: Service<
+        Request = Request,
+        Response = ServiceResponse<Body>,
+        Error = actix_web::Error,
+    >,
+{
+    let resp = test::call_service(
+        app,
+        TestRequest::post().uri("/login").set_json(a).to_request(),
+    );
+    auth_cookie(&resp).unwrap()
+}
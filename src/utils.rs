@@ -1,11 +1,45 @@
 //! Utilitiles
 use bcrypt::{hash, verify, DEFAULT_COST};
+use rand::distributions::Alphanumeric;
 use rand::Rng;
+use ring::digest;
+use serde::{Deserialize, Deserializer};
 
-use crate::error::{ErrorKind, Result, ResultExt};
+use crate::error::{Error, ErrorKind, Result, ResultExt};
+
+pub const DEFAULT_PASSWORD_COST: u32 = DEFAULT_COST;
+
+/// Hash checked against when the user does not exist, so that unknown
+/// usernames take as long to reject as wrong passwords.
+const DUMMY_HASH: &str =
+    "$2y$12$hX5WXE3VtkQWc.3mPe4ZLeLXew2peQpmiUOBwuynqQTI1WDBxG8YG";
+
+/// Loosely checks an email address, leaving the rest to the mail server.
+pub fn validate_email(email: &str) -> Result<()> {
+    let mut parts = email.splitn(2, '@');
+    let valid = match (parts.next(), parts.next()) {
+        (Some(local), Some(domain)) => {
+            !local.is_empty()
+                && !domain.is_empty()
+                && !domain.contains('@')
+                && !email.chars().any(|c| c.is_whitespace() || c.is_control())
+        }
+        _ => false,
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::validation("email", "is not a valid email address"))
+    }
+}
 
 pub fn hash_password(password: &str) -> Result<String> {
-    Ok(hash(password, DEFAULT_COST).context(ErrorKind::HashPasswordFailure)?)
+    hash_password_with_cost(password, DEFAULT_PASSWORD_COST)
+}
+
+pub fn hash_password_with_cost(password: &str, cost: u32) -> Result<String> {
+    Ok(hash(password, cost).context(ErrorKind::HashPasswordFailure)?)
 }
 
 pub fn verify_password(raw_password: &str, password: &str) -> Result<bool> {
@@ -13,8 +47,80 @@ pub fn verify_password(raw_password: &str, password: &str) -> Result<bool> {
         .context(ErrorKind::HashPasswordFailure)?)
 }
 
+/// Spends the time of a password verification, always failing.
+pub fn dummy_verify_password(raw_password: &str) {
+    let _ = verify(raw_password, DUMMY_HASH);
+}
+
+/// Returns `true` if the value looks like a bcrypt hash (`$2a$`, `$2b$`,
+/// `$2x$` or `$2y$` followed by cost and 53 characters of salt and hash).
+pub fn is_bcrypt_hash(value: &str) -> bool {
+    let parts = value.split('$').collect::<Vec<&str>>();
+
+    match parts.as_slice() {
+        ["", version, cost, hash] => {
+            ["2a", "2b", "2x", "2y"].contains(version)
+                && cost.len() == 2
+                && cost.chars().all(|c| c.is_ascii_digit())
+                && hash.len() == 53
+        }
+        _ => false,
+    }
+}
+
+pub fn random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .collect()
+}
+
+/// SHA-256 of a random token, base64url encoded, for storing tokens which are
+/// long enough not to need a slow hash.
+pub fn hash_token(token: &str) -> String {
+    let hash = digest::digest(&digest::SHA256, token.as_bytes());
+    base64::encode_config(hash.as_ref(), base64::URL_SAFE_NO_PAD)
+}
+
+/// Deserializes a present field as `Some`, `null` included. With
+/// `#[serde(default)]` a missing field stays `None` and `null` becomes
+/// `Some(None)`.
+pub fn deserialize_some<'de, T, D>(
+    deserializer: D,
+) -> std::result::Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
 pub fn random_avatar() -> String {
     let mut rng = rand::thread_rng();
     let avatar_num: i32 = rng.gen_range(1, 21);
     format!("/api/images/avatars/{}.png", avatar_num)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_email() {
+        assert!(validate_email("bob@example.com").is_ok());
+
+        for email in &["", "bob", "@example.com", "bob@", "bob@a@b", "b ob@a"] {
+            let err = validate_email(email).unwrap_err();
+            assert_eq!("email", err.details()[0].field);
+        }
+    }
+
+    #[test]
+    fn test_is_bcrypt_hash() {
+        let hashed = hash_password("123456").unwrap();
+
+        assert!(is_bcrypt_hash(&hashed));
+        assert!(!is_bcrypt_hash("123456"));
+        assert!(!is_bcrypt_hash("$2b$12$short"));
+    }
+}